@@ -14,9 +14,9 @@ function usageAndExit() {
     Usage:
       node client.js ping
 
-      node client.js new --client <u64> --id <u64> --side <buy|sell|0|1> --price <i64> --qty <i64> --tif <gtc|ioc|0|1>
+      node client.js new --client <u64> --id <u64> --sym <u32> --side <buy|sell|0|1> --price <i64> --qty <i64> --tif <gtc|ioc|fok|0|1|2>
 
-      node client.js cancel --client <u64> --id <u64>
+      node client.js cancel --client <u64> --id <u64> --sym <u32>
 
     Env/config:
       HOST, PORT via ./config.js
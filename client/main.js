@@ -1,9 +1,10 @@
 // main.js
 const net = require("node:net");
+const crypto = require("node:crypto");
 const fs = require("node:fs");
 const readline = require("node:readline");
 const { host, port, localPort } = require("./config");
-const { buildFrame, u64, i64 } = require("./helpers");
+const { buildFrame, u16, u32, u64, i64 } = require("./helpers");
 
 // ---------------- CLI args ----------------
 const args = Object.fromEntries(
@@ -22,22 +23,82 @@ const LOOP = !!args.loop;
 
 // ---------- encoders & frames ----------
 function ping() { return buildFrame(1); }
-function newOrder({ client_id, cl_ord_id, side, price, qty, tif }) {
-  const payload = Buffer.concat([
+function newOrderBody({ client_id, cl_ord_id, symbol = 0, side, price, qty, tif, ord_type = 0, flags = 0, stp = 0, expire_ms = 0n, display_qty = 0n, stop_price = 0n }) {
+  return Buffer.concat([
     u64(client_id),
     u64(cl_ord_id),
+    u32(symbol),
     Buffer.from([side]),          // 0=bid, 1=ask
     i64(price),
     i64(qty),
-    Buffer.from([tif]),           // 0=GTC, 1=IOC
+    Buffer.from([tif]),           // 0=GTC, 1=IOC, 2=FOK, 3=GTD
+    Buffer.from([ord_type]),      // 0=LIMIT, 1=MARKET, 2=STOP, 3=STOP_LIMIT
+    Buffer.from([flags]),         // bit0=POST_ONLY, bit1=REDUCE_ONLY
+    Buffer.from([stp]),           // 0=OFF, 1=CANCEL_RESTING, 2=CANCEL_AGGRESSING
+    u64(expire_ms),               // GTD expiry (unix ms), ignored otherwise
+    u64(display_qty),             // Iceberg slice size, 0 = show it all
+    i64(stop_price),              // Trigger of a stop / stop-limit
   ]);
-  return buildFrame(10, payload);
 }
-function cancel({ client_id, cl_ord_id }) {
-  const payload = Buffer.concat([u64(client_id), u64(cl_ord_id)]);
+function newOrder(o) { return buildFrame(10, newOrderBody(o)); }
+// Every optional field is sent, so each body is one fixed-size BATCH_NEW record
+function batchNew(orders) { return buildFrame(26, Buffer.concat([u16(orders.length), ...orders.map(newOrderBody)])); }
+function cancel({ client_id, cl_ord_id, symbol = 0 }) {
+  const payload = Buffer.concat([u64(client_id), u64(cl_ord_id), u32(symbol)]);
   return buildFrame(11, payload);
 }
 
+function replace({ client_id, cl_ord_id, symbol = 0, price, qty }) {
+  const payload = Buffer.concat([u64(client_id), u64(cl_ord_id), u32(symbol), i64(price), i64(qty)]);
+  return buildFrame(14, payload);
+}
+
+function reduce({ client_id, cl_ord_id, symbol = 0, qty }) {
+  const payload = Buffer.concat([u64(client_id), u64(cl_ord_id), u32(symbol), i64(qty)]);
+  return buildFrame(29, payload);
+}
+
+function cancelAll({ client_id, symbol }) {
+  const payload = symbol === undefined ? u64(client_id) : Buffer.concat([u64(client_id), u32(symbol)]);
+  return buildFrame(15, payload);
+}
+
+// Protocol version this client speaks; the server answers HELLO_ACK with the one both know
+const PROTO_VERSION = 1;
+function hello({ flags = 0 }) {
+  return buildFrame(16, Buffer.concat([u16(PROTO_VERSION), Buffer.from([flags])]));
+}
+
+// ADMIN_AUTH: empty asks for a challenge; the answer is HMAC-SHA256(token, nonce)
+function adminAuth(mac) { return buildFrame(28, mac ?? Buffer.alloc(0)); }
+let adminSecret = null; // Token to answer the next ADMIN_CHALLENGE with
+
+function queryOrders({ client_id }) { return buildFrame(17, u64(client_id)); }
+function resync({ client_id, since = 0n }) { return buildFrame(27, Buffer.concat([u64(client_id), u64(since)])); }
+
+function queryStats({ symbol = 0 }) { return buildFrame(18, u32(symbol)); }
+
+const MARKET_STATE_MSG = { halt: 20, resume: 21, auction: 22, uncross: 23 };
+function marketState({ symbol, cmd }) { return buildFrame(MARKET_STATE_MSG[cmd], u32(symbol)); }
+
+function register({ client_id }) { return buildFrame(24, u64(client_id)); }
+
+function dropCopySubscribe() { return buildFrame(25, Buffer.alloc(0)); }
+
+function sessionRoll({ symbol }) {
+  return buildFrame(19, symbol === undefined ? Buffer.alloc(0) : u32(symbol));
+}
+
+function subscribe({ conflate_ms = 0, orders = false, snap, depth = 10, prints = false } = {}) {
+  const flags = (orders ? 0x01 : 0) | (snap !== undefined ? 0x02 : 0) | (prints ? 0x04 : 0);
+  if (snap !== undefined) return buildFrame(12, Buffer.concat([u16(conflate_ms), Buffer.from([flags]), u32(snap), u16(depth)]));
+  if (flags) return buildFrame(12, Buffer.concat([u16(conflate_ms), Buffer.from([flags])]));
+  return buildFrame(12, conflate_ms > 0 ? u16(conflate_ms) : Buffer.alloc(0));
+}
+function snapshot({ symbol = 0, depth = 10 }) {
+  return buildFrame(13, Buffer.concat([u32(symbol), u16(depth)]));
+}
+
 // ---------- small parsers ----------
 function toSide(v) {
   if (v === undefined) throw new Error("side is required");
@@ -51,7 +112,27 @@ function toTif(v) {
   const t = String(v).toLowerCase();
   if (t === "0" || t === "gtc") return 0;
   if (t === "1" || t === "ioc") return 1;
-  throw new Error("tif must be gtc|ioc|0|1");
+  if (t === "2" || t === "fok") return 2;
+  if (t === "3" || t === "gtd") return 3;
+  if (t === "4" || t === "day") return 4;
+  throw new Error("tif must be gtc|ioc|fok|gtd|day|0|1|2|3|4");
+}
+function toOrdType(v) {
+  if (v === undefined) return 0; // limit by default
+  const t = String(v).toLowerCase();
+  if (t === "0" || t === "limit")  return 0;
+  if (t === "1" || t === "market") return 1;
+  if (t === "2" || t === "stop") return 2;
+  if (t === "3" || t === "stoplimit" || t === "stop_limit") return 3;
+  throw new Error("type must be limit|market|stop|stoplimit|0|1|2|3");
+}
+function toStp(v) {
+  if (v === undefined) return 0; // off by default
+  const t = String(v).toLowerCase();
+  if (t === "0" || t === "off")        return 0;
+  if (t === "1" || t === "resting")    return 1;
+  if (t === "2" || t === "aggressing") return 2;
+  throw new Error("stp must be off|resting|aggressing|0|1|2");
 }
 function parseKV(tokens) {
   const out = {};
@@ -65,8 +146,23 @@ function parseKV(tokens) {
 const HELP = `
 Commands:
   ping
-  new client=<u64> id=<u64> side=<bid|ask|0|1> price=<i64> qty=<i64> tif=<gtc|ioc|0|1>
-  cancel client=<u64> id=<u64>
+  hello [cod=1] [reports=1]  (handshake, sent with no options on connect; cod=1 cancels this connection's orders when it drops; reports=1 sends order events as text execution reports)
+  register client=<u64>    (route that client's maker fills, stops and expiries to this connection)
+  auth secret=<token>      (become admin, which unlocks roll/halt/resume: answers the server's challenge with an HMAC of the token)
+  dropcopy                 (admin: market data plus a copy of every client's order events)
+  sub [conflate=<ms>] [orders=1] [snap=<u32>] [depth=<u16>] [prints=1]  (subscribe to market data: trades, book deltas, BBO, candles; conflate batches level/BBO updates; orders=1 adds the order-level feed; snap starts with that symbol's snapshot and only the events after it; prints=1 sums a taker's fills at each price into one trade)
+  snap [sym=<u32>] [depth=<u16>]
+  new client=<u64> id=<u64> [sym=<u32>] side=<bid|ask|0|1> price=<i64> qty=<i64> tif=<gtc|ioc|fok|gtd|day|0|1|2|3|4> [type=<limit|market|stop|stoplimit>] [post=1] [reduce=1] [stp=<off|resting|aggressing>] [expire=<unix_ms>|ttl=<ms>] [display=<i64>] [stop=<i64>] [batch=<n>]  (batch sends n copies under ids id, id+1, ... in one BATCH_NEW)
+  cancel client=<u64> id=<u64> [sym=<u32>]
+  replace client=<u64> id=<u64> [sym=<u32>] price=<i64> qty=<i64>
+  reduce client=<u64> id=<u64> [sym=<u32>] qty=<i64>   (smaller qty, same price, priority kept)
+  cancelall client=<u64> [sym=<u32>]   (every symbol when sym is omitted)
+  orders client=<u64>      (list that client's resting orders)
+  resync client=<u64> [since=<u64>]   (replay that client's order events after seq since, e.g. fills missed while disconnected)
+  stats [sym=<u32>]        (last price, high/low, volume and trade count this session)
+  roll [sym=<u32>]         (admin: start a new session, every symbol when sym is omitted)
+  halt sym=<u32> | resume sym=<u32>   (admin: stop or restart new orders and replaces; cancels still work)
+  auction sym=<u32> | uncross sym=<u32>   (admin: collect orders without matching, then clear them at one price)
   help
   quit | :q | exit
 
@@ -95,6 +191,8 @@ const socket = net.createConnection(connectOpts, () => {
   console.log(`✅ Connected (local ${local.address}:${local.port}) → ${host}:${port}`);
   socket.setNoDelay(true);
   socket.setKeepAlive(true, 10_000);
+  // The server wants a HELLO before anything else
+  socket.write(hello({}));
 
   if (TEST_MODE) {
     runFile(TEST_FILE, { sleepMs: SLEEP_MS, loop: LOOP });
@@ -130,37 +228,219 @@ function handleCommand(text) {
       return;
     }
 
+    if (cmd === "hello") {
+      const kv = parseKV(rest);
+      const on = v => v === "1" || v === "true";
+      const flags = (on(kv.cod) ? 0x01 : 0) | (on(kv.reports) ? 0x02 : 0);
+      console.log("📤 \x1b[34mHELLO\x1b[0m", { cancel_on_disconnect: on(kv.cod), exec_reports: on(kv.reports) });
+      socket.write(hello({ flags }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "auth") {
+      const kv = parseKV(rest);
+      if (kv.secret === undefined) {
+        console.log("Usage: auth secret=<token>");
+      } else {
+        adminSecret = kv.secret;
+        console.log("📤 \x1b[34mADMIN_AUTH\x1b[0m (challenge request)");
+        socket.write(adminAuth());
+      }
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "register") {
+      const kv = parseKV(rest);
+      if (kv.client === undefined) {
+        console.log("Usage: register client=<u64>");
+        if (!TEST_MODE) rl.prompt();
+        return;
+      }
+      console.log("📤 \x1b[34mREGISTER\x1b[0m", { client: kv.client });
+      socket.write(register({ client_id: kv.client }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "dropcopy") {
+      console.log("📤 \x1b[34mDROPCOPY_SUBSCRIBE\x1b[0m");
+      socket.write(dropCopySubscribe());
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "sub" || cmd === "subscribe") {
+      const kv = parseKV(rest);
+      const conflate_ms = Number(kv.conflate ?? 0);
+      const orders = kv.orders === "1";
+      const snap = kv.snap === undefined ? undefined : Number(kv.snap);
+      const depth = Number(kv.depth ?? 10);
+      const prints = kv.prints === "1";
+      console.log("📤 \x1b[34mSUBSCRIBE\x1b[0m", { conflate_ms, orders, snap, depth, prints });
+      socket.write(subscribe({ conflate_ms, orders, snap, depth, prints }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "snap" || cmd === "snapshot") {
+      const kv = parseKV(rest);
+      const symbol = Number(kv.sym ?? kv.symbol ?? 0);
+      const depth = Number(kv.depth ?? 10);
+      console.log("📤 \x1b[34mBOOK_SNAPSHOT\x1b[0m", { symbol, depth });
+      socket.write(snapshot({ symbol, depth }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
     if (cmd === "new" || cmd === "order" || cmd === "neworder") {
       const kv = parseKV(rest);
       const client_id = BigInt(kv.client ?? kv.client_id ?? (()=>{throw new Error("missing client id");})());
       const cl_ord_id = BigInt(kv.id ?? kv.cl_ord_id ?? (()=>{throw new Error("missing order id");})());
+      const symbol = Number(kv.sym ?? kv.symbol ?? 0);
       const side = toSide(kv.side);
       const tif  = toTif(kv.tif);
+      const ord_type = toOrdType(kv.type);
+      const on = v => v === "1" || v === "true";
+      const flags = (on(kv.post) ? 0x01 : 0) | (on(kv.reduce) ? 0x02 : 0);
+      const stp = toStp(kv.stp);
       const price = BigInt(kv.price ?? (()=>{throw new Error("missing price");})());
       const qty   = BigInt(kv.qty   ?? (()=>{throw new Error("missing qty");})());
+      const expire_ms = kv.expire !== undefined ? BigInt(kv.expire)
+        : kv.ttl !== undefined ? BigInt(Date.now()) + BigInt(kv.ttl) : 0n;
+      const display_qty = BigInt(kv.display ?? 0);
+      const stop_price = BigInt(kv.stop ?? 0);
 
-      console.log("📤 \x1b[34mNEW_ORDER\x1b[0m", {
+      const order = { client_id, cl_ord_id, symbol, side, price, qty, tif, ord_type, flags, stp, expire_ms, display_qty, stop_price };
+      if (kv.batch !== undefined) {
+        // The same order `batch` times under consecutive ids, in one BATCH_NEW frame
+        const n = Number(kv.batch);
+        console.log("📤 \x1b[34mBATCH_NEW\x1b[0m", { count: n, ids: `${cl_ord_id}..${cl_ord_id + BigInt(n - 1)}` });
+        socket.write(batchNew(Array.from({ length: n }, (_, i) => ({ ...order, cl_ord_id: cl_ord_id + BigInt(i) }))));
+      } else {
+        console.log("📤 \x1b[34mNEW_ORDER\x1b[0m", {
+          client_id: client_id.toString(),
+          cl_ord_id: cl_ord_id.toString(),
+          symbol,
+          side: side === 0 ? "BID" : "ASK",
+          price: price.toString(),
+          qty: qty.toString(),
+          tif: ["GTC", "IOC", "FOK", "GTD", "DAY"][tif],
+          type: ["LIMIT", "MARKET", "STOP", "STOP_LIMIT"][ord_type],
+          post_only: (flags & 0x01) !== 0,
+          reduce_only: (flags & 0x02) !== 0,
+          stp: ["OFF", "CANCEL_RESTING", "CANCEL_AGGRESSING"][stp],
+          expire_ms: expire_ms.toString(),
+          display_qty: display_qty.toString(),
+          stop_price: stop_price.toString(),
+        });
+        socket.write(newOrder(order));
+      }
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "cancel") {
+      const kv = parseKV(rest);
+      const client_id = BigInt(kv.client ?? kv.client_id ?? (()=>{throw new Error("missing client id");})());
+      const cl_ord_id = BigInt(kv.id ?? kv.cl_ord_id ?? (()=>{throw new Error("missing order id");})());
+      const symbol = Number(kv.sym ?? kv.symbol ?? 0);
+      console.log("📤 \x1b[34mCANCEL\x1b[0m", {
         client_id: client_id.toString(),
         cl_ord_id: cl_ord_id.toString(),
-        side: side === 0 ? "BID" : "ASK",
-        price: price.toString(),
-        qty: qty.toString(),
-        tif: tif === 0 ? "GTC" : "IOC",
+        symbol,
       });
-      socket.write(newOrder({ client_id, cl_ord_id, side, price, qty, tif }));
+      socket.write(cancel({ client_id, cl_ord_id, symbol }));
       if (!TEST_MODE) rl.prompt();
       return;
     }
 
-    if (cmd === "cancel") {
+    if (cmd === "orders" || cmd === "open") {
+      const kv = parseKV(rest);
+      const client_id = BigInt(kv.client ?? kv.client_id ?? (()=>{throw new Error("missing client id");})());
+      console.log("📤 \x1b[34mQUERY_ORDERS\x1b[0m", { client_id: client_id.toString() });
+      socket.write(queryOrders({ client_id }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "resync") {
+      const kv = parseKV(rest);
+      const client_id = BigInt(kv.client ?? kv.client_id ?? (()=>{throw new Error("missing client id");})());
+      const since = BigInt(kv.since ?? 0);
+      console.log("📤 \x1b[34mRESYNC\x1b[0m", { client_id: client_id.toString(), since: since.toString() });
+      socket.write(resync({ client_id, since }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "stats") {
+      const kv = parseKV(rest);
+      const symbol = Number(kv.sym ?? kv.symbol ?? 0);
+      console.log("📤 \x1b[34mQUERY_STATS\x1b[0m", { symbol });
+      socket.write(queryStats({ symbol }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd in MARKET_STATE_MSG) {
+      const kv = parseKV(rest);
+      const symbol = Number(kv.sym ?? kv.symbol ?? (()=>{throw new Error("missing sym");})());
+      console.log(`📤 \x1b[34m${cmd.toUpperCase()}\x1b[0m`, { symbol });
+      socket.write(marketState({ symbol, cmd }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "roll") {
+      const kv = parseKV(rest);
+      const sym = kv.sym ?? kv.symbol;
+      const symbol = sym === undefined ? undefined : Number(sym);
+      console.log("📤 \x1b[34mSESSION_ROLL\x1b[0m", { symbol: symbol ?? "all" });
+      socket.write(sessionRoll({ symbol }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "cancelall" || cmd === "flatten") {
+      const kv = parseKV(rest);
+      const client_id = BigInt(kv.client ?? kv.client_id ?? (()=>{throw new Error("missing client id");})());
+      const sym = kv.sym ?? kv.symbol;
+      const symbol = sym === undefined ? undefined : Number(sym);
+      console.log("📤 \x1b[34mCANCEL_ALL\x1b[0m", { client_id: client_id.toString(), symbol: symbol ?? "all" });
+      socket.write(cancelAll({ client_id, symbol }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "replace" || cmd === "amend") {
       const kv = parseKV(rest);
       const client_id = BigInt(kv.client ?? kv.client_id ?? (()=>{throw new Error("missing client id");})());
       const cl_ord_id = BigInt(kv.id ?? kv.cl_ord_id ?? (()=>{throw new Error("missing order id");})());
-      console.log("📤 \x1b[34mCANCEL\x1b[0m", {
+      const symbol = Number(kv.sym ?? kv.symbol ?? 0);
+      const price = BigInt(kv.price ?? kv.px ?? (()=>{throw new Error("missing price");})());
+      const qty = BigInt(kv.qty ?? kv.q ?? (()=>{throw new Error("missing qty");})());
+      console.log("📤 \x1b[34mREPLACE\x1b[0m", {
         client_id: client_id.toString(),
         cl_ord_id: cl_ord_id.toString(),
+        symbol,
+        price: price.toString(),
+        qty: qty.toString(),
       });
-      socket.write(cancel({ client_id, cl_ord_id }));
+      socket.write(replace({ client_id, cl_ord_id, symbol, price, qty }));
+      if (!TEST_MODE) rl.prompt();
+      return;
+    }
+
+    if (cmd === "reduce") {
+      const kv = parseKV(rest);
+      const client_id = BigInt(kv.client ?? kv.client_id ?? (()=>{throw new Error("missing client id");})());
+      const cl_ord_id = BigInt(kv.id ?? kv.cl_ord_id ?? (()=>{throw new Error("missing order id");})());
+      const symbol = Number(kv.sym ?? kv.symbol ?? 0);
+      const qty = BigInt(kv.qty ?? kv.q ?? (()=>{throw new Error("missing qty");})());
+      console.log("📤 \x1b[34mREDUCE\x1b[0m", { client_id: client_id.toString(), cl_ord_id: cl_ord_id.toString(), symbol, qty: qty.toString() });
+      socket.write(reduce({ client_id, cl_ord_id, symbol, qty }));
       if (!TEST_MODE) rl.prompt();
       return;
     }
@@ -236,36 +516,209 @@ socket.on("data", (chunk) => {
 
     console.log("\n🧾 \x1b[35mReceived Frame\x1b[0m — Type:", type);
 
-    if (type === 100) { // ACK (and PONG as ACK+"pong")
-      const cl = body.readBigUInt64LE(2);
-      const l  = body.readUInt16LE(10);
-      const txt = body.subarray(12, 12 + l).toString();
-      console.log("✅ \x1b[32mACK\x1b[0m", { cl: cl.toString(), text: txt });
+    if (type === 2) { // PONG
+      console.log("🏓 \x1b[32mPONG\x1b[0m");
+    } else if (type === 100) { // ACK
+      const cl = body.readBigUInt64LE(4);
+      const code = body.readUInt16LE(12);
+      const l  = body.readUInt16LE(14);
+      const txt = body.subarray(16, 16 + l).toString();
+      console.log("✅ \x1b[32mACK\x1b[0m", { cl: cl.toString(), code, text: txt });
     } else if (type === 199) { // REJECT
-      const cl = body.readBigUInt64LE(2);
-      const l  = body.readUInt16LE(10);
-      const reason = body.subarray(12, 12 + l).toString();
-      console.log("❌ \x1b[31mREJECT\x1b[0m", { cl: cl.toString(), reason });
+      const cl = body.readBigUInt64LE(4);
+      const code = body.readUInt16LE(12);
+      const l  = body.readUInt16LE(14);
+      const reason = body.subarray(16, 16 + l).toString();
+      console.log("❌ \x1b[31mREJECT\x1b[0m", { cl: cl.toString(), code, reason });
     } else if (type === 101) { // TRADE
-      const price = body.readBigInt64LE(2);
-      const qty   = body.readBigInt64LE(10);
-      const tak   = body.readBigUInt64LE(18);
-      const mak   = body.readBigUInt64LE(26);
+      const price = body.readBigInt64LE(4);
+      const qty   = body.readBigInt64LE(12);
+      const tak   = body.readBigUInt64LE(20);
+      const mak   = body.readBigUInt64LE(28);
+      const takOrd = body.readBigUInt64LE(36);
+      const makOrd = body.readBigUInt64LE(44);
+      const aggr  = body.readUInt8(52) === 0 ? "BID" : "ASK";
+      const sym   = body.readUInt32LE(53);
+      const makFee = body.readBigUInt64LE(57);
+      const takFee = body.readBigUInt64LE(65);
+      const seq   = body.readBigUInt64LE(73);
+      const improved = body.readBigUInt64LE(81);
       console.log("💥 \x1b[33mTRADE\x1b[0m", {
+        seq: seq.toString(),
+        sym,
         price: price.toString(),
         qty: qty.toString(),
         tak: tak.toString(),
         mak: mak.toString(),
+        takOrd: takOrd.toString(),
+        makOrd: makOrd.toString(),
+        aggr,
+        makFee: makFee.toString(),
+        takFee: takFee.toString(),
+        improved: improved.toString(),
       });
     } else if (type === 102) { // BOOK_DELTA
-      const side  = body.readUInt8(2) === 0 ? "BID" : "ASK";
-      const price = body.readBigInt64LE(3);
-      const lvl   = body.readBigInt64LE(11);
+      const side  = body.readUInt8(4) === 0 ? "BID" : "ASK";
+      const price = body.readBigInt64LE(5);
+      const lvl   = body.readBigInt64LE(13);
+      const sym   = body.readUInt32LE(21);
+      const seq   = body.readBigUInt64LE(25);
       console.log("📊 \x1b[36mBOOK_DELTA\x1b[0m", {
+        seq: seq.toString(),
+        sym,
         side,
         price: price.toString(),
         level: lvl.toString(),
       });
+    } else if (type === 103) { // STP_CANCELED
+      const id  = body.readBigUInt64LE(4);
+      const qty = body.readBigInt64LE(12);
+      console.log("🚫 \x1b[33mSTP_CANCELED\x1b[0m", { id: id.toString(), qty: qty.toString() });
+    } else if (type === 105) { // ACCEPTED
+      const id     = body.readBigUInt64LE(4);
+      const side   = body.readUInt8(12) === 0 ? "BID" : "ASK";
+      const price  = body.readBigInt64LE(13);
+      const filled = body.readBigInt64LE(21);
+      const rest   = body.readBigInt64LE(29);
+      const exch   = body.readBigUInt64LE(37);
+      console.log("✅ \x1b[32mACCEPTED\x1b[0m", {
+        id: id.toString(), exch: exch.toString(), side, price: price.toString(), filled: filled.toString(), resting: rest.toString(),
+      });
+    } else if (type === 106) { // REPLACED
+      const id    = body.readBigUInt64LE(4);
+      const price = body.readBigInt64LE(12);
+      const qty   = body.readBigInt64LE(20);
+      const kept  = body.readUInt8(28) === 1;
+      console.log("✏️ \x1b[32mREPLACED\x1b[0m", {
+        id: id.toString(), price: price.toString(), qty: qty.toString(), priority_kept: kept,
+      });
+    } else if (type === 107) { // DONE
+      const id     = body.readBigUInt64LE(4);
+      const reason = ["?", "filled", "canceled", "expired", "ioc_remainder", "reduce_only", "session_end"][body.readUInt8(12)] ?? "?";
+      console.log("🏁 \x1b[32mDONE\x1b[0m", { id: id.toString(), reason });
+    } else if (type === 121) { // BATCH_ACK
+      const n = body.readUInt16LE(4);
+      const results = Array.from({ length: n }, (_, i) => {
+        const code = body.readUInt16LE(14 + i * 10);
+        return `${body.readBigUInt64LE(6 + i * 10)}:${code === 0 ? "ok" : code}`;
+      });
+      console.log("📦 \x1b[32mBATCH_ACK\x1b[0m", { results });
+    } else if (type === 108) { // CANCEL_ALL_DONE
+      const count = body.readBigUInt64LE(4);
+      console.log("🧹 \x1b[32mCANCEL_ALL_DONE\x1b[0m", { count: count.toString() });
+    } else if (type === 109) { // OPEN_ORDERS
+      const client = body.readBigUInt64LE(4);
+      const n = body.readUInt16LE(12);
+      const orders = [];
+      for (let i = 0, o = 14; i < n; i++, o += 29) {
+        orders.push({
+          sym: body.readUInt32LE(o),
+          id: body.readBigUInt64LE(o + 4).toString(),
+          side: body.readUInt8(o + 12) === 0 ? "BID" : "ASK",
+          price: body.readBigInt64LE(o + 13).toString(),
+          qty: body.readBigInt64LE(o + 21).toString(),
+        });
+      }
+      console.log("📋 \x1b[36mOPEN_ORDERS\x1b[0m", { client: client.toString(), orders });
+    } else if (type === 110) { // BBO
+      const sym    = body.readUInt32LE(4);
+      const bidPx  = body.readBigInt64LE(8);
+      const bidQty = body.readBigInt64LE(16);
+      const askPx  = body.readBigInt64LE(24);
+      const askQty = body.readBigInt64LE(32);
+      const seq    = body.readBigUInt64LE(40);
+      console.log("🎯 \x1b[36mBBO\x1b[0m", {
+        seq: seq.toString(), sym, bid: `${bidPx}x${bidQty}`, ask: `${askPx}x${askQty}`,
+      });
+    } else if (type === 111) { // STATS
+      const sym = body.readUInt32LE(4);
+      console.log("📈 \x1b[36mSTATS\x1b[0m", {
+        sym,
+        last: body.readBigInt64LE(8).toString(),
+        high: body.readBigInt64LE(16).toString(),
+        low: body.readBigInt64LE(24).toString(),
+        volume: body.readBigInt64LE(32).toString(),
+        trades: body.readBigUInt64LE(40).toString(),
+      });
+    } else if (type === 112) { // CANDLE
+      console.log("🕯️ \x1b[36mCANDLE\x1b[0m", {
+        sym: body.readUInt32LE(4),
+        open: body.readBigInt64LE(8).toString(),
+        high: body.readBigInt64LE(16).toString(),
+        low: body.readBigInt64LE(24).toString(),
+        close: body.readBigInt64LE(32).toString(),
+        volume: body.readBigInt64LE(40).toString(),
+        start: new Date(Number(body.readBigUInt64LE(48))).toISOString(),
+      });
+    } else if (type === 113) { // DROPCOPY
+      const n = body.readUInt16LE(14);
+      console.log("🗂️ \x1b[90mDROPCOPY\x1b[0m", {
+        client: body.readBigUInt64LE(4).toString(),
+        type: body.readUInt16LE(12),
+        body: body.subarray(16, 16 + n).toString("hex"),
+      });
+    } else if (type === 122) { // RESYNC_EVENT
+      const n = body.readUInt16LE(22);
+      console.log("🔁 \x1b[36mRESYNC_EVENT\x1b[0m", {
+        client: body.readBigUInt64LE(4).toString(),
+        seq: body.readBigUInt64LE(12).toString(),
+        type: body.readUInt16LE(20),
+        body: body.subarray(24, 24 + n).toString("hex"),
+      });
+    } else if (type === 123) { // RESYNC_DONE
+      console.log("🔁 \x1b[32mRESYNC_DONE\x1b[0m", {
+        client: body.readBigUInt64LE(4).toString(),
+        last_seq: body.readBigUInt64LE(12).toString(),
+        complete: body.readUInt8(20) === 1,
+      });
+    } else if (type === 124) { // ADMIN_CHALLENGE
+      const nonce = body.subarray(4, 20);
+      console.log("🔑 \x1b[33mADMIN_CHALLENGE\x1b[0m", { nonce: nonce.toString("hex") });
+      if (adminSecret !== null) {
+        socket.write(adminAuth(crypto.createHmac("sha256", adminSecret).update(nonce).digest()));
+        adminSecret = null;
+        console.log("📤 \x1b[34mADMIN_AUTH\x1b[0m (answer)");
+      }
+    } else if (type === 115) { // HELLO_ACK
+      console.log("👋 \x1b[32mHELLO_ACK\x1b[0m", { version: body.readUInt16LE(4) });
+    } else if (type === 116) { // ORDER_ADD
+      console.log("➕ \x1b[36mORDER_ADD\x1b[0m", {
+        seq: body.readBigUInt64LE(33).toString(),
+        sym: body.readUInt32LE(29),
+        id: body.readBigUInt64LE(4).toString(),
+        side: body.readUInt8(12) === 0 ? "BID" : "ASK",
+        price: body.readBigInt64LE(13).toString(),
+        qty: body.readBigInt64LE(21).toString(),
+      });
+    } else if (type === 117 || type === 118) { // ORDER_EXECUTE / ORDER_REDUCE
+      console.log(type === 117 ? "💥 \x1b[36mORDER_EXECUTE\x1b[0m" : "✂️ \x1b[36mORDER_REDUCE\x1b[0m", {
+        seq: body.readBigUInt64LE(24).toString(),
+        sym: body.readUInt32LE(20),
+        id: body.readBigUInt64LE(4).toString(),
+        qty: body.readBigInt64LE(12).toString(),
+      });
+    } else if (type === 119) { // ORDER_REMOVE
+      console.log("➖ \x1b[36mORDER_REMOVE\x1b[0m", {
+        seq: body.readBigUInt64LE(16).toString(),
+        sym: body.readUInt32LE(12),
+        id: body.readBigUInt64LE(4).toString(),
+      });
+    } else if (type === 120) { // CANCELED
+      const reason = ["?", "filled", "canceled", "expired", "ioc_remainder", "reduce_only", "session_end"][body.readUInt8(20)] ?? "?";
+      console.log("🚫 \x1b[33mCANCELED\x1b[0m", {
+        id: body.readBigUInt64LE(4).toString(), unfilled: body.readBigInt64LE(12).toString(), reason,
+      });
+    } else if (type === 114) { // EXEC_REPORT
+      console.log("📄 \x1b[32mEXEC_REPORT\x1b[0m", body.subarray(4, 4 + body.readUInt16LE(2)).toString());
+    } else if (type === 104) { // SNAPSHOT
+      const sym = body.readUInt32LE(4);
+      const nb  = body.readUInt16LE(8);
+      const na  = body.readUInt16LE(10);
+      const lvl = i => [body.readBigInt64LE(12 + i * 16).toString(), body.readBigInt64LE(20 + i * 16).toString()];
+      const bids = Array.from({ length: nb }, (_, i) => lvl(i));
+      const asks = Array.from({ length: na }, (_, i) => lvl(nb + i));
+      const seq  = body.readBigUInt64LE(12 + (nb + na) * 16);
+      console.log("📸 \x1b[36mSNAPSHOT\x1b[0m", { seq: seq.toString(), sym, bids, asks });
     } else {
       console.log("❓ \x1b[90mUNKNOWN EVT\x1b[0m", { type });
     }
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::time::{Duration, Instant};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use crossbeam::channel::unbounded;
+use tokio::sync::broadcast;
+use tracing::subscriber::NoSubscriber;
+use clob_engine::book::LadderSpec;
+use clob_engine::engine::{handle_cancel, handle_new, run_engine, MdFeed, DEFAULT_DRAIN_BATCH};
+use clob_engine::sink::ClientSink;
+use clob_engine::types::{Command, Event, Order, OrderBook, OrderType, Side, Stp, Tif};
+use clob_engine::wal::Wal;
+
+// Engine handlers driven directly on an `OrderBook`, no TCP or engine thread.
+// Receivers are dropped, so event sends fail fast instead of queueing.
+
+fn order(id: u64, cl_id: u64, side: Side, price: i64, qty: u64) -> Order {
+    Order {
+        id,
+        cl_id,
+        symbol: 0,
+        side,
+        price,
+        qty,
+        timestamp: 0,
+        arrival_seq: 0,
+        queue_seq: 0,
+        tif: Tif::Gtc,
+        ord_type: OrderType::Limit,
+        post_only: false,
+        reduce_only: false,
+        stp: Stp::Off,
+        expire_ms: 0,
+        display_qty: 0,
+        total_qty: qty,
+        stop_price: 0,
+        exch_ord_id: id, // Handlers are called directly, so nothing else assigns one
+    }
+}
+
+/// 100 ask levels (101..=200) five orders deep, plus the same on the bid side (1..=100).
+fn warm_book(mut b: OrderBook, sink: &ClientSink, md: &MdFeed) -> OrderBook {
+    let mut id = 0;
+    for px in 1..=100 {
+        for _ in 0..5 {
+            id += 1;
+            handle_new(order(id, 1, Side::Bid, px, 10), &mut b, sink, &HashMap::new(), md);
+            id += 1;
+            handle_new(order(id, 1, Side::Ask, px + 100, 10), &mut b, sink, &HashMap::new(), md);
+        }
+    }
+    b
+}
+
+/// Rest an ask at the touch and immediately take it: one trade per iteration, book unchanged.
+fn crossing(c: &mut Criterion) {
+    let sink = ClientSink::detached();
+    let md = MdFeed::new(broadcast::channel::<Event>(1).0);
+    let mut b = warm_book(OrderBook::default(), &sink, &md);
+    let mut id = 1_000_000;
+
+    let mut g = c.benchmark_group("crossing");
+    g.throughput(Throughput::Elements(2));
+    g.bench_function("rest_then_take", |bench| {
+        bench.iter(|| {
+            id += 2;
+            handle_new(order(id, 2, Side::Ask, 101, 10), &mut b, &sink, &HashMap::new(), &md);
+            handle_new(black_box(order(id + 1, 3, Side::Bid, 101, 10)), &mut b, &sink, &HashMap::new(), &md);
+        })
+    });
+    g.finish();
+}
+
+/// Same loop with a real log subscriber (writing to `io::sink`) so `info!` does its full
+/// formatting work. Compare `cargo bench` with `cargo bench --features matching-trace`.
+fn trace_overhead(c: &mut Criterion) {
+    let _ = tracing_subscriber::fmt().with_writer(io::sink).try_init();
+    let sink = ClientSink::detached();
+    let md = MdFeed::new(broadcast::channel::<Event>(1).0);
+    let mut b = warm_book(OrderBook::default(), &sink, &md);
+    let mut id = 1_000_000;
+
+    let name = if cfg!(feature = "matching-trace") { "trace_on" } else { "trace_off" };
+    let mut g = c.benchmark_group("matching_trace");
+    g.throughput(Throughput::Elements(2));
+    g.bench_function(name, |bench| {
+        bench.iter(|| {
+            id += 2;
+            handle_new(order(id, 2, Side::Ask, 101, 10), &mut b, &sink, &HashMap::new(), &md);
+            handle_new(black_box(order(id + 1, 3, Side::Bid, 101, 10)), &mut b, &sink, &HashMap::new(), &md);
+        })
+    });
+    g.finish();
+}
+
+enum Op {
+    New(Order),
+    Cancel(u64, u64),
+}
+
+/// Deterministic flow around a drifting mid: 60% limits within 10 ticks of it (a quarter of
+/// them marketable), 5% market orders, 35% cancels of recent passive orders.
+fn order_flow(n: usize) -> Vec<Op> {
+    let mut seed = 42u64;
+    let mut rnd = move |m: u64| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) % m
+    };
+    let mut mid = 100u64;
+    let mut ops = Vec::with_capacity(n);
+    let mut passive: Vec<(u64, u64)> = Vec::new();
+    for i in 0..n as u64 {
+        let id = 2_000_000 + i;
+        let cl_id = 10 + i % 4;
+        mid = (mid + rnd(3)).saturating_sub(1).clamp(60, 140);
+        let side = if rnd(2) == 0 { Side::Bid } else { Side::Ask };
+        match rnd(100) {
+            0..35 if !passive.is_empty() => {
+                let k = passive.len() - 1 - rnd(passive.len().min(200) as u64) as usize;
+                let (cl_id, id) = passive.swap_remove(k);
+                ops.push(Op::Cancel(cl_id, id));
+            }
+            35..40 => {
+                let mut o = order(id, cl_id, side, 0, 1 + rnd(20));
+                o.ord_type = OrderType::Market;
+                o.tif = Tif::Ioc;
+                ops.push(Op::New(o));
+            }
+            _ => {
+                let (away, through) = (1 + rnd(10), rnd(4) == 0);
+                let px = match (side, through) {
+                    (Side::Bid, false) | (Side::Ask, true) => mid - away,
+                    (Side::Ask, false) | (Side::Bid, true) => mid + away,
+                };
+                if !through {
+                    passive.push((cl_id, id));
+                }
+                ops.push(Op::New(order(id, cl_id, side, px as i64, 1 + rnd(20))));
+            }
+        }
+    }
+    ops
+}
+
+fn run(ops: &[Op], b: &mut OrderBook, sink: &ClientSink, md: &MdFeed) {
+    for op in ops {
+        match op {
+            Op::New(o) => handle_new(o.clone(), b, sink, &HashMap::new(), md),
+            Op::Cancel(cl_id, id) => { let _ = handle_cancel(0, *cl_id, *id, b, md); }
+        }
+    }
+}
+
+/// Passive limits spread over both sides, never reaching the touch.
+fn resting_flow(n: u64) -> Vec<Op> {
+    (0..n)
+        .map(|i| {
+            let px = 1 + (i % 100) as i64;
+            let (side, px) = if i % 2 == 0 { (Side::Bid, px) } else { (Side::Ask, px + 100) };
+            Op::New(order(3_000_000 + i, 4, side, px, 10))
+        })
+        .collect()
+}
+
+/// IOC sweeps alternating sides, each taking 6 makers across two levels.
+fn sweep_flow(n: u64) -> Vec<Op> {
+    (0..n)
+        .map(|i| {
+            let (side, px) = if i % 2 == 0 { (Side::Bid, 200) } else { (Side::Ask, 1) };
+            let mut o = order(3_000_000 + i, 5, side, px, 60);
+            o.tif = Tif::Ioc;
+            Op::New(o)
+        })
+        .collect()
+}
+
+/// Pairs of one passive limit and one IOC taking a single maker at the touch.
+fn mixed_flow(n: u64) -> Vec<Op> {
+    (0..n)
+        .map(|i| {
+            let id = 3_000_000 + i;
+            let px = 1 + ((i / 2) % 100) as i64;
+            match (i % 2, i % 4) {
+                (0, 0) => Op::New(order(id, 4, Side::Bid, px, 10)),
+                (0, _) => Op::New(order(id, 4, Side::Ask, px + 100, 10)),
+                (_, 1) => Op::New(Order { tif: Tif::Ioc, ..order(id, 5, Side::Ask, 1, 10) }),
+                _ => Op::New(Order { tif: Tif::Ioc, ..order(id, 5, Side::Bid, 200, 10) }),
+            }
+        })
+        .collect()
+}
+
+/// Orders/sec per workload, each batch on a freshly warmed book.
+/// Baseline (bench profile, BTreeMap backend, dev box), in orders/sec:
+///   workload/resting   1000 orders            ~3.6 M/s
+///   workload/sweep      160 orders, 6 fills   ~0.85 M/s
+///   workload/mixed     1000 orders            ~3.2 M/s
+fn workloads(c: &mut Criterion) {
+    let sink = ClientSink::detached();
+    let md = MdFeed::new(broadcast::channel::<Event>(1).0);
+
+    let mut g = c.benchmark_group("workload");
+    // Sweeps stay under the warm book's 5000 units a side, so every order trades
+    for (name, ops) in [("resting", resting_flow(1_000)), ("sweep", sweep_flow(160)), ("mixed", mixed_flow(1_000))] {
+        g.throughput(Throughput::Elements(ops.len() as u64));
+        g.bench_function(name, |bench| {
+            bench.iter_batched(
+                || warm_book(OrderBook::default(), &sink, &md),
+                |mut b| {
+                    run(&ops, &mut b, &sink, &md);
+                    b
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    g.finish();
+}
+
+/// The same flow replayed on a warm book per price-level backend.
+fn backends(c: &mut Criterion) {
+    let sink = ClientSink::detached();
+    let md = MdFeed::new(broadcast::channel::<Event>(1).0);
+    let ops = order_flow(10_000);
+
+    let mut g = c.benchmark_group("backend");
+    g.throughput(Throughput::Elements(ops.len() as u64));
+    for name in ["btree", "ladder"] {
+        let fresh = || {
+            let mut b = OrderBook::default();
+            if name == "ladder" {
+                b.use_ladder(LadderSpec { min: 1, max: 1_000, tick: 1 }).expect("empty book");
+            }
+            warm_book(b, &sink, &md)
+        };
+        g.bench_function(name, |bench| {
+            bench.iter_batched(
+                fresh,
+                |mut b| {
+                    run(&ops, &mut b, &sink, &md);
+                    b
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    g.finish();
+}
+
+/// The mixed flow through the engine loop itself, all of it queued before the loop starts:
+/// one command per wake-up against the default batch. Each wake-up pays a WAL flush and a `select!`.
+/// Both finish with the shutdown snapshot of a warm book.
+/// Baseline (bench profile, dev box), 10000 orders:
+///   drain/single    ~0.55 M/s
+///   drain/batched   ~0.78 M/s
+fn draining(c: &mut Criterion) {
+    let sink = ClientSink::detached();
+    let scratch = std::env::temp_dir().join(format!("clob-bench-{}", std::process::id()));
+    fs::create_dir_all(&scratch).expect("scratch dir");
+    let ops = mixed_flow(10_000);
+
+    let mut g = c.benchmark_group("drain");
+    g.throughput(Throughput::Elements(ops.len() as u64));
+    for (name, batch) in [("single", 1), ("batched", DEFAULT_DRAIN_BATCH)] {
+        g.bench_function(name, |bench| {
+            bench.iter_batched(
+                || {
+                    let md = MdFeed::new(broadcast::channel::<Event>(1).0);
+                    let books = HashMap::from([(0, warm_book(OrderBook::default(), &sink, &md))]);
+                    let (tx, rx) = unbounded();
+                    for op in &ops {
+                        if let Op::New(o) = op {
+                            tx.send(Command::Order(o.clone(), sink.clone(), Instant::now())).expect("receiver alive");
+                        }
+                    }
+                    tx.send(Command::Shutdown).expect("receiver alive");
+                    let wal = Wal::open(scratch.join("engine.wal"), 0).expect("scratch WAL");
+                    (rx, md, books, wal)
+                },
+                // Exchange ids start past the warm book's, which `order` set by hand. Without the log
+                // subscriber `trace_overhead` installs, whose formatting would swamp the difference
+                |(rx, md, books, wal)| {
+                    tracing::subscriber::with_default(NoSubscriber::default(), || {
+                        run_engine(rx, md, books, 1_000_000, wal, scratch.join("books.snap"), Duration::MAX, None, None, batch, 0)
+                    })
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    g.finish();
+    let _ = fs::remove_dir_all(&scratch);
+}
+
+criterion_group!(benches, crossing, workloads, backends, trace_overhead, draining);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use clob_engine::protocol::{decode_frame, encode_frame, parse_new_order, ByteOrder, FrameFormat, MSG_NEW_ORDER};
+use clob_engine::types::Order;
+
+// Inbound NEW_ORDERs from the read buffer to an `Order`: frame split plus field parse, no TCP.
+// Every heap allocation in the process is counted, so the bench also checks that parsing makes none.
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const FRAMES: u64 = 1024;
+
+// A read buffer holding `FRAMES` back-to-back GTC limit NEW_ORDERs, as a connection would fill it
+fn read_buffer(fmt: FrameFormat) -> BytesMut {
+    let mut buf = BytesMut::new();
+    for id in 0..FRAMES {
+        let mut body = Vec::with_capacity(38);
+        for v in [1u64, id] {
+            body.extend_from_slice(&match fmt.order { ByteOrder::Little => v.to_le_bytes(), ByteOrder::Big => v.to_be_bytes() });
+        }
+        body.extend_from_slice(&[0; 4]); // symbol 0
+        body.push((id % 2) as u8);
+        for v in [100 + (id % 10) as i64, 10] {
+            body.extend_from_slice(&match fmt.order { ByteOrder::Little => v.to_le_bytes(), ByteOrder::Big => v.to_be_bytes() });
+        }
+        body.push(0); // GTC
+        buf.extend_from_slice(&encode_frame(MSG_NEW_ORDER, &body, fmt));
+    }
+    buf
+}
+
+fn next_order(buf: &mut BytesMut, fmt: FrameFormat) -> Option<Order> {
+    let frame = decode_frame(buf, fmt).expect("well-formed frame")?;
+    Some(parse_new_order(&frame.body, fmt.order).expect("valid order"))
+}
+
+fn drain(buf: &mut BytesMut, fmt: FrameFormat) -> u64 {
+    let mut n = 0;
+    while let Some(order) = next_order(buf, fmt) {
+        black_box(order);
+        n += 1;
+    }
+    n
+}
+
+// The first frame split moves the read buffer into shared storage, its one allocation;
+// every frame after that is sliced out of it and parsed in place
+fn assert_no_allocations(fmt: FrameFormat) {
+    let mut buf = read_buffer(fmt);
+    next_order(&mut buf, fmt).expect("first frame");
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let parsed = drain(&mut buf, fmt);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert_eq!(parsed, FRAMES - 1);
+    assert_eq!(allocations, 0, "parsing {parsed} NEW_ORDERs ({fmt:?}) allocated {allocations} times");
+}
+
+/// Baseline (bench profile, dev box), 1024 NEW_ORDERs:
+///   parse/new_order              ~7.7 M/s
+///   parse/new_order_crc          ~4.1 M/s
+///   parse/new_order_big_endian   ~7.9 M/s
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(FRAMES));
+    let formats = [
+        ("new_order", FrameFormat::default()),
+        ("new_order_crc", FrameFormat { crc: true, ..FrameFormat::default() }),
+        ("new_order_big_endian", FrameFormat { order: ByteOrder::Big, ..FrameFormat::default() }),
+    ];
+    for (name, fmt) in formats {
+        assert_no_allocations(fmt);
+        group.bench_function(name, |b| {
+            b.iter_batched(|| read_buffer(fmt), |mut buf| drain(&mut buf, fmt), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
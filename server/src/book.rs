@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use crate::types::Level;
+
+// ========================== Price-level backends ==========================
+// One side of a book: price → `Level`. Prices iterate ascending; bids read from the
+// high end and asks from the low end. Every book starts on a `BTreeMap`; symbols
+// configured at startup move onto a `Ladder`.
+
+pub trait BookBackend: Send {
+    fn get(&self, px: i64) -> Option<&Level>;
+    fn get_mut(&mut self, px: i64) -> Option<&mut Level>;
+    /// The level at `px`, inserted empty if missing. The caller must push to it (or remove it).
+    fn level_mut(&mut self, px: i64) -> &mut Level;
+    fn remove(&mut self, px: i64) -> Option<Level>;
+    /// Number of price levels.
+    fn len(&self) -> usize;
+    fn lowest(&self) -> Option<(i64, &Level)>;
+    fn highest(&self) -> Option<(i64, &Level)>;
+    /// Every level in ascending price order.
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (i64, &Level)> + '_>;
+    /// Whether an order may rest at `px`.
+    fn accepts(&self, _px: i64) -> bool {
+        true
+    }
+    /// Price range of a bounded backend.
+    fn bounds(&self) -> Option<LadderSpec> {
+        None
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Box<dyn BookBackend> {
+    fn default() -> Self {
+        Box::new(BTreeMap::new())
+    }
+}
+
+// Unbounded, sparse: any price, O(log n) per level access
+impl BookBackend for BTreeMap<i64, Level> {
+    fn get(&self, px: i64) -> Option<&Level> {
+        BTreeMap::get(self, &px)
+    }
+
+    fn get_mut(&mut self, px: i64) -> Option<&mut Level> {
+        BTreeMap::get_mut(self, &px)
+    }
+
+    fn level_mut(&mut self, px: i64) -> &mut Level {
+        self.entry(px).or_default()
+    }
+
+    fn remove(&mut self, px: i64) -> Option<Level> {
+        BTreeMap::remove(self, &px)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn lowest(&self) -> Option<(i64, &Level)> {
+        self.iter().next().map(|(px, l)| (*px, l))
+    }
+
+    fn highest(&self) -> Option<(i64, &Level)> {
+        self.iter().next_back().map(|(px, l)| (*px, l))
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (i64, &Level)> + '_> {
+        Box::new(BTreeMap::iter(self).map(|(px, l)| (*px, l)))
+    }
+}
+
+/// Bounds of a price ladder: prices `min, min + tick, ..., <= max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LadderSpec {
+    pub min: i64,
+    pub max: i64,
+    pub tick: u64,
+}
+
+// Keeps a misconfigured range from allocating gigabytes per book side
+const MAX_LADDER_SLOTS: u64 = 1 << 20;
+
+impl FromStr for LadderSpec {
+    type Err = String;
+
+    /// "min:max:tick", e.g. "9000:11000:5".
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parts: Vec<i64> = s.split(':')
+            .map(|v| v.trim().parse::<i64>().map_err(|_| format!("bad ladder value {v:?} in {s:?}")))
+            .collect::<Result<_, _>>()?;
+        let &[min, max, tick] = parts.as_slice() else {
+            return Err(format!("ladder spec {s:?} is not min:max:tick"));
+        };
+        if tick <= 0 || max < min {
+            return Err(format!("ladder spec {s:?} needs min <= max and tick > 0"));
+        }
+        let tick = tick as u64;
+        if max.abs_diff(min) / tick >= MAX_LADDER_SLOTS {
+            return Err(format!("ladder spec {s:?} spans more than {MAX_LADDER_SLOTS} ticks"));
+        }
+        Ok(LadderSpec { min, max, tick })
+    }
+}
+
+// Dense, bounded: one slot per tick, with the lowest and highest occupied slots cached,
+// so top of book is O(1) and emptying the touch walks to the next occupied slot.
+pub struct Ladder {
+    spec: LadderSpec,
+    slots: Vec<Option<Level>>,
+    len: usize,
+    lo: usize, // Lowest occupied slot; meaningless while `len == 0`
+    hi: usize, // Highest occupied slot; same
+}
+
+impl Ladder {
+    pub fn new(spec: LadderSpec) -> Self {
+        let n = (spec.max.abs_diff(spec.min) / spec.tick + 1) as usize;
+        let mut slots = Vec::with_capacity(n);
+        slots.resize_with(n, || None);
+        Ladder { spec, slots, len: 0, lo: 0, hi: 0 }
+    }
+
+    fn slot(&self, px: i64) -> Option<usize> {
+        self.accepts(px).then(|| (px.abs_diff(self.spec.min) / self.spec.tick) as usize)
+    }
+
+    fn price(&self, i: usize) -> i64 {
+        self.spec.min + (i as u64 * self.spec.tick) as i64
+    }
+
+    fn occupied(&self, i: usize) -> (i64, &Level) {
+        (self.price(i), self.slots[i].as_ref().expect("cached slot must be occupied"))
+    }
+}
+
+impl BookBackend for Ladder {
+    fn get(&self, px: i64) -> Option<&Level> {
+        self.slots[self.slot(px)?].as_ref()
+    }
+
+    fn get_mut(&mut self, px: i64) -> Option<&mut Level> {
+        let i = self.slot(px)?;
+        self.slots[i].as_mut()
+    }
+
+    fn level_mut(&mut self, px: i64) -> &mut Level {
+        let i = self.slot(px).expect("price outside ladder: check `accepts` first");
+        if self.slots[i].is_none() {
+            if self.len == 0 {
+                (self.lo, self.hi) = (i, i);
+            } else {
+                self.lo = self.lo.min(i);
+                self.hi = self.hi.max(i);
+            }
+            self.len += 1;
+        }
+        self.slots[i].get_or_insert_with(Level::default)
+    }
+
+    fn remove(&mut self, px: i64) -> Option<Level> {
+        let i = self.slot(px)?;
+        let level = self.slots[i].take()?;
+        self.len -= 1;
+        // Some other slot is still occupied, so both walks terminate inside the ladder
+        if self.len > 0 {
+            if i == self.lo {
+                self.lo = (i + 1..).find(|&j| self.slots[j].is_some()).expect("occupied slot above");
+            }
+            if i == self.hi {
+                self.hi = (0..i).rev().find(|&j| self.slots[j].is_some()).expect("occupied slot below");
+            }
+        }
+        Some(level)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn lowest(&self) -> Option<(i64, &Level)> {
+        (self.len > 0).then(|| self.occupied(self.lo))
+    }
+
+    fn highest(&self) -> Option<(i64, &Level)> {
+        (self.len > 0).then(|| self.occupied(self.hi))
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (i64, &Level)> + '_> {
+        let range = if self.len == 0 { &self.slots[..0] } else { &self.slots[self.lo..=self.hi] };
+        let lo = self.lo;
+        Box::new(range.iter().enumerate().filter_map(move |(k, s)| s.as_ref().map(|l| (self.price(lo + k), l))))
+    }
+
+    fn bounds(&self) -> Option<LadderSpec> {
+        Some(self.spec)
+    }
+
+    fn accepts(&self, px: i64) -> bool {
+        px >= self.spec.min && px <= self.spec.max && px.abs_diff(self.spec.min).is_multiple_of(self.spec.tick)
+    }
+}
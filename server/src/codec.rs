@@ -0,0 +1,117 @@
+use std::io;
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+use crate::protocol::{decode_frame, encode_event, encode_frame, Frame, FrameFormat, ProtocolError};
+use crate::types::Event;
+
+/// Frame codec for `Framed<TcpStream, ClobCodec>`.
+///
+/// Protocol errors come out as `Err` items rather than as the decoder's error, since
+/// `Framed` ends the stream after an error and a malformed frame only costs a NACK.
+/// `FrameTooLarge` leaves the buffer untouched, so the caller must close after it.
+/// `format` applies to both directions and may be switched between frames.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClobCodec {
+    pub format: FrameFormat,
+}
+
+impl Decoder for ClobCodec {
+    type Item = Result<Frame, ProtocolError>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        Ok(decode_frame(src, self.format).transpose())
+    }
+
+    // A peer that hangs up mid-frame is just gone; its partial frame is dropped
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        let item = self.decode(src)?;
+        if item.is_none() {
+            src.clear();
+        }
+        Ok(item)
+    }
+}
+
+/// What a socket read error means for the connection reading it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadError {
+    Transient, // Interrupted or WouldBlock: nothing was consumed, read again
+    PeerGone,  // Reset, aborted, broken pipe or EOF mid-read: the peer hung up
+    Fatal,     // Anything else: close and report it
+}
+
+impl ReadError {
+    pub fn classify(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => ReadError::Transient,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => ReadError::PeerGone,
+            _ => ReadError::Fatal,
+        }
+    }
+}
+
+impl Encoder<Event> for ClobCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, evt: Event, dst: &mut BytesMut) -> io::Result<()> {
+        dst.extend_from_slice(&encode_event(&evt, self.format));
+        Ok(())
+    }
+}
+
+// Raw frames, for replies that aren't engine events (NACKs)
+impl Encoder<Frame> for ClobCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, f: Frame, dst: &mut BytesMut) -> io::Result<()> {
+        dst.extend_from_slice(&encode_frame(f.msg_type, &f.body, self.format));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::Framed;
+    use crate::protocol::{decode_event, ByteOrder, MSG_ACCEPTED, MSG_CANCEL, MSG_NEW_ORDER, MSG_PING, MSG_PONG};
+    use crate::types::Side;
+
+    #[tokio::test]
+    async fn frames_and_events_round_trip_through_framed() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = Framed::new(client, ClobCodec::default());
+        let mut server = Framed::new(server, ClobCodec::default());
+
+        // More than the pipe holds at once, so frames arrive split across reads
+        let sent = [(MSG_PING, vec![]), (MSG_NEW_ORDER, vec![7; 65]), (MSG_CANCEL, vec![9; 20])];
+        let writer = tokio::spawn(async move {
+            for (msg_type, body) in sent {
+                client.send(Frame { msg_type, body: Bytes::from(body) }).await.unwrap();
+            }
+            client
+        });
+        let mut got = Vec::new();
+        for _ in 0..3 {
+            let frame = server.next().await.unwrap().unwrap().unwrap();
+            got.push((frame.msg_type, frame.body.len()));
+        }
+        assert_eq!(got, [(MSG_PING, 0), (MSG_NEW_ORDER, 65), (MSG_CANCEL, 20)]);
+        let mut client = writer.await.unwrap();
+
+        let accepted = Event::Accepted { ord_id: 42, exch_ord_id: 7, filled_qty: 3, resting_qty: 5, side: Side::Ask, price: -15 };
+        server.send(Event::Pong).await.unwrap();
+        server.send(accepted.clone()).await.unwrap();
+        let pong = client.next().await.unwrap().unwrap().unwrap();
+        assert_eq!(pong.msg_type, MSG_PONG);
+        let frame = client.next().await.unwrap().unwrap().unwrap();
+        assert_eq!(frame.msg_type, MSG_ACCEPTED);
+        let decoded = decode_event(frame.msg_type, &frame.body, ByteOrder::Little).unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{accepted:?}"));
+    }
+}
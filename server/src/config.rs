@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+use serde::{de, Deserialize, Deserializer};
+use crate::book::LadderSpec;
+use crate::types::{FeeSchedule, Increments, MatchPolicy, PriceBand, QtyScale};
+
+// ========================== Per-symbol engine config ==========================
+// The SYMBOL_CONFIG file: {"symbols": [{"symbol": 1, "policy": "pro_rata", "tick_lot": "5:100"}, ...]}.
+// Every field but `symbol` is optional and written as in its *_SYMBOLS env var ("tick_lot" as
+// TICK_LOT_SYMBOLS, "qty_scale" as QTY_SCALE_SYMBOLS, ...), except `max_depth`, a plain number.
+// An unknown key, a bad value or a symbol listed twice is an error, so a typo stops startup.
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    symbols: Vec<SymbolConfig>,
+}
+
+/// Engine config of one symbol; a field left `None` keeps the book's default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolConfig {
+    pub symbol: u32,
+    #[serde(default, deserialize_with = "spec")]
+    pub policy: Option<MatchPolicy>,
+    #[serde(default, deserialize_with = "spec")]
+    pub ladder: Option<LadderSpec>,
+    #[serde(default, deserialize_with = "spec")]
+    pub tick_lot: Option<Increments>,
+    #[serde(default, deserialize_with = "spec")]
+    pub price_band: Option<PriceBand>,
+    #[serde(default, deserialize_with = "spec")]
+    pub fees: Option<FeeSchedule>,
+    #[serde(default)]
+    pub max_depth: Option<NonZeroUsize>,
+    #[serde(default, deserialize_with = "spec")]
+    pub qty_scale: Option<QtyScale>,
+}
+
+impl SymbolConfig {
+    pub fn new(symbol: u32) -> Self {
+        SymbolConfig { symbol, ..Default::default() }
+    }
+}
+
+/// A field given in its env-var notation, parsed by the type's `FromStr`.
+fn spec<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(d)?;
+    s.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Parse a SYMBOL_CONFIG document, keyed by symbol.
+pub fn parse(json: &str) -> Result<BTreeMap<u32, SymbolConfig>, String> {
+    let file: ConfigFile = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let mut symbols = BTreeMap::new();
+    for c in file.symbols {
+        let symbol = c.symbol;
+        if symbols.insert(symbol, c).is_some() {
+            return Err(format!("symbol {symbol} is listed twice"));
+        }
+    }
+    Ok(symbols)
+}
+
+/// Read and parse the SYMBOL_CONFIG file at `path`.
+pub fn load(path: impl AsRef<Path>) -> Result<BTreeMap<u32, SymbolConfig>, String> {
+    let path = path.as_ref();
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    parse(&json).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_field_parses_in_its_env_notation() {
+        let symbols = parse(r#"{"symbols": [
+            {"symbol": 1, "policy": "pro_rata", "ladder": "9000:11000:5", "tick_lot": "5:100", "price_band": "5%",
+             "fees": "1:5", "max_depth": 10, "qty_scale": "1e6"},
+            {"symbol": 7}
+        ]}"#).unwrap();
+        let c = &symbols[&1];
+        assert_eq!(c.policy, Some(MatchPolicy::ProRata));
+        assert_eq!(c.ladder, Some(LadderSpec { min: 9000, max: 11000, tick: 5 }));
+        assert_eq!(c.tick_lot, Some(Increments { tick_size: 5, lot_size: 100 }));
+        assert_eq!(c.price_band, Some(PriceBand::Bps(500)));
+        assert_eq!(c.fees, Some(FeeSchedule { maker_bps: 1, taker_bps: 5 }));
+        assert_eq!(c.max_depth, NonZeroUsize::new(10));
+        assert_eq!(c.qty_scale, Some(QtyScale(1_000_000)));
+        assert!(symbols[&7].policy.is_none() && symbols[&7].tick_lot.is_none());
+    }
+
+    #[test]
+    fn unknown_names_and_repeats_are_errors() {
+        let err = parse(r#"{"symbols": [{"symbol": 1, "policy": "random"}]}"#).unwrap_err();
+        assert!(err.contains("unknown match policy \"random\""), "{err}");
+        let err = parse(r#"{"symbols": [{"symbol": 1, "polcy": "fifo"}]}"#).unwrap_err();
+        assert!(err.contains("unknown field `polcy`"), "{err}");
+        let err = parse(r#"{"symbols": [{"symbol": 1, "max_depth": 0}]}"#).unwrap_err();
+        assert!(err.contains("nonzero"), "{err}");
+        let err = parse(r#"{"symbols": [{"symbol": 1}, {"symbol": 1, "policy": "fifo"}]}"#).unwrap_err();
+        assert_eq!(err, "symbol 1 is listed twice");
+    }
+}
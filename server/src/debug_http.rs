@@ -0,0 +1,90 @@
+use std::convert::Infallible;
+use std::time::Duration;
+use bytes::Bytes;
+use crossbeam::channel::{Sender, TrySendError};
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tracing::warn;
+use crate::protocol::MAX_SNAPSHOT_DEPTH;
+use crate::types::Command;
+
+// ========================== HTTP debug endpoint ==========================
+// `GET /book/<symbol>[?depth=N]` on its own port returns one book as JSON. The handler asks the
+// engine with a `DebugSnapshot` command and waits on a oneshot, so it never reads the books itself.
+
+const DEFAULT_DEPTH: u16 = 10;
+const ENGINE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn reply(status: StatusCode, content_type: &str, body: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("content-type", content_type)
+        .body(Full::new(Bytes::from(body)))
+        .expect("static response parts")
+}
+
+fn error(status: StatusCode, msg: &str) -> Response<Full<Bytes>> {
+    reply(status, "application/json", serde_json::json!({ "error": msg }).to_string())
+}
+
+/// `/book/<symbol>` with an optional `depth` query parameter (levels per side).
+fn parse_book_path(req: &Request<Incoming>) -> Option<(u32, u16)> {
+    let symbol = req.uri().path().strip_prefix("/book/")?.parse().ok()?;
+    let depth = match req.uri().query() {
+        None => DEFAULT_DEPTH,
+        Some(q) => q.split('&').find_map(|kv| kv.strip_prefix("depth="))?.parse::<u16>().ok()?,
+    };
+    Some((symbol, depth.min(MAX_SNAPSHOT_DEPTH)))
+}
+
+async fn handle(req: Request<Incoming>, queue: Sender<Command>) -> Response<Full<Bytes>> {
+    if req.method() != Method::GET || !req.uri().path().starts_with("/book/") {
+        return error(StatusCode::NOT_FOUND, "not found; try GET /book/<symbol>[?depth=N]");
+    }
+    let Some((symbol, depth)) = parse_book_path(&req) else {
+        return error(StatusCode::BAD_REQUEST, "expected /book/<u32 symbol>[?depth=<u16>]");
+    };
+    let (tx, rx) = oneshot::channel();
+    match queue.try_send(Command::DebugSnapshot { symbol, depth, reply: tx }) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => return error(StatusCode::SERVICE_UNAVAILABLE, "engine_busy"),
+        Err(TrySendError::Disconnected(_)) => return error(StatusCode::SERVICE_UNAVAILABLE, "engine stopped"),
+    }
+    match tokio::time::timeout(ENGINE_TIMEOUT, rx).await {
+        Ok(Ok(view)) => match serde_json::to_string_pretty(&view) {
+            Ok(json) => reply(StatusCode::OK, "application/json", json + "\n"),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+        Ok(Err(_)) => error(StatusCode::SERVICE_UNAVAILABLE, "engine stopped"),
+        Err(_) => error(StatusCode::GATEWAY_TIMEOUT, "engine did not answer in time"),
+    }
+}
+
+/// Serve `GET /book/<symbol>` until the runtime shuts down.
+pub async fn serve(listener: TcpListener, queue: Sender<Command>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("[debug] ⚠️ accept failed: {e}");
+                continue;
+            }
+        };
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            let svc = service_fn(move |req: Request<Incoming>| {
+                let queue = queue.clone();
+                async move { Ok::<_, Infallible>(handle(req, queue).await) }
+            });
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), svc).await {
+                warn!("[debug] ⚠️ connection error: {e}");
+            }
+        });
+    }
+}
@@ -1,19 +1,323 @@
-use std::collections::VecDeque;
-use std::time::Duration;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::fmt::Write;
-use crossbeam::channel::{Receiver, Sender, tick, select};
-use tracing::{info, warn};
-use crate::types::{Command, Event, Order, OrderBook, Side, Tif};
+use crossbeam::channel::{never, select, tick, Receiver};
+use hdrhistogram::Histogram;
+use tokio::sync::broadcast;
+use tracing::{error, info, info_span, warn};
+use crate::types::{AckNote, BookView, Command, DoneReason, Event, FeeSchedule, Flow, Level, LevelView, Lookup, MarketState, MatchPolicy, Ohlc, Order, OrderBook, OrderType, RejectReason, RiskLimit, RiskLimits, SessionStats, Side, Stp, Tape, Tif, Trade};
+use crate::metrics::Metrics;
+use crate::record::Recorder;
+use crate::sink::ClientSink;
+use crate::snapshot;
+use crate::wal::{Record, Wal};
 
-/// Engine main loop: single thread, deterministic execution
-pub fn run_engine(rx_cmd: Receiver<Command>, tx_md: Sender<Event>) {
+// Per-fill and per-order matching logs, behind the `matching-trace` feature. Without it
+// they expand to nothing, arguments included: the hot path is matching and event sends
+#[cfg(feature = "matching-trace")]
+macro_rules! fill_log {
+    ($($arg:tt)*) => {
+        info!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "matching-trace"))]
+macro_rules! fill_log {
+    ($($arg:tt)*) => {};
+}
+
+/// Commands applied per wake-up unless `DRAIN_BATCH` says otherwise; they share one WAL flush,
+/// and the bound keeps the ticker responsive under a flood.
+pub const DEFAULT_DRAIN_BATCH: usize = 256;
+
+/// Levels per side the book summary's order-flow imbalance covers.
+pub const IMBALANCE_LEVELS: usize = 5;
+
+/// OHLC candles on the market-data feed: closed every `every`, per symbol.
+/// An interval without trades gives a flat candle at the last price, unless `skip_empty`.
+#[derive(Debug, Clone, Copy)]
+pub struct CandleConfig {
+    pub every: Duration,
+    pub skip_empty: bool,
+}
+
+/// Market-data publisher. Every event on the feed carries the next `seq`, so a
+/// subscriber can spot gaps; the counter starts over at 1 when the engine restarts.
+pub struct MdFeed {
+    tx: broadcast::Sender<Event>,
+    seq: Cell<u64>,
+    recorder: Option<Recorder>, // Session recording, if one is running; it sees commands too
+    metrics: Arc<Metrics>, // Engine-side counters; a private set unless `metered`
+    risk: Arc<RiskLimits>, // Per-client pre-trade limits; none unless `risk_limits`
+    dropcopy: Option<broadcast::Sender<Event>>, // Copies of every client's order events, if `drop_copy`
+    orders: Option<broadcast::Sender<Event>>, // Order-level (L3) events, if `order_feed`
+    order_seq: Cell<u64>, // The order feed's own sequence, so L2 subscribers see no gaps
+    journal: Option<RefCell<Journal>>, // Each client's recent order events for RESYNC, if `journal`
+    print: Cell<Option<Trade>>, // Fills summed into the next `TradePrint`, until the taker or price changes
+}
+
+/// Every client's order events, numbered per client from 1, the latest `depth` of each kept for
+/// `RESYNC`. In memory only: a restarted engine starts over.
+struct Journal {
+    depth: usize,
+    clients: HashMap<u64, ClientJournal>,
+}
+
+#[derive(Default)]
+struct ClientJournal {
+    last_seq: u64,
+    events: VecDeque<(u64, Event)>,
+}
+
+impl Journal {
+    fn push(&mut self, cl_id: u64, evt: Event) {
+        let c = self.clients.entry(cl_id).or_default();
+        c.last_seq += 1;
+        if c.events.len() == self.depth {
+            c.events.pop_front();
+        }
+        c.events.push_back((c.last_seq, evt));
+    }
+}
+
+impl MdFeed {
+    pub fn new(tx: broadcast::Sender<Event>) -> Self {
+        MdFeed {
+            tx,
+            seq: Cell::new(0),
+            recorder: None,
+            metrics: Arc::default(),
+            risk: Arc::default(),
+            dropcopy: None,
+            orders: None,
+            order_seq: Cell::new(0),
+            journal: None,
+            print: Cell::new(None),
+        }
+    }
+
+    pub fn metered(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn recording(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Check new orders against `limits`. Recovery and replays must run with the same ones.
+    pub fn risk_limits(mut self, limits: Arc<RiskLimits>) -> Self {
+        self.risk = limits;
+        self
+    }
+
+    /// Publish a `DropCopy` of every order event sent to a client on `tx`.
+    pub fn drop_copy(mut self, tx: broadcast::Sender<Event>) -> Self {
+        self.dropcopy = Some(tx);
+        self
+    }
+
+    /// Publish order-level events on `tx` as well.
+    pub fn order_feed(mut self, tx: broadcast::Sender<Event>) -> Self {
+        self.orders = Some(tx);
+        self
+    }
+
+    /// Keep the latest `depth` order events of each client for `RESYNC` (0 = none).
+    pub fn journal(mut self, depth: usize) -> Self {
+        self.journal = (depth > 0).then(|| RefCell::new(Journal { depth, clients: HashMap::new() }));
+        self
+    }
+
+    /// Claim the sequence number for an event about to be `send`.
+    fn next_seq(&self) -> u64 {
+        self.seq.set(self.seq.get() + 1);
+        self.seq.get()
+    }
+
+    /// Last sequence number handed out; a snapshot taken now reflects everything up to it.
+    fn last_seq(&self) -> u64 {
+        self.seq.get()
+    }
+
+    fn send(&self, evt: Event) {
+        if let Some(rec) = &self.recorder {
+            rec.event(&evt);
+        }
+        let _ = self.tx.send(evt);
+    }
+
+    /// Stamp an order-level event with the order feed's next `seq` and publish it there.
+    fn send_order(&self, mut evt: Event) {
+        let Some(tx) = &self.orders else { return };
+        self.order_seq.set(self.order_seq.get() + 1);
+        if let Some(seq) = evt.seq_mut() {
+            *seq = self.order_seq.get();
+        }
+        let _ = tx.send(evt);
+    }
+
+    /// Send an order event to client `cl_id` on `sink`, copying it to the drop-copy feed.
+    fn reply(&self, sink: &ClientSink, cl_id: u64, evt: Event) {
+        self.copy(cl_id, &evt);
+        sink.send(evt);
+    }
+
+    /// `reply` on the client's registered connection. The copy goes out even if it has none.
+    fn notify(&self, owners: &HashMap<u64, ClientSink>, cl_id: u64, evt: Event) {
+        self.copy(cl_id, &evt);
+        if let Some(sink) = owners.get(&cl_id) {
+            sink.send(evt);
+        }
+    }
+
+    fn copy(&self, cl_id: u64, evt: &Event) {
+        if let Some(journal) = &self.journal {
+            journal.borrow_mut().push(cl_id, evt.clone());
+        }
+        if let Some(tx) = &self.dropcopy
+            && tx.receiver_count() > 0
+        {
+            let _ = tx.send(Event::DropCopy { cl_id, evt: Box::new(evt.clone()) });
+        }
+    }
+
+    /// Journal a fill for both its sides; the trade itself goes out on the feed, not as a copy.
+    fn journal_trade(&self, t: &Trade) {
+        if let Some(journal) = &self.journal {
+            let mut journal = journal.borrow_mut();
+            journal.push(t.taker_cl_id, Event::Trade(*t));
+            if t.maker_cl_id != t.taker_cl_id {
+                journal.push(t.maker_cl_id, Event::Trade(*t));
+            }
+        }
+    }
+
+    /// Add a fill to the pending trade print. A fill of another taker or at another price publishes
+    /// the pending one first.
+    fn add_to_print(&self, t: &Trade) {
+        let print = match self.print.take() {
+            Some(mut p) if p.taker_ord_id == t.taker_ord_id && p.price == t.price => {
+                if p.maker_ord_id != t.maker_ord_id {
+                    p.maker_cl_id = 0;
+                    p.maker_ord_id = 0;
+                }
+                p.qty += t.qty;
+                p.maker_fee += t.maker_fee;
+                p.taker_fee += t.taker_fee;
+                p.seq = t.seq;
+                p
+            }
+            pending => {
+                if let Some(p) = pending {
+                    let _ = self.tx.send(Event::TradePrint(p));
+                }
+                *t
+            }
+        };
+        self.print.set(Some(print));
+    }
+
+    /// Publish the pending trade print, if any. It takes the `seq` of its last fill and is not recorded:
+    /// the fills it sums already are.
+    fn flush_print(&self) {
+        if let Some(p) = self.print.take() {
+            let _ = self.tx.send(Event::TradePrint(p));
+        }
+    }
+
+    /// Replay to `sink` every journaled event of `cl_id` after `since`, then a `ResyncDone`.
+    /// Incomplete if some of them were already dropped, or `since` is past what the journal
+    /// has (it started over with the engine).
+    fn resync(&self, cl_id: u64, since: u64, sink: &ClientSink) {
+        let journal = self.journal.as_ref().map(RefCell::borrow);
+        let Some(c) = journal.as_ref().and_then(|j| j.clients.get(&cl_id)) else {
+            sink.send(Event::ResyncDone { cl_id, last_seq: 0, complete: journal.is_some() && since == 0 });
+            return;
+        };
+        for (seq, evt) in c.events.iter().filter(|(seq, _)| *seq > since) {
+            sink.send(Event::Resynced { cl_id, seq: *seq, evt: Box::new(evt.clone()) });
+        }
+        let oldest = c.events.front().map_or(c.last_seq + 1, |(seq, _)| *seq);
+        let complete = since + 1 >= oldest && since <= c.last_seq;
+        sink.send(Event::ResyncDone { cl_id, last_seq: c.last_seq, complete });
+    }
+}
+
+/// Time from the gateway parsing a NEW_ORDER to the engine finishing it, split out
+/// into the queue wait before the engine picked it up. Nanoseconds; reset every heartbeat.
+struct OrderLatency {
+    wait: Histogram<u64>,
+    total: Histogram<u64>,
+}
+
+impl OrderLatency {
+    fn new() -> Self {
+        // 1ns .. 60s, 3 significant digits
+        let hist = || Histogram::<u64>::new_with_bounds(1, 60_000_000_000, 3).expect("valid histogram bounds");
+        OrderLatency { wait: hist(), total: hist() }
+    }
+
+    fn record(&mut self, parsed: Instant, dequeued: Instant) {
+        self.wait.saturating_record(dequeued.saturating_duration_since(parsed).as_nanos() as u64);
+        self.total.saturating_record(parsed.elapsed().as_nanos() as u64);
+    }
+
+    fn report(&mut self, window: Duration) {
+        if self.total.is_empty() {
+            info!("[latency {}s] n=0 (no orders)", window.as_secs());
+            return;
+        }
+        let us = |h: &Histogram<u64>, q: f64| h.value_at_quantile(q) as f64 / 1_000.0;
+        info!(
+            "[latency {}s] n={} wait+match p50={:.1}µs p95={:.1}µs p99={:.1}µs max={:.1}µs | queue wait p50={:.1}µs p95={:.1}µs p99={:.1}µs",
+            window.as_secs(), self.total.len(),
+            us(&self.total, 0.50), us(&self.total, 0.95), us(&self.total, 0.99), self.total.max() as f64 / 1_000.0,
+            us(&self.wait, 0.50), us(&self.wait, 0.95), us(&self.wait, 0.99),
+        );
+        self.wait.reset();
+        self.total.reset();
+    }
+}
+
+/// Engine main loop: single thread, deterministic execution.
+/// `heartbeat` is the GTD sweep / book summary / snapshot ticker; `None` turns it off,
+/// so only the commands themselves move the books (replay). `candles` likewise.
+/// Exchange order ids count up from `next_exch_ord_id` (see `recover`). Each wake-up applies up to
+/// `drain_batch` queued commands before flushing and going back to `select!`; 1 means one at a time.
+/// The heartbeat's book summary adds a depth ladder of `ladder_levels` per side, if not 0.
+#[allow(clippy::too_many_arguments)]
+pub fn run_engine(
+    rx_cmd: Receiver<Command>,
+    tx_md: MdFeed,
+    mut books: HashMap<u32, OrderBook>,
+    mut next_exch_ord_id: u64,
+    mut wal: Wal,
+    snap_path: PathBuf,
+    snap_every: Duration,
+    heartbeat: Option<Duration>,
+    candles: Option<CandleConfig>,
+    drain_batch: usize,
+    ladder_levels: usize,
+) {
     info!("[engine] ✅ Engine started — waiting for incoming commands...");
+    let mut last_snap = Instant::now();
 
-    let mut book = OrderBook::default();
-    info!("[engine] OrderBook summary => bids={}, asks={}", book.bids.len(), book.asks.len());
+    // Connection registry: the sink a client last registered or sent an order on, for events
+    // nobody asked for (maker fills, GTD expiry). Only the engine touches it, so no lock
+    let mut owners: HashMap<u64, ClientSink> = HashMap::new();
+    info!("[engine] OrderBook summary => symbols={}", books.len());
 
     // 🔔 5s heartbeat
-    let ticker = tick(Duration::from_secs(5));
+    let ticker = heartbeat.map_or_else(never, tick);
+    let candle_ticker = candles.map_or_else(never, |c| tick(c.every));
+    let mut candle_start = now_ms();
+    let mut latency = OrderLatency::new();
 
     loop {
         select! {
@@ -25,72 +329,679 @@ pub fn run_engine(rx_cmd: Receiver<Command>, tx_md: Sender<Event>) {
                         break;
                     }
                 };
-
-                match cmd {
-                    Command::Ping(sink) => {
-                        info!("[engine] 🔁 Received PING");
-                        let _ = sink.send(Event::Pong);
-                        info!("[engine] 🏓 Sent PONG");
-                    }
-                    Command::Order(no, sink) => {
-                        info!(id=no.id, side=?no.side, price=no.price, qty=no.qty, tif=?no.tif,
-                              "[engine] 🆕 New Order");
-                        handle_new(no, &mut book, &sink, &tx_md);
-                    }
-                    Command::Cancel { ord_id, sink, .. } => {
-                        info!(ord_id, "[engine] ❌ Cancel Request");
-                        if handle_cancel(ord_id, &mut book, &tx_md) {
-                            info!(ord_id, "[engine] ✅ Cancel Success");
-                            let _ = sink.send(Event::Ack { ord_id, note: "canceled" });
-                        } else {
-                            warn!(ord_id, "[engine] ⚠️ Cancel Failed — not found");
-                            let _ = sink.send(Event::Reject { ord_id, reason: "not_found" });
-                        }
+                let mut shutdown = matches!(cmd, Command::Shutdown);
+                dispatch(cmd, &mut books, &mut next_exch_ord_id, &mut owners, &tx_md, &mut wal, &mut latency);
+                // Whatever queued up meanwhile shares this batch's flush
+                for cmd in rx_cmd.try_iter().take(drain_batch.saturating_sub(1)) {
+                    shutdown |= matches!(cmd, Command::Shutdown);
+                    dispatch(cmd, &mut books, &mut next_exch_ord_id, &mut owners, &tx_md, &mut wal, &mut latency);
+                }
+                if shutdown {
+                    // Everything enqueued before the gateway stopped still gets applied
+                    for cmd in rx_cmd.try_iter() {
+                        dispatch(cmd, &mut books, &mut next_exch_ord_id, &mut owners, &tx_md, &mut wal, &mut latency);
                     }
+                    persist(&mut wal, &books, next_exch_ord_id, &snap_path);
+                    flush_recording(&tx_md);
+                    info!("[engine] 🛑 Engine stopped — queue drained, state persisted");
+                    break;
                 }
+                if let Err(e) = wal.flush() {
+                    error!("[wal] ❌ flush failed: {e}");
+                }
+                flush_recording(&tx_md);
+                tx_md.metrics.set_resting(&books);
             },
             // ⏱️ every 5 seconds
             recv(ticker) -> _ => {
-                info!("{}", summarize_book(&book));
+                if books.is_empty() {
+                    info!("[engine] ⏱️ Book@5s  no symbols yet");
+                }
+                let now = now_ms();
+                if books.values().any(|b| b.expiries.peek().is_some_and(|Reverse((expire_ms, _))| *expire_ms <= now)) {
+                    log_record(&mut wal, &tx_md, &Record::Sweep { now_ms: now });
+                    if let Err(e) = wal.flush() {
+                        error!("[wal] ❌ flush failed: {e}");
+                    }
+                }
+                sweep_all(&mut books, now, &owners, &tx_md);
+                flush_recording(&tx_md);
+                tx_md.metrics.set_resting(&books);
+                let mut symbols: Vec<u32> = books.keys().copied().collect();
+                symbols.sort_unstable();
+                for symbol in symbols {
+                    info!("{}", summarize_book(symbol, &books[&symbol], ladder_levels));
+                }
+                // The summary reports aggressive flow per tick
+                for book in books.values_mut() {
+                    book.tape.flow = Flow::default();
+                }
+                latency.report(heartbeat.unwrap_or_default());
+
+                // Bound recovery time: replay only needs the WAL past this point
+                if last_snap.elapsed() >= snap_every {
+                    last_snap = Instant::now();
+                    persist(&mut wal, &books, next_exch_ord_id, &snap_path);
+                }
+            }
+            recv(candle_ticker) -> _ => {
+                let skip_empty = candles.is_some_and(|c| c.skip_empty);
+                close_candles(&mut books, candle_start, skip_empty, &tx_md);
+                candle_start = now_ms();
+            }
+        }
+    }
+}
+
+/// Apply one command: book-mutating ones hit the WAL first.
+fn dispatch(
+    cmd: Command,
+    books: &mut HashMap<u32, OrderBook>,
+    next_exch_ord_id: &mut u64,
+    owners: &mut HashMap<u64, ClientSink>,
+    tx_md: &MdFeed,
+    wal: &mut Wal,
+    latency: &mut OrderLatency,
+) {
+    // Every log line of the command names the connection that sent it
+    let _session = cmd.session().map(|session| info_span!("command", session).entered());
+    match cmd {
+        Command::Ping(sink) => {
+            info!("[engine] 🔁 Received PING");
+            sink.send(Event::Pong);
+            info!("[engine] 🏓 Sent PONG");
+        }
+        Command::Order(mut no, sink, parsed) => {
+            let dequeued = Instant::now();
+            // Before the WAL, so recovery and replays see the same id
+            no.exch_ord_id = *next_exch_ord_id;
+            *next_exch_ord_id += 1;
+            info!(symbol=no.symbol, id=no.id, exch_ord_id=no.exch_ord_id, side=?no.side, price=no.price, qty=no.qty, tif=?no.tif,
+                  ord_type=?no.ord_type, post_only=no.post_only, stp=?no.stp, "[engine] 🆕 New Order");
+            log_record(wal, tx_md, &Record::Order(no.clone()));
+            claim(owners, no.cl_id, &sink);
+            let elsewhere = open_elsewhere(books, &no, &tx_md.risk);
+            let book = books.entry(no.symbol).or_default();
+            // Never match against an order that should already be gone
+            // (the order's own timestamp, not the wall clock, so replay sweeps identically)
+            with_bbo(no.symbol, book, tx_md, |book| {
+                expire_orders(no.symbol, book, no.timestamp, owners, tx_md);
+                handle_new_capped(no, book, elsewhere, &sink, owners, tx_md);
+                trigger_stops(book, owners, tx_md);
+            });
+            latency.record(parsed, dequeued);
+        }
+        Command::Cancel { symbol, cl_id, ord_id, sink } => {
+            info!(symbol, cl_id, ord_id, "[engine] ❌ Cancel Request");
+            log_record(wal, tx_md, &Record::Cancel { symbol, cl_id, ord_id });
+            let res = match books.get_mut(&symbol) {
+                Some(book) => with_bbo(symbol, book, tx_md, |book| handle_cancel(symbol, cl_id, ord_id, book, tx_md)),
+                None => Err(RejectReason::NotFound),
+            };
+            match res {
+                Ok(_) => {
+                    tx_md.metrics.orders_canceled(1);
+                    info!(ord_id, "[engine] ✅ Cancel Success");
+                    tx_md.reply(&sink, cl_id, Event::Done { ord_id, reason: DoneReason::Canceled });
+                }
+                Err(reason) => {
+                    warn!(ord_id, reason = reason.as_str(), "[engine] ⚠️ Cancel Failed");
+                    tx_md.reply(&sink, cl_id, Event::Reject { ord_id, reason });
+                }
+            }
+        }
+        Command::Replace { symbol, cl_id, ord_id, new_price, new_qty, timestamp, sink } => {
+            info!(symbol, cl_id, ord_id, new_price, new_qty, "[engine] ✏️ Replace Request");
+            log_record(wal, tx_md, &Record::Replace { symbol, cl_id, ord_id, new_price, new_qty, timestamp });
+            let res = match books.get_mut(&symbol) {
+                Some(book) => with_bbo(symbol, book, tx_md, |book| {
+                    let res = handle_replace(symbol, cl_id, ord_id, new_price, new_qty, timestamp, book, &sink, owners, tx_md);
+                    trigger_stops(book, owners, tx_md);
+                    res
+                }),
+                None => Err(RejectReason::NotFound),
+            };
+            if let Err(reason) = res {
+                warn!(ord_id, reason = reason.as_str(), "[engine] ⚠️ Replace Failed");
+                tx_md.reply(&sink, cl_id, Event::Reject { ord_id, reason });
+            }
+        }
+        Command::Reduce { symbol, cl_id, ord_id, new_qty, sink } => {
+            info!(symbol, cl_id, ord_id, new_qty, "[engine] ✂️ Reduce Request");
+            log_record(wal, tx_md, &Record::Reduce { symbol, cl_id, ord_id, new_qty });
+            let res = match books.get_mut(&symbol) {
+                Some(book) => with_bbo(symbol, book, tx_md, |book| handle_reduce(symbol, cl_id, ord_id, new_qty, book, &sink, tx_md)),
+                None => Err(RejectReason::NotFound),
+            };
+            if let Err(reason) = res {
+                warn!(ord_id, reason = reason.as_str(), "[engine] ⚠️ Reduce Failed");
+                tx_md.reply(&sink, cl_id, Event::Reject { ord_id, reason });
+            }
+        }
+        Command::CancelAll { cl_id, symbol, sink } => {
+            info!(cl_id, symbol=?symbol, "[engine] 🧹 Cancel-All Request");
+            log_record(wal, tx_md, &Record::CancelAll { cl_id, symbol });
+            let pulled = cancel_all(books, cl_id, symbol, tx_md);
+            let count = pulled.len() as u64;
+            tx_md.metrics.orders_canceled(count);
+            for ord_id in pulled {
+                tx_md.reply(&sink, cl_id, Event::Done { ord_id, reason: DoneReason::Canceled });
+            }
+            info!(cl_id, count, "[engine] ✅ Cancel-All Done");
+            tx_md.reply(&sink, cl_id, Event::CancelAllDone { count });
+        }
+        Command::QueryOrders { cl_id, sink } => {
+            info!(cl_id, "[engine] 📋 Open-Orders Query");
+            sink.send(open_orders(books, cl_id));
+        }
+        Command::Resync { cl_id, since, sink } => {
+            info!(cl_id, since, "[engine] 🔁 Resync Request");
+            tx_md.resync(cl_id, since, &sink);
+        }
+        Command::QueryStats { symbol, sink } => {
+            info!(symbol, "[engine] 📊 Stats Query");
+            let stats = books.get(&symbol).map_or_else(SessionStats::default, |b| b.tape.session);
+            sink.send(Event::Stats {
+                symbol,
+                last: stats.last,
+                high: stats.high,
+                low: stats.low,
+                volume: stats.volume,
+                trades: stats.trades,
+            });
+        }
+        Command::SessionRoll { symbol, sink } => {
+            info!(symbol=?symbol, "[engine] 🔄 Session Roll");
+            log_record(wal, tx_md, &Record::SessionRoll { symbol });
+            roll_session(books, symbol, owners, tx_md);
+            sink.send(Event::Ack { ord_id: 0, note: AckNote::SessionRolled });
+        }
+        Command::SetMarketState { symbol, state, sink } => {
+            info!(symbol, state=?state, "[engine] 🚦 Market State");
+            log_record(wal, tx_md, &Record::MarketState { symbol, state });
+            let book = books.entry(symbol).or_default();
+            let was = book.state;
+            with_bbo(symbol, book, tx_md, |book| {
+                set_market_state(symbol, book, state, owners, tx_md);
+                trigger_stops(book, owners, tx_md);
+            });
+            let note = match state {
+                MarketState::Open if was == MarketState::Auction => AckNote::Uncrossed,
+                MarketState::Open => AckNote::Resumed,
+                MarketState::Halted => AckNote::Halted,
+                MarketState::Auction => AckNote::Auction,
+            };
+            sink.send(Event::Ack { ord_id: 0, note });
+        }
+        Command::Register { cl_id, admin, sink } => {
+            // An admin connection takes the client over even from an open one
+            let claimed = if admin {
+                owners.insert(cl_id, sink.clone());
+                true
+            } else {
+                claim(owners, cl_id, &sink)
+            };
+            if claimed {
+                info!(cl_id, "[engine] 🪪 Client registered");
+                sink.send(Event::Ack { ord_id: 0, note: AckNote::Registered });
+            } else {
+                warn!(cl_id, "[engine] ⛔ Register refused — another open connection has the client");
+                sink.send(Event::Reject { ord_id: 0, reason: RejectReason::Unauthorized });
+            }
+        }
+        Command::Deregister { cl_id, sink } => {
+            if owners.get(&cl_id).is_some_and(|s| s.same_connection(&sink)) {
+                info!(cl_id, "[engine] 🪪 Client deregistered");
+                owners.remove(&cl_id);
+            }
+        }
+        Command::Sweep { now_ms } => {
+            info!(now_ms, "[engine] ⌛ Sweep Request");
+            log_record(wal, tx_md, &Record::Sweep { now_ms });
+            sweep_all(books, now_ms, owners, tx_md);
+        }
+        Command::Shutdown => {
+            info!("[engine] 🛑 Shutdown requested — draining queued commands");
+        }
+        Command::Snapshot { symbol, depth, sink } => {
+            info!(symbol, depth, "[engine] 📸 Snapshot Request");
+            let snap = match books.get(&symbol) {
+                Some(book) => snapshot_book(symbol, book, depth, tx_md.last_seq()),
+                None => Event::Snapshot { symbol, bids: Vec::new(), asks: Vec::new(), seq: tx_md.last_seq() },
+            };
+            sink.send(snap);
+        }
+        Command::DebugSnapshot { symbol, depth, reply } => {
+            info!(symbol, depth, "[engine] 🔬 Debug Snapshot Request");
+            let view = book_view(symbol, books.get(&symbol).unwrap_or(&OrderBook::default()), depth, tx_md.last_seq());
+            // The HTTP side may have given up waiting
+            let _ = reply.send(view);
+        }
+    }
+}
+
+/// Flush the WAL and snapshot the books at its current end.
+fn persist(wal: &mut Wal, books: &HashMap<u32, OrderBook>, next_exch_ord_id: u64, snap_path: &Path) {
+    match wal.flush().and_then(|_| snapshot::write(snap_path, books, wal.offset(), next_exch_ord_id)) {
+        Ok(()) => info!("[engine] 📦 Snapshot written at WAL offset {}", wal.offset()),
+        Err(e) => error!("[engine] ❌ snapshot failed: {e}"),
+    }
+}
+
+/// Route `cl_id`'s unsolicited events to `sink`, unless another connection that is still open has them.
+/// Returns whether `sink` has them now.
+fn claim(owners: &mut HashMap<u64, ClientSink>, cl_id: u64, sink: &ClientSink) -> bool {
+    match owners.get(&cl_id) {
+        Some(owner) if !owner.same_connection(sink) && !owner.is_closed() => false,
+        _ => {
+            owners.insert(cl_id, sink.clone());
+            true
+        }
+    }
+}
+
+/// Append to the WAL, and to the session recording if one is running.
+fn log_record(wal: &mut Wal, tx_md: &MdFeed, rec: &Record) {
+    if let Err(e) = wal.append(rec) {
+        error!("[wal] ❌ append failed: {e}");
+    }
+    if let Some(recorder) = &tx_md.recorder {
+        recorder.command(rec);
+    }
+}
+
+fn flush_recording(tx_md: &MdFeed) {
+    if let Some(recorder) = &tx_md.recorder
+        && let Err(e) = recorder.flush()
+    {
+        error!("[record] ❌ flush failed: {e}");
+    }
+}
+
+/// Move a book to `state`. Opening it from an auction (or a halt that followed one) uncrosses it first:
+/// the trades go to both sides' connections and to the feed, with one `BookDelta` per touched level,
+/// then each order it used up gets its `Done`.
+fn set_market_state(symbol: u32, b: &mut OrderBook, state: MarketState, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    if state == MarketState::Open && b.state != MarketState::Open {
+        let (events, filled) = run_auction_uncross(b);
+        if !events.is_empty() {
+            info!(symbol, "[engine] 🔔 Uncross: {} trades", events.iter().filter(|e| matches!(e, Event::Trade(_))).count());
+        }
+        for mut evt in events {
+            if evt.is_order_level() {
+                tx_md.send_order(evt);
+                continue;
+            }
+            if let Some(seq) = evt.seq_mut() {
+                *seq = tx_md.next_seq();
+            }
+            if let Event::Trade(t) = &evt {
+                tx_md.metrics.trade(t.qty);
+                tx_md.journal_trade(t);
+                tx_md.add_to_print(t);
+                for (i, cl_id) in [t.taker_cl_id, t.maker_cl_id].into_iter().enumerate() {
+                    if (i == 0 || cl_id != t.taker_cl_id)
+                        && let Some(sink) = owners.get(&cl_id)
+                    {
+                        sink.send(evt.clone());
+                    }
+                }
+            }
+            tx_md.send(evt);
+        }
+        tx_md.flush_print();
+        for (cl_id, ord_id) in filled {
+            tx_md.notify(owners, cl_id, Event::Done { ord_id, reason: DoneReason::Filled });
+        }
+    }
+    b.state = state;
+    debug_check(b);
+}
+
+/// Opening cross. Finds the one price that executes the most volume and fills every crossable order
+/// there, best price first and oldest first within a level, leaving the book uncrossed.
+/// Volume ties go to the smallest demand/supply imbalance, then the price nearest the last trade,
+/// then the lowest price. Each fill names the newer of the two orders as taker, since an auction
+/// has no aggressor. Returns the trades, each followed by its orders' order-level events, and then the final
+/// qty of every touched level, unsequenced (seq 0),
+/// along with the `(cl_id, ord_id)` of every order it filled completely.
+pub fn run_auction_uncross(b: &mut OrderBook) -> (Vec<Event>, Vec<(u64, u64)>) {
+    let mut events = Vec::new();
+    let mut filled = Vec::new();
+    let Some((px, volume)) = clearing_price(b) else {
+        return (events, filled);
+    };
+    fill_log!(price=px, volume, "[engine] 🔔 Auction clears");
+
+    let mut touched: Vec<(Side, i64)> = Vec::new();
+    let mut symbol = 0;
+    let mut left = volume;
+    while left > 0 {
+        let bid_px = b.bids.highest().expect("clearing volume left on the bid side").0;
+        let ask_px = b.asks.lowest().expect("clearing volume left on the ask side").0;
+        for key in [(Side::Bid, bid_px), (Side::Ask, ask_px)] {
+            if !touched.contains(&key) {
+                touched.push(key);
+            }
+        }
+
+        let OrderBook { bids, asks, lookup, fees, tape, .. } = &mut *b;
+        let bq = bids.get_mut(bid_px).expect("level must exist");
+        let aq = asks.get_mut(ask_px).expect("level must exist");
+        let bo = bq.orders.front_mut().expect("levels are never empty");
+        let ao = aq.orders.front_mut().expect("levels are never empty");
+        let fill = left.min(bo.qty).min(ao.qty);
+        bo.qty -= fill;
+        ao.qty -= fill;
+        bo.total_qty -= fill;
+        ao.total_qty -= fill;
+        bq.level_qty -= fill;
+        aq.level_qty -= fill;
+        lookup.reduce(bo.cl_id, bid_px, fill);
+        lookup.reduce(ao.cl_id, ask_px, fill);
+        left -= fill;
+
+        let (bid_done, ask_done) = (bo.qty == 0, ao.qty == 0);
+
+        let (taker, maker) = if bo.exch_ord_id > ao.exch_ord_id { (&*bo, &*ao) } else { (&*ao, &*bo) };
+        symbol = taker.symbol;
+        let (maker_fee, taker_fee) = fees.fees(px, fill);
+        let trade = Trade {
+            symbol: taker.symbol,
+            price: px,
+            qty: fill,
+            taker_cl_id: taker.cl_id,
+            maker_cl_id: maker.cl_id,
+            taker_ord_id: taker.exch_ord_id,
+            maker_ord_id: maker.exch_ord_id,
+            aggressor_side: taker.side,
+            maker_fee,
+            taker_fee,
+            seq: 0,
+            price_improvement: taker.improvement(px),
+        };
+        tape.record(&trade);
+        events.push(Event::Trade(trade));
+        for o in [&*bo, &*ao] {
+            events.push(Event::OrderExecute { symbol: o.symbol, ord_id: o.exch_ord_id, qty: fill, seq: 0 });
+        }
+
+        for (done, q) in [(bid_done, &mut *bq), (ask_done, &mut *aq)] {
+            if !done {
+                continue;
+            }
+            let mut o = q.pop_front().expect("front must exist");
+            if o.total_qty > 0 {
+                o.refill();
+                events.push(Event::OrderAdd { symbol: o.symbol, ord_id: o.exch_ord_id, side: o.side, price: o.price, qty: o.qty, seq: 0 });
+                q.requeue(o);
+            } else {
+                lookup.remove(&o);
+                filled.push((o.cl_id, o.id));
+            }
+        }
+        if bq.is_empty() {
+            bids.remove(bid_px);
+        }
+        if aq.is_empty() {
+            asks.remove(ask_px);
+        }
+    }
+
+    for (side, level_px) in touched {
+        let level_qty = b.levels(side).get(level_px).map_or(0, |l| l.level_qty);
+        events.push(Event::BookDelta { symbol, side, price: level_px, level_qty, seq: 0 });
+    }
+    (events, filled)
+}
+
+/// Price and volume of the auction: the most volume any single price executes, with the tie-breaks
+/// of `run_auction_uncross`. `None` if nothing crosses.
+fn clearing_price(b: &OrderBook) -> Option<(i64, u64)> {
+    // Hidden reserves take part: they refill as the visible slices fill
+    let bids: Vec<(i64, u64)> = b.bids.iter().map(|(px, l)| (px, l.total_qty())).collect();
+    let asks: Vec<(i64, u64)> = b.asks.iter().map(|(px, l)| (px, l.total_qty())).collect();
+    let mut prices: Vec<i64> = bids.iter().chain(&asks).map(|&(px, _)| px).collect();
+    prices.sort_unstable();
+    prices.dedup();
+
+    // Sweep candidate prices upwards: supply at p is every ask <= p, demand every bid >= p
+    let last = b.tape.reference();
+    let mut demand: u64 = bids.iter().map(|&(_, q)| q).sum();
+    let mut supply = 0u64;
+    let (mut bi, mut ai) = (0, 0);
+    let mut best: Option<(i64, u64, u64, u64)> = None; // (price, volume, imbalance, distance to last)
+    for p in prices {
+        while ai < asks.len() && asks[ai].0 <= p {
+            supply += asks[ai].1;
+            ai += 1;
+        }
+        while bi < bids.len() && bids[bi].0 < p {
+            demand -= bids[bi].1;
+            bi += 1;
+        }
+        let volume = demand.min(supply);
+        if volume == 0 {
+            continue;
+        }
+        let imbalance = demand.abs_diff(supply);
+        let distance = if last != 0 { p.abs_diff(last) } else { 0 };
+        if best.is_none_or(|(_, v, i, d)| (Reverse(volume), imbalance, distance) < (Reverse(v), i, d)) {
+            best = Some((p, volume, imbalance, distance));
+        }
+    }
+    best.map(|(p, volume, _, _)| (p, volume))
+}
+
+/// Publish the candle of the interval that began at `start_ts` for every book, in symbol order,
+/// and start the next one. Books that never traded this session have no price for a flat candle.
+fn close_candles(books: &mut HashMap<u32, OrderBook>, start_ts: u64, skip_empty: bool, tx_md: &MdFeed) {
+    let mut symbols: Vec<u32> = books.keys().copied().collect();
+    symbols.sort_unstable();
+    for symbol in symbols {
+        let tape = &mut books.get_mut(&symbol).expect("symbol listed from books").tape;
+        let c = match tape.candle.take() {
+            Some(c) => c,
+            None if skip_empty || tape.session.trades == 0 => continue,
+            None => {
+                let px = tape.session.last;
+                Ohlc { open: px, high: px, low: px, close: px, volume: 0 }
+            }
+        };
+        tx_md.send(Event::Candle { symbol, open: c.open, high: c.high, low: c.low, close: c.close, volume: c.volume, start_ts });
+    }
+}
+
+/// Reset the session statistics of `symbol`, or of every book.
+fn roll_session(books: &mut HashMap<u32, OrderBook>, symbol: Option<u32>, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    let mut symbols: Vec<u32> = books.keys().copied().filter(|s| symbol.is_none_or(|want| *s == want)).collect();
+    symbols.sort_unstable();
+    for symbol in symbols {
+        let book = books.get_mut(&symbol).expect("symbol listed from books");
+        book.tape.roll_session();
+        with_bbo(symbol, book, tx_md, |book| end_day_orders(symbol, book, owners, tx_md));
+    }
+}
+
+/// Pull every DAY order, resting or a pending stop, and tell its owner. GTC and GTD orders stay.
+fn end_day_orders(symbol: u32, b: &mut OrderBook, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    let resting: Vec<u64> = b.bids.iter().chain(b.asks.iter())
+        .flat_map(|(_, level)| level.orders.iter().filter(|o| o.tif == Tif::Day).map(|o| o.exch_ord_id))
+        .collect();
+    let stops: Vec<u64> = b.stops.iter().filter(|o| o.tif == Tif::Day).map(|o| o.exch_ord_id).collect();
+    let mut ended: Vec<Order> = resting.into_iter().filter_map(|exch_ord_id| remove_order(symbol, exch_ord_id, b, tx_md)).collect();
+    ended.extend(stops.into_iter().filter_map(|exch_ord_id| b.stops.remove(exch_ord_id)));
+    for o in ended {
+        info!("[book] 🌙 DAY order {} ended with the session (qty={})", o.id, o.qty);
+        tx_md.notify(owners, o.cl_id, Event::Done { ord_id: o.id, reason: DoneReason::SessionEnd });
+    }
+}
+
+/// Expire due GTD orders in every book, in symbol order.
+fn sweep_all(books: &mut HashMap<u32, OrderBook>, now: u64, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    let mut symbols: Vec<u32> = books.keys().copied().collect();
+    symbols.sort_unstable();
+    for symbol in symbols {
+        let book = books.get_mut(&symbol).expect("symbol listed from books");
+        with_bbo(symbol, book, tx_md, |book| expire_orders(symbol, book, now, owners, tx_md));
+    }
+}
+
+/// Roll `books` (empty, or loaded from a snapshot) forward through WAL records with the live handlers.
+/// Events go to closed channels, so replay needs no clients and produces no output.
+/// Also rolls the exchange order id counter past every id the records assigned.
+/// `risk` must be the limits the records ran under, or risk rejects won't replay the same.
+pub fn recover(
+    mut books: HashMap<u32, OrderBook>,
+    mut next_exch_ord_id: u64,
+    records: Vec<Record>,
+    risk: Arc<RiskLimits>,
+) -> (HashMap<u32, OrderBook>, u64) {
+    let sink = ClientSink::detached();
+    let tx_md = MdFeed::new(broadcast::channel::<Event>(1).0).risk_limits(risk);
+    let owners = HashMap::new();
+
+    for rec in records {
+        match rec {
+            Record::Order(no) => {
+                next_exch_ord_id = next_exch_ord_id.max(no.exch_ord_id + 1);
+                let elsewhere = open_elsewhere(&books, &no, &tx_md.risk);
+                let book = books.entry(no.symbol).or_default();
+                expire_orders(no.symbol, book, no.timestamp, &owners, &tx_md);
+                handle_new_capped(no, book, elsewhere, &sink, &owners, &tx_md);
+                trigger_stops(book, &owners, &tx_md);
+            }
+            Record::Cancel { symbol, cl_id, ord_id } => {
+                if let Some(book) = books.get_mut(&symbol) {
+                    let _ = handle_cancel(symbol, cl_id, ord_id, book, &tx_md);
+                }
+            }
+            Record::Replace { symbol, cl_id, ord_id, new_price, new_qty, timestamp } => {
+                if let Some(book) = books.get_mut(&symbol) {
+                    let _ = handle_replace(symbol, cl_id, ord_id, new_price, new_qty, timestamp, book, &sink, &owners, &tx_md);
+                    trigger_stops(book, &owners, &tx_md);
+                }
+            }
+            Record::Reduce { symbol, cl_id, ord_id, new_qty } => {
+                if let Some(book) = books.get_mut(&symbol) {
+                    let _ = handle_reduce(symbol, cl_id, ord_id, new_qty, book, &sink, &tx_md);
+                }
+            }
+            Record::CancelAll { cl_id, symbol } => {
+                cancel_all(&mut books, cl_id, symbol, &tx_md);
+            }
+            Record::Sweep { now_ms } => {
+                for (&symbol, book) in books.iter_mut() {
+                    expire_orders(symbol, book, now_ms, &owners, &tx_md);
+                }
+            }
+            Record::SessionRoll { symbol } => roll_session(&mut books, symbol, &owners, &tx_md),
+            Record::MarketState { symbol, state } => {
+                let book = books.entry(symbol).or_default();
+                set_market_state(symbol, book, state, &owners, &tx_md);
+                trigger_stops(book, &owners, &tx_md);
             }
         }
     }
+    // Candles aren't persisted: replayed fills may already be in a published one. Nor is the flow
+    for book in books.values_mut() {
+        book.tape.candle = None;
+        book.tape.flow = Flow::default();
+    }
+    info!("[engine] 📜 Recovered {} symbols from WAL", books.len());
+    (books, next_exch_ord_id)
+}
+
+/// Run `f` against one book and publish a `Bbo` if it moved the top of book (price or size).
+/// Wrapping whole commands means a replace or a sweep-then-match shows only its end state.
+fn with_bbo<R>(symbol: u32, b: &mut OrderBook, tx_md: &MdFeed, f: impl FnOnce(&mut OrderBook) -> R) -> R {
+    let before = b.bbo();
+    let out = f(b);
+    let (bid, ask) = b.bbo();
+    if (bid, ask) != before {
+        let (bid_px, bid_qty) = bid.unwrap_or((0, 0));
+        let (ask_px, ask_qty) = ask.unwrap_or((0, 0));
+        fill_log!("[book] 🎯 BBO symbol={} bid={}x{} ask={}x{}", symbol, bid_px, bid_qty, ask_px, ask_qty);
+        tx_md.send(Event::Bbo { symbol, bid_px, bid_qty, ask_px, ask_qty, seq: tx_md.next_seq() });
+    }
+    out
+}
+
+/// Debug builds verify the whole book after every mutation; release builds skip it.
+fn debug_check(b: &OrderBook) {
+    if cfg!(debug_assertions)
+        && let Err(e) = b.check_invariants()
+    {
+        panic!("[book] invariant broken: {e}");
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+/// Pull every GTD order due at or before `now` and tell its owner.
+fn expire_orders(
+    symbol: u32,
+    b: &mut OrderBook,
+    now: u64,
+    owners: &HashMap<u64, ClientSink>,
+    tx_md: &MdFeed,
+) {
+    for o in sweep_expired(symbol, b, now, tx_md) {
+        info!("[book] ⌛ GTD order {} expired (expire_ms={}, qty={})", o.id, o.expire_ms, o.qty);
+        tx_md.notify(owners, o.cl_id, Event::Done { ord_id: o.id, reason: DoneReason::Expired });
+    }
+}
+
+/// Pop due entries off the expiry heap, earliest first, and remove the orders still live.
+/// Entries whose order already filled or was canceled are dropped.
+fn sweep_expired(symbol: u32, b: &mut OrderBook, now: u64, tx_md: &MdFeed) -> Vec<Order> {
+    let mut expired = Vec::new();
+    while let Some(&Reverse((expire_ms, exch_ord_id))) = b.expiries.peek() {
+        if expire_ms > now { break; }
+        b.expiries.pop();
+
+        if let Some(o) = remove_order(symbol, exch_ord_id, b, tx_md).or_else(|| b.stops.remove(exch_ord_id)) {
+            expired.push(o);
+        }
+    }
+    expired
 }
 
-// ---- helper: compact book snapshot
-fn summarize_book(b: &OrderBook) -> String {
+// ---- helper: compact book snapshot, plus the best `ladder_levels` per side as a ladder if not 0
+fn summarize_book(symbol: u32, b: &OrderBook, ladder_levels: usize) -> String {
     let mut out = String::new();
 
     // --- top of book (best levels)
-    let best_bid = b.bids
-        .iter()
-        .next_back()
-        .map(|(px, q)| (*px, q.iter().map(|o| o.qty).sum::<u64>()));
-    let best_ask = b.asks
-        .iter()
-        .next()
-        .map(|(px, q)| (*px, q.iter().map(|o| o.qty).sum::<u64>()));
+    let (best_bid, best_ask) = b.bbo();
 
     // --- levels and cum quantities
     let bid_levels = b.bids.len();
     let ask_levels = b.asks.len();
-    let bid_qty: u64 = b.bids.values().map(|v| v.iter().map(|o| o.qty).sum::<u64>()).sum();
-    let ask_qty: u64 = b.asks.values().map(|v| v.iter().map(|o| o.qty).sum::<u64>()).sum();
+    let bid_qty: u64 = b.bids.iter().map(|(_, l)| l.level_qty).sum();
+    let ask_qty: u64 = b.asks.iter().map(|(_, l)| l.level_qty).sum();
 
     // --- pending order counts (number of resting orders)
-    let bid_orders: usize = b.bids.values().map(|q| q.len()).sum();
-    let ask_orders: usize = b.asks.values().map(|q| q.len()).sum();
+    let bid_orders: usize = b.bids.iter().map(|(_, l)| l.orders.len()).sum();
+    let ask_orders: usize = b.asks.iter().map(|(_, l)| l.orders.len()).sum();
 
-    // --- spread
+    // --- spread (ask - bid). Matching never leaves bid >= ask resting, so a crossed
+    // book in continuous trading means a bug upstream: shout about it instead of printing a bogus number
     let spread = match (best_bid, best_ask) {
-        (Some((bp, _)), Some((ap, _))) if ap >= bp => Some(ap - bp),
-        _ => None,
+        (Some((bp, _)), Some((ap, _))) if ap >= bp => Ok(Some(ap - bp)),
+        (Some((bp, _)), Some((ap, _))) if b.state != MarketState::Open => Err((bp, ap)),
+        (Some((bp, _)), Some((ap, _))) => {
+            warn!("[book] ❗ CROSSED symbol={} best_bid={} > best_ask={} — matching invariant broken", symbol, bp, ap);
+            Err((bp, ap))
+        }
+        _ => Ok(None),
     };
 
     let _ = writeln!(
         out,
-        "[engine] ⏱️ Book@5s  levels: bids={} asks={}  totals: bid_qty={} ask_qty={}  pending: bid_orders={} ask_orders={}",
-        bid_levels, ask_levels, bid_qty, ask_qty, bid_orders, ask_orders
+        "[engine] ⏱️ Book@5s  symbol={}  levels: bids={} asks={}  totals: bid_qty={} ask_qty={}  pending: bid_orders={} ask_orders={}",
+        symbol, bid_levels, ask_levels, bid_qty, ask_qty, bid_orders, ask_orders
     );
 
     match best_bid {
@@ -101,191 +1012,2472 @@ fn summarize_book(b: &OrderBook) -> String {
         Some((px, lvl)) => { let _ = writeln!(out, "  • best_ask: px={} level_qty={}", px, lvl); }
         None => { let _ = writeln!(out, "  • best_ask: none"); }
     }
-    if let Some(s) = spread { let _ = writeln!(out, "  • spread: {}", s); }
+    match spread {
+        Ok(Some(s)) => { let _ = writeln!(out, "  • spread: {}", s); }
+        Ok(None) => {}
+        Err((bp, ap)) => { let _ = writeln!(out, "  • spread: CROSSED (bid {} > ask {}, {:?})", bp, ap, b.state); }
+    }
+    let st = &b.tape.session;
+    match st.trades {
+        0 => { let _ = writeln!(out, "  • last: none"); }
+        _ => { let _ = writeln!(out, "  • last: px={} high={} low={} volume={} trades={}", st.last, st.high, st.low, st.volume, st.trades); }
+    }
+    let imbalance = b.imbalance(IMBALANCE_LEVELS).map_or("none".to_string(), |i| format!("{i:+.3}"));
+    let flow = &b.tape.flow;
+    let ratio = flow.ratio().map_or("none".to_string(), |r| format!("{r:.3}"));
+    let _ = writeln!(
+        out,
+        "  • flow: imbalance(top {})={}  aggressive: buy={} sell={} ratio={}",
+        IMBALANCE_LEVELS, imbalance, flow.buy_qty, flow.sell_qty, ratio
+    );
+    if ladder_levels > 0 {
+        let _ = write!(out, "{b:.ladder_levels$}");
+    }
 
     out
 }
 
-/// Insert a new order:
-fn handle_new(mut no: Order, b: &mut OrderBook, sink: &Sender<Event>, tx_md: &Sender<Event>) {
-    let mut remaining = no.qty;
-    match no.side {
-        Side::Bid => {
-            info!("[engine] ↕ Matching BID order against ASK levels...");
-            while remaining > 0 {
-                let Some((&ask_px, _)) = b.asks.iter().next() else {
-                    info!("[engine] No ASK levels available — resting remaining order.");
-                    break;
-                };
-                if no.price < ask_px {
-                    info!("[engine] Bid price {} < best ask {} — stop crossing.", no.price, ask_px);
-                    break;
-                }
+/// Every resting order of `cl_id`: by symbol, bids then asks best-first, FIFO within a level.
+fn open_orders(books: &HashMap<u32, OrderBook>, cl_id: u64) -> Event {
+    let mut symbols: Vec<u32> = books.keys().copied().collect();
+    symbols.sort_unstable();
+    let mut orders = Vec::new();
+    for symbol in symbols {
+        let b = &books[&symbol];
+        for (_, level) in b.bids.iter().rev().chain(b.asks.iter()) {
+            orders.extend(
+                level.orders.iter()
+                    .filter(|o| o.cl_id == cl_id)
+                    .map(|o| (symbol, o.id, o.side, o.price, o.total_qty)),
+            );
+        }
+    }
+    Event::OpenOrders { cl_id, orders }
+}
 
-                let q = b.asks.get_mut(&ask_px).expect("ask level must exist");
-                while remaining > 0 {
-                    let (maker_ord_id, maker_cl_id, fill, emptied) = {
-                        let Some(front) = q.front_mut() else { break; };
-                        let fill = remaining.min(front.qty);
-                        remaining -= fill;
-                        front.qty -= fill;
-                        let maker_ord_id = front.id;
-                        let maker_cl_id = front.cl_id;
-                        let emptied = front.qty == 0;
-                        (maker_ord_id, maker_cl_id, fill, emptied)
-                    };
-
-                    info!(price=ask_px, qty=fill, taker=no.id, maker=maker_ord_id,
-                          "[trade] 💥 TRADE");
-
-                    let trade = Event::Trade {
-                        price: ask_px,
-                        qty: fill,
-                        taker_cl_id: no.cl_id,
-                        maker_cl_id,
-                    };
-                    let _ = sink.send(trade.clone());
-                    let _ = tx_md.send(trade);
-
-                    if emptied {
-                        q.pop_front();
-                        info!("[book] Ask order {} fully filled and removed", maker_ord_id);
-                    }
-                }
+/// Aggregate the top `depth` price levels per side, best first, as of feed sequence `seq`.
+fn snapshot_book(symbol: u32, b: &OrderBook, depth: u16, seq: u64) -> Event {
+    let level = |(px, l): (i64, &Level)| (px, l.level_qty);
+    Event::Snapshot {
+        symbol,
+        bids: b.bids.iter().rev().take(depth as usize).map(level).collect(),
+        asks: b.asks.iter().take(depth as usize).map(level).collect(),
+        seq,
+    }
+}
 
-                if q.is_empty() {
-                    b.asks.remove(&ask_px);
-                    info!("[book] Ask level {} now empty and removed", ask_px);
-                }
+/// `snapshot_book` for the debug endpoint: levels with their order counts, top of book, spread and totals.
+fn book_view(symbol: u32, b: &OrderBook, depth: u16, seq: u64) -> BookView {
+    let level = |(price, l): (i64, &Level)| LevelView { price, qty: l.level_qty, orders: l.orders.len() };
+    let bids: Vec<LevelView> = b.bids.iter().rev().take(depth as usize).map(level).collect();
+    let asks: Vec<LevelView> = b.asks.iter().take(depth as usize).map(level).collect();
+    let best_bid = b.bids.highest().map(level);
+    let best_ask = b.asks.lowest().map(level);
+    BookView {
+        symbol,
+        seq,
+        state: b.state,
+        best_bid,
+        best_ask,
+        // Crossed only in auction, where a spread means nothing
+        spread: best_bid.zip(best_ask).and_then(|(bid, ask)| (ask.price >= bid.price).then(|| ask.price.abs_diff(bid.price))),
+        bid_orders: b.bids.iter().map(|(_, l)| l.orders.len()).sum(),
+        ask_orders: b.asks.iter().map(|(_, l)| l.orders.len()).sum(),
+        bids,
+        asks,
+    }
+}
 
-                let lvl_qty: u64 = b.asks
-                    .get(&ask_px)
-                    .map(|v| v.iter().map(|o| o.qty).sum::<u64>())
-                    .unwrap_or(0u64);
-                info!("[book] 📉 Ask Level Update => px={} qty={}", ask_px, lvl_qty);
-                let _ = tx_md.send(Event::BookDelta { side: Side::Ask, price: ask_px, level_qty: lvl_qty });
-            }
+/// Best resting price on the side an incoming `side` order would trade against.
+fn best_opposite(b: &OrderBook, side: Side) -> Option<i64> {
+    match side {
+        Side::Bid => b.asks.lowest().map(|(px, _)| px),
+        Side::Ask => b.bids.highest().map(|(px, _)| px),
+    }
+}
 
-            let ack_id = no.id;
-            if remaining > 0 && matches!(no.tif, Tif::Gtc) {
-                info!("[book] 📥 Resting BID order => id={} px={} qty={}", no.id, no.price, remaining);
-                let rest_px = no.price;
-                no.qty = remaining;
-                let entry = b.bids.entry(rest_px).or_insert_with(VecDeque::new);
-                entry.push_back(no);
-                b.lookup.insert(ack_id, (Side::Bid, rest_px));
+/// Whether `no` is willing to trade at resting price `px` (market orders always are).
+fn crosses(no: &Order, px: i64) -> bool {
+    match (no.ord_type, no.side) {
+        (OrderType::Market | OrderType::Stop, _) => true,
+        (OrderType::Limit | OrderType::StopLimit, Side::Bid) => no.price >= px,
+        (OrderType::Limit | OrderType::StopLimit, Side::Ask) => no.price <= px,
+    }
+}
 
-                let lvl_qty: u64 = entry.iter().map(|o| o.qty).sum();
-                info!("[book] 📈 Bid Level Update => px={} qty={}", rest_px, lvl_qty);
-                let _ = tx_md.send(Event::BookDelta { side: Side::Bid, price: rest_px, level_qty: lvl_qty });
+/// Dry run: how much of `no` could fill against the opposite side right now.
+/// Walks levels best-first and stops once the order is covered; never mutates the book.
+fn fillable_qty(no: &Order, b: &OrderBook) -> u64 {
+    let levels: Box<dyn Iterator<Item = (i64, &Level)>> = match no.side {
+        Side::Bid => b.asks.iter(),
+        Side::Ask => Box::new(b.bids.iter().rev()),
+    };
+    let mut avail = 0u64;
+    for (px, level) in levels {
+        if !crosses(no, px) { break; }
+        for o in &level.orders {
+            // Own orders never fill under STP: they're either pulled or halt the taker
+            if no.stp != Stp::Off && o.cl_id == no.cl_id {
+                if no.stp == Stp::CancelAggressing { return avail; }
+                continue;
             }
-
-            info!("[engine] ✅ Ack Bid Order id={}", ack_id);
-            let _ = sink.send(Event::Ack { ord_id: ack_id, note: "ok" });
+            avail += o.total_qty;
+            if avail >= no.qty { return avail; }
         }
+    }
+    avail
+}
 
-        Side::Ask => {
-            info!("[engine] ↕ Matching ASK order against BID levels...");
-            while remaining > 0 {
-                let Some((&bid_px, _)) = b.bids.iter().next_back() else {
-                    info!("[engine] No BID levels available — resting remaining order.");
-                    break;
-                };
-                if no.price > bid_px {
-                    info!("[engine] Ask price {} > best bid {} — stop crossing.", no.price, bid_px);
-                    break;
-                }
-
-                let q = b.bids.get_mut(&bid_px).expect("bid level must exist");
-                while remaining > 0 {
-                    let (maker_ord_id, maker_cl_id, fill, emptied) = {
-                        let Some(front) = q.front_mut() else { break; };
-                        let fill = remaining.min(front.qty);
-                        remaining -= fill;
-                        front.qty -= fill;
-                        let maker_ord_id = front.id;
-                        let maker_cl_id = front.cl_id;
-                        let emptied = front.qty == 0;
-                        (maker_ord_id, maker_cl_id, fill, emptied)
-                    };
-
-                    info!(price=bid_px, qty=fill, taker=no.id, maker=maker_ord_id,
-                          "[trade] 💥 TRADE");
-
-                    let trade = Event::Trade {
-                        price: bid_px,
-                        qty: fill,
-                        taker_cl_id: no.cl_id,
-                        maker_cl_id,
-                    };
-                    let _ = sink.send(trade.clone());
-                    let _ = tx_md.send(trade);
-
-                    if emptied {
-                        q.pop_front();
-                        info!("[book] Bid order {} fully filled and removed", maker_ord_id);
-                    }
-                }
+/// Pre-trade limits of `cl_id` for an order of `qty` on `side` adding `notional` to its resting
+/// orders. The position assumes a full fill.
+fn check_risk(b: &OrderBook, limit: &RiskLimit, cl_id: u64, side: Side, qty: u64, notional: u128) -> Result<(), RejectReason> {
+    let signed = match side {
+        Side::Bid => qty as i128,
+        Side::Ask => -(qty as i128),
+    };
+    if limit.max_position.is_some_and(|max| (b.tape.position(cl_id) as i128 + signed).unsigned_abs() > max as u128) {
+        return Err(RejectReason::RiskPosition);
+    }
+    if limit.max_notional.is_some_and(|max| b.lookup.notional(cl_id) + notional > max as u128) {
+        return Err(RejectReason::RiskNotional);
+    }
+    Ok(())
+}
 
-                if q.is_empty() {
-                    b.bids.remove(&bid_px);
-                    info!("[book] Bid level {} now empty and removed", bid_px);
-                }
+/// Open orders of `no`'s client on every book but `no`'s own, or 0 when it has no `max_orders` to check.
+fn open_elsewhere(books: &HashMap<u32, OrderBook>, no: &Order, risk: &RiskLimits) -> u64 {
+    if risk.get(&no.cl_id).is_none_or(|limit| limit.max_orders.is_none()) {
+        return 0;
+    }
+    books.iter().filter(|&(&symbol, _)| symbol != no.symbol).map(|(_, b)| b.open_orders(no.cl_id)).sum()
+}
 
-                let lvl_qty: u64 = b.bids
-                    .get(&bid_px)
-                    .map(|v| v.iter().map(|o| o.qty).sum::<u64>())
-                    .unwrap_or(0u64);
-                info!("[book] 📉 Bid Level Update => px={} qty={}", bid_px, lvl_qty);
-                let _ = tx_md.send(Event::BookDelta { side: Side::Bid, price: bid_px, level_qty: lvl_qty });
-            }
+/// A client's `max_orders` caps its open orders over every symbol: resting ones and pending stops.
+/// Only a new order that could take a slot is checked, against `elsewhere` plus what it has open on `b`;
+/// a stop firing or a replace re-entering keeps the slot it already had, so those go straight to `handle_new`.
+#[allow(clippy::too_many_arguments)]
+fn handle_new_capped(no: Order, b: &mut OrderBook, elsewhere: u64, sink: &ClientSink, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    // IOC, FOK and market orders never rest, so they don't count
+    let may_rest = match no.ord_type {
+        OrderType::Limit => matches!(no.tif, Tif::Gtc | Tif::Gtd | Tif::Day),
+        OrderType::Market => false,
+        OrderType::Stop | OrderType::StopLimit => true,
+    };
+    let max = tx_md.risk.get(&no.cl_id).and_then(|limit| limit.max_orders);
+    if may_rest && max.is_some_and(|max| elsewhere + b.open_orders(no.cl_id) >= max) {
+        warn!("[engine] ⚠️ Order {} rejected — client {} already has its max_orders open", no.id, no.cl_id);
+        tx_md.metrics.order_rejected();
+        tx_md.reply(sink, no.cl_id, Event::Reject { ord_id: no.id, reason: RejectReason::MaxOrders });
+        return;
+    }
+    handle_new(no, b, sink, owners, tx_md);
+}
 
-            let ack_id = no.id;
-            if remaining > 0 && matches!(no.tif, Tif::Gtc) {
-                info!("[book] 📥 Resting ASK order => id={} px={} qty={}", no.id, no.price, remaining);
-                let rest_px = no.price;
-                no.qty = remaining;
-                let entry = b.asks.entry(rest_px).or_insert_with(VecDeque::new);
-                entry.push_back(no);
-                b.lookup.insert(ack_id, (Side::Ask, rest_px));
+/// Fire the pending stops the last trade price has reached, oldest first, as their owners' orders.
+/// Their own fills move the price again, so this repeats until none is left to fire.
+fn trigger_stops(b: &mut OrderBook, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    loop {
+        let fired = b.stops.take_triggered(b.tape.reference());
+        if fired.is_empty() {
+            break;
+        }
+        for mut o in fired {
+            info!("[engine] 🎯 Stop {} triggered — trigger={} last={}", o.id, o.stop_price, b.tape.reference());
+            o.activate();
+            let sink = owners.get(&o.cl_id).cloned().unwrap_or_else(ClientSink::detached);
+            handle_new(o, b, &sink, owners, tx_md);
+        }
+    }
+}
 
-                let lvl_qty: u64 = entry.iter().map(|o| o.qty).sum();
-                info!("[book] 📈 Ask Level Update => px={} qty={}", rest_px, lvl_qty);
-                let _ = tx_md.send(Event::BookDelta { side: Side::Ask, price: rest_px, level_qty: lvl_qty });
+/// Ready `no` to enter `b`: every reason to refuse it before it trades, checked without touching the book.
+/// A triggered stop becomes the order it stands for and a reduce-only order is cut to the client's
+/// position; returns the qty cut. A stop still waiting for its trigger only gets the checks before that.
+fn precheck(no: &mut Order, b: &OrderBook, risk: &RiskLimits) -> Result<u64, RejectReason> {
+    if b.state == MarketState::Halted {
+        warn!("[engine] ⚠️ Order {} rejected — symbol {} is halted", no.id, no.symbol);
+        return Err(RejectReason::Halted);
+    }
+    // An auction only collects resting interest; nothing trades until the uncross
+    let auction = b.state == MarketState::Auction;
+    if auction && (no.ord_type != OrderType::Limit || !matches!(no.tif, Tif::Gtc | Tif::Gtd | Tif::Day)) {
+        warn!("[engine] ⚠️ Order {} rejected — symbol {} is in auction, only GTC/GTD/DAY limits rest", no.id, no.symbol);
+        return Err(RejectReason::AuctionRestingOnly);
+    }
+    // Off-increment prices and sizes never reach the book; a stop's trigger and an iceberg's slice included.
+    // On a fixed-point symbol the lot divides the qty scale, so sizes also stay on the scale's steps
+    let inc = b.increments;
+    if let Err(reason) = inc.check(no.price, no.qty).and_then(|_| inc.check(no.stop_price, no.display_qty)) {
+        warn!("[engine] ⚠️ Order {} rejected — px={} qty={} off the symbol's tick/lot", no.id, no.price, no.qty);
+        return Err(reason);
+    }
+    // A live order already owns this client id: resting both would orphan the first in `lookup`
+    if b.lookup.exch_id(no.cl_id, no.id).or(b.stops.exch_id(no.cl_id, no.id)).is_some() {
+        warn!("[engine] ⚠️ Order {} rejected — duplicate id for client {}", no.id, no.cl_id);
+        return Err(RejectReason::DupOrderId);
+    }
+    // A stop waits off the book for its trigger, unless the last trade has already reached it
+    if matches!(no.ord_type, OrderType::Stop | OrderType::StopLimit) {
+        if !Order::stop_reached(no.side, no.stop_price, b.tape.reference()) {
+            return Ok(0);
+        }
+        no.activate();
+    }
+    // Reduce-only: only what flattens the client's position here, so it can never grow or flip it
+    let mut trimmed = 0;
+    if no.reduce_only {
+        let position = b.tape.position(no.cl_id);
+        let reducible = match no.side {
+            Side::Bid => (-position).max(0) as u64,
+            Side::Ask => position.max(0) as u64,
+        };
+        if reducible == 0 {
+            warn!("[engine] ⚠️ Reduce-only order {} rejected — client {} has no position to reduce (position={})", no.id, no.cl_id, position);
+            return Err(RejectReason::ReduceOnly);
+        }
+        if no.qty > reducible {
+            trimmed = no.qty - reducible;
+            no.qty = reducible;
+            no.total_qty = reducible;
+            if no.display_qty >= reducible {
+                no.display_qty = 0;
             }
-
-            info!("[engine] ✅ Ack Ask Order id={}", ack_id);
-            let _ = sink.send(Event::Ack { ord_id: ack_id, note: "ok" });
         }
     }
+    // Fat-finger guard, before anything can trade
+    if no.ord_type == OrderType::Limit
+        && let Err(reason) = b.check_band(no.price)
+    {
+        warn!("[engine] ⚠️ Order {} rejected — px={} outside the price band (ref={})", no.id, no.price, b.tape.reference());
+        return Err(reason);
+    }
+    // A bounded backend has nowhere to put it, and no one could trade against it there
+    if no.ord_type == OrderType::Limit && !b.levels(no.side).accepts(no.price) {
+        warn!("[engine] ⚠️ Order {} rejected — price {} outside the book's range", no.id, no.price);
+        return Err(RejectReason::PriceOutOfRange);
+    }
+    if let Some(limit) = risk.get(&no.cl_id) {
+        // A market order has no price of its own; it's valued at the touch it would take
+        let px = match no.ord_type {
+            OrderType::Limit | OrderType::StopLimit => no.price,
+            OrderType::Market | OrderType::Stop => best_opposite(b, no.side).unwrap_or(0),
+        };
+        if let Err(reason) = check_risk(b, limit, no.cl_id, no.side, no.qty, px.unsigned_abs() as u128 * no.qty as u128) {
+            warn!("[engine] ⚠️ Order {} rejected — client {} over its {} limit", no.id, no.cl_id, reason.as_str());
+            return Err(reason);
+        }
+    }
+    if no.tif == Tif::Fok {
+        let avail = fillable_qty(no, b);
+        if avail < no.qty {
+            warn!("[engine] ⚠️ FOK order {} rejected — fillable={} < qty={}", no.id, avail, no.qty);
+            return Err(RejectReason::FokUnfilled);
+        }
+    }
+    if !auction && no.post_only && best_opposite(b, no.side).is_some_and(|px| crosses(no, px)) {
+        warn!("[engine] ⚠️ Post-only order {} rejected — would cross", no.id);
+        return Err(RejectReason::WouldCross);
+    }
+    // Only an order that trades nothing can rest this far out: one that crosses becomes the touch
+    let rests = no.ord_type == OrderType::Limit && matches!(no.tif, Tif::Gtc | Tif::Gtd | Tif::Day);
+    if rests
+        && (auction || !best_opposite(b, no.side).is_some_and(|px| crosses(no, px)))
+        && let Err(reason) = b.check_depth(no.side, no.price)
+    {
+        warn!("[engine] ⚠️ Order {} rejected — {:?} px={} beyond the best {:?} levels", no.id, no.side, no.price, b.max_depth);
+        return Err(reason);
+    }
+    Ok(trimmed)
 }
 
-/// Cancel an existing order by `ord_id`.
-fn handle_cancel(ord_id: u64, b: &mut OrderBook, tx_md: &Sender<Event>) -> bool {
-    info!("[engine] 🔍 Attempting to cancel order {}", ord_id);
-    if let Some((side, px)) = b.lookup.remove(&ord_id) {
-        let book_side = match side {
-            Side::Bid => &mut b.bids,
-            Side::Ask => &mut b.asks,
+/// Insert a new order. Makers it fills completely get their `Done` through `owners`.
+pub fn handle_new(no: Order, b: &mut OrderBook, sink: &ClientSink, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    match_new(no, b, sink, owners, tx_md);
+    debug_check(b);
+}
+
+/// Match (and maybe rest) a new order; every early return is a complete outcome.
+fn match_new(mut no: Order, b: &mut OrderBook, sink: &ClientSink, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    let cl_id = no.cl_id;
+    // Priority is the engine's own count, not the gateway's clock; a triggered stop or re-entered replace arrives anew
+    no.arrival_seq = b.next_arrival_seq;
+    b.next_arrival_seq += 1;
+    let trimmed = match precheck(&mut no, b, &tx_md.risk) {
+        Ok(trimmed) => trimmed,
+        Err(reason) => {
+            tx_md.metrics.order_rejected();
+            tx_md.reply(sink, cl_id, Event::Reject { ord_id: no.id, reason });
+            return;
+        }
+    };
+    // Still a stop after `precheck`: it waits off the book for its trigger
+    if matches!(no.ord_type, OrderType::Stop | OrderType::StopLimit) {
+        info!("[engine] ⏸️ Stop {} pending — {:?} trigger={} last={}", no.id, no.side, no.stop_price, b.tape.reference());
+        if no.tif == Tif::Gtd {
+            b.expiries.push(Reverse((no.expire_ms, no.exch_ord_id)));
+        }
+        tx_md.reply(sink, cl_id, Event::Ack { ord_id: no.id, note: AckNote::StopPending });
+        b.stops.insert(no);
+        return;
+    }
+    // The reduce-only cut is told only now, so a refused order gets its REJECT alone
+    if trimmed > 0 {
+        info!("[engine] ✂️ Reduce-only order {} trimmed by {} to the position's {}", no.id, trimmed, no.qty);
+        tx_md.reply(sink, cl_id, Event::Canceled { ord_id: no.id, unfilled_qty: trimmed, reason: DoneReason::ReduceOnly });
+    }
+    let auction = b.state == MarketState::Auction;
+
+    let symbol = no.symbol;
+    let mut remaining = no.qty;
+    let mut stp_halted = false;
+    let maker_side = no.side.opposite();
+
+    fill_log!("[engine] ↕ Matching {:?} order against {:?} levels...", no.side, maker_side);
+    // Each touched level with the qty it had before, as last published; an iceberg refill can bring the loop back to one
+    let mut touched: Vec<(i64, u64)> = Vec::new();
+    while !auction && remaining > 0 && !stp_halted {
+        let Some(px) = best_opposite(b, no.side) else {
+            fill_log!("[engine] No {:?} levels available — resting remaining order.", maker_side);
+            break;
         };
+        if !crosses(&no, px) {
+            fill_log!("[engine] {:?} price {} does not reach best {:?} {} — stop crossing.",
+                  no.side, no.price, maker_side, px);
+            break;
+        }
+
+        let OrderBook { bids, asks, lookup, policy, fees, tape, .. } = &mut *b;
+        let levels = match maker_side {
+            Side::Bid => bids,
+            Side::Ask => asks,
+        };
+        let q = levels.get_mut(px).expect("level must exist");
+        if touched.last().is_none_or(|&(last, _)| last != px) {
+            touched.push((px, q.level_qty));
+        }
+        stp_halted = match policy {
+            MatchPolicy::FifoPriceTime => match_level_fifo(&no, px, q, lookup, *fees, tape, &mut remaining, sink, owners, tx_md),
+            MatchPolicy::ProRata => match_level_allocated(&no, px, q, prorata_fills, lookup, *fees, tape, &mut remaining, sink, owners, tx_md),
+            MatchPolicy::SizeTime => match_level_allocated(&no, px, q, size_time_fills, lookup, *fees, tape, &mut remaining, sink, owners, tx_md),
+        };
+
+        tx_md.flush_print();
+        if q.is_empty() {
+            levels.remove(px);
+            fill_log!("[book] {:?} level {} now empty and removed", maker_side, px);
+        }
+    }
+    // One delta per level the sweep changed, with its final qty
+    for (px, published) in touched {
+        let lvl_qty: u64 = b.levels(maker_side).get(px).map_or(0, |l| l.level_qty);
+        if lvl_qty != published {
+            fill_log!("[book] 📉 {:?} Level Update => px={} qty={}", maker_side, px, lvl_qty);
+            tx_md.send(Event::BookDelta { symbol, side: maker_side, price: px, level_qty: lvl_qty, seq: tx_md.next_seq() });
+        }
+    }
 
-        if let Some(q) = book_side.get_mut(&px) {
-            if let Some(pos) = q.iter().position(|o| o.id == ord_id) {
-                q.remove(pos);
-                info!("[book] ❎ Order {} removed from {:?} px={}", ord_id, side, px);
+    let ack_id = no.id;
+    let exch_ord_id = no.exch_ord_id;
+    let ack_side = no.side;
+    let ack_px = no.price;
+    let filled_qty = no.qty - remaining;
+    if stp_halted {
+        warn!("[engine] ⚠️ Order {} canceled by STP, remainder={}", ack_id, remaining);
+        tx_md.reply(sink, cl_id, Event::StpCanceled { ord_id: ack_id, qty: remaining });
+        return;
+    }
+    // A market order never rests: one that found nothing is rejected, a partial fill ends like an IOC's
+    if remaining > 0 && no.ord_type == OrderType::Market && filled_qty == 0 {
+        warn!("[engine] ⚠️ Market order {} found no liquidity — rejected", ack_id);
+        tx_md.metrics.order_rejected();
+        tx_md.reply(sink, cl_id, Event::Reject { ord_id: ack_id, reason: RejectReason::Unfilled });
+        return;
+    }
+    let mut resting_qty = 0;
+    if remaining > 0 && no.ord_type != OrderType::Market && matches!(no.tif, Tif::Gtc | Tif::Gtd | Tif::Day) {
+        resting_qty = remaining;
+        fill_log!("[book] 📥 Resting {:?} order => id={} px={} qty={}", no.side, no.id, no.price, remaining);
+        let rest_side = no.side;
+        let rest_px = no.price;
+        no.total_qty = remaining;
+        no.qty = remaining;
+        if no.display_qty > 0 {
+            no.refill();
+        }
+        b.lookup.insert(&no);
+        if no.tif == Tif::Gtd {
+            b.expiries.push(Reverse((no.expire_ms, no.exch_ord_id)));
+        }
+        tx_md.send_order(Event::OrderAdd { symbol, ord_id: exch_ord_id, side: rest_side, price: rest_px, qty: no.qty, seq: 0 });
+        let entry = b.levels_mut(rest_side).level_mut(rest_px);
+        entry.insert(no);
 
-                let lvl_qty: u64 = q.iter().map(|o| o.qty).sum();
-                info!("[book] 📊 Level Update => side={:?} px={} qty={}", side, px, lvl_qty);
-                let _ = tx_md.send(Event::BookDelta { side, price: px, level_qty: lvl_qty });
+        let lvl_qty: u64 = entry.level_qty;
+        fill_log!("[book] 📈 {:?} Level Update => px={} qty={}", rest_side, rest_px, lvl_qty);
+        tx_md.send(Event::BookDelta { symbol, side: rest_side, price: rest_px, level_qty: lvl_qty, seq: tx_md.next_seq() });
+    }
 
-                if q.is_empty() {
-                    book_side.remove(&px);
-                    info!("[book] Level {} {:?} now empty — removed", px, side);
-                }
+    fill_log!("[engine] ✅ Accepted {:?} Order id={} filled={} resting={}", ack_side, ack_id, filled_qty, resting_qty);
+    tx_md.metrics.order_accepted();
+    tx_md.reply(sink, cl_id, Event::Accepted {
+        ord_id: ack_id,
+        exch_ord_id,
+        filled_qty,
+        resting_qty,
+        side: ack_side,
+        price: ack_px,
+    });
+    // IOC, FOK and market: what neither traded nor rested is gone, and the client hears how much
+    if remaining > resting_qty {
+        tx_md.reply(sink, cl_id, Event::Canceled { ord_id: ack_id, unfilled_qty: remaining - resting_qty, reason: DoneReason::IocRemainder });
+    }
+}
+
+/// Fill `no` against level `px` oldest order first. Returns true if STP halted the taker.
+#[allow(clippy::too_many_arguments)]
+fn match_level_fifo(
+    no: &Order,
+    px: i64,
+    q: &mut Level,
+    lookup: &mut Lookup,
+    fees: FeeSchedule,
+    tape: &mut Tape,
+    remaining: &mut u64,
+    sink: &ClientSink,
+    owners: &HashMap<u64, ClientSink>,
+    tx_md: &MdFeed,
+) -> bool {
+    while *remaining > 0 {
+        let Some(front) = q.orders.front_mut() else { break; };
+
+        // Self-trade prevention: the maker belongs to the aggressing client
+        if no.stp != Stp::Off && front.cl_id == no.cl_id {
+            if no.stp == Stp::CancelAggressing {
+                fill_log!("[engine] 🚫 STP — order {} would trade with own order {}, halting", no.id, front.id);
                 return true;
             }
+            let maker = q.pop_front().expect("front must exist");
+            lookup.remove(&maker);
+            tx_md.send_order(Event::OrderRemove { symbol: maker.symbol, ord_id: maker.exch_ord_id, seq: 0 });
+            fill_log!("[engine] 🚫 STP — canceled resting order {} qty={}", maker.id, maker.total_qty);
+            tx_md.reply(sink, maker.cl_id, Event::StpCanceled { ord_id: maker.id, qty: maker.total_qty });
+            continue;
+        }
+
+        let fill = (*remaining).min(front.qty);
+        *remaining -= fill;
+        front.qty -= fill;
+        front.total_qty -= fill;
+        q.level_qty -= fill;
+        lookup.reduce(front.cl_id, px, fill);
+        publish_trade(no, px, fill, front, fees, tape, sink, owners, tx_md);
+        tx_md.send_order(Event::OrderExecute { symbol: front.symbol, ord_id: front.exch_ord_id, qty: fill, seq: 0 });
+
+        if front.qty == 0 {
+            let mut maker = q.pop_front().expect("front must exist");
+            if maker.total_qty > 0 {
+                maker.refill();
+                tx_md.send_order(Event::OrderAdd { symbol: maker.symbol, ord_id: maker.exch_ord_id, side: maker.side, price: px, qty: maker.qty, seq: 0 });
+                fill_log!("[book] {:?} iceberg {} refilled to {} at the back of {}", no.side.opposite(), maker.id, maker.qty, px);
+                q.requeue(maker);
+            } else {
+                lookup.remove(&maker);
+                fill_log!("[book] {:?} order {} fully filled and removed", no.side.opposite(), maker.id);
+                maker_done(&maker, owners, tx_md);
+            }
+        }
+    }
+    false
+}
+
+/// Fill `no` against level `px` by size: `allocate` splits what the taker takes among the
+/// resting orders, given in queue order. It only looks at the level, so a replay allocates the same.
+/// STP is applied to the whole level before allocating. Returns true if STP halted the taker.
+#[allow(clippy::too_many_arguments)]
+fn match_level_allocated(
+    no: &Order,
+    px: i64,
+    q: &mut Level,
+    allocate: fn(&Level, u64) -> Vec<u64>,
+    lookup: &mut Lookup,
+    fees: FeeSchedule,
+    tape: &mut Tape,
+    remaining: &mut u64,
+    sink: &ClientSink,
+    owners: &HashMap<u64, ClientSink>,
+    tx_md: &MdFeed,
+) -> bool {
+    if no.stp != Stp::Off && q.orders.iter().any(|o| o.cl_id == no.cl_id) {
+        if no.stp == Stp::CancelAggressing {
+            fill_log!("[engine] 🚫 STP — order {} would trade with own orders at {}, halting", no.id, px);
+            return true;
+        }
+        while let Some(pos) = q.orders.iter().position(|o| o.cl_id == no.cl_id) {
+            let maker = q.remove(pos).expect("position is in range");
+            lookup.remove(&maker);
+            tx_md.send_order(Event::OrderRemove { symbol: maker.symbol, ord_id: maker.exch_ord_id, seq: 0 });
+            fill_log!("[engine] 🚫 STP — canceled resting order {} qty={}", maker.id, maker.total_qty);
+            tx_md.reply(sink, maker.cl_id, Event::StpCanceled { ord_id: maker.id, qty: maker.total_qty });
         }
     }
+
+    let total = q.level_qty;
+    let take = (*remaining).min(total);
+    if take == 0 {
+        return false;
+    }
+    let fills = allocate(q, take);
+
+    for (o, fill) in q.orders.iter_mut().zip(&fills) {
+        if *fill > 0 {
+            o.qty -= fill;
+            o.total_qty -= fill;
+            lookup.reduce(o.cl_id, px, *fill);
+            publish_trade(no, px, *fill, o, fees, tape, sink, owners, tx_md);
+            tx_md.send_order(Event::OrderExecute { symbol: o.symbol, ord_id: o.exch_ord_id, qty: *fill, seq: 0 });
+        }
+    }
+    q.level_qty -= take;
+    *remaining -= take;
+
+    // Icebergs whose slice is gone refill at the back, in their queue order
+    let mut refilled = Vec::new();
+    q.orders.retain(|o| {
+        if o.qty == 0 && o.total_qty > 0 {
+            refilled.push(o.clone());
+        } else if o.qty == 0 {
+            lookup.remove(o);
+            fill_log!("[book] {:?} order {} fully filled and removed", no.side.opposite(), o.id);
+            maker_done(o, owners, tx_md);
+        }
+        o.qty > 0
+    });
+    for mut o in refilled {
+        o.refill();
+        tx_md.send_order(Event::OrderAdd { symbol: o.symbol, ord_id: o.exch_ord_id, side: o.side, price: px, qty: o.qty, seq: 0 });
+        fill_log!("[book] {:?} iceberg {} refilled to {} at the back of {}", no.side.opposite(), o.id, o.qty, px);
+        q.requeue(o);
+    }
+    false
+}
+
+/// Pro-rata split of `take` over `q`: each maker gets floor(take * qty / level_qty), and the few
+/// units that rounding leaves over go one apiece to the largest makers, the oldest first among equal sizes.
+fn prorata_fills(q: &Level, take: u64) -> Vec<u64> {
+    let total = q.level_qty;
+    let mut fills: Vec<u64> = q.orders.iter()
+        .map(|o| (take as u128 * o.qty as u128 / total as u128) as u64)
+        .collect();
+    let mut leftover = take - fills.iter().sum::<u64>();
+    for i in by_size(q) {
+        if leftover == 0 {
+            break;
+        }
+        if fills[i] < q.orders[i].qty {
+            fills[i] += 1;
+            leftover -= 1;
+        }
+    }
+    fills
+}
+
+/// Size-time split of `take` over `q`: the largest maker fills first, then the next largest,
+/// the oldest first among equal sizes.
+fn size_time_fills(q: &Level, take: u64) -> Vec<u64> {
+    let mut fills = vec![0; q.orders.len()];
+    let mut left = take;
+    for i in by_size(q) {
+        if left == 0 {
+            break;
+        }
+        fills[i] = left.min(q.orders[i].qty);
+        left -= fills[i];
+    }
+    fills
+}
+
+/// Positions in `q`, largest visible qty first and the lowest `arrival_seq` first among equal sizes.
+fn by_size(q: &Level) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..q.orders.len()).collect();
+    order.sort_by_key(|&i| (Reverse(q.orders[i].qty), q.orders[i].arrival_seq));
+    order
+}
+
+/// Tell the owner of a maker that was just filled completely.
+fn maker_done(maker: &Order, owners: &HashMap<u64, ClientSink>, tx_md: &MdFeed) {
+    tx_md.notify(owners, maker.cl_id, Event::Done { ord_id: maker.id, reason: DoneReason::Filled });
+}
+
+/// One fill between taker `no` and `maker`, charged at `fees`: onto the book's tape, to both sides and to the market-data feed.
+/// The maker's copy goes through `owners`, and only if that is another connection than the taker's.
+#[allow(clippy::too_many_arguments)]
+fn publish_trade(
+    no: &Order,
+    px: i64,
+    fill: u64,
+    maker: &Order,
+    fees: FeeSchedule,
+    tape: &mut Tape,
+    sink: &ClientSink,
+    owners: &HashMap<u64, ClientSink>,
+    tx_md: &MdFeed,
+) {
+    fill_log!(price=px, qty=fill, taker=no.exch_ord_id, maker=maker.exch_ord_id,
+          "[trade] 💥 TRADE");
+
+    let (maker_fee, taker_fee) = fees.fees(px, fill);
+    let trade = Trade {
+        symbol: no.symbol,
+        price: px,
+        qty: fill,
+        taker_cl_id: no.cl_id,
+        maker_cl_id: maker.cl_id,
+        taker_ord_id: no.exch_ord_id,
+        maker_ord_id: maker.exch_ord_id,
+        aggressor_side: no.side,
+        maker_fee,
+        taker_fee,
+        seq: tx_md.next_seq(),
+        price_improvement: no.improvement(px),
+    };
+    tape.record(&trade);
+    tx_md.metrics.trade(fill);
+    tx_md.journal_trade(&trade);
+    tx_md.add_to_print(&trade);
+    sink.send(Event::Trade(trade));
+    if let Some(maker_sink) = owners.get(&maker.cl_id)
+        && !maker_sink.same_connection(sink)
+    {
+        maker_sink.send(Event::Trade(trade));
+    }
+    tx_md.send(Event::Trade(trade));
+}
+
+/// Cancel an existing order by `(cl_id, ord_id)`, handing back the removed order.
+/// Fails with `WrongOwner` if the id only exists under another client.
+pub fn handle_cancel(symbol: u32, cl_id: u64, ord_id: u64, b: &mut OrderBook, tx_md: &MdFeed) -> Result<Order, RejectReason> {
+    info!("[engine] 🔍 Attempting to cancel order {} for client {}", ord_id, cl_id);
+    if let Some(exch_ord_id) = b.lookup.exch_id(cl_id, ord_id)
+        && let Some(removed) = remove_order(symbol, exch_ord_id, b, tx_md)
+    {
+        return Ok(removed);
+    }
+    if let Some(exch_ord_id) = b.stops.exch_id(cl_id, ord_id)
+        && let Some(stop) = b.stops.remove(exch_ord_id)
+    {
+        info!("[book] ❎ Pending stop {} (exch {}) pulled", stop.id, exch_ord_id);
+        return Ok(stop);
+    }
+
+    if b.lookup.owned_by_other(cl_id, ord_id) {
+        warn!("[engine] ⚠️ Cancel failed — order {} belongs to another client", ord_id);
+        return Err(RejectReason::WrongOwner);
+    }
     warn!("[engine] ⚠️ Cancel failed — order {} not found", ord_id);
+    Err(RejectReason::NotFound)
+}
+
+/// Pull a resting order by exchange id and publish its level; `None` if it isn't resting.
+fn remove_order(symbol: u32, exch_ord_id: u64, b: &mut OrderBook, tx_md: &MdFeed) -> Option<Order> {
+    let (side, px) = b.lookup.get(exch_ord_id)?;
+    let book_side = b.levels_mut(side);
+    let q = book_side.get_mut(px)?;
+    let pos = q.orders.iter().position(|o| o.exch_ord_id == exch_ord_id)?;
+    let removed = q.remove(pos).expect("position must exist");
+    info!("[book] ❎ Order {} (exch {}) removed from {:?} px={}", removed.id, exch_ord_id, side, px);
+    tx_md.send_order(Event::OrderRemove { symbol, ord_id: exch_ord_id, seq: 0 });
+
+    let lvl_qty: u64 = q.level_qty;
+    info!("[book] 📊 Level Update => side={:?} px={} qty={}", side, px, lvl_qty);
+    tx_md.send(Event::BookDelta { symbol, side, price: px, level_qty: lvl_qty, seq: tx_md.next_seq() });
+
+    if q.is_empty() {
+        book_side.remove(px);
+        info!("[book] Level {} {:?} now empty — removed", px, side);
+    }
+    b.lookup.remove(&removed);
+    debug_check(b);
+    Some(removed)
+}
+
+/// Amend a resting order. Shrinking it at the same price keeps its queue position;
+/// a new price or a larger size is cancel + re-entry at the tail (and may trade on entry).
+/// Either it goes through whole or the order stays as it was: nothing that would refuse
+/// the re-entry is found out after the cancel.
+#[allow(clippy::too_many_arguments)]
+fn handle_replace(
+    symbol: u32,
+    cl_id: u64,
+    ord_id: u64,
+    new_price: i64,
+    new_qty: u64,
+    timestamp: u64,
+    b: &mut OrderBook,
+    sink: &ClientSink,
+    owners: &HashMap<u64, ClientSink>,
+    tx_md: &MdFeed,
+) -> Result<(), RejectReason> {
+    if b.state == MarketState::Halted {
+        return Err(RejectReason::Halted);
+    }
+    b.increments.check(new_price, new_qty)?;
+    let exch_ord_id = b.lookup.exch_id(cl_id, ord_id);
+    if let Some(id) = exch_ord_id
+        && let Some((side, px)) = b.lookup.get(id)
+        && px == new_price
+        && resting(b, side, px, id).is_some_and(|o| new_qty <= o.total_qty)
+    {
+        reduce_in_place(symbol, id, side, px, new_qty, b, tx_md);
+        tx_md.reply(sink, cl_id, Event::Replaced { ord_id, price: px, qty: new_qty, priority_kept: true });
+        return Ok(());
+    }
+
+    // Priority lost: the order goes back through matching as a fresh one. It is taken out quietly
+    // first, so the checks see the book as re-entry will; if they refuse, it goes back where it was
+    let Some(old) = exch_ord_id.or_else(|| b.stops.exch_id(cl_id, ord_id)).and_then(|id| take_quietly(b, id)) else {
+        return Err(if b.lookup.owned_by_other(cl_id, ord_id) { RejectReason::WrongOwner } else { RejectReason::NotFound });
+    };
+    let mut order = old.clone();
+    order.price = new_price;
+    order.qty = new_qty;
+    order.total_qty = new_qty;
+    order.timestamp = timestamp;
+    let mut entering = order.clone();
+    let checked = precheck(&mut entering, b, &tx_md.risk).and_then(|_| {
+        if stp_halts(&entering, b) { Err(RejectReason::SelfTrade) } else { Ok(()) }
+    });
+    if let Err(reason) = checked {
+        restore(b, old);
+        return Err(reason);
+    }
+
+    if exch_ord_id.is_some() {
+        let (side, px) = (old.side, old.price);
+        tx_md.send_order(Event::OrderRemove { symbol, ord_id: old.exch_ord_id, seq: 0 });
+        let lvl_qty = b.levels(side).get(px).map_or(0, |q| q.level_qty);
+        info!("[book] 📊 Level Update => side={:?} px={} qty={}", side, px, lvl_qty);
+        tx_md.send(Event::BookDelta { symbol, side, price: px, level_qty: lvl_qty, seq: tx_md.next_seq() });
+    }
+    info!("[book] ✏️ Order {} re-entered at px={} qty={} (priority lost)", ord_id, new_price, new_qty);
+    tx_md.reply(sink, cl_id, Event::Replaced { ord_id, price: new_price, qty: new_qty, priority_kept: false });
+    handle_new(order, b, sink, owners, tx_md);
+    Ok(())
+}
+
+/// Take a resting order or pending stop out of `b` without publishing anything, for `restore`
+/// to put back unless whoever took it publishes the removal.
+fn take_quietly(b: &mut OrderBook, exch_ord_id: u64) -> Option<Order> {
+    let Some((side, px)) = b.lookup.get(exch_ord_id) else {
+        return b.stops.remove(exch_ord_id);
+    };
+    let levels = b.levels_mut(side);
+    let q = levels.get_mut(px)?;
+    let pos = q.orders.iter().position(|o| o.exch_ord_id == exch_ord_id)?;
+    let o = q.remove(pos).expect("position must exist");
+    if q.is_empty() {
+        levels.remove(px);
+    }
+    b.lookup.remove(&o);
+    Some(o)
+}
+
+/// Undo `take_quietly`: the order goes back to its place in the queue, or among the stops.
+fn restore(b: &mut OrderBook, o: Order) {
+    if matches!(o.ord_type, OrderType::Stop | OrderType::StopLimit) {
+        b.stops.insert(o);
+        return;
+    }
+    b.lookup.insert(&o);
+    b.levels_mut(o.side).level_mut(o.price).restore(o);
+}
+
+/// Whether cancel-aggressing STP would stop `no` on one of its client's own orders before it is filled.
+/// Walks the opposite side like `fillable_qty`; the allocating policies stop at a level holding any of them.
+/// A pending stop and an order collected by an auction trade nothing yet.
+fn stp_halts(no: &Order, b: &OrderBook) -> bool {
+    if no.stp != Stp::CancelAggressing
+        || b.state == MarketState::Auction
+        || matches!(no.ord_type, OrderType::Stop | OrderType::StopLimit)
+    {
+        return false;
+    }
+    let levels: Box<dyn Iterator<Item = (i64, &Level)>> = match no.side {
+        Side::Bid => b.asks.iter(),
+        Side::Ask => Box::new(b.bids.iter().rev()),
+    };
+    let mut avail = 0u64;
+    for (px, level) in levels {
+        if !crosses(no, px) {
+            break;
+        }
+        if b.policy != MatchPolicy::FifoPriceTime && level.orders.iter().any(|o| o.cl_id == no.cl_id) {
+            return true;
+        }
+        for o in &level.orders {
+            if o.cl_id == no.cl_id {
+                return true;
+            }
+            avail += o.total_qty;
+            if avail >= no.qty {
+                return false;
+            }
+        }
+    }
     false
 }
+
+/// Cut an order to `new_qty` without touching its price or priority, like a replace that
+/// keeps both; growing it is refused. A pending stop keeps its place among the stops.
+/// Allowed while halted, as it only takes liquidity away.
+fn handle_reduce(
+    symbol: u32,
+    cl_id: u64,
+    ord_id: u64,
+    new_qty: u64,
+    b: &mut OrderBook,
+    sink: &ClientSink,
+    tx_md: &MdFeed,
+) -> Result<(), RejectReason> {
+    if let Some(id) = b.lookup.exch_id(cl_id, ord_id)
+        && let Some((side, px)) = b.lookup.get(id)
+        && let Some(o) = resting(b, side, px, id)
+    {
+        if new_qty >= o.total_qty {
+            return Err(RejectReason::ReduceOnlyIncrease);
+        }
+        b.increments.check(px, new_qty)?;
+        reduce_in_place(symbol, id, side, px, new_qty, b, tx_md);
+        tx_md.reply(sink, cl_id, Event::Replaced { ord_id, price: px, qty: new_qty, priority_kept: true });
+        return Ok(());
+    }
+    if let Some(id) = b.stops.exch_id(cl_id, ord_id)
+        && let Some(mut stop) = b.stops.remove(id)
+    {
+        let res = if new_qty >= stop.total_qty {
+            Err(RejectReason::ReduceOnlyIncrease)
+        } else {
+            b.increments.check(stop.price, new_qty)
+        };
+        if res.is_ok() {
+            stop.qty = new_qty;
+            stop.total_qty = new_qty;
+            info!("[book] ✏️ Pending stop {} reduced to qty={}", ord_id, new_qty);
+            tx_md.reply(sink, cl_id, Event::Replaced { ord_id, price: stop.price, qty: new_qty, priority_kept: true });
+        }
+        b.stops.insert(stop);
+        return res;
+    }
+    if b.lookup.owned_by_other(cl_id, ord_id) {
+        return Err(RejectReason::WrongOwner);
+    }
+    Err(RejectReason::NotFound)
+}
+
+/// The resting order `exch_ord_id`, which `lookup` places at `px` on `side`.
+fn resting(b: &OrderBook, side: Side, px: i64, exch_ord_id: u64) -> Option<&Order> {
+    b.levels(side).get(px)?.orders.iter().find(|o| o.exch_ord_id == exch_ord_id)
+}
+
+/// Shrink a resting order to `new_qty` where it stands, so it keeps its queue position,
+/// and publish its level. An iceberg keeps showing its slice, if the new size still covers it.
+fn reduce_in_place(symbol: u32, exch_ord_id: u64, side: Side, px: i64, new_qty: u64, b: &mut OrderBook, tx_md: &MdFeed) {
+    let q = b.levels_mut(side).get_mut(px).expect("lookup places the order on this level");
+    let o = q.orders.iter_mut().find(|o| o.exch_ord_id == exch_ord_id).expect("lookup places the order on this level");
+    let cut = o.total_qty - new_qty;
+    let shown = o.qty.min(new_qty);
+    if shown < o.qty {
+        tx_md.send_order(Event::OrderReduce { symbol, ord_id: exch_ord_id, qty: o.qty - shown, seq: 0 });
+    }
+    q.level_qty -= o.qty - shown;
+    o.qty = shown;
+    o.total_qty = new_qty;
+    let cl_id = o.cl_id;
+    info!("[book] ✏️ Order {} reduced in place to qty={} (priority kept)", o.id, new_qty);
+
+    let lvl_qty = q.level_qty;
+    info!("[book] 📊 Level Update => side={:?} px={} qty={}", side, px, lvl_qty);
+    tx_md.send(Event::BookDelta { symbol, side, price: px, level_qty: lvl_qty, seq: tx_md.next_seq() });
+    b.lookup.reduce(cl_id, px, cut);
+    debug_check(b);
+}
+
+/// Cancel-all across one symbol, or every symbol when `symbol` is `None`. Returns the ids of the orders that went.
+fn cancel_all(books: &mut HashMap<u32, OrderBook>, cl_id: u64, symbol: Option<u32>, tx_md: &MdFeed) -> Vec<u64> {
+    let mut symbols: Vec<u32> = match symbol {
+        Some(s) => vec![s],
+        None => books.keys().copied().collect(),
+    };
+    symbols.sort_unstable();
+    symbols
+        .into_iter()
+        .filter_map(|s| books.get_mut(&s).map(|b| with_bbo(s, b, tx_md, |b| handle_cancel_all(s, cl_id, b, tx_md))))
+        .flatten()
+        .collect()
+}
+
+/// Pull every resting order and pending stop of `cl_id` from one book.
+/// Emits one `BookDelta` per touched level rather than one per order. Returns the pulled orders' ids.
+fn handle_cancel_all(symbol: u32, cl_id: u64, b: &mut OrderBook, tx_md: &MdFeed) -> Vec<u64> {
+    let mine: Vec<u64> = b.lookup.of_client(cl_id).collect();
+    let stops: Vec<u64> = b.stops.of_client(cl_id).collect();
+    let mut pulled = Vec::with_capacity(mine.len() + stops.len());
+    for &exch_ord_id in &stops {
+        pulled.extend(b.stops.remove(exch_ord_id).map(|o| o.id));
+    }
+
+    let mut touched: BTreeSet<(Side, i64)> = BTreeSet::new();
+    for &exch_ord_id in &mine {
+        let Some((side, px)) = b.lookup.get(exch_ord_id) else { continue };
+        if let Some(q) = b.levels_mut(side).get_mut(px)
+            && let Some(pos) = q.orders.iter().position(|o| o.exch_ord_id == exch_ord_id)
+        {
+            let o = q.remove(pos).expect("position must exist");
+            b.lookup.remove(&o);
+            tx_md.send_order(Event::OrderRemove { symbol, ord_id: exch_ord_id, seq: 0 });
+            pulled.push(o.id);
+            touched.insert((side, px));
+        }
+    }
+
+    for (side, px) in touched {
+        let levels = b.levels_mut(side);
+        let lvl_qty = levels.get(px).map_or(0, |q| q.level_qty);
+        info!("[book] 📊 Level Update => side={:?} px={} qty={}", side, px, lvl_qty);
+        tx_md.send(Event::BookDelta { symbol, side, price: px, level_qty: lvl_qty, seq: tx_md.next_seq() });
+        if levels.get(px).is_some_and(Level::is_empty) {
+            levels.remove(px);
+            info!("[book] Level {} {:?} now empty — removed", px, side);
+        }
+    }
+
+    debug_check(b);
+    info!("[book] 🧹 Client {} pulled {} orders and {} stops from symbol {}", cl_id, mine.len(), stops.len(), symbol);
+    pulled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use tokio::sync::mpsc;
+    use crate::sink;
+    use crate::book::LadderSpec;
+    use crate::types::{Increments, PriceBand, QtyScale};
+
+    /// A GTC limit order with exchange id `exch`, as `dispatch` would hand it over.
+    fn limit(cl_id: u64, id: u64, exch: u64, side: Side, price: i64, qty: u64) -> Order {
+        Order {
+            id,
+            exch_ord_id: exch,
+            cl_id,
+            symbol: 0,
+            side,
+            price,
+            qty,
+            timestamp: 0,
+            arrival_seq: 0,
+            queue_seq: 0,
+            tif: Tif::Gtc,
+            ord_type: OrderType::Limit,
+            post_only: false,
+            reduce_only: false,
+            stp: Stp::Off,
+            expire_ms: 0,
+            display_qty: 0,
+            total_qty: qty,
+            stop_price: 0,
+        }
+    }
+
+    /// One book with a client connection per `cl_id`, and the market-data feed they trade on.
+    struct Venue {
+        book: OrderBook,
+        md: MdFeed,
+        feed: broadcast::Receiver<Event>,
+        owners: HashMap<u64, ClientSink>,
+        inbox: HashMap<u64, mpsc::Receiver<Event>>,
+        next_exch: u64,
+    }
+
+    impl Venue {
+        fn new(book: OrderBook) -> Venue {
+            let (tx, feed) = broadcast::channel(4096);
+            Venue { book, md: MdFeed::new(tx), feed, owners: HashMap::new(), inbox: HashMap::new(), next_exch: 1 }
+        }
+
+        /// Check new orders against `risk`, as `run_engine` does with its limits.
+        fn risk_limits(mut self, risk: RiskLimits) -> Venue {
+            let md = std::mem::replace(&mut self.md, MdFeed::new(broadcast::channel(1).0));
+            self.md = md.risk_limits(Arc::new(risk));
+            self
+        }
+
+        fn sink(&mut self, cl_id: u64) -> ClientSink {
+            let inbox = &mut self.inbox;
+            self.owners
+                .entry(cl_id)
+                .or_insert_with(|| {
+                    let (sink, rx) = sink::channel(4096, u32::MAX, cl_id);
+                    inbox.insert(cl_id, rx);
+                    sink
+                })
+                .clone()
+        }
+
+        /// Send `o` in under the next exchange id and return what its client heard.
+        fn new_order(&mut self, mut o: Order) -> Vec<Event> {
+            o.exch_ord_id = self.next_exch;
+            self.next_exch += 1;
+            let sink = self.sink(o.cl_id);
+            let cl_id = o.cl_id;
+            handle_new_capped(o, &mut self.book, 0, &sink, &self.owners, &self.md);
+            self.book.check_invariants().expect("book invariants");
+            self.events(cl_id)
+        }
+
+        fn replace(&mut self, cl_id: u64, ord_id: u64, new_price: i64, new_qty: u64) -> Result<Vec<Event>, RejectReason> {
+            let sink = self.sink(cl_id);
+            let res = handle_replace(0, cl_id, ord_id, new_price, new_qty, 0, &mut self.book, &sink, &self.owners, &self.md);
+            self.book.check_invariants().expect("book invariants");
+            res.map(|_| self.events(cl_id))
+        }
+
+        fn cancel(&mut self, cl_id: u64, ord_id: u64) -> Result<Order, RejectReason> {
+            let res = handle_cancel(0, cl_id, ord_id, &mut self.book, &self.md);
+            self.book.check_invariants().expect("book invariants");
+            res
+        }
+
+        fn reduce(&mut self, cl_id: u64, ord_id: u64, new_qty: u64) -> Result<Vec<Event>, RejectReason> {
+            let sink = self.sink(cl_id);
+            let res = handle_reduce(0, cl_id, ord_id, new_qty, &mut self.book, &sink, &self.md);
+            self.book.check_invariants().expect("book invariants");
+            res.map(|_| self.events(cl_id))
+        }
+
+        /// Everything client `cl_id` has been sent so far.
+        fn events(&mut self, cl_id: u64) -> Vec<Event> {
+            let Some(rx) = self.inbox.get_mut(&cl_id) else {
+                return Vec::new();
+            };
+            std::iter::from_fn(|| rx.try_recv().ok()).collect()
+        }
+
+        /// Market data published since the last look.
+        fn feed(&mut self) -> Vec<Event> {
+            std::iter::from_fn(|| self.feed.try_recv().ok()).collect()
+        }
+
+        /// Trades published on the feed since the last look.
+        fn trades(&mut self) -> Vec<Trade> {
+            self.feed().into_iter().filter_map(|e| if let Event::Trade(t) = e { Some(t) } else { None }).collect()
+        }
+
+        /// Client ids of the resting orders at `side` `px`, front of the queue first.
+        fn queue(&self, side: Side, px: i64) -> Vec<(u64, u64)> {
+            self.book.levels(side).get(px).map_or(Vec::new(), |q| q.orders.iter().map(|o| (o.cl_id, o.id)).collect())
+        }
+    }
+
+    #[test]
+    fn reduce_only_sell_is_trimmed_to_the_long_position() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(2, 1, 0, Side::Ask, 100, 10));
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 10));
+        assert_eq!(v.trades().len(), 1);
+        assert_eq!(v.book.tape.position(1), 10);
+
+        let mut sell = limit(1, 2, 0, Side::Ask, 105, 25);
+        sell.reduce_only = true;
+        let events = v.new_order(sell);
+        assert!(matches!(events[0], Event::Canceled { ord_id: 2, unfilled_qty: 15, reason: DoneReason::ReduceOnly }), "{events:?}");
+        assert!(matches!(events[1], Event::Accepted { ord_id: 2, filled_qty: 0, resting_qty: 10, .. }), "{events:?}");
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn refused_reduce_only_order_gets_no_trim_notice() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(2, 1, 0, Side::Ask, 100, 10));
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 10));
+        v.new_order(limit(3, 1, 0, Side::Bid, 99, 5));
+
+        // Post-only and crossing the bid at 99
+        let mut sell = limit(1, 2, 0, Side::Ask, 99, 25);
+        sell.reduce_only = true;
+        sell.post_only = true;
+        let events = v.new_order(sell);
+        assert!(matches!(events[..], [Event::Reject { ord_id: 2, reason: RejectReason::WouldCross }]), "{events:?}");
+
+        // Beyond the max depth
+        v.book.max_depth = Some(1);
+        let mut sell = limit(1, 3, 0, Side::Ask, 120, 25);
+        sell.reduce_only = true;
+        v.new_order(limit(4, 1, 0, Side::Ask, 110, 5));
+        let events = v.new_order(sell);
+        assert!(matches!(events[..], [Event::Reject { ord_id: 3, reason: RejectReason::TooDeep }]), "{events:?}");
+    }
+
+    #[test]
+    fn replace_down_at_the_same_price_keeps_priority() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 10));
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 10));
+        let events = v.replace(1, 1, 100, 4).unwrap();
+        assert!(matches!(events[..], [Event::Replaced { ord_id: 1, price: 100, qty: 4, priority_kept: true }]), "{events:?}");
+        assert_eq!(v.queue(Side::Bid, 100), [(1, 1), (2, 1)]);
+
+        v.feed();
+        v.new_order(limit(3, 1, 0, Side::Ask, 100, 4));
+        let trades = v.trades();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].maker_cl_id, trades[0].qty), (1, 4));
+    }
+
+    #[test]
+    fn replace_up_or_to_a_new_price_loses_priority() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 10));
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 10));
+        let events = v.replace(1, 1, 100, 12).unwrap();
+        assert!(matches!(events[0], Event::Replaced { ord_id: 1, price: 100, qty: 12, priority_kept: false }), "{events:?}");
+        assert!(matches!(events[1], Event::Accepted { ord_id: 1, resting_qty: 12, .. }), "{events:?}");
+        assert_eq!(v.queue(Side::Bid, 100), [(2, 1), (1, 1)]);
+
+        v.replace(2, 1, 101, 10).unwrap();
+        assert!(v.book.levels(Side::Bid).get(101).is_some());
+        assert_eq!(v.queue(Side::Bid, 100), [(1, 1)]);
+    }
+
+    #[test]
+    fn refused_reentry_leaves_the_order_where_it_was() {
+        let mut v = Venue::new(OrderBook::default());
+        let mut first = limit(1, 1, 0, Side::Bid, 100, 10);
+        first.stp = Stp::CancelAggressing;
+        v.new_order(first);
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 10));
+        let mut post = limit(1, 3, 0, Side::Bid, 99, 10);
+        post.post_only = true;
+        v.new_order(post);
+        v.new_order(limit(3, 1, 0, Side::Ask, 102, 10));
+        v.new_order(limit(1, 2, 0, Side::Ask, 105, 10));
+        v.events(1);
+        v.feed();
+
+        // Post-only, now crossing the ask at 102
+        assert_eq!(v.replace(1, 3, 102, 10).unwrap_err(), RejectReason::WouldCross);
+        // Past the ask at 102 it would run into its own ask at 105
+        assert_eq!(v.replace(1, 1, 105, 20).unwrap_err(), RejectReason::SelfTrade);
+        // Behind the bid at 99 with only one level allowed
+        v.book.max_depth = Some(1);
+        assert_eq!(v.replace(1, 1, 97, 10).unwrap_err(), RejectReason::TooDeep);
+
+        // Nothing was published or told, and the orders kept their places
+        assert!(v.feed().is_empty());
+        assert!(v.events(1).is_empty());
+        assert_eq!(v.queue(Side::Bid, 100), [(1, 1), (2, 1)]);
+        assert_eq!(v.queue(Side::Bid, 99), [(1, 3)]);
+        assert_eq!(v.book.lookup.resting(1), 3);
+    }
+
+    #[test]
+    fn clients_can_reuse_each_others_order_ids() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 7, 0, Side::Bid, 100, 10));
+        v.new_order(limit(2, 7, 0, Side::Bid, 99, 10));
+        assert_eq!(v.book.lookup.resting(1), 1);
+        assert_eq!(v.book.lookup.resting(2), 1);
+
+        assert_eq!(v.cancel(3, 7).unwrap_err(), RejectReason::WrongOwner);
+        let canceled = v.cancel(1, 7).unwrap();
+        assert_eq!((canceled.cl_id, canceled.price), (1, 100));
+        assert_eq!(v.queue(Side::Bid, 99), [(2, 7)]);
+
+        // Client 2's order 7 is still there, and it is not client 1's to touch
+        assert_eq!(v.cancel(1, 7).unwrap_err(), RejectReason::WrongOwner);
+        assert_eq!(v.replace(1, 7, 99, 5).unwrap_err(), RejectReason::WrongOwner);
+        v.cancel(2, 7).unwrap();
+        assert_eq!(v.cancel(2, 7).unwrap_err(), RejectReason::NotFound);
+    }
+
+    #[test]
+    fn fractional_quantities_rest_and_match_as_raw_units() {
+        // 1e6 raw units per unit, traded in lots of 0.1
+        let book = OrderBook { qty_scale: QtyScale(1_000_000), increments: Increments { tick_size: 1, lot_size: 100_000 }, ..Default::default() };
+        assert!(book.qty_scale.fits(book.increments.lot_size));
+        let mut v = Venue::new(book);
+
+        // 0.5 units
+        let events = v.new_order(limit(1, 1, 0, Side::Ask, 100, 500_000));
+        assert!(matches!(events[..], [Event::Accepted { resting_qty: 500_000, .. }]), "{events:?}");
+        assert_eq!(v.book.levels(Side::Ask).get(100).unwrap().orders[0].qty, 500_000);
+        assert_eq!(v.book.qty_scale.render(500_000), "0.5");
+
+        // 0.55 units is off the scale's 0.1 steps
+        let events = v.new_order(limit(2, 1, 0, Side::Bid, 100, 550_000));
+        assert!(matches!(events[..], [Event::Reject { reason: RejectReason::BadLot, .. }]), "{events:?}");
+
+        // 1.5 units takes the 0.5 and rests the other 1.0
+        v.feed();
+        let events = v.new_order(limit(2, 2, 0, Side::Bid, 100, 1_500_000));
+        assert!(events.iter().any(|e| matches!(e, Event::Accepted { filled_qty: 500_000, resting_qty: 1_000_000, .. })), "{events:?}");
+        let trades = v.trades();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].maker_cl_id, trades[0].qty), (1, 500_000));
+        assert!(v.book.levels(Side::Ask).get(100).is_none());
+    }
+
+    #[test]
+    fn sweeping_one_price_publishes_one_trade_print() {
+        let mut v = Venue::new(OrderBook::default());
+        for (cl_id, qty) in [(1, 5), (2, 7), (3, 9)] {
+            v.new_order(limit(cl_id, 1, 0, Side::Ask, 100, qty));
+        }
+        v.new_order(limit(4, 1, 0, Side::Ask, 101, 10));
+        v.feed();
+
+        v.new_order(limit(9, 1, 0, Side::Bid, 101, 25));
+        let feed = v.feed();
+        let fills: Vec<_> = feed.iter().filter_map(|e| if let Event::Trade(t) = e { Some((t.maker_cl_id, t.price, t.qty)) } else { None }).collect();
+        assert_eq!(fills, [(1, 100, 5), (2, 100, 7), (3, 100, 9), (4, 101, 4)]);
+        let prints: Vec<Trade> = feed.iter().filter_map(|e| if let Event::TradePrint(p) = e { Some(*p) } else { None }).collect();
+        assert_eq!(prints.len(), 2, "{feed:?}");
+        // One print for the three makers at 100, which no longer names a single maker
+        assert_eq!((prints[0].price, prints[0].qty, prints[0].taker_cl_id, prints[0].maker_cl_id, prints[0].maker_ord_id), (100, 21, 9, 0, 0));
+        assert_eq!((prints[1].price, prints[1].qty, prints[1].maker_cl_id), (101, 4, 4));
+        // Each print follows its last fill and takes its seq
+        let trade_seqs: Vec<u64> = feed.iter().filter_map(|e| if let Event::Trade(t) = e { Some(t.seq) } else { None }).collect();
+        assert_eq!((prints[0].seq, prints[1].seq), (trade_seqs[2], trade_seqs[3]));
+    }
+
+    #[test]
+    fn reduce_keeps_priority_and_only_goes_down() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 10));
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 10));
+
+        assert_eq!(v.reduce(1, 1, 10).unwrap_err(), RejectReason::ReduceOnlyIncrease);
+        assert_eq!(v.reduce(1, 1, 11).unwrap_err(), RejectReason::ReduceOnlyIncrease);
+        assert_eq!(v.reduce(1, 9, 5).unwrap_err(), RejectReason::NotFound);
+        let events = v.reduce(1, 1, 3).unwrap();
+        assert!(matches!(events[..], [Event::Replaced { ord_id: 1, price: 100, qty: 3, priority_kept: true }]), "{events:?}");
+        assert_eq!(v.queue(Side::Bid, 100), [(1, 1), (2, 1)]);
+        assert_eq!(v.book.levels(Side::Bid).get(100).unwrap().level_qty, 13);
+
+        // The reduced order still trades first
+        v.feed();
+        v.new_order(limit(3, 1, 0, Side::Ask, 100, 5));
+        let fills: Vec<_> = v.trades().iter().map(|t| (t.maker_cl_id, t.qty)).collect();
+        assert_eq!(fills, [(1, 3), (2, 2)]);
+    }
+
+    #[test]
+    fn trades_report_how_far_they_beat_the_taker_limit() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 102, 5));
+        v.new_order(limit(1, 2, 0, Side::Ask, 105, 5));
+        v.new_order(limit(2, 1, 0, Side::Bid, 95, 5));
+        v.feed();
+
+        v.new_order(limit(3, 1, 0, Side::Bid, 105, 10));
+        let improvement: Vec<_> = v.trades().iter().map(|t| (t.price, t.price_improvement)).collect();
+        assert_eq!(improvement, [(102, 3), (105, 0)]);
+
+        // A sell limited at 90 hitting the bid at 95, and a market sell, which has no limit to beat
+        v.new_order(limit(4, 1, 0, Side::Ask, 90, 2));
+        let mut market = limit(4, 2, 0, Side::Ask, 0, 2);
+        (market.ord_type, market.tif) = (OrderType::Market, Tif::Ioc);
+        v.new_order(market);
+        let improvement: Vec<_> = v.trades().iter().map(|t| (t.price, t.price_improvement)).collect();
+        assert_eq!(improvement, [(95, 5), (95, 0)]);
+    }
+
+    #[test]
+    fn negative_prices_cross_in_price_order() {
+        for ladder in [false, true] {
+            let mut book = OrderBook::default();
+            if ladder {
+                book.use_ladder(LadderSpec { min: -20, max: 20, tick: 1 }).unwrap();
+            }
+            let mut v = Venue::new(book);
+            v.new_order(limit(1, 1, 0, Side::Ask, -3, 5));
+            v.new_order(limit(1, 2, 0, Side::Ask, -5, 5));
+            v.new_order(limit(2, 1, 0, Side::Bid, -10, 5));
+            assert_eq!(v.book.bbo(), (Some((-10, 5)), Some((-5, 5))), "ladder={ladder}");
+            v.feed();
+
+            // A bid at -4 takes the ask at -5 but not the one at -3, then rests
+            v.new_order(limit(3, 1, 0, Side::Bid, -4, 8));
+            let fills: Vec<_> = v.trades().iter().map(|t| (t.price, t.qty, t.price_improvement)).collect();
+            assert_eq!(fills, [(-5, 5, 1)], "ladder={ladder}");
+            assert_eq!(v.book.bbo(), (Some((-4, 3)), Some((-3, 5))), "ladder={ladder}");
+
+            // A sell down to -10 hits the higher bid first
+            v.new_order(limit(4, 1, 0, Side::Ask, -10, 6));
+            let fills: Vec<_> = v.trades().iter().map(|t| (t.price, t.qty)).collect();
+            assert_eq!(fills, [(-4, 3), (-10, 3)], "ladder={ladder}");
+            assert_eq!(v.book.bbo(), (Some((-10, 2)), Some((-3, 5))), "ladder={ladder}");
+        }
+    }
+
+    #[test]
+    fn crossing_order_hears_its_trade_then_its_ack() {
+        use crate::protocol::{decode_event, decode_frame, encode_event, FrameFormat, MSG_ACCEPTED, MSG_TRADE};
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 10));
+        let events = v.new_order(limit(2, 1, 0, Side::Bid, 100, 4));
+
+        // Framed as the gateway writes them to the taker's socket
+        let fmt = FrameFormat::default();
+        let frames: Vec<(u16, Event)> = events
+            .iter()
+            .map(|e| {
+                let frame = decode_frame(&mut encode_event(e, fmt), fmt).unwrap().unwrap();
+                (frame.msg_type, decode_event(frame.msg_type, &frame.body, fmt.order).unwrap())
+            })
+            .collect();
+        assert!(
+            matches!(&frames[..], [(MSG_TRADE, Event::Trade(t)), (MSG_ACCEPTED, Event::Accepted { ord_id: 1, filled_qty: 4, resting_qty: 0, .. })]
+                if (t.taker_cl_id, t.maker_cl_id, t.price, t.qty) == (2, 1, 100, 4)),
+            "{frames:?}"
+        );
+    }
+
+    #[test]
+    fn market_order_remainder_is_canceled_after_its_fills() {
+        let market = |cl_id, id, side, qty| {
+            let mut o = limit(cl_id, id, 0, side, 0, qty);
+            (o.ord_type, o.tif) = (OrderType::Market, Tif::Ioc);
+            o
+        };
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 4));
+        v.new_order(limit(1, 2, 0, Side::Ask, 105, 6));
+
+        // Sweeps both levels whatever their price
+        let events = v.new_order(market(2, 1, Side::Bid, 10));
+        assert!(matches!(events.last(), Some(Event::Accepted { ord_id: 1, filled_qty: 10, resting_qty: 0, .. })), "{events:?}");
+        assert!(v.book.levels(Side::Ask).get(105).is_none());
+
+        v.new_order(limit(1, 3, 0, Side::Ask, 110, 3));
+        let events = v.new_order(market(2, 2, Side::Bid, 10));
+        let tail = &events[events.len() - 2..];
+        assert!(
+            matches!(tail, [
+                Event::Accepted { ord_id: 2, filled_qty: 3, resting_qty: 0, .. },
+                Event::Canceled { ord_id: 2, unfilled_qty: 7, reason: DoneReason::IocRemainder },
+            ]),
+            "{events:?}"
+        );
+        assert!(v.book.levels(Side::Bid).get(0).is_none());
+
+        // Only a market order that found nothing at all is rejected
+        let events = v.new_order(market(2, 3, Side::Bid, 10));
+        assert!(matches!(events[..], [Event::Reject { ord_id: 3, reason: RejectReason::Unfilled }]), "{events:?}");
+    }
+
+    #[test]
+    fn fok_fills_completely_or_leaves_the_book_alone() {
+        let fok = |id, qty| {
+            let mut o = limit(2, id, 0, Side::Bid, 101, qty);
+            o.tif = Tif::Fok;
+            o
+        };
+        let mut v = Venue::new(OrderBook::default());
+        let events = v.new_order(fok(1, 5));
+        assert!(matches!(events[..], [Event::Reject { ord_id: 1, reason: RejectReason::FokUnfilled }]), "{events:?}");
+
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 4));
+        v.new_order(limit(1, 2, 0, Side::Ask, 101, 6));
+        v.feed();
+        let events = v.new_order(fok(2, 11));
+        assert!(matches!(events[..], [Event::Reject { ord_id: 2, reason: RejectReason::FokUnfilled }]), "{events:?}");
+        assert!(v.feed().is_empty());
+        assert_eq!(v.book.bbo().1, Some((100, 4)));
+
+        let events = v.new_order(fok(3, 10));
+        assert!(matches!(events.last(), Some(Event::Accepted { ord_id: 3, filled_qty: 10, resting_qty: 0, .. })), "{events:?}");
+        assert_eq!(v.trades().iter().map(|t| t.qty).sum::<u64>(), 10);
+        assert!(v.book.lookup.is_empty());
+    }
+
+    #[test]
+    fn post_only_bid_rests_only_below_the_ask() {
+        let post = |id, price| {
+            let mut o = limit(2, id, 0, Side::Bid, price, 5);
+            o.post_only = true;
+            o
+        };
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 10));
+        v.feed();
+        for (id, price) in [(1, 100), (2, 101)] {
+            let events = v.new_order(post(id, price));
+            assert!(matches!(events[..], [Event::Reject { reason: RejectReason::WouldCross, .. }]), "{price}: {events:?}");
+        }
+        assert!(v.trades().is_empty());
+
+        let events = v.new_order(post(3, 99));
+        assert!(matches!(events[..], [Event::Accepted { ord_id: 3, filled_qty: 0, resting_qty: 5, .. }]), "{events:?}");
+        assert_eq!(v.book.bbo(), (Some((99, 5)), Some((100, 10))));
+    }
+
+    #[test]
+    fn each_stp_mode_handles_a_self_cross() {
+        let bid = |id, stp| {
+            let mut o = limit(1, id, 0, Side::Bid, 100, 5);
+            o.stp = stp;
+            o
+        };
+
+        // Off: the client trades with itself
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 5));
+        v.feed();
+        let events = v.new_order(bid(2, Stp::Off));
+        assert!(matches!(events.last(), Some(Event::Accepted { ord_id: 2, filled_qty: 5, .. })), "{events:?}");
+        let fills: Vec<_> = v.trades().iter().map(|t| (t.taker_cl_id, t.maker_cl_id, t.qty)).collect();
+        assert_eq!(fills, [(1, 1, 5)]);
+
+        // Cancel resting: its own ask goes, and it trades with the next one
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 5));
+        v.new_order(limit(2, 1, 0, Side::Ask, 100, 5));
+        v.feed();
+        let events = v.new_order(bid(2, Stp::CancelResting));
+        assert!(matches!(events[0], Event::StpCanceled { ord_id: 1, qty: 5 }), "{events:?}");
+        assert!(matches!(events.last(), Some(Event::Accepted { ord_id: 2, filled_qty: 5, .. })), "{events:?}");
+        let fills: Vec<_> = v.trades().iter().map(|t| (t.maker_cl_id, t.qty)).collect();
+        assert_eq!(fills, [(2, 5)]);
+        assert!(v.book.lookup.is_empty());
+
+        // Cancel aggressing: the incoming order stops, the resting one stays
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 5));
+        v.feed();
+        let events = v.new_order(bid(2, Stp::CancelAggressing));
+        assert!(matches!(events[..], [Event::StpCanceled { ord_id: 2, qty: 5 }]), "{events:?}");
+        assert!(v.trades().is_empty());
+        assert_eq!(v.queue(Side::Ask, 100), [(1, 1)]);
+    }
+
+    #[test]
+    fn half_filled_order_is_accepted_with_what_rests() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 5));
+        let events = v.new_order(limit(2, 1, 0, Side::Bid, 101, 10));
+        assert!(
+            matches!(events.last(), Some(Event::Accepted { ord_id: 1, filled_qty: 5, resting_qty: 5, side: Side::Bid, price: 101, .. })),
+            "{events:?}"
+        );
+        assert_eq!(v.book.bbo().0, Some((101, 5)));
+
+        // And on the ask side
+        let events = v.new_order(limit(3, 1, 0, Side::Ask, 101, 8));
+        assert!(
+            matches!(events.last(), Some(Event::Accepted { ord_id: 1, filled_qty: 5, resting_qty: 3, side: Side::Ask, price: 101, .. })),
+            "{events:?}"
+        );
+    }
+
+    #[test]
+    fn duplicate_order_id_is_rejected_and_the_first_stays() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 7, 0, Side::Bid, 100, 10));
+        v.feed();
+        let events = v.new_order(limit(1, 7, 0, Side::Bid, 101, 5));
+        assert!(matches!(events[..], [Event::Reject { ord_id: 7, reason: RejectReason::DupOrderId }]), "{events:?}");
+        assert!(v.feed().is_empty());
+        assert_eq!(v.queue(Side::Bid, 100), [(1, 7)]);
+        assert!(v.book.levels(Side::Bid).get(101).is_none());
+
+        let canceled = v.cancel(1, 7).unwrap();
+        assert_eq!((canceled.price, canceled.qty), (100, 10));
+        assert!(v.book.lookup.is_empty());
+    }
+
+    #[test]
+    fn gtd_order_is_swept_at_its_expiry_and_not_before() {
+        let gtd = |id, expire_ms| {
+            let mut o = limit(1, id, 0, Side::Bid, 100, 5);
+            (o.tif, o.expire_ms) = (Tif::Gtd, expire_ms);
+            o
+        };
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(gtd(1, 1_000));
+        v.new_order(gtd(2, 2_000));
+
+        expire_orders(0, &mut v.book, 1_500, &v.owners, &v.md);
+        v.book.check_invariants().expect("book invariants");
+        assert!(matches!(v.events(1)[..], [Event::Done { ord_id: 1, reason: DoneReason::Expired }]));
+        assert_eq!(v.queue(Side::Bid, 100), [(1, 2)]);
+
+        expire_orders(0, &mut v.book, 2_000, &v.owners, &v.md);
+        assert!(matches!(v.events(1)[..], [Event::Done { ord_id: 2, reason: DoneReason::Expired }]));
+        assert!(v.book.lookup.is_empty() && v.book.expiries.is_empty());
+    }
+
+    /// Every resting order, side by side and best price first: (side, price, cl_id, ord_id, exch_ord_id, qty).
+    fn resting(b: &OrderBook) -> Vec<(Side, i64, u64, u64, u64, u64)> {
+        let mut out = Vec::new();
+        for side in [Side::Bid, Side::Ask] {
+            for (px, level) in b.levels(side).iter() {
+                out.extend(level.orders.iter().map(|o| (side, px, o.cl_id, o.id, o.exch_ord_id, o.qty)));
+            }
+        }
+        out
+    }
+
+    /// A WAL file of its own under the system temp dir; gone once the test removes it.
+    fn scratch_wal(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("clob-engine-{name}-{}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// Random orders, cancels and replaces, applied to `v` and appended to `wal` as `dispatch` does.
+    fn logged_flow(v: &mut Venue, wal: &mut Wal, rng: &mut Rng, ops: std::ops::Range<u64>) {
+        for n in ops {
+            let cl_id = 1 + rng.below(3);
+            let ord_id = rng.below(n + 1);
+            match rng.below(5) {
+                0..=2 => {
+                    let side = rng.pick(&[Side::Bid, Side::Ask]);
+                    let mut o = limit(cl_id, n, v.next_exch, side, 95 + rng.below(11) as i64, 1 + rng.below(30));
+                    o.tif = rng.pick(&[Tif::Gtc, Tif::Gtc, Tif::Ioc]);
+                    wal.append(&Record::Order(o.clone())).unwrap();
+                    v.new_order(o);
+                }
+                3 => {
+                    wal.append(&Record::Cancel { symbol: 0, cl_id, ord_id }).unwrap();
+                    let _ = v.cancel(cl_id, ord_id);
+                }
+                _ => {
+                    let (new_price, new_qty) = (95 + rng.below(11) as i64, 1 + rng.below(30));
+                    wal.append(&Record::Replace { symbol: 0, cl_id, ord_id, new_price, new_qty, timestamp: 0 }).unwrap();
+                    let _ = v.replace(cl_id, ord_id, new_price, new_qty);
+                }
+            }
+            v.events(cl_id);
+        }
+        wal.flush().unwrap();
+    }
+
+    #[test]
+    fn book_rebuilt_from_the_wal_matches_the_live_one() {
+        let path = scratch_wal("rebuild");
+        let mut wal = Wal::open(&path, 0).unwrap();
+        let mut v = Venue::new(OrderBook::default());
+        logged_flow(&mut v, &mut wal, &mut Rng(0x1234_5678_9abc_def1), 0..600);
+        assert!(!v.book.lookup.is_empty());
+
+        let (records, _) = crate::wal::replay(&path, 0).unwrap();
+        let (books, next_exch) = recover(HashMap::new(), 1, records, Arc::new(RiskLimits::default()));
+        let _ = std::fs::remove_file(&path);
+        books[&0].check_invariants().expect("recovered book invariants");
+        assert_eq!(resting(&books[&0]), resting(&v.book));
+        assert_eq!(books[&0].tape.positions, v.book.tape.positions);
+        assert_eq!(next_exch, v.next_exch);
+    }
+
+    #[test]
+    fn snapshot_plus_tail_replay_matches_a_full_replay() {
+        let path = scratch_wal("tail");
+        let snap_path = path.with_extension("snap");
+        let mut wal = Wal::open(&path, 0).unwrap();
+        let mut v = Venue::new(OrderBook::default());
+        let mut rng = Rng(0x0dd_ba11_cafe_f00d);
+        logged_flow(&mut v, &mut wal, &mut rng, 0..300);
+        let mut books = HashMap::from([(0, std::mem::take(&mut v.book))]);
+        crate::snapshot::write(&snap_path, &books, wal.offset(), v.next_exch).unwrap();
+        v.book = books.remove(&0).unwrap();
+        logged_flow(&mut v, &mut wal, &mut rng, 300..600);
+
+        let risk = Arc::new(RiskLimits::default());
+        let (records, _) = crate::wal::replay(&path, 0).unwrap();
+        let (full, full_next) = recover(HashMap::new(), 1, records, risk.clone());
+        let snap = crate::snapshot::load(&snap_path).unwrap().unwrap();
+        let (tail, _) = crate::wal::replay(&path, snap.wal_offset).unwrap();
+        let (books, next) = recover(snap.books, snap.next_exch_ord_id, tail, risk);
+        let _ = (std::fs::remove_file(&path), std::fs::remove_file(&snap_path));
+
+        assert_eq!(resting(&books[&0]), resting(&full[&0]));
+        assert_eq!(resting(&books[&0]), resting(&v.book));
+        assert_eq!(books[&0].tape.positions, full[&0].tape.positions);
+        assert_eq!(next, full_next);
+    }
+
+    #[test]
+    fn shutdown_applies_everything_queued_before_it() {
+        let path = scratch_wal("shutdown");
+        let snap_path = path.with_extension("snap");
+        let (tx_cmd, rx_cmd) = crossbeam::channel::unbounded();
+        let (sink, mut rx) = sink::channel(4096, u32::MAX, 1);
+        for id in 1..=50 {
+            tx_cmd.send(Command::Order(limit(1, id, 0, Side::Bid, 50 + id as i64, 1), sink.clone(), Instant::now())).unwrap();
+        }
+        tx_cmd.send(Command::Shutdown).unwrap();
+        // Behind the sentinel, as a disconnect's cancel-all would be
+        tx_cmd.send(Command::Cancel { symbol: 0, cl_id: 1, ord_id: 50, sink: sink.clone() }).unwrap();
+
+        let (tx, _feed) = broadcast::channel(4096);
+        let wal = Wal::open(&path, 0).unwrap();
+        run_engine(rx_cmd, MdFeed::new(tx), HashMap::new(), 1, wal, snap_path.clone(), Duration::from_secs(3600), None, None, 16, 0);
+
+        let events: Vec<Event> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(events.iter().filter(|e| matches!(e, Event::Accepted { .. })).count(), 50);
+        assert!(matches!(events.last(), Some(Event::Done { ord_id: 50, reason: DoneReason::Canceled })), "{events:?}");
+        // And it is all on disk
+        let snap = crate::snapshot::load(&snap_path).unwrap().unwrap();
+        let _ = (std::fs::remove_file(&path), std::fs::remove_file(&snap_path));
+        assert_eq!(snap.books[&0].lookup.resting(1), 49);
+        assert_eq!(snap.next_exch_ord_id, 51);
+    }
+
+    #[test]
+    fn register_takes_a_client_only_from_a_closed_connection_unless_admin() {
+        let path = scratch_wal("register");
+        let snap_path = path.with_extension("snap");
+        let (tx_cmd, rx_cmd) = crossbeam::channel::unbounded();
+        let (a, mut rx_a) = sink::channel(64, u32::MAX, 1);
+        let (b, mut rx_b) = sink::channel(64, u32::MAX, 2);
+        let (admin, mut rx_admin) = sink::channel(64, u32::MAX, 3);
+        let (gone, rx_gone) = sink::channel(64, u32::MAX, 4);
+        drop(rx_gone);
+        let register = |cl_id, admin, sink: &ClientSink| Command::Register { cl_id, admin, sink: sink.clone() };
+        let order = |o: Order, sink: &ClientSink| Command::Order(o, sink.clone(), Instant::now());
+        for cmd in [
+            register(1, false, &a),
+            register(1, false, &b),
+            order(limit(1, 1, 0, Side::Ask, 100, 2), &a),
+            order(limit(9, 1, 0, Side::Bid, 100, 1), &b),
+            register(1, true, &admin),
+            order(limit(9, 2, 0, Side::Bid, 100, 1), &b),
+            // Client 2's connection closed without deregistering yet, so it is up for grabs
+            register(2, false, &gone),
+            register(2, false, &b),
+            Command::Shutdown,
+        ] {
+            tx_cmd.send(cmd).unwrap();
+        }
+
+        let (tx, _feed) = broadcast::channel(4096);
+        let wal = Wal::open(&path, 0).unwrap();
+        run_engine(rx_cmd, MdFeed::new(tx), HashMap::new(), 1, wal, snap_path.clone(), Duration::from_secs(3600), None, None, 16, 0);
+        let _ = (std::fs::remove_file(&path), std::fs::remove_file(&snap_path));
+
+        let drain = |rx: &mut mpsc::Receiver<Event>| std::iter::from_fn(|| rx.try_recv().ok()).collect::<Vec<_>>();
+        let registered = |events: &[Event]| events.iter().filter(|e| matches!(e, Event::Ack { note: AckNote::Registered, .. })).count();
+        let maker_fills = |events: &[Event]| events.iter().filter(|e| matches!(e, Event::Trade(t) if t.maker_cl_id == 1)).count();
+        let (events_a, events_b, events_admin) = (drain(&mut rx_a), drain(&mut rx_b), drain(&mut rx_admin));
+        assert_eq!((registered(&events_a), maker_fills(&events_a)), (1, 1), "{events_a:?}");
+        assert_eq!((registered(&events_admin), maker_fills(&events_admin)), (1, 1), "{events_admin:?}");
+        // B was refused client 1, got client 2, and only hears client 1's fills as their taker
+        assert!(matches!(events_b[0], Event::Reject { ord_id: 0, reason: RejectReason::Unauthorized }), "{events_b:?}");
+        assert_eq!(registered(&events_b), 1, "{events_b:?}");
+        assert!(matches!(events_b.last(), Some(Event::Ack { note: AckNote::Registered, .. })), "{events_b:?}");
+    }
+
+    #[test]
+    fn sequenced_orders_match_in_ingress_order_not_arrival_order() {
+        use crate::sequencer::{run_sequencer, Stamped};
+        let path = scratch_wal("sequencer");
+        let snap_path = path.with_extension("snap");
+        let (tx_seq, rx_seq) = crossbeam::channel::unbounded();
+        let (tx_cmd, rx_cmd) = crossbeam::channel::unbounded();
+        let (a, _rx_a) = sink::channel(64, u32::MAX, 1);
+        let (b, _rx_b) = sink::channel(64, u32::MAX, 2);
+        let t0 = Instant::now();
+        // B's task won the race to the sequencer, but A's frame came off the socket first
+        for (ingress, o, sink) in [
+            (t0 + Duration::from_millis(1), limit(2, 1, 0, Side::Bid, 100, 5), &b),
+            (t0, limit(1, 1, 0, Side::Bid, 100, 5), &a),
+            (t0 + Duration::from_millis(2), limit(3, 1, 0, Side::Ask, 100, 5), &b),
+        ] {
+            tx_seq.send(Stamped { ingress, cmd: Command::Order(o, sink.clone(), ingress) }).unwrap();
+        }
+        drop(tx_seq);
+        let sequencer = std::thread::spawn({
+            let tx_cmd = tx_cmd.clone();
+            move || run_sequencer(rx_seq, tx_cmd, Duration::from_millis(50))
+        });
+        sequencer.join().unwrap();
+        tx_cmd.send(Command::Shutdown).unwrap();
+
+        let (tx, mut feed) = broadcast::channel(4096);
+        let wal = Wal::open(&path, 0).unwrap();
+        run_engine(rx_cmd, MdFeed::new(tx), HashMap::new(), 1, wal, snap_path.clone(), Duration::from_secs(3600), None, None, 16, 0);
+        let _ = (std::fs::remove_file(&path), std::fs::remove_file(&snap_path));
+
+        let trades: Vec<_> = std::iter::from_fn(|| feed.try_recv().ok()).filter_map(|e| if let Event::Trade(t) = e { Some(t) } else { None }).collect();
+        assert_eq!(trades.iter().map(|t| (t.maker_cl_id, t.qty)).collect::<Vec<_>>(), [(1, 5)]);
+    }
+
+    #[test]
+    fn cancel_all_pulls_exactly_that_clients_orders() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 5));
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 5));
+        v.new_order(limit(1, 2, 0, Side::Bid, 99, 5));
+        v.new_order(limit(1, 3, 0, Side::Ask, 105, 5));
+        v.new_order(limit(2, 2, 0, Side::Ask, 106, 5));
+        v.feed();
+
+        let mut pulled = handle_cancel_all(0, 1, &mut v.book, &v.md);
+        v.book.check_invariants().expect("book invariants");
+        pulled.sort_unstable();
+        assert_eq!(pulled, [1, 2, 3]);
+        assert_eq!(resting(&v.book).iter().map(|o| (o.2, o.3)).collect::<Vec<_>>(), [(2, 1), (2, 2)]);
+        // One delta per level it touched
+        let deltas: Vec<_> = v.feed().into_iter().filter_map(|e| if let Event::BookDelta { side, price, level_qty, .. } = e { Some((side, price, level_qty)) } else { None }).collect();
+        assert_eq!(deltas.len(), 3, "{deltas:?}");
+        assert!(deltas.contains(&(Side::Bid, 100, 5)) && deltas.contains(&(Side::Bid, 99, 0)) && deltas.contains(&(Side::Ask, 105, 0)));
+
+        assert!(handle_cancel_all(0, 1, &mut v.book, &v.md).is_empty());
+    }
+
+    #[test]
+    fn only_a_change_at_the_top_publishes_a_bbo() {
+        let mut v = Venue::new(OrderBook::default());
+        for (id, px) in [(1, 100), (2, 99), (3, 98)] {
+            v.new_order(limit(1, id, 0, Side::Bid, px, 10));
+        }
+        v.new_order(limit(2, 1, 0, Side::Ask, 105, 10));
+        v.feed();
+        let bbos = |v: &mut Venue| -> Vec<(i64, u64, i64, u64)> {
+            v.feed().into_iter().filter_map(|e| if let Event::Bbo { bid_px, bid_qty, ask_px, ask_qty, .. } = e { Some((bid_px, bid_qty, ask_px, ask_qty)) } else { None }).collect()
+        };
+
+        // Resting and pulling orders behind the top
+        let sink = v.sink(3);
+        with_bbo(0, &mut v.book, &v.md, |b| handle_new(limit(3, 1, 100, Side::Bid, 97, 10), b, &sink, &v.owners, &v.md));
+        with_bbo(0, &mut v.book, &v.md, |b| handle_cancel(0, 1, 2, b, &v.md)).unwrap();
+        assert!(bbos(&mut v).is_empty());
+
+        // A fill at the top moves it
+        with_bbo(0, &mut v.book, &v.md, |b| handle_new(limit(3, 2, 101, Side::Ask, 100, 4), b, &sink, &v.owners, &v.md));
+        assert_eq!(bbos(&mut v), [(100, 6, 105, 10)]);
+    }
+
+    #[test]
+    fn crossed_book_is_flagged() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 5));
+        v.new_order(limit(2, 1, 0, Side::Bid, 99, 5));
+        assert!(summarize_book(0, &v.book, 0).contains("spread: 1"), "{}", summarize_book(0, &v.book, 0));
+
+        // A bid at 101 slipped past matching, as a bug would leave it
+        let bid = limit(3, 1, 9, Side::Bid, 101, 5);
+        v.book.lookup.insert(&bid);
+        v.book.levels_mut(Side::Bid).level_mut(101).insert(bid);
+        let err = v.book.check_invariants().unwrap_err();
+        assert!(err.contains("crossed: best bid 101 >= best ask 100"), "{err}");
+        let summary = summarize_book(0, &v.book, 0);
+        assert!(summary.contains("spread: CROSSED (bid 101 > ask 100"), "{summary}");
+    }
+
+    #[test]
+    fn feed_seqs_are_strictly_increasing_and_gapless() {
+        let mut v = Venue::new(OrderBook::default());
+        let step = |v: &mut Venue, mut o: Order| {
+            let sink = v.sink(o.cl_id);
+            o.exch_ord_id = 100 + v.next_exch;
+            v.next_exch += 1;
+            with_bbo(0, &mut v.book, &v.md, |b| handle_new(o, b, &sink, &v.owners, &v.md));
+        };
+        step(&mut v, limit(1, 1, 0, Side::Ask, 101, 5));
+        step(&mut v, limit(1, 2, 0, Side::Ask, 102, 5));
+        step(&mut v, limit(2, 1, 0, Side::Bid, 99, 5));
+        step(&mut v, limit(3, 1, 0, Side::Bid, 102, 8));
+        with_bbo(0, &mut v.book, &v.md, |b| handle_cancel(0, 2, 1, b, &v.md)).unwrap();
+
+        // Prints reuse their last fill's seq; a subscriber takes either them or the per-maker trades
+        let feed: Vec<Event> = v.feed().into_iter().filter(|e| !matches!(e, Event::TradePrint(_))).collect();
+        for kind in ["Trade", "BookDelta", "Bbo"] {
+            assert!(feed.iter().any(|e| format!("{e:?}").starts_with(kind)), "no {kind} in {feed:?}");
+        }
+        let seqs: Vec<u64> = feed.iter().map(|e| e.seq().expect("feed event without a seq")).collect();
+        assert_eq!(seqs, (1..=seqs.len() as u64).collect::<Vec<_>>());
+
+        // A snapshot now is as of the last event sent, so deltas resume right after it
+        let Event::Snapshot { seq, .. } = snapshot_book(0, &v.book, 10, v.md.last_seq()) else { unreachable!() };
+        assert_eq!(seq, seqs.len() as u64);
+        step(&mut v, limit(2, 2, 0, Side::Bid, 98, 5));
+        assert_eq!(v.feed().first().and_then(Event::seq), Some(seq + 1));
+    }
+
+    #[test]
+    fn pro_rata_taker_splits_by_resting_size() {
+        let mut v = Venue::new(OrderBook { policy: MatchPolicy::ProRata, ..Default::default() });
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 150));
+        v.new_order(limit(2, 1, 0, Side::Ask, 100, 100));
+        v.new_order(limit(3, 1, 0, Side::Bid, 100, 100));
+
+        let mut fills: Vec<(u64, u64)> = v.trades().iter().map(|t| (t.maker_cl_id, t.qty)).collect();
+        fills.sort_unstable();
+        assert_eq!(fills, [(1, 60), (2, 40)]);
+        let left: Vec<(u64, u64)> = v.book.asks.get(100).unwrap().orders.iter().map(|o| (o.cl_id, o.qty)).collect();
+        assert_eq!(left, [(1, 90), (2, 60)]);
+    }
+
+    #[test]
+    fn pro_rata_rounding_leftover_goes_to_the_largest_then_the_oldest() {
+        let mut v = Venue::new(OrderBook { policy: MatchPolicy::ProRata, ..Default::default() });
+        let fills = |v: &mut Venue| {
+            let mut fills: Vec<(u64, u64)> = v.trades().iter().map(|t| (t.maker_cl_id, t.qty)).collect();
+            fills.sort_unstable();
+            fills
+        };
+        // 10 over 50/30/70 is 3.33/2/4.67: the floors leave 1 over, which goes to the 70
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 50));
+        v.new_order(limit(2, 1, 0, Side::Ask, 100, 30));
+        v.new_order(limit(3, 1, 0, Side::Ask, 100, 70));
+        v.new_order(limit(4, 1, 0, Side::Bid, 100, 10));
+        assert_eq!(fills(&mut v), [(1, 3), (2, 2), (3, 5)]);
+
+        // 6 over 40/40/20 is 2.4/2.4/1.2: the leftover 1 goes to the older of the two 40s
+        v.new_order(limit(1, 2, 0, Side::Bid, 90, 40));
+        v.new_order(limit(2, 2, 0, Side::Bid, 90, 40));
+        v.new_order(limit(3, 2, 0, Side::Bid, 90, 20));
+        v.new_order(limit(4, 2, 0, Side::Ask, 90, 6));
+        assert_eq!(fills(&mut v), [(1, 3), (2, 2), (3, 1)]);
+    }
+
+    #[test]
+    fn bid_lifting_an_ask_is_the_aggressor_with_both_exchange_ids() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 7, 0, Side::Ask, 100, 5));
+        v.new_order(limit(2, 7, 0, Side::Bid, 101, 5));
+
+        let trades = v.trades();
+        assert_eq!(trades.len(), 1);
+        let t = trades[0];
+        assert_eq!((t.aggressor_side, t.taker_cl_id, t.taker_ord_id, t.maker_cl_id, t.maker_ord_id), (Side::Bid, 2, 2, 1, 1));
+
+        // And they survive the wire
+        use crate::protocol::{decode_event, decode_frame, encode_event};
+        let mut frame = encode_event(&Event::Trade(t), Default::default());
+        let frame = decode_frame(&mut frame, Default::default()).unwrap().unwrap();
+        let Some(Event::Trade(wire)) = decode_event(frame.msg_type, &frame.body, Default::default()) else {
+            panic!("not a trade");
+        };
+        assert_eq!(wire, t);
+    }
+
+    #[test]
+    fn session_stats_follow_the_trades_until_a_roll() {
+        let mut v = Venue::new(OrderBook::default());
+        for (n, (px, qty)) in [(100, 5), (104, 2), (97, 7), (101, 1)].into_iter().enumerate() {
+            v.new_order(limit(1, n as u64, 0, Side::Ask, px, qty));
+            v.new_order(limit(2, n as u64, 0, Side::Bid, px, qty));
+        }
+        assert_eq!(v.book.tape.session, SessionStats { last: 101, high: 104, low: 97, volume: 15, trades: 4 });
+
+        // A sweep of two levels counts each fill
+        v.new_order(limit(1, 10, 0, Side::Ask, 95, 3));
+        v.new_order(limit(1, 11, 0, Side::Ask, 96, 3));
+        v.new_order(limit(2, 10, 0, Side::Bid, 96, 6));
+        assert_eq!(v.book.tape.session, SessionStats { last: 96, high: 104, low: 95, volume: 21, trades: 6 });
+
+        let mut books = HashMap::from([(0, std::mem::take(&mut v.book))]);
+        roll_session(&mut books, None, &v.owners, &v.md);
+        assert_eq!(books[&0].tape.session, SessionStats::default());
+    }
+
+    #[test]
+    fn sweep_sends_one_delta_per_touched_level_after_its_trades() {
+        let mut v = Venue::new(OrderBook::default());
+        for (cl, px, qty) in [(1, 100, 2), (2, 100, 3), (1, 101, 1), (2, 101, 1), (3, 101, 2), (1, 102, 5), (2, 102, 5), (3, 103, 9)] {
+            v.new_order(limit(cl, px as u64 * 10 + cl, 0, Side::Ask, px, qty));
+        }
+        // An iceberg showing 1 of 3 brings the sweep back to 100 after each slice
+        let mut ice = limit(4, 1, 0, Side::Ask, 100, 3);
+        ice.display_qty = 1;
+        v.new_order(ice);
+        v.feed();
+
+        // 8 at 100, 4 at 101 and 6 of the 10 at 102: seven makers over three levels; 103 is untouched
+        v.new_order(limit(5, 1, 0, Side::Bid, 102, 18));
+        let feed = v.feed();
+        let kinds: Vec<_> = feed.iter().filter_map(|e| match e {
+            Event::Trade(t) => Some((t.price, None)),
+            Event::BookDelta { side: Side::Ask, price, level_qty, .. } => Some((*price, Some(*level_qty))),
+            _ => None,
+        }).collect();
+        let (trades, deltas) = kinds.split_at(kinds.iter().position(|(_, d)| d.is_some()).expect("deltas"));
+        assert_eq!(trades.len(), 10, "{kinds:?}");
+        assert!(trades.iter().all(|(_, d)| d.is_none()));
+        assert_eq!(deltas, [(100, Some(0)), (101, Some(0)), (102, Some(4))]);
+    }
+
+    #[test]
+    fn session_roll_ends_day_orders_and_keeps_gtc() {
+        let mut v = Venue::new(OrderBook::default());
+        let day = |mut o: Order| {
+            o.tif = Tif::Day;
+            o
+        };
+        v.new_order(limit(1, 1, 0, Side::Bid, 99, 10));
+        v.new_order(day(limit(1, 2, 0, Side::Bid, 99, 5)));
+        v.new_order(day(limit(2, 1, 0, Side::Ask, 105, 3)));
+        v.new_order(limit(2, 2, 0, Side::Ask, 106, 4));
+        let mut stop = day(limit(2, 3, 0, Side::Bid, 0, 1));
+        (stop.ord_type, stop.stop_price) = (OrderType::Stop, 120);
+        v.new_order(stop);
+        let mut gtc_stop = limit(2, 4, 0, Side::Bid, 0, 1);
+        (gtc_stop.ord_type, gtc_stop.stop_price) = (OrderType::Stop, 120);
+        v.new_order(gtc_stop);
+        v.events(1);
+        v.events(2);
+        v.feed();
+
+        let mut books = HashMap::from([(0, std::mem::take(&mut v.book))]);
+        roll_session(&mut books, None, &v.owners, &v.md);
+        v.book = books.remove(&0).unwrap();
+
+        let ended = |events: Vec<Event>| events.into_iter().filter_map(|e| match e {
+            Event::Done { ord_id, reason: DoneReason::SessionEnd } => Some(ord_id),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(ended(v.events(1)), [2]);
+        assert_eq!(ended(v.events(2)), [1, 3]);
+        assert_eq!(v.queue(Side::Bid, 99), [(1, 1)]);
+        assert!(v.queue(Side::Ask, 105).is_empty());
+        assert_eq!(v.queue(Side::Ask, 106), [(2, 2)]);
+        assert_eq!(v.book.stops.iter().map(|o| o.id).collect::<Vec<_>>(), [4]);
+        let deltas: Vec<_> = v.feed().into_iter().filter_map(|e| match e {
+            Event::BookDelta { side, price, level_qty, .. } => Some((side, price, level_qty)),
+            _ => None,
+        }).collect();
+        assert_eq!(deltas, [(Side::Bid, 99, 10), (Side::Ask, 105, 0)]);
+        v.book.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn candles_close_at_each_boundary_and_empty_intervals_are_flat_or_skipped() {
+        let mut v = Venue::new(OrderBook::default());
+        let cross = |v: &mut Venue, n: u64, px: i64, qty: u64| {
+            v.new_order(limit(1, n, 0, Side::Ask, px, qty));
+            v.new_order(limit(2, n, 0, Side::Bid, px, qty));
+        };
+        // Closes the interval that began at `start_ts`, with the book in a map as `run_engine` keeps it
+        let close = |v: &mut Venue, start_ts: u64, skip_empty: bool| {
+            let mut books = HashMap::from([(0, std::mem::take(&mut v.book)), (1, OrderBook::default())]);
+            close_candles(&mut books, start_ts, skip_empty, &v.md);
+            v.book = books.remove(&0).unwrap();
+            v.feed().into_iter().filter_map(|e| match e {
+                Event::Candle { symbol, open, high, low, close, volume, start_ts } => Some((symbol, [open, high, low, close], volume, start_ts)),
+                _ => None,
+            }).collect::<Vec<_>>()
+        };
+
+        // Symbol 1 never traded, so it has no candle even when empty ones are sent
+        cross(&mut v, 1, 100, 5);
+        cross(&mut v, 2, 103, 1);
+        cross(&mut v, 3, 98, 2);
+        cross(&mut v, 4, 101, 4);
+        assert_eq!(close(&mut v, 0, false), [(0, [100, 103, 98, 101], 12, 0)]);
+
+        // The next interval starts from its own first trade, not the last close
+        cross(&mut v, 5, 105, 3);
+        assert_eq!(close(&mut v, 60_000, false), [(0, [105, 105, 105, 105], 3, 60_000)]);
+
+        assert_eq!(close(&mut v, 120_000, false), [(0, [105, 105, 105, 105], 0, 120_000)]);
+        assert_eq!(close(&mut v, 180_000, true), []);
+    }
+
+    fn band_rejected(events: &[Event], ord_id: u64) -> bool {
+        events.iter().any(|e| matches!(e, Event::Reject { ord_id: id, reason: RejectReason::PriceBand } if *id == ord_id))
+    }
+
+    #[test]
+    fn band_is_open_until_the_first_trade_then_centred_on_the_last() {
+        let mut v = Venue::new(OrderBook { band: Some(PriceBand::Bps(500)), ..Default::default() });
+        assert!(!band_rejected(&v.new_order(limit(1, 1, 0, Side::Ask, 300, 5)), 1));
+        v.new_order(limit(1, 2, 0, Side::Ask, 100, 5));
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 1));
+
+        // 5% of 100 either way, edges included
+        assert!(!band_rejected(&v.new_order(limit(2, 2, 0, Side::Bid, 95, 1)), 2));
+        assert!(band_rejected(&v.new_order(limit(2, 3, 0, Side::Bid, 94, 1)), 3));
+        assert!(!band_rejected(&v.new_order(limit(1, 3, 0, Side::Ask, 105, 1)), 3));
+        assert!(band_rejected(&v.new_order(limit(1, 4, 0, Side::Ask, 106, 1)), 4));
+        assert_eq!(v.replace(2, 2, 90, 1).unwrap_err(), RejectReason::PriceBand);
+        let mut market = limit(2, 5, 0, Side::Bid, 0, 1);
+        (market.ord_type, market.tif) = (OrderType::Market, Tif::Ioc);
+        assert!(!band_rejected(&v.new_order(market), 5), "market orders aren't banded");
+    }
+
+    #[test]
+    fn band_and_stops_keep_the_previous_close_across_a_session_roll() {
+        let mut v = Venue::new(OrderBook { band: Some(PriceBand::Bps(500)), ..Default::default() });
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 5));
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 5));
+        let mut books = HashMap::from([(0, std::mem::take(&mut v.book))]);
+        roll_session(&mut books, None, &v.owners, &v.md);
+        roll_session(&mut books, None, &v.owners, &v.md);
+        v.book = books.remove(&0).unwrap();
+        v.feed();
+        assert_eq!(v.book.tape.session, SessionStats::default());
+        assert_eq!(v.book.tape.reference(), 100);
+
+        assert!(band_rejected(&v.new_order(limit(2, 2, 0, Side::Bid, 120, 1)), 2));
+        assert!(!band_rejected(&v.new_order(limit(2, 3, 0, Side::Bid, 99, 1)), 3));
+        // A sell stop at 101 has already been reached by the close of 100, so it fires on arrival
+        let mut stop = limit(1, 2, 0, Side::Ask, 99, 1);
+        (stop.ord_type, stop.stop_price) = (OrderType::StopLimit, 101);
+        v.new_order(stop);
+        assert_eq!(v.trades().len(), 1);
+
+        // The new session's own trade takes over from the close, and a snapshot keeps the close
+        v.new_order(limit(1, 3, 0, Side::Ask, 103, 1));
+        v.new_order(limit(2, 4, 0, Side::Bid, 103, 1));
+        assert!(!band_rejected(&v.new_order(limit(2, 5, 0, Side::Bid, 108, 1)), 5));
+        let snap_path = scratch_wal("band-roll-snap");
+        crate::snapshot::write(&snap_path, &HashMap::from([(0, std::mem::take(&mut v.book))]), 0, v.next_exch).unwrap();
+        let tape = &crate::snapshot::load(&snap_path).unwrap().unwrap().books[&0].tape;
+        assert_eq!((tape.prev_close, tape.reference()), (100, 103));
+        let _ = std::fs::remove_file(snap_path);
+    }
+
+    #[test]
+    fn halted_book_rejects_orders_but_still_cancels_until_resumed() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 5));
+        v.new_order(limit(1, 2, 0, Side::Ask, 101, 5));
+        set_market_state(0, &mut v.book, MarketState::Halted, &v.owners, &v.md);
+
+        let events = v.new_order(limit(2, 1, 0, Side::Bid, 100, 5));
+        assert!(matches!(events[..], [Event::Reject { ord_id: 1, reason: RejectReason::Halted }]), "{events:?}");
+        assert_eq!(v.replace(1, 2, 102, 5).unwrap_err(), RejectReason::Halted);
+        assert_eq!(v.cancel(1, 2).unwrap().id, 2);
+        assert!(v.trades().is_empty());
+
+        set_market_state(0, &mut v.book, MarketState::Open, &v.owners, &v.md);
+        let events = v.new_order(limit(2, 2, 0, Side::Bid, 100, 5));
+        assert!(matches!(events.last(), Some(Event::Accepted { ord_id: 2, filled_qty: 5, .. })), "{events:?}");
+        assert!(v.book.lookup.is_empty());
+    }
+
+    fn rejected_for(events: &[Event]) -> Option<RejectReason> {
+        events.iter().find_map(|e| if let Event::Reject { reason, .. } = e { Some(*reason) } else { None })
+    }
+
+    #[test]
+    fn position_and_notional_limits_hold_a_client_back() {
+        let mut risk = RiskLimits::default();
+        risk.insert(1, RiskLimit { max_position: Some(10), max_notional: Some(2_000), max_orders: None });
+        let mut v = Venue::new(OrderBook::default()).risk_limits(risk);
+        v.new_order(limit(2, 1, 0, Side::Ask, 100, 8));
+        assert_eq!(rejected_for(&v.new_order(limit(1, 1, 0, Side::Bid, 100, 8))), None);
+        assert_eq!(v.book.tape.position(1), 8);
+
+        // Long 8 of 10: a bid counts as if it filled completely
+        assert_eq!(rejected_for(&v.new_order(limit(1, 2, 0, Side::Bid, 99, 3))), Some(RejectReason::RiskPosition));
+        assert_eq!(rejected_for(&v.new_order(limit(1, 3, 0, Side::Bid, 99, 2))), None);
+        // Selling 20 would leave it short 12
+        assert_eq!(rejected_for(&v.new_order(limit(1, 4, 0, Side::Ask, 101, 20))), Some(RejectReason::RiskPosition));
+        // 198 resting plus 18 * 101 is over 2000; 17 * 101 is not
+        assert_eq!(rejected_for(&v.new_order(limit(1, 5, 0, Side::Ask, 101, 18))), Some(RejectReason::RiskNotional));
+        assert_eq!(rejected_for(&v.new_order(limit(1, 6, 0, Side::Ask, 101, 17))), None);
+        assert_eq!(v.book.lookup.notional(1), 198 + 1_717);
+
+        // Clients without limits trade unchecked
+        assert_eq!(rejected_for(&v.new_order(limit(2, 2, 0, Side::Bid, 100, 1_000))), None);
+    }
+
+    #[test]
+    fn buy_stop_fires_on_a_trade_at_or_above_its_trigger() {
+        let mut v = Venue::new(OrderBook::default());
+        // A print at `px` between clients 2 and 4, then the stop pass `dispatch` runs after every command
+        let print = |v: &mut Venue, n: u64, px: i64| {
+            v.new_order(limit(2, n, 0, Side::Ask, px, 1));
+            v.new_order(limit(4, n, 0, Side::Bid, px, 1));
+            trigger_stops(&mut v.book, &v.owners, &v.md);
+        };
+        // The first stop's own fill prints at 103, short of the second trigger
+        v.new_order(limit(3, 1, 0, Side::Ask, 103, 3));
+        v.new_order(limit(3, 2, 0, Side::Ask, 110, 10));
+        for (ord_id, trigger) in [(1, 102), (2, 104)] {
+            let mut stop = limit(1, ord_id, 0, Side::Bid, 0, 3);
+            (stop.ord_type, stop.tif, stop.stop_price) = (OrderType::Stop, Tif::Ioc, trigger);
+            let events = v.new_order(stop);
+            assert!(matches!(events[..], [Event::Ack { note: AckNote::StopPending, .. }]), "{events:?}");
+        }
+
+        print(&mut v, 1, 101);
+        assert_eq!(v.book.stops.len(), 2);
+        print(&mut v, 2, 102);
+        assert_eq!(v.book.stops.len(), 1, "a print right at the trigger fires it");
+        // Gapping over 104 fires the other one too
+        print(&mut v, 3, 106);
+        assert!(v.book.stops.is_empty());
+
+        let fills: Vec<(u64, i64, u64)> = v.trades().iter().filter(|t| t.taker_cl_id == 1).map(|t| (t.maker_cl_id, t.price, t.qty)).collect();
+        assert_eq!(fills, [(3, 103, 3), (3, 110, 3)]);
+        assert_eq!(v.book.tape.position(1), 6);
+    }
+
+    #[test]
+    fn filled_maker_gets_exactly_one_done() {
+        for policy in [MatchPolicy::FifoPriceTime, MatchPolicy::ProRata, MatchPolicy::SizeTime] {
+            let mut v = Venue::new(OrderBook { policy, ..Default::default() });
+            v.new_order(limit(1, 1, 0, Side::Ask, 100, 10));
+            v.new_order(limit(1, 2, 0, Side::Ask, 100, 4));
+            let mut iceberg = limit(1, 3, 0, Side::Ask, 101, 9);
+            iceberg.display_qty = 3;
+            v.new_order(iceberg);
+            v.events(1);
+
+            // A partial fill, then the rest in a sweep that also works through every iceberg slice
+            v.new_order(limit(2, 1, 0, Side::Bid, 100, 6));
+            v.new_order(limit(2, 2, 0, Side::Bid, 101, 17));
+            assert!(v.book.lookup.is_empty(), "{policy:?}");
+
+            let events = v.events(1);
+            for ord_id in 1..=3 {
+                let dones = events.iter().filter(|e| matches!(e, Event::Done { ord_id: id, reason: DoneReason::Filled } if *id == ord_id)).count();
+                assert_eq!(dones, 1, "{policy:?} order {ord_id}: {events:?}");
+            }
+            let last_fill = events.iter().rposition(|e| matches!(e, Event::Trade(_))).unwrap();
+            let last_done = events.iter().rposition(|e| matches!(e, Event::Done { .. })).unwrap();
+            assert!(last_done > last_fill, "{policy:?}: a maker's Done follows its fills");
+        }
+    }
+
+    #[test]
+    fn each_fill_is_charged_its_symbols_fees() {
+        for (fees, expected) in [(FeeSchedule { maker_bps: 1, taker_bps: 5 }, [(0, 1), (1, 3)]), (FeeSchedule::default(), [(0, 0), (0, 0)])] {
+            let mut v = Venue::new(OrderBook { fees, ..Default::default() });
+            v.new_order(limit(1, 1, 0, Side::Ask, 100, 10));
+            v.new_order(limit(1, 2, 0, Side::Ask, 100, 50));
+            v.new_order(limit(2, 1, 0, Side::Bid, 100, 60));
+            let charged: Vec<(u64, u64)> = v.trades().iter().map(|t| (t.maker_fee, t.taker_fee)).collect();
+            assert_eq!(charged, expected, "{fees:?}");
+        }
+    }
+
+    #[test]
+    fn order_past_the_max_depth_is_rejected_too_deep() {
+        let mut v = Venue::new(OrderBook::default());
+        v.book.max_depth = Some(3);
+        for (ord_id, px) in [(1, 100), (2, 99), (3, 98)] {
+            v.new_order(limit(1, ord_id, 0, Side::Bid, px, 10));
+            v.new_order(limit(2, ord_id, 0, Side::Ask, 210 - px, 10));
+        }
+        v.events(1);
+        v.events(2);
+
+        // The fourth level on either side is one too many
+        assert_eq!(rejected_for(&v.new_order(limit(1, 4, 0, Side::Bid, 97, 10))), Some(RejectReason::TooDeep));
+        assert_eq!(rejected_for(&v.new_order(limit(2, 4, 0, Side::Ask, 113, 10))), Some(RejectReason::TooDeep));
+        // Joining the Nth level, or bettering the touch, is still fine
+        assert_eq!(rejected_for(&v.new_order(limit(1, 5, 0, Side::Bid, 98, 10))), None);
+        assert_eq!(rejected_for(&v.new_order(limit(1, 6, 0, Side::Bid, 101, 10))), None);
+        assert_eq!(v.book.levels(Side::Bid).len(), 4);
+        assert!(v.book.levels(Side::Bid).get(97).is_none());
+    }
+
+    #[test]
+    fn order_feed_replayed_rebuilds_every_queue() {
+        let (tx, mut l3) = broadcast::channel(4096);
+        let mut v = Venue::new(OrderBook::default());
+        v.md = std::mem::replace(&mut v.md, MdFeed::new(broadcast::channel(1).0)).order_feed(tx);
+
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 10));
+        v.new_order(limit(2, 1, 0, Side::Bid, 100, 10));
+        v.new_order(limit(3, 1, 0, Side::Bid, 99, 5));
+        let mut iceberg = limit(4, 1, 0, Side::Bid, 100, 9);
+        iceberg.display_qty = 3;
+        v.new_order(iceberg);
+        v.new_order(limit(5, 1, 0, Side::Ask, 103, 8));
+        v.new_order(limit(6, 1, 0, Side::Ask, 100, 12)); // All of 1, two of 2
+        v.reduce(2, 1, 6).unwrap();
+        v.replace(3, 1, 100, 5).unwrap();
+        v.new_order(limit(6, 2, 0, Side::Ask, 100, 17)); // The rest of 2 and two slices of the iceberg
+        v.cancel(5, 1).unwrap();
+        v.new_order(limit(5, 2, 0, Side::Ask, 102, 4));
+
+        // Level queues of (exchange id, visible qty) from nothing but the L3 events, in seq order
+        let mut queues: BTreeMap<(Side, i64), Vec<(u64, u64)>> = BTreeMap::new();
+        let mut at: HashMap<u64, (Side, i64)> = HashMap::new();
+        let mut seq = 0;
+        for mut evt in std::iter::from_fn(|| l3.try_recv().ok()) {
+            let s = *evt.seq_mut().expect("sequenced");
+            assert_eq!(s, seq + 1, "{evt:?}");
+            seq = s;
+            match evt {
+                Event::OrderAdd { ord_id, side, price, qty, .. } => {
+                    queues.entry((side, price)).or_default().push((ord_id, qty));
+                    at.insert(ord_id, (side, price));
+                }
+                Event::OrderExecute { ord_id, qty, .. } | Event::OrderReduce { ord_id, qty, .. } => {
+                    let q = queues.get_mut(&at[&ord_id]).unwrap();
+                    let i = q.iter().position(|&(id, _)| id == ord_id).unwrap();
+                    q[i].1 -= qty;
+                    if q[i].1 == 0 {
+                        q.remove(i);
+                    }
+                }
+                Event::OrderRemove { ord_id, .. } => {
+                    queues.get_mut(&at[&ord_id]).unwrap().retain(|&(id, _)| id != ord_id);
+                }
+                other => panic!("not an order-level event: {other:?}"),
+            }
+        }
+        queues.retain(|_, q| !q.is_empty());
+
+        let mut book = BTreeMap::new();
+        for side in [Side::Bid, Side::Ask] {
+            for (px, level) in v.book.levels(side).iter() {
+                book.insert((side, px), level.orders.iter().map(|o| (o.exch_ord_id, o.qty)).collect::<Vec<_>>());
+            }
+        }
+        assert_eq!(queues, book);
+        assert!(book.len() >= 2, "{book:?}");
+    }
+
+    #[test]
+    fn ioc_filling_30_of_100_cancels_the_other_70() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Ask, 100, 10));
+        v.new_order(limit(1, 2, 0, Side::Ask, 101, 20));
+        v.new_order(limit(1, 3, 0, Side::Ask, 103, 50));
+
+        let mut ioc = limit(2, 1, 0, Side::Bid, 102, 100);
+        ioc.tif = Tif::Ioc;
+        let events = v.new_order(ioc);
+        let fills: u64 = events.iter().filter_map(|e| if let Event::Trade(t) = e { Some(t.qty) } else { None }).sum();
+        assert_eq!(fills, 30, "{events:?}");
+        assert!(
+            matches!(events[events.len() - 2..], [
+                Event::Accepted { ord_id: 1, filled_qty: 30, resting_qty: 0, .. },
+                Event::Canceled { ord_id: 1, unfilled_qty: 70, reason: DoneReason::IocRemainder },
+            ]),
+            "{events:?}"
+        );
+        // Nothing of it rests, and the ask past its limit is untouched
+        assert!(v.book.levels(Side::Bid).is_empty());
+        assert_eq!(v.queue(Side::Ask, 103), [(1, 3)]);
+    }
+
+    #[test]
+    fn level_serves_by_arrival_seq_whatever_order_or_timestamp_orders_join_in() {
+        let mut v = Venue::new(OrderBook::default());
+        // As a replay or a merge might hand them over: out of arrival order, and the
+        // earliest arrival with the latest gateway timestamp
+        for (cl_id, arrival_seq, timestamp) in [(1, 7, 100), (2, 3, 300), (3, 5, 200)] {
+            let mut o = limit(cl_id, 1, cl_id, Side::Ask, 100, 5);
+            (o.arrival_seq, o.timestamp) = (arrival_seq, timestamp);
+            v.book.lookup.insert(&o);
+            v.book.levels_mut(Side::Ask).level_mut(100).insert(o);
+        }
+        v.book.next_arrival_seq = 8;
+        v.next_exch = 4;
+        assert_eq!(v.queue(Side::Ask, 100), [(2, 1), (3, 1), (1, 1)]);
+
+        v.new_order(limit(9, 1, 0, Side::Bid, 100, 12));
+        let makers: Vec<_> = v.trades().iter().map(|t| (t.maker_cl_id, t.qty)).collect();
+        assert_eq!(makers, [(2, 5), (3, 5), (1, 2)]);
+        assert_eq!(v.queue(Side::Ask, 100), [(1, 1)]);
+    }
+
+    #[test]
+    fn same_millisecond_orders_match_in_arrival_seq_order() {
+        let mut v = Venue::new(OrderBook::default());
+        for (cl_id, exch) in [(1, 1), (2, 2)] {
+            let mut o = limit(cl_id, 1, exch, Side::Ask, 100, 5);
+            // Same gateway millisecond, and whatever the sender put in the slot is overwritten
+            (o.timestamp, o.arrival_seq) = (1_700_000_000_000, 99 - cl_id);
+            v.new_order(o);
+        }
+        let seqs: Vec<_> = v.book.levels(Side::Ask).get(100).unwrap().orders.iter().map(|o| (o.cl_id, o.arrival_seq)).collect();
+        assert!(matches!(seqs[..], [(1, a), (2, b)] if a < b), "{seqs:?}");
+
+        v.new_order(limit(9, 1, 3, Side::Bid, 100, 7));
+        let makers: Vec<_> = v.trades().iter().map(|t| (t.maker_cl_id, t.qty)).collect();
+        assert_eq!(makers, [(1, 5), (2, 2)]);
+    }
+
+    #[test]
+    fn ladder_renders_best_levels_around_the_spread() {
+        let mut v = Venue::new(OrderBook::default());
+        assert_eq!(v.book.to_string(), "ASK  (empty)\n----- no spread -----\nBID  (empty)\n");
+
+        for (id, side, px, qty) in [
+            (1, Side::Ask, 101, 3),
+            (2, Side::Ask, 102, 5),
+            (3, Side::Ask, 102, 6),
+            (4, Side::Ask, 1005, 1),
+            (5, Side::Bid, 99, 7),
+            (6, Side::Bid, 99, 5),
+            (7, Side::Bid, 98, 4),
+        ] {
+            v.new_order(limit(1, id, 0, side, px, qty));
+        }
+        // Asks read down to the touch, bids away from it; `(n)` is the order count
+        assert_eq!(
+            format!("{:.2}", v.book),
+            "\
+ASK  102  11  (2)
+ASK  101   3  (1)
+----- spread 2 -----
+BID   99  12  (2)
+BID   98   4  (1)
+"
+        );
+        assert_eq!(
+            v.book.to_string(),
+            "\
+ASK  1005   1  (1)
+ASK   102  11  (2)
+ASK   101   3  (1)
+----- spread 2 -----
+BID    99  12  (2)
+BID    98   4  (1)
+"
+        );
+    }
+
+    #[test]
+    fn mid_microprice_and_weighted_mid_match_the_hand_computed_values() {
+        let mut v = Venue::new(OrderBook::default());
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 30));
+        assert_eq!((v.book.mid(), v.book.microprice(), v.book.weighted_mid(2)), (None, None, None));
+
+        v.new_order(limit(1, 2, 0, Side::Bid, 99, 10));
+        v.new_order(limit(2, 1, 0, Side::Ask, 102, 10));
+        v.new_order(limit(2, 2, 0, Side::Ask, 104, 30));
+        assert_eq!(v.book.mid(), Some(101.0));
+        // (100 * 10 + 102 * 30) / 40: three times the size bid leans it toward the ask
+        assert_eq!(v.book.microprice(), Some(101.5));
+        assert_eq!(v.book.weighted_mid(1), v.book.microprice());
+        // Bid vwap (100 * 30 + 99 * 10) / 40 = 99.75, ask vwap (102 * 10 + 104 * 30) / 40 = 103.5, 40 a side
+        assert_eq!(v.book.weighted_mid(2), Some(101.625));
+        assert_eq!(v.book.weighted_mid(10), Some(101.625));
+        assert_eq!(v.book.weighted_mid(0), None);
+    }
+
+    #[test]
+    fn imbalance_and_flow_follow_the_book_and_the_aggressors() {
+        let mut v = Venue::new(OrderBook::default());
+        assert_eq!(v.book.imbalance(IMBALANCE_LEVELS), None);
+        v.new_order(limit(1, 1, 0, Side::Bid, 100, 30));
+        v.new_order(limit(1, 2, 0, Side::Bid, 99, 10));
+        v.new_order(limit(2, 1, 0, Side::Ask, 102, 10));
+        v.new_order(limit(2, 2, 0, Side::Ask, 104, 30));
+        // (30 - 10) / 40 at the touch; 40 a side two levels deep
+        assert_eq!(v.book.imbalance(1), Some(0.5));
+        assert_eq!(v.book.imbalance(2), Some(0.0));
+        assert_eq!(v.book.imbalance(0), None);
+        assert_eq!(v.book.tape.flow.ratio(), None);
+
+        v.new_order(limit(3, 1, 0, Side::Bid, 102, 4)); // Lifts 4 of the ask
+        v.new_order(limit(4, 1, 0, Side::Ask, 100, 6)); // Hits 6 of the bid
+        assert_eq!(v.book.tape.flow, Flow { buy_qty: 4, sell_qty: 6 });
+        assert_eq!(v.book.tape.flow.ratio(), Some(4.0 / 6.0));
+        // Bids 24 + 10 against asks 6 + 30
+        assert_eq!(v.book.imbalance(1), Some(0.6));
+        assert_eq!(v.book.imbalance(IMBALANCE_LEVELS), Some(-2.0 / 70.0));
+        let summary = summarize_book(0, &v.book, 0);
+        assert!(summary.contains("imbalance(top 5)=-0.029  aggressive: buy=4 sell=6 ratio=0.667"), "{summary}");
+    }
+
+    /// xorshift64: reproducible noise for the randomized tests, no crate needed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn pick<T: Copy>(&mut self, from: &[T]) -> T {
+            from[self.below(from.len() as u64) as usize]
+        }
+    }
+
+    #[test]
+    fn cached_level_qty_matches_a_brute_force_sum() {
+        let mut v = Venue::new(OrderBook::default());
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for n in 0..2_000 {
+            let cl_id = 1 + rng.below(3);
+            match rng.below(4) {
+                0 | 1 => {
+                    let side = rng.pick(&[Side::Bid, Side::Ask]);
+                    v.new_order(limit(cl_id, n, 0, side, 95 + rng.below(11) as i64, 1 + rng.below(30)));
+                }
+                2 => {
+                    let _ = v.cancel(cl_id, rng.below(n + 1));
+                }
+                _ => {
+                    let _ = v.reduce(cl_id, rng.below(n + 1), 1 + rng.below(10));
+                }
+            }
+            v.events(cl_id);
+            v.feed();
+            for side in [Side::Bid, Side::Ask] {
+                for (px, level) in v.book.levels(side).iter() {
+                    let sum: u64 = level.orders.iter().map(|o| o.qty).sum();
+                    assert_eq!(level.level_qty, sum, "{side:?} {px} after op {n}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_flow_keeps_the_book_consistent() {
+        for policy in [MatchPolicy::FifoPriceTime, MatchPolicy::ProRata, MatchPolicy::SizeTime] {
+            for ladder in [false, true] {
+                let mut book = OrderBook { policy, ..Default::default() };
+                if ladder {
+                    book.use_ladder(LadderSpec { min: 80, max: 120, tick: 1 }).unwrap();
+                }
+                let mut v = Venue::new(book);
+                let mut rng = Rng(0x9e37_79b9_7f4a_7c15 ^ policy as u64 ^ ladder as u64);
+                let mut sent: Vec<(u64, u64)> = Vec::new();
+                let mut traded = 0;
+                for n in 0..3_000 {
+                    let cl_id = 1 + rng.below(4);
+                    match rng.below(20) {
+                        0..=10 => {
+                            let side = rng.pick(&[Side::Bid, Side::Ask]);
+                            let mut o = limit(cl_id, n, 0, side, 90 + rng.below(21) as i64, 1 + rng.below(50));
+                            o.tif = rng.pick(&[Tif::Gtc, Tif::Gtc, Tif::Gtc, Tif::Ioc, Tif::Fok]);
+                            o.stp = rng.pick(&[Stp::Off, Stp::Off, Stp::CancelResting, Stp::CancelAggressing]);
+                            o.post_only = rng.below(10) == 0;
+                            if rng.below(10) == 0 {
+                                o.display_qty = rng.below(o.qty);
+                            }
+                            if rng.below(15) == 0 {
+                                (o.ord_type, o.price, o.tif, o.display_qty, o.post_only) = (OrderType::Market, 0, Tif::Ioc, 0, false);
+                            }
+                            v.new_order(o);
+                            sent.push((cl_id, n));
+                        }
+                        11..=14 if !sent.is_empty() => {
+                            let (cl_id, ord_id) = rng.pick(&sent);
+                            let _ = v.cancel(cl_id, ord_id);
+                        }
+                        15..=17 if !sent.is_empty() => {
+                            let (cl_id, ord_id) = rng.pick(&sent);
+                            let _ = v.replace(cl_id, ord_id, 90 + rng.below(21) as i64, 1 + rng.below(50));
+                        }
+                        18..=19 if !sent.is_empty() => {
+                            let (cl_id, ord_id) = rng.pick(&sent);
+                            let _ = v.reduce(cl_id, ord_id, 1 + rng.below(20));
+                        }
+                        _ => {}
+                    }
+                    v.events(cl_id);
+                    traded += v.trades().len();
+                }
+                // The flow has to actually trade and leave a book behind for the checks to mean anything
+                assert!(traded > 500 && !v.book.lookup.is_empty(), "{policy:?} ladder={ladder}: {traded} trades");
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use crate::types::{DoneReason, Event, Order, QtyScale, Side, Trade};
+
+// ========================== Execution reports ==========================
+// An alternate rendering of one connection's order events, for integrators used to FIX:
+// one `key=value` line per report, sent as an EXEC_REPORT frame in place of the binary event.
+// The binary events don't carry leaves/cum quantities, so the encoder tracks the orders it
+// has seen by `cl_ord_id`. A taker's fills arrive before the ACCEPTED that names its exchange
+// id, so they are held back until then. A connection should not reuse a `cl_ord_id` across
+// the client ids it trades for. Quantities read as decimals on symbols with a qty scale.
+
+struct OrderState {
+    side: Side,
+    price: i64,
+    qty: u64, // Everything the order may fill, so leaves = qty - cum
+    cum: u64,
+    exch_ord_id: Option<u64>,
+    scale: QtyScale,
+}
+
+#[derive(Default)]
+pub struct ExecReports {
+    orders: HashMap<u64, OrderState>, // By cl_ord_id
+    by_exch: HashMap<u64, u64>,       // exch_ord_id → cl_ord_id, learned from ACCEPTED
+    pending: Vec<Trade>,              // Taker fills waiting for their order's ACCEPTED
+    scales: Arc<HashMap<u32, QtyScale>>,
+}
+
+impl ExecReports {
+    pub fn new(scales: Arc<HashMap<u32, QtyScale>>) -> Self {
+        ExecReports { scales, ..Default::default() }
+    }
+
+    /// Remember an order sent on this connection, for its side, price, size and symbol's qty scale.
+    pub fn entered(&mut self, o: &Order) {
+        let scale = self.scales.get(&o.symbol).copied().unwrap_or_default();
+        self.orders.insert(o.id, OrderState { side: o.side, price: o.price, qty: o.qty, cum: 0, exch_ord_id: None, scale });
+    }
+
+    /// Turn `evt` into the frames to send: reports for order events, the event itself otherwise.
+    /// Fills still waiting for their ACCEPTED give nothing yet.
+    pub fn render(&mut self, evt: Event) -> Vec<Event> {
+        let mut out = Vec::new();
+        match evt {
+            Event::Trade(t) => {
+                let maker = self.by_exch.get(&t.maker_ord_id).copied();
+                let taker = self.by_exch.get(&t.taker_ord_id).copied();
+                if let Some(id) = maker {
+                    out.push(self.fill(id, &t, t.maker_fee));
+                }
+                match taker {
+                    Some(id) => out.push(self.fill(id, &t, t.taker_fee)),
+                    // Ours as the taker, unless this connection only owns the maker
+                    None if maker.is_none() || t.taker_cl_id == t.maker_cl_id => self.pending.push(t),
+                    None => {}
+                }
+            }
+            Event::Accepted { ord_id, exch_ord_id, filled_qty, resting_qty, side, price } => {
+                let o = self.orders.entry(ord_id).or_insert(OrderState {
+                    side,
+                    price,
+                    qty: filled_qty + resting_qty,
+                    cum: 0,
+                    exch_ord_id: None,
+                    scale: QtyScale::default(),
+                });
+                // A replace that lost priority comes back through matching under the same id
+                let reentry = o.exch_ord_id == Some(exch_ord_id);
+                o.exch_ord_id = Some(exch_ord_id);
+                o.price = price;
+                self.by_exch.insert(exch_ord_id, ord_id);
+                if !reentry {
+                    out.push(self.report(ord_id, "new", None, "", false));
+                }
+                for t in std::mem::take(&mut self.pending) {
+                    if t.taker_ord_id == exch_ord_id {
+                        out.push(self.fill(ord_id, &t, t.taker_fee));
+                    } else {
+                        out.push(Event::Trade(t));
+                    }
+                }
+                // An IOC remainder is closed by the CANCELED that follows
+                if resting_qty == 0 && self.leaves(ord_id) == 0 {
+                    self.forget(ord_id);
+                }
+            }
+            Event::Reject { ord_id, reason } => {
+                // An order that never rested may have filled part of the way first (market remainder)
+                if self.orders.get(&ord_id).is_some_and(|o| o.exch_ord_id.is_none()) {
+                    for t in std::mem::take(&mut self.pending) {
+                        out.push(self.fill(ord_id, &t, t.taker_fee));
+                    }
+                    out.push(self.closed(ord_id, "rejected", reason.as_str()));
+                } else {
+                    out.push(self.report(ord_id, "rejected", None, reason.as_str(), false));
+                }
+            }
+            Event::StpCanceled { ord_id, .. } => {
+                if self.orders.get(&ord_id).is_some_and(|o| o.exch_ord_id.is_none()) {
+                    for t in std::mem::take(&mut self.pending) {
+                        out.push(self.fill(ord_id, &t, t.taker_fee));
+                    }
+                }
+                out.push(self.closed(ord_id, "canceled", "stp"));
+            }
+            Event::Done { ord_id, reason: DoneReason::SessionEnd } => out.push(self.closed(ord_id, "expired", DoneReason::SessionEnd.as_str())),
+            Event::Done { ord_id, reason } => out.push(self.closed(ord_id, reason.as_str(), "")),
+            // A reduce-only order's excess is cut before it trades; the rest stays live
+            Event::Canceled { ord_id, unfilled_qty, reason: DoneReason::ReduceOnly } => {
+                if let Some(o) = self.orders.get_mut(&ord_id) {
+                    o.qty = o.qty.saturating_sub(unfilled_qty);
+                }
+                out.push(self.report(ord_id, "restated", None, DoneReason::ReduceOnly.as_str(), false));
+            }
+            Event::Canceled { ord_id, reason, .. } => out.push(self.closed(ord_id, "canceled", reason.as_str())),
+            Event::Replaced { ord_id, price, qty, .. } => {
+                if let Some(o) = self.orders.get_mut(&ord_id) {
+                    o.price = price;
+                    o.qty = o.cum + qty;
+                }
+                out.push(self.report(ord_id, "replaced", None, "", false));
+            }
+            Event::Ack { ord_id, note } => out.push(self.report(ord_id, note.as_str(), None, "", false)),
+            evt => out.push(evt),
+        }
+        out
+    }
+
+    fn leaves(&self, ord_id: u64) -> u64 {
+        self.orders.get(&ord_id).map_or(0, |o| o.qty.saturating_sub(o.cum))
+    }
+
+    fn fill(&mut self, ord_id: u64, t: &Trade, fee: u64) -> Event {
+        if let Some(o) = self.orders.get_mut(&ord_id) {
+            o.cum += t.qty;
+        }
+        self.report(ord_id, "trade", Some((t.price, t.qty, fee)), "", false)
+    }
+
+    /// The order's last report: nothing is left to fill, and it is no longer tracked.
+    fn closed(&mut self, ord_id: u64, exec_type: &str, reason: &str) -> Event {
+        let report = self.report(ord_id, exec_type, None, reason, true);
+        self.forget(ord_id);
+        report
+    }
+
+    fn forget(&mut self, ord_id: u64) {
+        if let Some(exch_ord_id) = self.orders.remove(&ord_id).and_then(|o| o.exch_ord_id) {
+            self.by_exch.remove(&exch_ord_id);
+        }
+    }
+
+    /// One line. `last` is the fill's (price, qty, fee); other reports show the order's price and size.
+    fn report(&self, ord_id: u64, exec_type: &str, last: Option<(i64, u64, u64)>, reason: &str, closed: bool) -> Event {
+        let mut line = format!("ord_id={ord_id} exec_type={exec_type}");
+        if let Some(o) = self.orders.get(&ord_id) {
+            let side = match o.side { Side::Bid => "buy", Side::Ask => "sell" };
+            let (price, qty) = last.map_or((o.price, o.qty), |(px, q, _)| (px, q));
+            let leaves = if closed { 0 } else { o.qty.saturating_sub(o.cum) };
+            let q = |v| o.scale.render(v);
+            let _ = write!(line, " side={side} price={price} qty={} leaves_qty={} cum_qty={}", q(qty), q(leaves), q(o.cum));
+        }
+        if let Some((_, _, fee)) = last {
+            let _ = write!(line, " fee={fee}");
+        }
+        if !reason.is_empty() {
+            let _ = write!(line, " reason={reason}");
+        }
+        Event::ExecReport(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(maker_ord_id: u64, taker_ord_id: u64, qty: u64) -> Event {
+        Event::Trade(Trade {
+            symbol: 0, price: 100, qty, taker_cl_id: 2, maker_cl_id: 1, taker_ord_id, maker_ord_id,
+            aggressor_side: Side::Ask, maker_fee: 0, taker_fee: 0, seq: 0, price_improvement: 0,
+        })
+    }
+
+    fn lines(events: Vec<Event>) -> Vec<String> {
+        events.into_iter().map(|e| match e {
+            Event::ExecReport(line) => line,
+            e => panic!("not a report: {e:?}"),
+        }).collect()
+    }
+
+    #[test]
+    fn fills_count_up_cum_qty_and_down_leaves_qty() {
+        let mut r = ExecReports::default();
+        let accepted = Event::Accepted { ord_id: 5, exch_ord_id: 1, filled_qty: 0, resting_qty: 10, side: Side::Bid, price: 100 };
+        assert_eq!(lines(r.render(accepted)), ["ord_id=5 exec_type=new side=buy price=100 qty=10 leaves_qty=10 cum_qty=0"]);
+        assert_eq!(lines(r.render(trade(1, 9, 4))), ["ord_id=5 exec_type=trade side=buy price=100 qty=4 leaves_qty=6 cum_qty=4 fee=0"]);
+        assert_eq!(lines(r.render(trade(1, 9, 6))), ["ord_id=5 exec_type=trade side=buy price=100 qty=6 leaves_qty=0 cum_qty=10 fee=0"]);
+        assert_eq!(
+            lines(r.render(Event::Done { ord_id: 5, reason: DoneReason::Filled })),
+            ["ord_id=5 exec_type=filled side=buy price=100 qty=10 leaves_qty=0 cum_qty=10"],
+        );
+    }
+
+    #[test]
+    fn taker_fills_wait_for_the_accepted_then_count_against_the_whole_order() {
+        let mut r = ExecReports::default();
+        assert!(r.render(trade(7, 2, 3)).is_empty());
+        let accepted = Event::Accepted { ord_id: 6, exch_ord_id: 2, filled_qty: 3, resting_qty: 7, side: Side::Ask, price: 99 };
+        assert_eq!(lines(r.render(accepted)), [
+            "ord_id=6 exec_type=new side=sell price=99 qty=10 leaves_qty=10 cum_qty=0",
+            "ord_id=6 exec_type=trade side=sell price=100 qty=3 leaves_qty=7 cum_qty=3 fee=0",
+        ]);
+    }
+}
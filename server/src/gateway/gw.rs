@@ -4,25 +4,31 @@ use tokio::{
     sync::mpsc,
     time::{interval, Duration},
 };
-use bytes::{BytesMut, Buf, BufMut};
-use std::{convert::TryInto, time::Instant};
-
-const MSG_PING: u16 = 1;
-const MSG_ACK:  u16 = 100;
+use bytes::BytesMut;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use clob_engine::protocol::{
+    decode_frame, encode_frame, encode_nack, Frame, FrameFormat, MSG_ACK, MSG_CANCEL, MSG_NEW_ORDER, MSG_PING, MSG_REDUCE, MSG_REPLACE,
+};
 
 /// Send: [u32 len][u16 MSG_ACK][u16 body_len][body…]
 async fn ack(sock: &mut TcpStream, body: &[u8]) -> anyhow::Result<()> {
-    let total = 2 + 2 + body.len();
-    let mut out = BytesMut::with_capacity(4 + total);
-    out.put_u32_le(total as u32);
-    out.put_u16_le(MSG_ACK);
-    out.put_u16_le(body.len() as u16);
-    out.extend_from_slice(body);
-    sock.write_all(&out).await?;
+    sock.write_all(&encode_frame(MSG_ACK, body, FrameFormat::default())).await?;
     Ok(())
 }
 
-async fn process(mut socket: TcpStream, lat_tx: mpsc::UnboundedSender<u64>) -> anyhow::Result<()> {
+/// One latency sample: the frame's symbol (None off the order path) and its micros.
+type Sample = (Option<u32>, u64);
+
+/// NEW_ORDER, CANCEL, REPLACE and REDUCE carry `[u32 symbol_id]` after `[u64 client_id][u64 cl_ord_id]`
+fn order_symbol(frame: &Frame) -> Option<u32> {
+    match frame.msg_type {
+        MSG_NEW_ORDER | MSG_CANCEL | MSG_REPLACE | MSG_REDUCE => frame.body.get(16..20).map(|b| u32::from_le_bytes(b.try_into().unwrap())),
+        _ => None,
+    }
+}
+
+async fn process(mut socket: TcpStream, lat_tx: mpsc::UnboundedSender<Sample>) -> anyhow::Result<()> {
     socket.set_nodelay(true)?;
     let mut buf = BytesMut::with_capacity(16 * 1024);
 
@@ -31,30 +37,28 @@ async fn process(mut socket: TcpStream, lat_tx: mpsc::UnboundedSender<u64>) -> a
         if n == 0 { break; }
 
         loop {
-            if buf.len() < 4 { break; }
-            let payload_len = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
-            if buf.len() < 4 + payload_len { break; }
+            let frame = match decode_frame(&mut buf, FrameFormat::default()) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                // Desynced stream: NACK and close
+                Err(e) if e.is_fatal() => {
+                    eprintln!("⛔ {e} — closing");
+                    socket.write_all(&encode_nack(e.msg_type(), e.reason(), FrameFormat::default())).await?;
+                    return Ok(());
+                }
+                // Well-delimited frame with a bad body: NACK and read on
+                Err(e) => {
+                    eprintln!("⚠️ {e}");
+                    socket.write_all(&encode_nack(e.msg_type(), e.reason(), FrameFormat::default())).await?;
+                    continue;
+                }
+            };
 
             // Start timing when a full frame is available
             let t0 = Instant::now();
+            let symbol = order_symbol(&frame);
 
-            let mut frame = buf.split_to(4 + payload_len);
-            frame.advance(4);
-
-            if frame.len() < 4 {
-                // malformed
-                continue;
-            }
-
-            let msg_type = frame.get_u16_le();
-            let body_len = frame.get_u16_le() as usize;
-            if frame.len() < body_len {
-                // malformed
-                continue;
-            }
-            let _body = frame.split_to(body_len);
-
-            match msg_type {
+            match frame.msg_type {
                 MSG_PING => ack(&mut socket, b"pong").await?,
                 _ => ack(&mut socket, b"").await?,
             }
@@ -62,48 +66,114 @@ async fn process(mut socket: TcpStream, lat_tx: mpsc::UnboundedSender<u64>) -> a
             // Stop timer ONLY after ACK write completes; send micros to metrics task
             let dt = t0.elapsed().as_micros() as u64;
             // best-effort (ignore send error if shutting down)
-            let _ = lat_tx.send(dt);
+            let _ = lat_tx.send((symbol, dt));
         }
     }
     Ok(())
 }
 
-/// Single background task that aggregates latencies and prints p50/p95/p99 every `period_s`.
+/// Histogram bounds: `LAT_MAX_US` (default 10 s) and `LAT_SIGFIG` significant digits (default 3), from the env.
+#[derive(Clone, Copy, Debug)]
+struct LatencyBounds {
+    max_us: u64,
+    sigfig: u8,
+}
+
+impl LatencyBounds {
+    const DEFAULT: LatencyBounds = LatencyBounds { max_us: 10_000_000, sigfig: 3 };
+
+    fn from_env() -> Self {
+        LatencyBounds {
+            max_us: std::env::var("LAT_MAX_US").ok().and_then(|v| v.parse().ok()).unwrap_or(Self::DEFAULT.max_us),
+            sigfig: std::env::var("LAT_SIGFIG").ok().and_then(|v| v.parse().ok()).unwrap_or(Self::DEFAULT.sigfig),
+        }
+    }
+}
+
+/// Latencies in microseconds; anything above `max_us` goes to `overflow` instead of the histogram.
+struct LatencyHist {
+    hist: hdrhistogram::Histogram<u64>,
+    max_us: u64,
+    overflow: u64,
+}
+
+impl LatencyHist {
+    fn new(bounds: LatencyBounds) -> Self {
+        let (hist, max_us) = match hdrhistogram::Histogram::new_with_bounds(1, bounds.max_us, bounds.sigfig) {
+            Ok(hist) => (hist, bounds.max_us),
+            Err(e) => {
+                let LatencyBounds { max_us, sigfig } = LatencyBounds::DEFAULT;
+                eprintln!("⚠️ latency histogram {bounds:?}: {e}, using max_us={max_us} sigfig={sigfig}");
+                (hdrhistogram::Histogram::new_with_bounds(1, max_us, sigfig).expect("valid default bounds"), max_us)
+            }
+        };
+        LatencyHist { hist, max_us, overflow: 0 }
+    }
+
+    // The histogram itself tracks a little past `max_us` (its top bucket is rounded up), so check the bound here
+    fn record(&mut self, v: u64) {
+        if v > self.max_us {
+            self.overflow += 1;
+        } else if let Err(e) = self.hist.record(v) {
+            eprintln!("⚠️ latency {v}µs not recorded: {e}");
+            self.overflow += 1;
+        }
+    }
+}
+
+/// One window's report lines: p50/p95/p99 per symbol (`-` off the order path), in symbol order.
+fn report(period_s: u64, lats: &BTreeMap<Option<u32>, LatencyHist>) -> Vec<String> {
+    if lats.is_empty() {
+        return vec![format!("[latency {}s] n=0 (no messages)", period_s)];
+    }
+    lats.iter()
+        .map(|(symbol, lat)| {
+            let sym = symbol.map_or("-".to_string(), |s| s.to_string());
+            let hist = &lat.hist;
+            let count = hist.len();
+            if count > 0 {
+                let p50 = hist.value_at_quantile(0.50);
+                let p95 = hist.value_at_quantile(0.95);
+                let p99 = hist.value_at_quantile(0.99);
+                let max = hist.max();
+                let min = hist.min();
+                format!(
+                    "[latency {}s] sym={} n={} p50={}µs p95={}µs p99={}µs min={}µs max={}µs overflow={}",
+                    period_s, sym, count, p50, p95, p99, min, max, lat.overflow
+                )
+            } else {
+                format!("[latency {}s] sym={} n=0 overflow={}", period_s, sym, lat.overflow)
+            }
+        })
+        .collect()
+}
+
+/// Single background task that aggregates latencies per symbol and prints p50/p95/p99 of each every `period_s`.
 async fn spawn_latency_reporter(
-    mut lat_rx: mpsc::UnboundedReceiver<u64>,
+    mut lat_rx: mpsc::UnboundedReceiver<Sample>,
     period_s: u64,
+    bounds: LatencyBounds,
 ) {
-    use hdrhistogram::Histogram;
-    // Track 1 microsecond .. 10 seconds, 3 significant digits
-    let mut hist = Histogram::<u64>::new_with_bounds(1, 10_000_000, 3).unwrap();
+    // One histogram per symbol seen this window, each tracking 1 microsecond .. bounds.max_us;
+    // slower messages are only counted, as overflow
+    let mut lats: BTreeMap<Option<u32>, LatencyHist> = BTreeMap::new();
     let mut tick = interval(Duration::from_secs(period_s));
     tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
     loop {
         tokio::select! {
             // receive latencies continuously
-            Some(v) = lat_rx.recv() => {
+            Some((symbol, v)) = lat_rx.recv() => {
                 // v is in microseconds
-                let _ = hist.record(v);
+                lats.entry(symbol).or_insert_with(|| LatencyHist::new(bounds)).record(v);
             }
             // periodic report
             _ = tick.tick() => {
-                let count = hist.len();
-                if count > 0 {
-                    let p50 = hist.value_at_quantile(0.50);
-                    let p95 = hist.value_at_quantile(0.95);
-                    let p99 = hist.value_at_quantile(0.99);
-                    let max = hist.max();
-                    let min = hist.min();
-                    println!(
-                        "[latency {}s] n={} p50={}µs p95={}µs p99={}µs min={}µs max={}µs",
-                        period_s, count, p50, p95, p99, min, max
-                    );
-                    // reset for the next window (rolling intervals)
-                    hist.reset();
-                } else {
-                    println!("[latency {}s] n=0 (no messages)", period_s);
+                for line in report(period_s, &lats) {
+                    println!("{line}");
                 }
+                // next window starts empty (rolling intervals); symbols with no messages drop out of the report
+                lats.clear();
             }
         }
     }
@@ -114,10 +184,10 @@ async fn main() -> anyhow::Result<()> {
     let listener = TcpListener::bind("0.0.0.0:9000").await?;
     println!("🚀 listening on {}", listener.local_addr()?);
 
-    // Channel: hot path just does `lat_tx.send(micros)` (non-blocking)
-    let (lat_tx, lat_rx) = mpsc::unbounded_channel::<u64>();
+    // Channel: hot path just does `lat_tx.send((symbol, micros))` (non-blocking)
+    let (lat_tx, lat_rx) = mpsc::unbounded_channel::<Sample>();
     // Print every 5 seconds (tune to taste)
-    tokio::spawn(spawn_latency_reporter(lat_rx, 5));
+    tokio::spawn(spawn_latency_reporter(lat_rx, 5, LatencyBounds::from_env()));
 
     loop {
         let (socket, addr) = listener.accept().await?;
@@ -130,3 +200,39 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_past_the_max_is_counted_as_overflow() {
+        let mut lat = LatencyHist::new(LatencyBounds { max_us: 1_000, sigfig: 3 });
+        lat.record(1);
+        lat.record(1_000);
+        lat.record(1_001);
+        lat.record(10_000_000);
+        assert_eq!((lat.hist.len(), lat.overflow), (2, 2));
+        assert!(lat.hist.max() <= 1_000 + 1, "max={}", lat.hist.max());
+
+        // Bounds hdrhistogram refuses fall back to the defaults
+        let lat = LatencyHist::new(LatencyBounds { max_us: 10, sigfig: 9 });
+        assert_eq!(lat.max_us, LatencyBounds::DEFAULT.max_us);
+    }
+
+    #[test]
+    fn each_symbol_is_reported_on_its_own() {
+        let bounds = LatencyBounds { max_us: 1_000, sigfig: 3 };
+        let mut lats: BTreeMap<Option<u32>, LatencyHist> = BTreeMap::new();
+        let samples = [(Some(7), 10), (Some(2), 500), (Some(7), 20), (None, 3), (Some(2), 2_000), (Some(7), 30)];
+        for (symbol, v) in samples {
+            lats.entry(symbol).or_insert_with(|| LatencyHist::new(bounds)).record(v);
+        }
+        assert_eq!(report(5, &lats), [
+            "[latency 5s] sym=- n=1 p50=3µs p95=3µs p99=3µs min=3µs max=3µs overflow=0",
+            "[latency 5s] sym=2 n=1 p50=500µs p95=500µs p99=500µs min=500µs max=500µs overflow=1",
+            "[latency 5s] sym=7 n=3 p50=20µs p95=30µs p99=30µs min=10µs max=30µs overflow=0",
+        ]);
+        assert_eq!(report(5, &BTreeMap::new()), ["[latency 5s] n=0 (no messages)"]);
+    }
+}
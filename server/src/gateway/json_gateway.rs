@@ -0,0 +1,319 @@
+use tokio::net::{TcpListener, TcpStream};
+use bytes::{BufMut, Bytes, BytesMut};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio_util::codec::{Framed, LinesCodec};
+use clob_engine::codec::ClobCodec;
+use clob_engine::protocol::*;
+use clob_engine::types::{Event, OrderType, RejectReason, Side, Stp, Tif};
+
+// ========================== JSON gateway ==========================
+// Line-delimited JSON in front of the binary protocol, for manual testing and scripts.
+// Each JSON connection gets its own binary connection to the engine (ENGINE_ADDR): request
+// lines become frames, and every frame the engine sends back becomes one JSON line.
+
+/// One request line, picked by `type`. Fields left out default as they do on the wire.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    Ping,
+    Hello {
+        #[serde(default)]
+        cancel_on_disconnect: bool,
+    },
+    AdminAuth {
+        token: String, // Never sent: the gateway answers the engine's challenge with an HMAC of it
+    },
+    NewOrder {
+        cl_id: u64,
+        ord_id: Option<u64>, // Picked by the gateway when missing, counting up from 1 per connection
+        #[serde(default)]
+        symbol: u32,
+        side: Side,
+        #[serde(default)]
+        price: i64,
+        qty: i64,
+        tif: Option<Tif>,
+        ord_type: Option<OrderType>,
+        #[serde(default)]
+        post_only: bool,
+        #[serde(default)]
+        reduce_only: bool,
+        stp: Option<Stp>,
+        #[serde(default)]
+        expire_ms: u64,
+        #[serde(default)]
+        display_qty: u64,
+        #[serde(default)]
+        stop_price: i64,
+    },
+    Cancel {
+        cl_id: u64,
+        ord_id: u64,
+        #[serde(default)]
+        symbol: u32,
+    },
+    Replace {
+        cl_id: u64,
+        ord_id: u64,
+        #[serde(default)]
+        symbol: u32,
+        price: i64,
+        qty: i64,
+    },
+    Reduce {
+        cl_id: u64,
+        ord_id: u64,
+        #[serde(default)]
+        symbol: u32,
+        qty: i64,
+    },
+    CancelAll {
+        cl_id: u64,
+        symbol: Option<u32>,
+    },
+    Subscribe {
+        #[serde(default)]
+        conflate_ms: u16,
+        #[serde(default)]
+        orders: bool,
+        snapshot_symbol: Option<u32>, // Anchor the feed to a snapshot of this symbol
+        snapshot_depth: Option<u16>,  // Levels per side in it; every level the engine allows when missing
+    },
+    Snapshot {
+        #[serde(default)]
+        symbol: u32,
+        depth: u16,
+    },
+    QueryOrders {
+        cl_id: u64,
+    },
+    QueryStats {
+        #[serde(default)]
+        symbol: u32,
+    },
+    Register {
+        cl_id: u64,
+    },
+    SessionRoll {
+        symbol: Option<u32>,
+    },
+    Halt {
+        symbol: u32,
+    },
+    Resume {
+        symbol: u32,
+    },
+    Auction {
+        symbol: u32,
+    },
+    Uncross {
+        symbol: u32,
+    },
+    DropcopySubscribe,
+}
+
+impl Request {
+    /// The binary frame for this request, little-endian and without a checksum.
+    fn into_frame(self, next_ord_id: &mut u64) -> Frame {
+        let mut body = BytesMut::new();
+        let msg_type = match self {
+            Request::Ping => MSG_PING,
+            Request::Hello { cancel_on_disconnect } => {
+                body.put_u16_le(PROTO_VERSION);
+                body.put_u8(cancel_on_disconnect as u8); // Flag bit 0; this gateway never asks for BE or CRC
+                MSG_HELLO
+            }
+            Request::AdminAuth { .. } => MSG_ADMIN_AUTH, // Empty: asks for the challenge
+            Request::NewOrder { cl_id, ord_id, symbol, side, price, qty, tif, ord_type, post_only, reduce_only, stp, expire_ms, display_qty, stop_price } => {
+                let ord_id = ord_id.unwrap_or_else(|| {
+                    *next_ord_id += 1;
+                    *next_ord_id
+                });
+                // The enums are declared in wire-code order
+                body.put_u64_le(cl_id);
+                body.put_u64_le(ord_id);
+                body.put_u32_le(symbol);
+                body.put_u8(side as u8);
+                body.put_i64_le(price);
+                body.put_i64_le(qty);
+                body.put_u8(tif.unwrap_or(Tif::Gtc) as u8);
+                body.put_u8(ord_type.unwrap_or(OrderType::Limit) as u8);
+                body.put_u8(post_only as u8 | (reduce_only as u8) << 1); // NEW_ORDER flag bits
+                body.put_u8(stp.unwrap_or(Stp::Off) as u8);
+                body.put_u64_le(expire_ms);
+                body.put_u64_le(display_qty);
+                body.put_i64_le(stop_price);
+                MSG_NEW_ORDER
+            }
+            Request::Cancel { cl_id, ord_id, symbol } => {
+                body.put_u64_le(cl_id);
+                body.put_u64_le(ord_id);
+                body.put_u32_le(symbol);
+                MSG_CANCEL
+            }
+            Request::Replace { cl_id, ord_id, symbol, price, qty } => {
+                body.put_u64_le(cl_id);
+                body.put_u64_le(ord_id);
+                body.put_u32_le(symbol);
+                body.put_i64_le(price);
+                body.put_i64_le(qty);
+                MSG_REPLACE
+            }
+            Request::Reduce { cl_id, ord_id, symbol, qty } => {
+                body.put_u64_le(cl_id);
+                body.put_u64_le(ord_id);
+                body.put_u32_le(symbol);
+                body.put_i64_le(qty);
+                MSG_REDUCE
+            }
+            Request::CancelAll { cl_id, symbol } => {
+                body.put_u64_le(cl_id);
+                if let Some(symbol) = symbol {
+                    body.put_u32_le(symbol);
+                }
+                MSG_CANCEL_ALL
+            }
+            Request::Subscribe { conflate_ms, orders, snapshot_symbol, snapshot_depth } => {
+                // Flag bit 0: the order-level feed too; bit 1: the snapshot, with [u32 symbol][u16 depth]
+                body.put_u16_le(conflate_ms);
+                body.put_u8(orders as u8 | (snapshot_symbol.is_some() as u8) << 1);
+                if let Some(symbol) = snapshot_symbol {
+                    body.put_u32_le(symbol);
+                    body.put_u16_le(snapshot_depth.unwrap_or(u16::MAX));
+                }
+                MSG_SUBSCRIBE
+            }
+            Request::Snapshot { symbol, depth } => {
+                body.put_u32_le(symbol);
+                body.put_u16_le(depth);
+                MSG_BOOK_SNAPSHOT
+            }
+            Request::QueryOrders { cl_id } => {
+                body.put_u64_le(cl_id);
+                MSG_QUERY_ORDERS
+            }
+            Request::QueryStats { symbol } => {
+                body.put_u32_le(symbol);
+                MSG_QUERY_STATS
+            }
+            Request::Register { cl_id } => {
+                body.put_u64_le(cl_id);
+                MSG_REGISTER
+            }
+            Request::SessionRoll { symbol } => {
+                if let Some(symbol) = symbol {
+                    body.put_u32_le(symbol);
+                }
+                MSG_SESSION_ROLL
+            }
+            Request::Halt { symbol } | Request::Resume { symbol } | Request::Auction { symbol } | Request::Uncross { symbol } => {
+                body.put_u32_le(symbol);
+                match self {
+                    Request::Halt { .. } => MSG_HALT,
+                    Request::Resume { .. } => MSG_RESUME,
+                    Request::Auction { .. } => MSG_AUCTION,
+                    _ => MSG_UNCROSS,
+                }
+            }
+            Request::DropcopySubscribe => MSG_DROPCOPY_SUBSCRIBE,
+        };
+        Frame { msg_type, body: body.freeze() }
+    }
+}
+
+/// Lines that aren't engine events (those serialize as `Event`, tagged the same way).
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Nack { ref_msg_type: u16, reason: Option<RejectReason> },
+    Error { error: String }, // The request line couldn't be parsed; nothing was sent
+}
+
+async fn process(client: TcpStream, engine_addr: &str) -> anyhow::Result<()> {
+    let peer_addr = client.peer_addr()?;
+    let engine = TcpStream::connect(engine_addr).await?;
+    engine.set_nodelay(true)?;
+    let mut engine = Framed::new(engine, ClobCodec::default());
+    let mut json = Framed::new(client, LinesCodec::new_with_max_length(MAX_FRAME_LEN));
+    let mut next_ord_id = 0;
+    let mut admin_token: Option<String> = None; // Waiting for the ADMIN_CHALLENGE to answer with it
+
+    // The engine wants a HELLO first; its HELLO_ACK tells the JSON client it is connected
+    let hello = Request::Hello { cancel_on_disconnect: false };
+    engine.send(hello.into_frame(&mut next_ord_id)).await?;
+
+    loop {
+        tokio::select! {
+            line = json.next() => {
+                let Some(line) = line else {
+                    println!("🔴 {peer_addr} closed");
+                    break;
+                };
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<Request>(&line) {
+                    Ok(req) => {
+                        if let Request::AdminAuth { token } = &req {
+                            admin_token = Some(token.clone());
+                        }
+                        engine.send(req.into_frame(&mut next_ord_id)).await?
+                    }
+                    Err(e) => json.send(serde_json::to_string(&Reply::Error { error: e.to_string() })?).await?,
+                }
+            }
+
+            frame = engine.next() => {
+                let Some(frame) = frame else {
+                    println!("🔴 engine closed {peer_addr}'s connection");
+                    break;
+                };
+                let frame = frame?.map_err(|e| anyhow::anyhow!("engine sent a bad frame: {e}"))?;
+                let line = if frame.msg_type == MSG_NACK {
+                    // [u16 ref_msg_type][u16 code][u16 reason_len][reason...]
+                    let ref_msg_type = ByteOrder::Little.read_u16(&frame.body[0..2]);
+                    let reason = RejectReason::from_code(ByteOrder::Little.read_u16(&frame.body[2..4]));
+                    serde_json::to_string(&Reply::Nack { ref_msg_type, reason })?
+                } else if let Some(evt) = decode_event(frame.msg_type, &frame.body, ByteOrder::Little) {
+                    if let (Event::AdminChallenge { nonce }, Some(token)) = (&evt, admin_token.take()) {
+                        let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC takes keys of any length");
+                        mac.update(nonce);
+                        let answer = mac.finalize().into_bytes();
+                        engine.send(Frame { msg_type: MSG_ADMIN_AUTH, body: Bytes::copy_from_slice(&answer) }).await?;
+                        continue;
+                    }
+                    serde_json::to_string(&evt)?
+                } else {
+                    eprintln!("⚠️ {peer_addr}: skipping msg_type={} the gateway can't decode", frame.msg_type);
+                    continue;
+                };
+                json.send(line).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let addr = std::env::var("JSON_ADDR").unwrap_or_else(|_| "0.0.0.0:9002".to_string());
+    let engine_addr: Arc<str> = std::env::var("ENGINE_ADDR").unwrap_or_else(|_| "127.0.0.1:9000".to_string()).into();
+    let listener = TcpListener::bind(&addr).await?;
+    println!("🚀 JSON gateway listening on {} → engine {}", listener.local_addr()?, engine_addr);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        println!("✅ accepted {addr}");
+        let engine_addr = engine_addr.clone();
+        tokio::spawn(async move {
+            if let Err(e) = process(socket, &engine_addr).await {
+                eprintln!("💥 {addr} error: {e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,15 @@
+// Engine, persistence and wire protocol; the binaries are thin gateways on top
+pub mod types;
+pub mod book;
+pub mod engine;
+pub mod sequencer;
+pub mod wal;
+pub mod snapshot;
+pub mod record;
+pub mod config;
+pub mod protocol;
+pub mod codec;
+pub mod metrics;
+pub mod debug_http;
+pub mod sink;
+pub mod exec_report;
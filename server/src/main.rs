@@ -1,150 +1,1019 @@
 use tokio::{
-    io::AsyncReadExt,
-    net::{TcpStream, TcpListener}
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::{broadcast, mpsc, Semaphore},
+    time::{interval, sleep_until, Instant, Interval, MissedTickBehavior},
 };
-use crossbeam::channel::{bounded, Receiver, Sender};
-use bytes::{BytesMut, Buf};
-use tracing::{error, info};
+use crossbeam::channel::{bounded, Sender, TrySendError};
+use futures::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio_util::codec::Framed;
+use tracing::{error, info, info_span, warn, Instrument};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::str::FromStr;
 use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-mod types;
-mod engine;
-use crate::types::{Command, Event, Order, Side, Tif};
-use crate::engine::run_engine;
+use clob_engine::{config, debug_http, snapshot, wal};
+use clob_engine::codec::{ClobCodec, ReadError};
+use clob_engine::config::SymbolConfig;
+use clob_engine::exec_report::ExecReports;
+use clob_engine::record::Recorder;
+use clob_engine::protocol::*;
+use clob_engine::types::{AckNote, Command, Event, MarketState, MatchPolicy, QtyScale, RejectReason, RiskLimit, RiskLimits, Side};
+use clob_engine::engine::{recover, run_engine, CandleConfig, MdFeed, DEFAULT_DRAIN_BATCH};
+use clob_engine::metrics::{self, Metrics};
+use clob_engine::sequencer::{run_sequencer, Stamped};
+use clob_engine::sink::{self, ClientSink};
+use clob_engine::wal::Wal;
 
 use tracing_appender::rolling;
-use tracing_subscriber::fmt;
 
-// ========================== Protocol ==========================
-// Frame: [u32 len][u16 type][u16 body_len][payload...]
-// Side encoding: 0 = BID, 1 = ASK
+// HELLO flag bits
+const HELLO_CANCEL_ON_DISCONNECT: u8 = 0x01;
+const HELLO_EXEC_REPORTS: u8 = 0x02;
+const HELLO_BIG_ENDIAN: u8 = 0x04;
+const HELLO_CRC32: u8 = 0x08;
 
-const MSG_PING: u16 = 1;
-const MSG_NEW_ORDER: u16 = 10;
-const MSG_CANCEL: u16 = 11;
+// SUBSCRIBE flag bits
+const SUBSCRIBE_ORDERS: u8 = 0x01;
+const SUBSCRIBE_SNAPSHOT: u8 = 0x02;
+const SUBSCRIBE_TRADE_PRINTS: u8 = 0x04;
+
+// Transient read errors in a row before the connection gives up on its socket
+const MAX_READ_RETRIES: u32 = 8;
+
+// ========================== Rate Limiting ==========================
+
+// Order-entry budget per connection: `per_sec` sustained, up to `burst` at once
+#[derive(Clone, Copy)]
+struct RateLimit {
+    per_sec: u32,
+    burst: u32,
+}
+
+// Token bucket refilled lazily from elapsed time; one token per order-entry frame, or per order of a batch
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_sec: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            tokens: limit.burst as f64,
+            capacity: limit.burst as f64,
+            refill_per_sec: limit.per_sec as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.refill_per_sec;
+        self.tokens = (self.tokens + refill).min(self.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// ========================== Admin Authentication ==========================
+
+// ADMIN_AUTH: the gateway sends a random nonce and the client answers with
+// HMAC-SHA256(ADMIN_TOKEN, nonce), so the token itself never crosses the wire
+const ADMIN_NONCE_LEN: usize = 16;
+const ADMIN_MAC_LEN: usize = 32;
+
+fn admin_nonce() -> [u8; ADMIN_NONCE_LEN] {
+    let mut nonce = [0; ADMIN_NONCE_LEN];
+    getrandom::fill(&mut nonce).expect("OS random source");
+    nonce
+}
+
+/// Whether `mac` is the HMAC of `nonce` under `token`; the comparison takes constant time.
+fn admin_mac_ok(token: &str, nonce: &[u8], mac: &[u8]) -> bool {
+    let mut hmac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC takes keys of any length");
+    hmac.update(nonce);
+    hmac.verify_slice(mac).is_ok()
+}
+
+/// Numeric setting from `var`. Unset keeps `default`; so does a value that doesn't parse or that
+/// `valid` refuses, with a warning. A mistyped tunable shouldn't keep the engine down.
+fn env_or<T: FromStr + std::fmt::Display>(var: &str, default: T, valid: impl Fn(&T) -> bool) -> T {
+    let Ok(value) = std::env::var(var) else {
+        return default;
+    };
+    match value.trim().parse::<T>() {
+        Ok(v) if valid(&v) => v,
+        _ => {
+            warn!("{var}={value:?} is not a valid setting, using the default {default}");
+            default
+        }
+    }
+}
+
+/// Per-symbol engine config from `var`: `symbol=value` entries separated by commas, e.g.
+/// "1=5:100,7=1:10"; unset = none. A malformed entry stops startup, naming the `shape` it should have.
+fn parse_symbol_map<T: FromStr>(var: &str, shape: &str) -> anyhow::Result<Vec<(u32, T)>>
+where
+    T::Err: std::fmt::Display,
+{
+    let Ok(list) = std::env::var(var) else {
+        return Ok(Vec::new());
+    };
+    list.split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .map(|entry| {
+            let Some((symbol, value)) = entry.split_once('=') else {
+                anyhow::bail!("{var} entry {entry:?} is not {shape}");
+            };
+            let symbol: u32 = symbol.trim().parse().map_err(|e| anyhow::anyhow!("{var} entry {entry:?}: symbol {e}"))?;
+            let value = value.trim().parse().map_err(|e| anyhow::anyhow!("{var} entry {entry:?}: {e}"))?;
+            Ok((symbol, value))
+        })
+        .collect()
+}
+
+/// Fold the `symbol=value` entries of `var` into `symbols`, as their `field`.
+fn fold_symbol_env<T: FromStr>(
+    symbols: &mut BTreeMap<u32, SymbolConfig>,
+    var: &str,
+    shape: &str,
+    field: &str,
+    slot: fn(&mut SymbolConfig) -> &mut Option<T>,
+) -> anyhow::Result<()>
+where
+    T::Err: std::fmt::Display,
+{
+    for (symbol, value) in parse_symbol_map::<T>(var, shape)? {
+        let c = symbols.entry(symbol).or_insert_with(|| SymbolConfig::new(symbol));
+        set_once(slot(c), value, symbol, field, var)?;
+    }
+    Ok(())
+}
+
+/// Set a symbol's config `field` from `source`, unless SYMBOL_CONFIG or an earlier entry already did.
+fn set_once<T>(slot: &mut Option<T>, value: T, symbol: u32, field: &str, source: &str) -> anyhow::Result<()> {
+    if slot.is_some() {
+        anyhow::bail!("symbol {symbol}: {field} is set more than once (again by {source})");
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+// ========================== Market-Data Conflation ==========================
+
+// Coalescing for a subscriber that asked for `conflate_ms`: only the latest BookDelta per
+// (symbol, side, price) and the latest BBO per symbol survive until the next flush.
+// Trades are never held back. Flushed events keep their own `seq`, so gaps are expected.
+struct Conflator {
+    flush: Interval,
+    levels: HashMap<(u32, Side, i64), Event>,
+    bbos: HashMap<u32, Event>,
+}
+
+impl Conflator {
+    fn new(every: Duration) -> Self {
+        let mut flush = interval(every);
+        flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Conflator { flush, levels: HashMap::new(), bbos: HashMap::new() }
+    }
+
+    /// Buffer `evt` if it can be conflated; otherwise hand it back to be sent now.
+    fn offer(&mut self, evt: Event) -> Option<Event> {
+        match evt {
+            Event::BookDelta { symbol, side, price, .. } => {
+                self.levels.insert((symbol, side, price), evt);
+                None
+            }
+            Event::Bbo { symbol, .. } => {
+                self.bbos.insert(symbol, evt);
+                None
+            }
+            evt => Some(evt),
+        }
+    }
+
+    /// Everything buffered since the last flush, in feed order.
+    fn drain(&mut self) -> Vec<Event> {
+        let mut out: Vec<Event> = self.levels.drain().map(|(_, evt)| evt).chain(self.bbos.drain().map(|(_, evt)| evt)).collect();
+        out.sort_by_key(Event::seq);
+        out
+    }
+}
+
+// ========================== Channel Capacities ==========================
+
+// Queue sizes, read once at startup from CMD_CAP, BCAST_CAP and CONN_EVT_CAP
+#[derive(Debug, Clone, Copy)]
+struct ChannelCaps {
+    cmd: usize, // Commands waiting for the engine, and for the sequencer when it is on
+    bcast: usize, // Events each broadcast feed (market data, drop copy, order-level) keeps for its slowest subscriber
+    conn_evt: usize, // Events queued to one connection
+}
+
+impl ChannelCaps {
+    const DEFAULT: ChannelCaps = ChannelCaps { cmd: 10_000, bcast: 10_000, conn_evt: 2048 };
+    // Every queue allocates its whole capacity up front
+    const MAX: usize = 1 << 24;
+
+    // Anything but 1..=MAX keeps the default
+    fn from_env() -> Self {
+        let d = Self::DEFAULT;
+        let cap = |var, default| env_or(var, default, |n| (1..=Self::MAX).contains(n));
+        ChannelCaps { cmd: cap("CMD_CAP", d.cmd), bcast: cap("BCAST_CAP", d.bcast), conn_evt: cap("CONN_EVT_CAP", d.conn_evt) }
+    }
+}
 
 // ========================== Task Process ==========================
 
-async fn process(
-    mut socket: TcpStream,
-    tx_cmd: Sender<Command>,
-    sink_to_engine: Sender<Event>,
-    rx_evt: Receiver<Event>,
+/// Where connection tasks hand their commands: straight to the engine, or to the sequencer
+/// (`SEQUENCE_WINDOW_US`) stamped with the instant their frame arrived.
+#[derive(Clone)]
+enum Ingress {
+    Direct(Sender<Command>),
+    Sequenced(Sender<Stamped>),
+}
+
+impl Ingress {
+    /// Without blocking; on failure the command is dropped.
+    fn try_send(&self, cmd: Command, received: std::time::Instant) -> Result<(), TrySendError<()>> {
+        let full = match self {
+            Ingress::Direct(tx) => tx.try_send(cmd).map_err(|e| e.is_full()),
+            Ingress::Sequenced(tx) => tx.try_send(Stamped { ingress: received, cmd }).map_err(|e| e.is_full()),
+        };
+        full.map_err(|full| if full { TrySendError::Full(()) } else { TrySendError::Disconnected(()) })
+    }
+
+    /// Queue `cmds` in order without blocking the runtime: whatever doesn't fit right now is handed
+    /// to a blocking-pool thread that waits for room. For commands that must not be lost.
+    /// Returns how many had to wait, or `None` once the engine is gone.
+    fn send_or_defer(&self, cmds: Vec<Command>) -> Option<usize> {
+        let mut cmds = cmds.into_iter();
+        while let Some(cmd) = cmds.next() {
+            let full = match self {
+                Ingress::Direct(tx) => match tx.try_send(cmd) {
+                    Ok(()) => continue,
+                    Err(TrySendError::Full(cmd)) => cmd,
+                    Err(TrySendError::Disconnected(_)) => return None,
+                },
+                Ingress::Sequenced(tx) => match tx.try_send(Stamped { ingress: std::time::Instant::now(), cmd }) {
+                    Ok(()) => continue,
+                    Err(TrySendError::Full(stamped)) => stamped.cmd,
+                    Err(TrySendError::Disconnected(_)) => return None,
+                },
+            };
+            let rest: Vec<Command> = std::iter::once(full).chain(cmds).collect();
+            let deferred = rest.len();
+            let ingress = self.clone();
+            let send_all = move || rest.into_iter().for_each(|cmd| ingress.send(cmd));
+            // Off the runtime (it is shutting down) there is no worker to stall
+            match tokio::runtime::Handle::try_current() {
+                Ok(rt) => drop(rt.spawn_blocking(send_all)),
+                Err(_) => send_all(),
+            }
+            return Some(deferred);
+        }
+        Some(0)
+    }
+
+    /// Blocking send, never on a runtime worker: for the shutdown sentinel, which queues behind every
+    /// command stamped before it, and for commands `send_or_defer` couldn't queue at once.
+    fn send(&self, cmd: Command) {
+        match self {
+            Ingress::Direct(tx) => {
+                let _ = tx.send(cmd);
+            }
+            Ingress::Sequenced(tx) => {
+                let _ = tx.send(Stamped { ingress: std::time::Instant::now(), cmd });
+            }
+        }
+    }
+}
+
+// Remembers every client id that registered or sent orders on this connection. However the task
+// ends, they are deregistered and, with cancel-on-disconnect (opted into with HELLO), flattened
+// Which connection (by session id) holds each client id: the first one to send an order or a
+// REGISTER for it, until it disconnects. Shared by every connection task.
+#[derive(Clone, Default)]
+struct Claims(Arc<Mutex<HashMap<u64, u64>>>);
+
+impl Claims {
+    /// Claim `cl_id` for `session` unless another connection holds it; returns whether `session` holds it now.
+    fn claim(&self, cl_id: u64, session: u64) -> bool {
+        *self.0.lock().unwrap().entry(cl_id).or_insert(session) == session
+    }
+
+    /// Let go of the ones among `cl_ids` that `session` holds.
+    fn release(&self, cl_ids: &HashSet<u64>, session: u64) {
+        let mut held = self.0.lock().unwrap();
+        for cl_id in cl_ids {
+            if held.get(cl_id) == Some(&session) {
+                held.remove(cl_id);
+            }
+        }
+    }
+}
+
+struct DisconnectGuard {
+    enabled: bool,
+    cl_ids: HashSet<u64>, // Client ids this connection holds in `claims`
+    claims: Claims,
+    tx_cmd: Ingress,
+    sink: ClientSink,
+    peer_addr: SocketAddr,
+}
+
+impl Drop for DisconnectGuard {
+    fn drop(&mut self) {
+        let mut cmds = Vec::new();
+        for &cl_id in &self.cl_ids {
+            if self.enabled {
+                println!("🧹 [DISCONNECT] {} gone — canceling all orders of client {}", self.peer_addr, cl_id);
+                cmds.push(Command::CancelAll { cl_id, symbol: None, sink: self.sink.clone() });
+            }
+            cmds.push(Command::Deregister { cl_id, sink: self.sink.clone() });
+        }
+        // A full engine queue delays them rather than dropping them: the client's orders must not outlive it
+        match self.tx_cmd.send_or_defer(cmds) {
+            Some(0) => {}
+            Some(n) => println!("⏳ [DISCONNECT] Engine queue full, {n} commands for {} wait for room", self.peer_addr),
+            None => eprintln!("[gw] engine is gone, disconnect commands for {} not sent", self.peer_addr),
+        }
+        self.claims.release(&self.cl_ids, self.sink.session());
+    }
+}
+
+impl DisconnectGuard {
+    /// Claim `cl_id` for this connection; false if another connection holds it.
+    fn claim(&mut self, cl_id: u64) -> bool {
+        let held = self.cl_ids.contains(&cl_id) || self.claims.claim(cl_id, self.sink.session());
+        if held {
+            self.cl_ids.insert(cl_id);
+        }
+        held
+    }
+}
+
+/// Hand a command to the engine without ever blocking the runtime.
+/// A full queue is answered with a NACK "engine_busy" and the command is dropped.
+/// Returns whether the engine got it.
+async fn forward<S: AsyncRead + AsyncWrite + Unpin>(framed: &mut Framed<S, ClobCodec>, tx_cmd: &Ingress, received: std::time::Instant, msg_type: u16, cmd: Command) -> anyhow::Result<bool> {
+    match tx_cmd.try_send(cmd, received) {
+        Ok(()) => return Ok(true),
+        Err(TrySendError::Full(_)) => {
+            println!("⚠️ [BUSY] Engine queue full, NACK msg_type={msg_type}");
+            framed.send(nack(msg_type, RejectReason::EngineBusy, framed.codec().format.order)).await?;
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            eprintln!("[gw] engine is gone, dropping msg_type={msg_type}");
+        }
+    }
+    Ok(false)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: S,
+    peer_addr: SocketAddr,
+    tx_cmd: Ingress,
+    sink_to_engine: ClientSink,
+    mut rx_evt: mpsc::Receiver<Event>,
+    tx_md: broadcast::Sender<Event>,
+    tx_dropcopy: broadcast::Sender<Event>,
+    tx_orders: broadcast::Sender<Event>,
+    idle_timeout: Duration,
+    rate_limit: RateLimit,
+    metrics: Arc<Metrics>,
+    admin_token: Option<Arc<str>>,
+    qty_scales: Arc<HashMap<u32, QtyScale>>,
+    claims: Claims,
 ) -> anyhow::Result<()> {
-    socket.set_nodelay(true)?;
-    let peer_addr = socket.peer_addr()?;
     println!("🟢 [CONNECT] New client: {peer_addr}");
+    info!("[gw] 🟢 connected");
 
-    let mut buf = BytesMut::with_capacity(16 * 1024);
+    let mut framed = Framed::new(socket, ClobCodec::default());
+    // Any inbound frame (PINGs included) pushes the deadline out; outbound events don't
+    let mut idle_deadline = Instant::now() + idle_timeout;
+    // `Framed` answers the read after an IO error with one `None` before it reads again;
+    // after a transient error that `None` is not the peer's EOF
+    let mut read_retries = 0;
+    let mut retrying = false;
+    // Market-data feed, attached on MSG_SUBSCRIBE; dropping it unsubscribes
+    let mut rx_md: Option<broadcast::Receiver<Event>> = None;
+    let mut conflator: Option<Conflator> = None;
+    // Trades as one print per taker and price level instead of one per maker
+    let mut trade_prints = false;
+    // A SUBSCRIBE anchored to a snapshot holds the feed until the snapshot of `anchoring` arrives,
+    // then skips every event up to its `seq` (`md_anchor`): the snapshot already reflects them
+    let mut anchoring: Option<u32> = None;
+    let mut md_anchor = 0;
+    // Order-level (L3) feed, on top of the market data for subscribers that ask for it
+    let mut rx_orders: Option<broadcast::Receiver<Event>> = None;
+    // Drop-copy feed of every client's order events, attached on MSG_DROPCOPY_SUBSCRIBE
+    let mut rx_dc: Option<broadcast::Receiver<Event>> = None;
+    // Order events as text execution reports instead of binary frames, opted into with HELLO
+    let mut reports: Option<ExecReports> = None;
+    let mut order_bucket = TokenBucket::new(rate_limit);
+    // Protocol version agreed in the HELLO; until then nothing else is accepted
+    let mut proto_version: Option<u16> = None;
+    // Admin commands need the ADMIN_TOKEN in this connection's HELLO, or an ADMIN_AUTH answered with its HMAC
+    let mut admin = false;
+    // Nonce of the last ADMIN_CHALLENGE, good for one answer
+    let mut admin_challenge: Option<[u8; ADMIN_NONCE_LEN]> = None;
+    let mut guard = DisconnectGuard {
+        enabled: false,
+        cl_ids: HashSet::new(),
+        claims,
+        tx_cmd: tx_cmd.clone(),
+        sink: sink_to_engine.clone(),
+        peer_addr,
+    };
 
     loop {
-        // 1️⃣ Read inbound bytes
-        let n = socket.read_buf(&mut buf).await?;
-        if n == 0 {
-            println!("🔴 [DISCONNECT] Client closed connection: {peer_addr}");
-            break;
-        }
-
-        println!("\n📥 [RECV] {} bytes from {}", n, peer_addr);
-        println!("🧩 Raw buffer (hex): {}", hex::encode(&buf));
-
-        // 2️⃣ Parse complete frames
-        while buf.len() >= 6 {
-            let payload_len = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
-
-            if buf.len() < 4 + payload_len {
-                println!(
-                    "⚠️ [WAIT] Incomplete frame: have {} bytes, need {} bytes",
-                    buf.len(),
-                    4 + payload_len
-                );
-                break;
-            }
+        tokio::select! {
+            // 1️⃣ Read the next inbound frame
+            next = framed.next() => {
+                let Some(item) = next else {
+                    if std::mem::take(&mut retrying) {
+                        continue;
+                    }
+                    println!("🔴 [DISCONNECT] Client closed connection: {peer_addr}");
+                    break;
+                };
+                let item = match item {
+                    Ok(item) => item,
+                    Err(e) => match ReadError::classify(&e) {
+                        ReadError::Transient if read_retries < MAX_READ_RETRIES => {
+                            read_retries += 1;
+                            println!("🔁 [READ] {e} from {peer_addr} — retrying ({read_retries}/{MAX_READ_RETRIES})");
+                            retrying = true;
+                            continue;
+                        }
+                        ReadError::PeerGone => {
+                            println!("🔴 [DISCONNECT] {peer_addr} dropped the connection: {e}");
+                            info!("[gw] peer gone: {e}");
+                            break;
+                        }
+                        ReadError::Transient | ReadError::Fatal => {
+                            println!("❌ [READ] {e} from {peer_addr} — closing");
+                            warn!("[gw] read error peer={peer_addr}: {e}");
+                            return Err(e.into());
+                        }
+                    },
+                };
+                read_retries = 0;
+                idle_deadline = Instant::now() + idle_timeout;
+                // The ingress stamp the sequencer orders by, when it is on
+                let received = std::time::Instant::now();
 
-            // Extract full frame
-            let mut frame = buf.split_to(4 + payload_len);
-            frame.advance(4); // skip len prefix
-
-            let msg_type = frame.get_u16_le();
-            let body_len = frame.get_u16_le() as usize;
-
-            // Get payload body
-            let body = frame.split_to(body_len);
-            let body_hex = hex::encode(&body);
-            // println!(
-            //     "\n🔎 [FRAME DECODED]
-            //         • msg_type: {} ({})
-            //         • body_len: {}
-            //         • raw_body (hex): {}",
-            //     msg_type,
-            //     match msg_type {
-            //         MSG_PING => "PING",
-            //         MSG_NEW_ORDER => "NEW_ORDER",
-            //         MSG_CANCEL => "CANCEL",
-            //         _ => "UNKNOWN",
-            //     },
-            //     body_len,
-            //     body_hex
-            // );
-
-            // Decode payload meaningfully if known type
-            match msg_type {
-                MSG_PING => {
-                    // println!("💓 [PING] Received ping from {}", peer_addr);
-                    // forward to engine so it can respond
-                    if let Err(e) = tx_cmd.send(Command::Ping(sink_to_engine.clone())) {
-                        eprintln!("[gw] failed to send Ping to engine: {e}");
+                // 2️⃣ Malformed frames are NACKed; one that desyncs the stream also ends the connection
+                let Frame { msg_type, body } = match item {
+                    Ok(frame) => frame,
+                    Err(e) if e.is_fatal() => {
+                        println!("⛔ [PROTOCOL] {} from {} — closing", e, peer_addr);
+                        warn!("[gw] protocol error peer={peer_addr}: {e}");
+                        framed.send(nack(e.msg_type(), e.reason(), framed.codec().format.order)).await?;
+                        return Ok(());
                     }
+                    Err(e) => {
+                        println!("⚠️ [MALFORMED] {} from {}", e, peer_addr);
+                        framed.send(nack(e.msg_type(), e.reason(), framed.codec().format.order)).await?;
+                        continue;
+                    }
+                };
+                let body_len = body.len();
+                let byte_order = framed.codec().format.order;
+
+                println!("\n📥 [RECV] msg_type={} body_len={} from {}", msg_type, body_len, peer_addr);
+                info!(msg_type, body_len, "[gw] 📥 request");
+                println!("🧩 Body (hex): {}", hex::encode(&body));
+
+                // The first frame has to be a HELLO; anything else ends the connection
+                if proto_version.is_none() && msg_type != MSG_HELLO {
+                    println!("⛔ [HELLO] {peer_addr} sent msg_type={msg_type} before HELLO — closing");
+                    framed.send(nack(msg_type, RejectReason::HelloRequired, byte_order)).await?;
+                    return Ok(());
+                }
+                // Order entry is metered; PINGs, subscriptions and snapshots are not
+                if matches!(msg_type, MSG_NEW_ORDER | MSG_CANCEL | MSG_CANCEL_ALL | MSG_REPLACE | MSG_REDUCE) && !order_bucket.try_take() {
+                    println!("⚠️ [RATE] {peer_addr} over {}/s, NACK msg_type={msg_type}", rate_limit.per_sec);
+                    framed.send(nack(msg_type, RejectReason::RateLimited, framed.codec().format.order)).await?;
+                    continue;
                 }
+                if matches!(msg_type, MSG_SESSION_ROLL | MSG_HALT | MSG_RESUME | MSG_AUCTION | MSG_UNCROSS | MSG_DROPCOPY_SUBSCRIBE) && !admin {
+                    println!("⛔ [ADMIN] {peer_addr} is not an admin connection, NACK msg_type={msg_type}");
+                    framed.send(nack(msg_type, RejectReason::Unauthorized, framed.codec().format.order)).await?;
+                    continue;
+                }
+                // Decode payload meaningfully if known type
+                match msg_type {
+                    MSG_PING => {
+                        // forward to engine so it can respond
+                        forward(&mut framed, &tx_cmd, received, msg_type, Command::Ping(sink_to_engine.clone())).await?;
+                    }
+
+                    MSG_NEW_ORDER => {
+                        let parsed = std::time::Instant::now();
+                        match parse_new_order(&body, byte_order) {
+                            Ok(order) if !guard.claim(order.cl_id) && !admin => {
+                                println!("⛔ [NEW_ORDER] Client {} belongs to another connection, rejecting id={} from {peer_addr}", order.cl_id, order.id);
+                                metrics.order_rejected();
+                                framed.send(Event::Reject { ord_id: order.id, reason: RejectReason::Unauthorized }).await?;
+                            }
+                            Ok(order) => {
+                                if let Some(r) = reports.as_mut() {
+                                    r.entered(&order);
+                                }
+                                forward(&mut framed, &tx_cmd, received, msg_type, Command::Order(order, sink_to_engine.clone(), parsed)).await?;
+                            }
+                            Err(reason) => {
+                                // Soft reject: answer this order and keep reading frames
+                                let ord_id = body.get(8..16)
+                                    .map(|b| byte_order.read_u64(b))
+                                    .unwrap_or(0);
+                                println!("⚠️ [NEW_ORDER] Rejected at gateway: id={} reason={:?}", ord_id, reason);
+                                metrics.order_rejected();
+                                let reject = Event::Reject { ord_id, reason };
+                                framed.send(reject).await?;
+                            }
+                        }
+                    }
+
+                    MSG_BATCH_NEW => {
+                        let records = match batch_records(&body, byte_order) {
+                            Ok(records) => records,
+                            Err(reason) => {
+                                println!("⚠️ [BATCH_NEW] Invalid payload length: {}", body_len);
+                                framed.send(nack(msg_type, reason, byte_order)).await?;
+                                continue;
+                            }
+                        };
+                        // Each order is parsed, metered and queued on its own; the batch gets one receipt
+                        let parsed = std::time::Instant::now();
+                        let mut results = Vec::with_capacity(records.len());
+                        for record in records {
+                            let ord_id = byte_order.read_u64(&record[8..16]);
+                            let order = match order_bucket.try_take() {
+                                true => parse_new_order(record, byte_order),
+                                false => Err(RejectReason::RateLimited),
+                            };
+                            let reject = match order {
+                                Ok(order) if !guard.claim(order.cl_id) && !admin => {
+                                    metrics.order_rejected();
+                                    Some(RejectReason::Unauthorized)
+                                }
+                                Ok(order) => {
+                                    if let Some(r) = reports.as_mut() {
+                                        r.entered(&order);
+                                    }
+                                    match tx_cmd.try_send(Command::Order(order, sink_to_engine.clone(), parsed), received) {
+                                        Ok(()) => None,
+                                        Err(_) => Some(RejectReason::EngineBusy),
+                                    }
+                                }
+                                Err(reason) => {
+                                    metrics.order_rejected();
+                                    Some(reason)
+                                }
+                            };
+                            results.push((ord_id, reject));
+                        }
+                        println!("📦 [BATCH_NEW] {} orders, {} refused at gateway", results.len(), results.iter().filter(|(_, r)| r.is_some()).count());
+                        framed.send(Event::BatchAck { results }).await?;
+                    }
+
+                    MSG_CANCEL => {
+                        if body_len >= 20 {
+                            let client_id = byte_order.read_u64(&body[0..8]);
+                            if !admin && !guard.cl_ids.contains(&client_id) {
+                                println!("⛔ [CANCEL] {peer_addr} has not registered client {client_id} and is not an admin connection, NACK");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                continue;
+                            }
+                            let cl_ord_id = byte_order.read_u64(&body[8..16]);
+                            let symbol = byte_order.read_u32(&body[16..20]);
+                            let cancel = Command::Cancel {
+                                symbol,
+                                cl_id: client_id,
+                                ord_id: cl_ord_id,
+                                sink: sink_to_engine.clone(),
+                            };
+                            forward(&mut framed, &tx_cmd, received, msg_type, cancel).await?;
+                        } else {
+                            println!("⚠️ [CANCEL] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_CANCEL_ALL => {
+                        // [u64 client_id] and optionally [u32 symbol_id]; without it every symbol is flattened
+                        if body_len >= 8 {
+                            let client_id = byte_order.read_u64(&body[0..8]);
+                            // Someone else's orders only from an admin; a client reconnecting sends REGISTER first
+                            if !admin && !guard.cl_ids.contains(&client_id) {
+                                println!("⛔ [CANCEL_ALL] {peer_addr} has not registered client {client_id} and is not an admin connection, NACK");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                continue;
+                            }
+                            let symbol = body.get(8..12).map(|b| byte_order.read_u32(b));
+                            let cancel_all = Command::CancelAll { cl_id: client_id, symbol, sink: sink_to_engine.clone() };
+                            forward(&mut framed, &tx_cmd, received, msg_type, cancel_all).await?;
+                        } else {
+                            println!("⚠️ [CANCEL_ALL] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_REPLACE => {
+                        // [u64 client_id][u64 cl_ord_id][u32 symbol_id][i64 new_price][i64 new_qty]
+                        if body_len >= 36 {
+                            let client_id = byte_order.read_u64(&body[0..8]);
+                            if !admin && !guard.cl_ids.contains(&client_id) {
+                                println!("⛔ [REPLACE] {peer_addr} has not registered client {client_id} and is not an admin connection, NACK");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                continue;
+                            }
+                            let cl_ord_id = byte_order.read_u64(&body[8..16]);
+                            let symbol = byte_order.read_u32(&body[16..20]);
+                            let new_price = byte_order.read_i64(&body[20..28]);
+                            let new_qty = byte_order.read_i64(&body[28..36]);
+                            let invalid = if new_price == 0 {
+                                Some(RejectReason::BadPrice)
+                            } else if new_qty <= 0 {
+                                Some(RejectReason::BadQty)
+                            } else {
+                                None
+                            };
+                            if let Some(reason) = invalid {
+                                let reject = Event::Reject { ord_id: cl_ord_id, reason };
+                                framed.send(reject).await?;
+                            } else {
+                                let replace = Command::Replace {
+                                    symbol,
+                                    cl_id: client_id,
+                                    ord_id: cl_ord_id,
+                                    new_price,
+                                    new_qty: new_qty as u64,
+                                    timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+                                    sink: sink_to_engine.clone(),
+                                };
+                                forward(&mut framed, &tx_cmd, received, msg_type, replace).await?;
+                            }
+                        } else {
+                            println!("⚠️ [REPLACE] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_REDUCE => {
+                        // [u64 client_id][u64 cl_ord_id][u32 symbol_id][i64 new_qty]
+                        if body_len >= 28 {
+                            let client_id = byte_order.read_u64(&body[0..8]);
+                            if !admin && !guard.cl_ids.contains(&client_id) {
+                                println!("⛔ [REDUCE] {peer_addr} has not registered client {client_id} and is not an admin connection, NACK");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                continue;
+                            }
+                            let cl_ord_id = byte_order.read_u64(&body[8..16]);
+                            let symbol = byte_order.read_u32(&body[16..20]);
+                            let new_qty = byte_order.read_i64(&body[20..28]);
+                            if new_qty <= 0 {
+                                framed.send(Event::Reject { ord_id: cl_ord_id, reason: RejectReason::BadQty }).await?;
+                            } else {
+                                let reduce = Command::Reduce {
+                                    symbol,
+                                    cl_id: client_id,
+                                    ord_id: cl_ord_id,
+                                    new_qty: new_qty as u64,
+                                    sink: sink_to_engine.clone(),
+                                };
+                                forward(&mut framed, &tx_cmd, received, msg_type, reduce).await?;
+                            }
+                        } else {
+                            println!("⚠️ [REDUCE] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_BOOK_SNAPSHOT => {
+                        if body_len >= 6 {
+                            let symbol = byte_order.read_u32(&body[0..4]);
+                            let depth = byte_order.read_u16(&body[4..6])
+                                .min(MAX_SNAPSHOT_DEPTH);
+                            let snap = Command::Snapshot { symbol, depth, sink: sink_to_engine.clone() };
+                            forward(&mut framed, &tx_cmd, received, msg_type, snap).await?;
+                        } else {
+                            println!("⚠️ [SNAPSHOT] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
 
-                MSG_NEW_ORDER => {
-                    // println!("🟦 [NEW_ORDER] Raw payload len={}", body_len);
-                    if body_len >= (8 + 8 + 1 + 8 + 8 + 1) {
-                        let client_id = u64::from_le_bytes(body[0..8].try_into().unwrap());
-                        let cl_ord_id = u64::from_le_bytes(body[8..16].try_into().unwrap());
-                        let side = body[16]; // 0=BID, 1=ASK
-                        let price = i64::from_le_bytes(body[17..25].try_into().unwrap());
-                        let qty = i64::from_le_bytes(body[25..33].try_into().unwrap());
-                        let tif = body[33];
-                        let order = Order {
-                            id: cl_ord_id,
-                            cl_id: client_id,
-                            side: if side == 0 { Side::Bid } else { Side::Ask },
-                            price: u64::try_from(price).expect("price must be >= 0"),
-                            qty: u64::try_from(qty).expect("qty must be >= 0"),
-                            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
-                            tif: if tif == 0 { Tif::Gtc } else { Tif::Ioc },
+                    MSG_HELLO => {
+                        // [u16 proto_version][u8 flags] (see HELLO_*); admin access only comes from ADMIN_AUTH
+                        let offered = body.get(0..2).map_or(0, |b| byte_order.read_u16(b));
+                        let Some(version) = negotiate_version(offered) else {
+                            println!("⛔ [HELLO] {peer_addr} offered protocol version {offered}, need {PROTO_VERSION_MIN}..={PROTO_VERSION} — closing");
+                            let reject = Event::Reject { ord_id: 0, reason: RejectReason::UnsupportedVersion };
+                            framed.send(reject).await?;
+                            return Ok(());
+                        };
+                        proto_version = Some(version);
+                        let flags = body.get(2).copied().unwrap_or(0);
+                        guard.enabled = flags & HELLO_CANCEL_ON_DISCONNECT != 0;
+                        if flags & HELLO_EXEC_REPORTS == 0 {
+                            reports = None;
+                        } else if reports.is_none() {
+                            reports = Some(ExecReports::new(qty_scales.clone()));
+                        }
+                        // Everything after this frame, its ACK included, goes in the format it asks for
+                        framed.codec_mut().format = FrameFormat {
+                            order: if flags & HELLO_BIG_ENDIAN != 0 { ByteOrder::Big } else { ByteOrder::Little },
+                            crc: flags & HELLO_CRC32 != 0,
                         };
-            
-                        if let Err(e) = tx_cmd.send(Command::Order(order, sink_to_engine.clone())) {
-                            eprintln!("[gw] failed to send Order to engine: {e}");
+                        println!("👋 [HELLO] {} version={} cancel_on_disconnect={} exec_reports={} byte_order={:?} crc={} admin={}", peer_addr, version, guard.enabled, reports.is_some(), framed.codec().format.order, framed.codec().format.crc, admin);
+                        framed.send(Event::HelloAck { version }).await?;
+                    }
+
+                    MSG_ADMIN_AUTH => {
+                        // Empty: send a fresh ADMIN_CHALLENGE. [32-byte HMAC-SHA256(ADMIN_TOKEN, nonce)]: answer the last one
+                        match (body_len, admin_token.as_deref()) {
+                            (_, None) => {
+                                println!("⛔ [ADMIN_AUTH] {peer_addr}: ADMIN_TOKEN is not set, NACK");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                            }
+                            (0, Some(_)) => {
+                                let nonce = admin_nonce();
+                                admin_challenge = Some(nonce);
+                                println!("🔑 [ADMIN_AUTH] {peer_addr} challenged");
+                                framed.send(Event::AdminChallenge { nonce }).await?;
+                            }
+                            (ADMIN_MAC_LEN, Some(token)) => {
+                                if admin_challenge.take().is_some_and(|nonce| admin_mac_ok(token, &nonce, &body)) {
+                                    admin = true;
+                                    println!("🔑 [ADMIN_AUTH] {peer_addr} is now an admin connection");
+                                    framed.send(Event::Ack { ord_id: 0, note: AckNote::Admin }).await?;
+                                } else {
+                                    warn!("[gw] admin auth failed peer={peer_addr}");
+                                    println!("⛔ [ADMIN_AUTH] {peer_addr} answered no challenge or with the wrong HMAC, NACK");
+                                    framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                }
+                            }
+                            _ => {
+                                println!("⚠️ [ADMIN_AUTH] Invalid payload length: {}", body_len);
+                                framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                            }
+                        }
+                    }
+
+                    MSG_REGISTER => {
+                        // [u64 client_id]: this connection gets the client's maker fills and other unsolicited events
+                        if body_len >= 8 {
+                            let client_id = byte_order.read_u64(&body[0..8]);
+                            if !guard.claim(client_id) && !admin {
+                                println!("⛔ [REGISTER] Client {client_id} belongs to another connection, NACK {peer_addr}");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                continue;
+                            }
+                            println!("🪪 [REGISTER] {} registered client {}", peer_addr, client_id);
+                            let register = Command::Register { cl_id: client_id, admin, sink: sink_to_engine.clone() };
+                            forward(&mut framed, &tx_cmd, received, msg_type, register).await?;
+                        } else {
+                            println!("⚠️ [REGISTER] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_QUERY_ORDERS => {
+                        // [u64 client_id]
+                        if body_len >= 8 {
+                            let client_id = byte_order.read_u64(&body[0..8]);
+                            if !admin && !guard.cl_ids.contains(&client_id) {
+                                println!("⛔ [QUERY_ORDERS] {peer_addr} has not registered client {client_id} and is not an admin connection, NACK");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                continue;
+                            }
+                            let query = Command::QueryOrders { cl_id: client_id, sink: sink_to_engine.clone() };
+                            forward(&mut framed, &tx_cmd, received, msg_type, query).await?;
+                        } else {
+                            println!("⚠️ [QUERY_ORDERS] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_RESYNC => {
+                        // [u64 client_id][u64 since_seq]
+                        if body_len >= 16 {
+                            let client_id = byte_order.read_u64(&body[0..8]);
+                            let since = byte_order.read_u64(&body[8..16]);
+                            if !admin && !guard.cl_ids.contains(&client_id) {
+                                println!("⛔ [RESYNC] {peer_addr} has not registered client {client_id} and is not an admin connection, NACK");
+                                framed.send(nack(msg_type, RejectReason::Unauthorized, byte_order)).await?;
+                                continue;
+                            }
+                            let resync = Command::Resync { cl_id: client_id, since, sink: sink_to_engine.clone() };
+                            forward(&mut framed, &tx_cmd, received, msg_type, resync).await?;
+                        } else {
+                            println!("⚠️ [RESYNC] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_QUERY_STATS => {
+                        // [u32 symbol_id]
+                        if body_len >= 4 {
+                            let symbol = byte_order.read_u32(&body[0..4]);
+                            let query = Command::QueryStats { symbol, sink: sink_to_engine.clone() };
+                            forward(&mut framed, &tx_cmd, received, msg_type, query).await?;
+                        } else {
+                            println!("⚠️ [QUERY_STATS] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_SESSION_ROLL => {
+                        // Admin: optional [u32 symbol_id]; without it every symbol starts a new session
+                        let symbol = body.get(0..4).map(|b| byte_order.read_u32(b));
+                        println!("🔄 [SESSION_ROLL] {} rolled symbol={:?}", peer_addr, symbol);
+                        let roll = Command::SessionRoll { symbol, sink: sink_to_engine.clone() };
+                        forward(&mut framed, &tx_cmd, received, msg_type, roll).await?;
+                    }
+
+                    MSG_HALT | MSG_RESUME | MSG_AUCTION | MSG_UNCROSS => {
+                        // Admin: [u32 symbol_id]. A halted symbol rejects new orders and replaces; cancels still go through.
+                        // In auction orders rest without matching; UNCROSS (like RESUME) clears the book at one price
+                        if body_len >= 4 {
+                            let symbol = byte_order.read_u32(&body[0..4]);
+                            let state = match msg_type {
+                                MSG_HALT => MarketState::Halted,
+                                MSG_AUCTION => MarketState::Auction,
+                                _ => MarketState::Open,
+                            };
+                            println!("🚦 [MARKET_STATE] {} set symbol={} {:?}", peer_addr, symbol, state);
+                            let cmd = Command::SetMarketState { symbol, state, sink: sink_to_engine.clone() };
+                            forward(&mut framed, &tx_cmd, received, msg_type, cmd).await?;
+                        } else {
+                            println!("⚠️ [MARKET_STATE] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        }
+                    }
+
+                    MSG_SUBSCRIBE => {
+                        // Optional [u16 conflate_ms][u8 flags]; 0 or missing = every event as it happens, L2 only.
+                        // With SUBSCRIBE_SNAPSHOT, [u32 symbol_id][u16 depth] follow the flags.
+                        // Subscribing again switches mode, after flushing what was held back
+                        let conflate_ms = body.get(0..2).map_or(0, |b| byte_order.read_u16(b));
+                        let flags = body.get(2).copied().unwrap_or(0);
+                        if flags & SUBSCRIBE_SNAPSHOT != 0 && body_len < 9 {
+                            println!("⚠️ [SUBSCRIBE] Invalid payload length: {}", body_len);
+                            framed.send(nack(msg_type, RejectReason::BadLength, byte_order)).await?;
+                        } else {
+                            if let Some(mut c) = conflator.take() {
+                                for evt in c.drain() {
+                                    framed.feed(evt).await?;
+                                }
+                            }
+                            conflator = (conflate_ms > 0).then(|| Conflator::new(Duration::from_millis(conflate_ms as u64)));
+                            if rx_md.is_none() {
+                                rx_md = Some(tx_md.subscribe());
+                            }
+                            trade_prints = flags & SUBSCRIBE_TRADE_PRINTS != 0;
+                            if flags & SUBSCRIBE_ORDERS == 0 {
+                                rx_orders = None;
+                            } else if rx_orders.is_none() {
+                                rx_orders = Some(tx_orders.subscribe());
+                            }
+                            println!("📡 [SUBSCRIBE] {} joined the market-data feed (conflate_ms={}, orders={}, trade_prints={})", peer_addr, conflate_ms, rx_orders.is_some(), trade_prints);
+                            let ack = Event::Ack { ord_id: 0, note: AckNote::Subscribed };
+                            framed.send(ack).await?;
+
+                            if flags & SUBSCRIBE_SNAPSHOT != 0 {
+                                // The receiver is registered before the engine takes the snapshot, so whatever
+                                // the snapshot misses is queued in it; nothing is sent until we know where it ends
+                                let symbol = byte_order.read_u32(&body[3..7]);
+                                let depth = byte_order.read_u16(&body[7..9]).min(MAX_SNAPSHOT_DEPTH);
+                                let snap = Command::Snapshot { symbol, depth, sink: sink_to_engine.clone() };
+                                if forward(&mut framed, &tx_cmd, received, msg_type, snap).await? {
+                                    anchoring = Some(symbol);
+                                }
+                            }
+                        }
+                    }
+
+                    MSG_DROPCOPY_SUBSCRIBE => {
+                        // Admin: the whole market-data feed, unconflated, plus a copy of every client's order events
+                        if let Some(mut c) = conflator.take() {
+                            for evt in c.drain() {
+                                framed.feed(evt).await?;
+                            }
+                        }
+                        if rx_md.is_none() {
+                            rx_md = Some(tx_md.subscribe());
                         }
-                    } else {
-                        println!("⚠️ [NEW_ORDER] Unexpected payload length: {}", body_len);
+                        if rx_dc.is_none() {
+                            rx_dc = Some(tx_dropcopy.subscribe());
+                        }
+                        trade_prints = false;
+                        println!("🗂️ [DROPCOPY] {} joined the drop-copy feed", peer_addr);
+                        let ack = Event::Ack { ord_id: 0, note: AckNote::DropCopy };
+                        framed.send(ack).await?;
                     }
+
+                    _ => {
+                        // The frame was well delimited, so the stream is fine; just tell the client
+                        println!("❓ [UNKNOWN] Message type {} from {}", msg_type, peer_addr);
+                        framed.send(nack(msg_type, RejectReason::UnknownType, byte_order)).await?;
+                    }
+                }
+                println!("----------------------------------------------------------------------")
+            }
+
+            // 3️⃣ Deliver engine events (ack / reject / trade / pong) back to this client
+            Some(evt) = rx_evt.recv() => {
+                if let Event::Snapshot { symbol, seq, .. } = &evt
+                    && anchoring == Some(*symbol)
+                {
+                    anchoring = None;
+                    md_anchor = *seq;
+                }
+                match reports.as_mut() {
+                    Some(r) => {
+                        for evt in r.render(evt) {
+                            framed.feed(evt).await?;
+                        }
+                        SinkExt::<Event>::flush(&mut framed).await?;
+                    }
+                    None => framed.send(evt).await?,
+                }
+            }
+
+            // 📡 Market-data broadcast (trades / book deltas) for subscribers
+            md = async { rx_md.as_mut().unwrap().recv().await }, if rx_md.is_some() && anchoring.is_none() => {
+                match md {
+                    Ok(evt) if evt.seq().is_some_and(|seq| seq <= md_anchor) => {} // Already in the snapshot
+                    // Each fill comes both per maker and summed into a print; pass on the form this subscriber asked for
+                    Ok(Event::Trade(_)) if trade_prints => {}
+                    Ok(Event::TradePrint(_)) if !trade_prints => {}
+                    Ok(evt) => match conflator.as_mut() {
+                        Some(c) => if let Some(evt) = c.offer(evt) {
+                            framed.send(evt).await?;
+                        },
+                        None => framed.send(evt).await?,
+                    },
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("[gw] market-data subscriber {peer_addr} lagged, skipped {n} events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => rx_md = None,
                 }
+            }
 
-                MSG_CANCEL => {
-                    if body_len >= 16 {
-                        let client_id = u64::from_le_bytes(body[0..8].try_into().unwrap());
-                        let cl_ord_id = u64::from_le_bytes(body[8..16].try_into().unwrap());
-                        // println!(
-                        //     "🟧 [CANCEL]
-                        //     → client_id: {}
-                        //     → cl_ord_id: {}",
-                        //     client_id, cl_ord_id
-                        // );
-                    } else {
-                        println!("⚠️ [CANCEL] Invalid payload length: {}", body_len);
+            // 📡 Order-level feed, never conflated: a skipped event breaks the queue
+            l3 = async { rx_orders.as_mut().unwrap().recv().await }, if rx_orders.is_some() => {
+                match l3 {
+                    Ok(evt) => framed.send(evt).await?,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("[gw] order-feed subscriber {peer_addr} lagged, skipped {n} events");
                     }
+                    Err(broadcast::error::RecvError::Closed) => rx_orders = None,
                 }
+            }
 
-                _ => {
-                    println!("❓ [UNKNOWN] Message type {} from {}", msg_type, peer_addr);
+            // 🗂️ Drop copy of other clients' order events
+            dc = async { rx_dc.as_mut().unwrap().recv().await }, if rx_dc.is_some() => {
+                match dc {
+                    Ok(evt) => framed.send(evt).await?,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("[gw] drop-copy subscriber {peer_addr} lagged, skipped {n} events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => rx_dc = None,
                 }
             }
-            println!("----------------------------------------------------------------------")
+
+            // 📡 Conflated subscribers get the held-back levels once per interval
+            _ = async { conflator.as_mut().unwrap().flush.tick().await }, if conflator.is_some() => {
+                for evt in conflator.as_mut().unwrap().drain() {
+                    framed.feed(evt).await?;
+                }
+                SinkExt::<Event>::flush(&mut framed).await?;
+            }
+
+            // 4️⃣ Drop clients that went silent
+            _ = sleep_until(idle_deadline) => {
+                println!("⏳ [IDLE] No traffic from {peer_addr} for {:?} — closing", idle_timeout);
+                warn!("[engine] idle timeout peer={peer_addr}");
+                break;
+            }
         }
     }
 
@@ -164,32 +1033,444 @@ async fn main() -> anyhow::Result<()> {
         .with_level(true)
         .compact()
         .init();
+    // Crash recovery: latest book snapshot, then the WAL tail written after it,
+    // all before the first client can connect
+    let wal_path = std::env::var("WAL_PATH").unwrap_or_else(|_| "wal/engine.wal".to_string());
+    let snap_path = std::env::var("SNAPSHOT_PATH").unwrap_or_else(|_| "wal/books.snap".to_string());
+    let snap_every = Duration::from_secs(env_or("SNAPSHOT_SECS", 60, |_| true));
+    // Queued commands the engine applies per wake-up, sharing one WAL flush (1 = one at a time)
+    let drain_batch = env_or("DRAIN_BATCH", DEFAULT_DRAIN_BATCH, |&n| n > 0);
+    // Order events kept per client for RESYNC after a reconnect (0 = no journal)
+    let resync_depth = env_or("RESYNC_DEPTH", 1024, |_| true);
+    // Levels per side of the depth ladder in the 5s book summary (0 = summary only)
+    let ladder_levels = env_or("BOOK_LADDER_LEVELS", 0, |_| true);
+    // Exchange order ids start at 1 on a fresh engine
+    let (mut books, wal_from, next_exch_ord_id) = match snapshot::load(&snap_path)? {
+        Some(snap) => (snap.books, snap.wal_offset, snap.next_exch_ord_id),
+        None => (HashMap::new(), 0, 1),
+    };
+    if wal_from > 0 {
+        println!("📦 Loaded {} books from {snap_path} (WAL offset {wal_from})", books.len());
+    }
+    // Per-symbol engine config: the SYMBOL_CONFIG file (see `config`), then the *_SYMBOLS env vars,
+    // which may add symbols and fields the file leaves unset; one field set twice stops startup.
+    // Applied before replay so the WAL is re-matched the way it ran live; keep it unchanged across restarts
+    let mut symbols: BTreeMap<u32, SymbolConfig> = match std::env::var("SYMBOL_CONFIG") {
+        Ok(path) => config::load(&path).map_err(anyhow::Error::msg)?,
+        Err(_) => BTreeMap::new(),
+    };
+    // Symbols matched pro-rata instead of price-time, e.g. "1,7": short for a `pro_rata` policy
+    if let Ok(list) = std::env::var("PRO_RATA_SYMBOLS") {
+        for symbol in list.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let symbol: u32 = symbol.parse().map_err(|e| anyhow::anyhow!("PRO_RATA_SYMBOLS entry {symbol:?}: {e}"))?;
+            let c = symbols.entry(symbol).or_insert_with(|| SymbolConfig::new(symbol));
+            set_once(&mut c.policy, MatchPolicy::ProRata, symbol, "policy", "PRO_RATA_SYMBOLS")?;
+        }
+    }
+    // Dense price ladder instead of a BTreeMap, e.g. "1=9000:11000:5" (symbol=min:max:tick)
+    fold_symbol_env(&mut symbols, "LADDER_SYMBOLS", "symbol=min:max:tick", "ladder", |c| &mut c.ladder)?;
+    // Tick and lot sizes, e.g. "1=5:100,7=1:10" (symbol=tick_size:lot_size)
+    fold_symbol_env(&mut symbols, "TICK_LOT_SYMBOLS", "symbol=tick_size:lot_size", "tick_lot", |c| &mut c.tick_lot)?;
+    // Price bands around the last trade, e.g. "1=5%,7=20t" (percent, or ticks of the symbol's tick_size)
+    fold_symbol_env(&mut symbols, "PRICE_BAND_SYMBOLS", "symbol=<pct>% or symbol=<ticks>t", "price_band", |c| &mut c.price_band)?;
+    // Maker/taker fees per fill in basis points, e.g. "1=1:5,7=0:2" (symbol=maker_bps:taker_bps)
+    fold_symbol_env(&mut symbols, "FEE_SYMBOLS", "symbol=maker_bps:taker_bps", "fees", |c| &mut c.fees)?;
+    // Orders rest only within the best N levels of their side, e.g. "1=10,7=5"
+    fold_symbol_env(&mut symbols, "MAX_DEPTH_SYMBOLS", "symbol=levels", "max_depth", |c| &mut c.max_depth)?;
+    // Fixed-point quantities, e.g. "1=1e6,7=100" (symbol=raw units per whole unit, a power of ten)
+    fold_symbol_env(&mut symbols, "QTY_SCALE_SYMBOLS", "symbol=scale", "qty_scale", |c| &mut c.qty_scale)?;
+    for (&symbol, c) in &symbols {
+        let book = books.entry(symbol).or_default();
+        if let Some(policy) = c.policy {
+            book.policy = policy;
+            println!("⚖️  Symbol {symbol} matches {policy:?}");
+        }
+        // Limit orders priced off the ladder are rejected
+        if let Some(spec) = c.ladder {
+            book.use_ladder(spec).map_err(|e| anyhow::anyhow!("symbol {symbol}: {e}"))?;
+            println!("🪜 Symbol {symbol} on a price ladder {}..={} tick {}", spec.min, spec.max, spec.tick);
+        }
+        // Orders off them are rejected; other symbols take any price and quantity
+        if let Some(increments) = c.tick_lot {
+            book.increments = increments;
+            println!("📏 Symbol {symbol} tick_size {} lot_size {}", increments.tick_size, increments.lot_size);
+        }
+        // Limit orders priced outside are rejected; the band is open until the symbol trades
+        if let Some(band) = c.price_band {
+            book.band = Some(band);
+            println!("🚧 Symbol {symbol} price band {band:?}");
+        }
+        // Other symbols trade free
+        if let Some(fees) = c.fees {
+            book.fees = fees;
+            println!("💸 Symbol {symbol} fees maker {} bps taker {} bps", fees.maker_bps, fees.taker_bps);
+        }
+        if let Some(depth) = c.max_depth {
+            book.max_depth = Some(depth.get());
+            println!("📏 Symbol {symbol} rests orders within the best {depth} levels per side");
+        }
+        // Matching is unchanged; execution reports show those symbols' quantities as decimals
+        if let Some(scale) = c.qty_scale {
+            if !scale.fits(book.increments.lot_size) {
+                anyhow::bail!("symbol {symbol}: qty scale {} is not a whole number of lots of {}", scale.0, book.increments.lot_size);
+            }
+            book.qty_scale = scale;
+            println!("🔢 Symbol {symbol} quantities in units of 1/{} ({} decimals)", scale.0, scale.decimals());
+        }
+    }
+    let qty_scales: HashMap<u32, QtyScale> = books.iter()
+        .filter(|(_, b)| b.qty_scale != QtyScale::default())
+        .map(|(&symbol, b)| (symbol, b.qty_scale))
+        .collect();
+    let qty_scales = Arc::new(qty_scales);
+    // Per-client pre-trade limits, e.g. "7=500:1000000,9=:250000" (cl_id=max_position:max_notional,
+    // either empty for no limit), applied in each symbol separately. Set before replay too
+    let mut risk = RiskLimits::new();
+    if let Ok(list) = std::env::var("RISK_LIMITS") {
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((cl_id, spec)) = entry.split_once('=') else {
+                anyhow::bail!("RISK_LIMITS entry {entry:?} is not cl_id=max_position:max_notional");
+            };
+            let cl_id: u64 = cl_id.trim().parse()?;
+            let limit: RiskLimit = spec.parse().map_err(anyhow::Error::msg)?;
+            risk.insert(cl_id, limit);
+            println!("🛡️  Client {cl_id} max position {:?} max notional {:?}", limit.max_position, limit.max_notional);
+        }
+    }
+    let risk = Arc::new(risk);
+    let (records, wal_len) = wal::replay(&wal_path, wal_from)?;
+    println!("📜 Replaying {} WAL records from {wal_path} ...", records.len());
+    let recover_risk = risk.clone();
+    let (books, next_exch_ord_id) = tokio::task::spawn_blocking(move || recover(books, next_exch_ord_id, records, recover_risk)).await?;
+    let wal = Wal::open(&wal_path, wal_len)?;
+    // Session recording for the `replay` tool. It starts from an empty book, so a
+    // restart with resting orders (snapshot or WAL) can't be recorded
+    let recorder = match std::env::var("RECORD_PATH") {
+        Ok(path) => {
+            if books.values().any(|b| !b.lookup.is_empty() || !b.stops.is_empty() || b.state != MarketState::Open) {
+                anyhow::bail!("RECORD_PATH needs an empty, open book: remove {wal_path} and {snap_path} first");
+            }
+            println!("🎥 Recording session to {path}");
+            Some(Recorder::create(&path, &books, &risk)?)
+        }
+        Err(_) => None,
+    };
+
     // Bind address
     let addr = std::env::var("ADDR").unwrap_or_else(|_| "0.0.0.0:9000".to_string());
     let listener = TcpListener::bind(&addr).await?;
     println!("\n🚀 Listening on {}\n", listener.local_addr()?);
 
+    // Idle connection timeout (seconds)
+    let idle_timeout = Duration::from_secs(env_or("IDLE_TIMEOUT_SECS", 30, |_| true));
+
+    // Per-connection order-entry rate limit (frames/sec, burst defaults to the rate).
+    // Either at 0 would refuse every order, so that keeps the default too
+    let per_sec = env_or("RATE_LIMIT_PER_SEC", 1000, |&n: &u32| n > 0);
+    let burst = env_or("RATE_LIMIT_BURST", per_sec, |&n| n > 0);
+    let rate_limit = RateLimit { per_sec, burst };
+
+    // Consecutive sends that must find a client's outbound queue full before it is disconnected
+    let slow_after = env_or("SLOW_CONSUMER_SENDS", 64, |&n: &u32| n > 0);
+
+    // OHLC candle interval (0 = no candles); empty intervals give a flat candle unless skipped
+    let candle_secs = env_or("CANDLE_SECS", 60, |_| true);
+    let candles = (candle_secs > 0).then(|| CandleConfig {
+        every: Duration::from_secs(candle_secs),
+        skip_empty: std::env::var("CANDLE_SKIP_EMPTY").is_ok_and(|v| v == "1"),
+    });
+
+    // Concurrent connections; one more is told "server_full" and closed
+    let max_connections = env_or("MAX_CONNECTIONS", 1024, |&n| n > 0);
+    let conn_slots = Arc::new(Semaphore::new(max_connections));
+
+    // Shared secret for the ADMIN_AUTH handshake, which unlocks admin commands; unset = no admin access
+    let admin_token: Option<Arc<str>> = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()).map(Arc::from);
+    if admin_token.is_none() {
+        println!("🔒 ADMIN_TOKEN not set — admin commands are disabled");
+    }
+
     // Engine setup
-    let (tx_cmd, rx_cmd) = bounded::<Command>(10_000);
-    let (tx_bcast, _rx_bcast) = bounded::<Event>(10_000);
+    let caps = ChannelCaps::from_env();
+    println!("📬 Queue capacities: commands {}, feeds {}, per connection {}", caps.cmd, caps.bcast, caps.conn_evt);
+    let (tx_cmd, rx_cmd) = bounded::<Command>(caps.cmd);
+    // Market data fan-out: every subscribed connection holds its own receiver
+    let (tx_bcast, _) = broadcast::channel::<Event>(caps.bcast);
+    let (tx_dropcopy, _) = broadcast::channel::<Event>(caps.bcast);
+    let (tx_orders, _) = broadcast::channel::<Event>(caps.bcast);
+
+    // Prometheus scrape endpoint, on its own port
+    let metrics = Arc::new(Metrics::default());
+    let metrics_addr = std::env::var("METRICS_ADDR").unwrap_or_else(|_| "0.0.0.0:9001".to_string());
+    let metrics_listener = TcpListener::bind(&metrics_addr).await?;
+    println!("📈 Metrics on http://{}/metrics", metrics_listener.local_addr()?);
+    tokio::spawn(metrics::serve(metrics_listener, metrics.clone(), tx_cmd.clone()));
+
+    // Read-only book inspection over HTTP, on another port; local only unless told otherwise
+    let debug_addr = std::env::var("DEBUG_ADDR").unwrap_or_else(|_| "127.0.0.1:9003".to_string());
+    let debug_listener = TcpListener::bind(&debug_addr).await?;
+    println!("🔬 Book debug endpoint on http://{}/book/<symbol>", debug_listener.local_addr()?);
+    tokio::spawn(debug_http::serve(debug_listener, tx_cmd.clone()));
 
     println!("⚙️  Spawning matching engine thread ...");
-    thread::spawn(move || run_engine(rx_cmd, tx_bcast));
+    let tx_md = MdFeed::new(tx_bcast.clone()).recording(recorder).metered(metrics.clone()).risk_limits(risk).drop_copy(tx_dropcopy.clone())
+        .order_feed(tx_orders.clone()).journal(resync_depth);
+    let heartbeat = Some(Duration::from_secs(5));
+    let engine = thread::spawn(move || {
+        run_engine(rx_cmd, tx_md, books, next_exch_ord_id, wal, snap_path.into(), snap_every, heartbeat, candles, drain_batch, ladder_levels)
+    });
     println!("✅ Engine thread started.\n");
 
+    // Optional ingress sequencing: commands reach the engine in the order their frames arrived,
+    // within this window, instead of the order connection tasks got scheduled (0 = off)
+    let sequence_window = Duration::from_micros(env_or("SEQUENCE_WINDOW_US", 0, |_| true));
+    let ingress = if sequence_window.is_zero() {
+        Ingress::Direct(tx_cmd.clone())
+    } else {
+        let (tx_seq, rx_seq) = bounded::<Stamped>(caps.cmd);
+        let tx_cmd = tx_cmd.clone();
+        thread::spawn(move || run_sequencer(rx_seq, tx_cmd, sequence_window));
+        println!("⏱️  Sequencing commands by ingress time, window {}µs", sequence_window.as_micros());
+        Ingress::Sequenced(tx_seq)
+    };
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
     // Accept loop
+    let mut next_session: u64 = 0;
+    let claims = Claims::default();
     loop {
-        let (socket, peer) = listener.accept().await?;
-        println!("🔗 [ACCEPT] Client connected: {peer}");
+        let (socket, peer) = tokio::select! {
+            res = listener.accept() => res?,
+            _ = &mut ctrl_c => {
+                println!("\n🛑 [SHUTDOWN] Ctrl-C — no new connections, draining engine ...");
+                break;
+            }
+        };
+        // A slot is held for the connection's whole task and freed when it ends
+        let Ok(slot) = conn_slots.clone().try_acquire_owned() else {
+            println!("🚫 [FULL] {peer} refused — all {max_connections} connection slots taken");
+            warn!("[gw] connection refused peer={peer}: {max_connections} connections open");
+            tokio::spawn(async move {
+                let mut socket = socket;
+                let _ = socket.write_all(&encode_nack(0, RejectReason::ServerFull, FrameFormat::default())).await;
+                let _ = socket.shutdown().await;
+            });
+            continue;
+        };
+        if let Err(e) = socket.set_nodelay(true) {
+            warn!("[gw] TCP_NODELAY not set peer={peer}: {e}");
+        }
+        // Ids count up from 1 and are never reused while the server runs
+        next_session += 1;
+        let session = next_session;
+        println!("🔗 [ACCEPT] Client connected: {peer} (session {session})");
 
-        let tx_cmd_cl = tx_cmd.clone();
-        let (tx_evt, rx_evt) = bounded::<Event>(2048);
+        let tx_cmd_cl = ingress.clone();
+        let tx_md_cl = tx_bcast.clone();
+        let tx_dc_cl = tx_dropcopy.clone();
+        let tx_orders_cl = tx_orders.clone();
+        let (tx_evt, rx_evt) = sink::channel(caps.conn_evt, slow_after, session);
+        let metrics = metrics.clone();
+        let admin_token = admin_token.clone();
+        let qty_scales = qty_scales.clone();
+        let claims = claims.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = process(socket, tx_cmd_cl, tx_evt, rx_evt).await {
+            // A slow consumer is cut off even while the task is stuck writing to its socket;
+            // dropping `process` closes the connection and runs its cancel-on-disconnect
+            let slow = tx_evt.clone();
+            let res = tokio::select! {
+                res = process(socket, peer, tx_cmd_cl, tx_evt, rx_evt, tx_md_cl, tx_dc_cl, tx_orders_cl, idle_timeout, rate_limit, metrics.clone(), admin_token, qty_scales, claims) => res,
+                dropped = slow.slow_consumer() => {
+                    println!("🐢 [SLOW] {peer} stopped reading ({dropped} events dropped) — disconnecting");
+                    warn!("[gw] slow consumer peer={peer}: outbound queue full for {slow_after} sends in a row, {dropped} events dropped");
+                    metrics.slow_consumer();
+                    Ok(())
+                }
+            };
+            if let Err(e) = res {
                 error!("❌ [ERROR] {e:#}");
             }
             info!("🔚 [CLOSE] Client {peer} disconnected.");
+            drop(slot);
+        }.instrument(info_span!("connection", session, %peer)));
+    }
+    drop(listener);
+
+    // The sentinel queues behind every command already sent; the engine applies them all,
+    // flushes the WAL and snapshots before its thread returns
+    tokio::task::spawn_blocking(move || {
+        ingress.send(Command::Shutdown);
+        engine.join()
+    })
+    .await?
+    .map_err(|_| anyhow::anyhow!("engine thread panicked"))?;
+    println!("✅ Engine stopped cleanly.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use bytes::BytesMut;
+    use tokio::io::ReadBuf;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn full_engine_queue_is_nacked_engine_busy() {
+        // Stub engine: a queue of two that nothing ever drains
+        let (tx_cmd, _rx_cmd) = bounded::<Command>(2);
+        let ingress = Ingress::Direct(tx_cmd);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let mut framed = Framed::new(listener.accept().await.unwrap().0, ClobCodec::default());
+        let (sink, _rx_evt) = sink::channel(16, 16, 1);
+
+        let received = std::time::Instant::now();
+        for _ in 0..2 {
+            assert!(forward(&mut framed, &ingress, received, MSG_PING, Command::Ping(sink.clone())).await.unwrap());
+        }
+        assert!(!forward(&mut framed, &ingress, received, MSG_PING, Command::Ping(sink.clone())).await.unwrap());
+
+        let mut client = Framed::new(client, ClobCodec::default());
+        let frame = client.next().await.unwrap().unwrap().unwrap();
+        assert_eq!(frame.msg_type, MSG_NACK);
+        assert_eq!(ByteOrder::Little.read_u16(&frame.body[0..2]), MSG_PING);
+        assert_eq!(RejectReason::from_code(ByteOrder::Little.read_u16(&frame.body[2..4])), Some(RejectReason::EngineBusy));
+    }
+
+    /// A socket that reads the scripted chunks and errors in turn, then waits forever, and keeps what is written to it.
+    struct Scripted {
+        reads: VecDeque<io::Result<Vec<u8>>>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl AsyncRead for Scripted {
+        fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            match self.reads.pop_front() {
+                Some(Ok(chunk)) => {
+                    buf.put_slice(&chunk);
+                    Poll::Ready(Ok(()))
+                }
+                Some(Err(e)) => Poll::Ready(Err(e)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    impl AsyncWrite for Scripted {
+        fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_read_is_retried_and_keeps_the_partial_frame() {
+        // Stub engine: answers PINGs only
+        let (tx_cmd, rx_cmd) = bounded::<Command>(16);
+        thread::spawn(move || {
+            while let Ok(cmd) = rx_cmd.recv() {
+                if let Command::Ping(sink) = cmd {
+                    sink.send(Event::Pong);
+                }
+            }
         });
+        let mut hello = PROTO_VERSION.to_le_bytes().to_vec();
+        hello.push(0);
+        let hello = encode_frame(MSG_HELLO, &hello, FrameFormat::default()).to_vec();
+        let ping = encode_frame(MSG_PING, &[], FrameFormat::default()).to_vec();
+        let (head, tail) = ping.split_at(3);
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let socket = Scripted {
+            reads: VecDeque::from([Ok(hello), Ok(head.to_vec()), Err(io::ErrorKind::Interrupted.into()), Ok(tail.to_vec())]),
+            written: written.clone(),
+        };
+
+        let (sink, rx_evt) = sink::channel(16, 16, 1);
+        let peer: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let conn = tokio::spawn(process(
+            socket, peer, Ingress::Direct(tx_cmd), sink, rx_evt, broadcast::channel(1).0, broadcast::channel(1).0, broadcast::channel(1).0,
+            Duration::from_secs(60), RateLimit { per_sec: 1_000, burst: 1_000 }, Arc::new(Metrics::default()), None,
+            Arc::new(HashMap::new()), Claims::default(),
+        ));
+
+        // The PING split around the error still arrives whole, and the connection stays up
+        let replies = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let mut out = BytesMut::from(&written.lock().unwrap()[..]);
+                let mut replies = Vec::new();
+                while let Ok(Some(frame)) = decode_frame(&mut out, FrameFormat::default()) {
+                    replies.push(frame.msg_type);
+                }
+                if replies.len() >= 2 {
+                    return replies;
+                }
+                sleep_until(Instant::now() + Duration::from_millis(10)).await;
+            }
+        }).await.expect("no PONG");
+        assert_eq!(replies, [MSG_HELLO_ACK, MSG_PONG]);
+        assert!(!conn.is_finished());
+        conn.abort();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn full_engine_queue_never_stalls_the_runtime() {
+        // One worker thread, so a send that blocked it would hang the whole test
+        let (tx_cmd, rx_cmd) = bounded::<Command>(1);
+        let ingress = Ingress::Direct(tx_cmd);
+        ingress.try_send(Command::Sweep { now_ms: 0 }, std::time::Instant::now()).unwrap();
+        assert!(ingress.try_send(Command::Sweep { now_ms: 99 }, std::time::Instant::now()).unwrap_err().is_full());
+
+        // Must-not-lose commands wait off the runtime, which carries on meanwhile
+        let deferred = ingress.send_or_defer((1..=3).map(|now_ms| Command::Sweep { now_ms }).collect());
+        assert_eq!(deferred, Some(3));
+        assert_eq!(tokio::spawn(async { 7 }).await.unwrap(), 7);
+
+        // Once the engine drains, they arrive in order behind what was already queued
+        let drained = tokio::task::spawn_blocking(move || {
+            (0..4).map(|_| match rx_cmd.recv_timeout(Duration::from_secs(5)) {
+                Ok(Command::Sweep { now_ms }) => now_ms,
+                _ => panic!("expected a sweep"),
+            }).collect::<Vec<_>>()
+        });
+        assert_eq!(drained.await.unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn rapid_deltas_to_one_level_flush_as_one_with_the_final_qty() {
+        let delta = |price, level_qty, seq| Event::BookDelta { symbol: 1, side: Side::Bid, price, level_qty, seq };
+        let mut c = Conflator::new(Duration::from_millis(50));
+        for (qty, seq) in [(10, 1), (25, 2), (5, 4), (40, 6)] {
+            assert!(c.offer(delta(100, qty, seq)).is_none());
+        }
+        assert!(c.offer(delta(99, 7, 5)).is_none());
+        let trade = clob_engine::types::Trade {
+            symbol: 1, price: 100, qty: 3, taker_cl_id: 2, maker_cl_id: 1, taker_ord_id: 9, maker_ord_id: 8,
+            aggressor_side: Side::Ask, maker_fee: 0, taker_fee: 0, seq: 3, price_improvement: 0,
+        };
+        assert!(matches!(c.offer(Event::Trade(trade)), Some(Event::Trade(_))), "trades pass straight through");
+
+        c.flush.tick().await;
+        let flushed: Vec<(i64, u64, u64)> = c.drain().into_iter().map(|e| match e {
+            Event::BookDelta { price, level_qty, seq, .. } => (price, level_qty, seq),
+            e => panic!("unexpected {e:?}"),
+        }).collect();
+        assert_eq!(flushed, [(99, 7, 5), (100, 40, 6)]);
+        assert!(c.drain().is_empty());
     }
 }
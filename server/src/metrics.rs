@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, RwLock};
+use bytes::Bytes;
+use crossbeam::channel::Sender;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tracing::warn;
+use crate::types::{Command, OrderBook};
+
+// ========================== Prometheus metrics ==========================
+// Counters are relaxed atomics bumped by the engine thread and the gateway tasks;
+// `GET /metrics` on a separate port renders them in the Prometheus text format.
+
+#[derive(Default)]
+pub struct Metrics {
+    orders_accepted: AtomicU64,
+    orders_rejected: AtomicU64, // By the engine or, for malformed orders, the gateway
+    orders_canceled: AtomicU64, // Client cancels and cancel-all
+    trades: AtomicU64,
+    traded_volume: AtomicU64,
+    slow_consumers: AtomicU64, // Connections cut off for not reading their events
+    resting: RwLock<BTreeMap<u32, AtomicU64>>, // Symbol → resting orders, refreshed per engine batch
+}
+
+impl Metrics {
+    pub fn order_accepted(&self) {
+        self.orders_accepted.fetch_add(1, Relaxed);
+    }
+
+    pub fn order_rejected(&self) {
+        self.orders_rejected.fetch_add(1, Relaxed);
+    }
+
+    pub fn orders_canceled(&self, n: u64) {
+        self.orders_canceled.fetch_add(n, Relaxed);
+    }
+
+    pub fn trade(&self, qty: u64) {
+        self.trades.fetch_add(1, Relaxed);
+        self.traded_volume.fetch_add(qty, Relaxed);
+    }
+
+    pub fn slow_consumer(&self) {
+        self.slow_consumers.fetch_add(1, Relaxed);
+    }
+
+    /// Publish every book's resting order count. Takes the write lock only for a new symbol.
+    pub fn set_resting(&self, books: &HashMap<u32, OrderBook>) {
+        {
+            let resting = self.resting.read().expect("metrics lock");
+            if books.keys().all(|s| resting.contains_key(s)) {
+                for (symbol, b) in books {
+                    resting[symbol].store(b.lookup.len() as u64, Relaxed);
+                }
+                return;
+            }
+        }
+        let mut resting = self.resting.write().expect("metrics lock");
+        for (&symbol, b) in books {
+            resting.entry(symbol).or_default().store(b.lookup.len() as u64, Relaxed);
+        }
+    }
+
+    /// Prometheus text exposition (format 0.0.4).
+    pub fn render(&self, queue_depth: usize) -> String {
+        let mut out = String::with_capacity(1024);
+        let mut single = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}");
+        };
+        single("clob_orders_accepted_total", "counter", "Orders the engine accepted (filled and/or rested).", self.orders_accepted.load(Relaxed));
+        single("clob_orders_rejected_total", "counter", "Orders rejected by the engine or the gateway.", self.orders_rejected.load(Relaxed));
+        single("clob_orders_canceled_total", "counter", "Resting orders pulled by cancel or cancel-all.", self.orders_canceled.load(Relaxed));
+        single("clob_trades_total", "counter", "Fills.", self.trades.load(Relaxed));
+        single("clob_traded_volume_total", "counter", "Quantity filled.", self.traded_volume.load(Relaxed));
+        single("clob_slow_consumers_total", "counter", "Connections dropped as slow consumers.", self.slow_consumers.load(Relaxed));
+        single("clob_command_queue_depth", "gauge", "Commands waiting for the engine.", queue_depth as u64);
+
+        let _ = writeln!(out, "# HELP clob_resting_orders Resting orders per symbol.\n# TYPE clob_resting_orders gauge");
+        for (symbol, n) in self.resting.read().expect("metrics lock").iter() {
+            let _ = writeln!(out, "clob_resting_orders{{symbol=\"{symbol}\"}} {}", n.load(Relaxed));
+        }
+        out
+    }
+}
+
+/// Serve `GET /metrics` until the runtime shuts down; `queue` is only read for its depth.
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>, queue: Sender<Command>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("[metrics] ⚠️ accept failed: {e}");
+                continue;
+            }
+        };
+        let (metrics, queue) = (metrics.clone(), queue.clone());
+        tokio::spawn(async move {
+            let svc = service_fn(move |req: Request<Incoming>| {
+                let resp = match (req.method(), req.uri().path()) {
+                    (&Method::GET, "/metrics") => Response::builder()
+                        .header("content-type", "text/plain; version=0.0.4")
+                        .body(Full::new(Bytes::from(metrics.render(queue.len())))),
+                    _ => Response::builder().status(StatusCode::NOT_FOUND).body(Full::new(Bytes::from_static(b"not found\n"))),
+                };
+                async move { Ok::<_, Infallible>(resp.expect("static response parts")) }
+            });
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), svc).await {
+                warn!("[metrics] ⚠️ connection error: {e}");
+            }
+        });
+    }
+}
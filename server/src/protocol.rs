@@ -0,0 +1,1110 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::warn;
+use crate::types::{AckNote, DoneReason, Event, Order, OrderType, RejectReason, Side, Stp, Tif, Trade};
+
+// ========================== Protocol ==========================
+// Frame: [u32 len][u16 type][u16 body_len][payload...], then [u32 crc32] if negotiated
+// `len` counts everything after itself; integers little-endian unless the
+// connection's HELLO picked big-endian (`ByteOrder`), for the prefix and bodies alike.
+// Side encoding: 0 = BID, 1 = ASK
+
+// Requests (client → gateway)
+pub const MSG_PING: u16 = 1;
+pub const MSG_NEW_ORDER: u16 = 10;
+pub const MSG_CANCEL: u16 = 11;
+pub const MSG_SUBSCRIBE: u16 = 12;
+pub const MSG_BOOK_SNAPSHOT: u16 = 13;
+pub const MSG_REPLACE: u16 = 14;
+pub const MSG_CANCEL_ALL: u16 = 15;
+pub const MSG_HELLO: u16 = 16;
+pub const MSG_QUERY_ORDERS: u16 = 17;
+pub const MSG_QUERY_STATS: u16 = 18;
+pub const MSG_SESSION_ROLL: u16 = 19;
+pub const MSG_HALT: u16 = 20;
+pub const MSG_RESUME: u16 = 21;
+pub const MSG_AUCTION: u16 = 22;
+pub const MSG_UNCROSS: u16 = 23;
+pub const MSG_REGISTER: u16 = 24;
+pub const MSG_DROPCOPY_SUBSCRIBE: u16 = 25;
+pub const MSG_BATCH_NEW: u16 = 26;
+pub const MSG_RESYNC: u16 = 27;
+pub const MSG_ADMIN_AUTH: u16 = 28;
+pub const MSG_REDUCE: u16 = 29;
+
+// Events (engine → client)
+pub const MSG_PONG: u16 = 2;
+pub const MSG_ACK: u16 = 100;
+pub const MSG_TRADE: u16 = 101;
+pub const MSG_BOOK_DELTA: u16 = 102;
+pub const MSG_STP_CANCELED: u16 = 103;
+pub const MSG_SNAPSHOT: u16 = 104;
+pub const MSG_ACCEPTED: u16 = 105;
+pub const MSG_REPLACED: u16 = 106;
+pub const MSG_DONE: u16 = 107;
+pub const MSG_CANCEL_ALL_DONE: u16 = 108;
+pub const MSG_OPEN_ORDERS: u16 = 109;
+pub const MSG_BBO: u16 = 110;
+pub const MSG_STATS: u16 = 111;
+pub const MSG_CANDLE: u16 = 112;
+pub const MSG_DROPCOPY: u16 = 113;
+pub const MSG_EXEC_REPORT: u16 = 114;
+pub const MSG_HELLO_ACK: u16 = 115;
+pub const MSG_ORDER_ADD: u16 = 116;
+pub const MSG_ORDER_EXECUTE: u16 = 117;
+pub const MSG_ORDER_REDUCE: u16 = 118;
+pub const MSG_ORDER_REMOVE: u16 = 119;
+pub const MSG_CANCELED: u16 = 120;
+pub const MSG_BATCH_ACK: u16 = 121;
+pub const MSG_RESYNC_EVENT: u16 = 122;
+pub const MSG_RESYNC_DONE: u16 = 123;
+pub const MSG_ADMIN_CHALLENGE: u16 = 124;
+pub const MSG_NACK: u16 = 198;
+pub const MSG_REJECT: u16 = 199;
+
+// NEW_ORDER flag bits
+pub const FLAG_POST_ONLY: u8 = 0x01;
+pub const FLAG_REDUCE_ONLY: u8 = 0x02;
+
+// Protocol versions this server speaks; a HELLO gets the highest one both sides know
+pub const PROTO_VERSION_MIN: u16 = 1;
+pub const PROTO_VERSION: u16 = 1;
+
+/// Version to speak with a client that offers `client`, or `None` if it is older than we support.
+pub fn negotiate_version(client: u16) -> Option<u16> {
+    (client >= PROTO_VERSION_MIN).then(|| client.min(PROTO_VERSION))
+}
+
+// Upper bound on a single frame's payload; anything larger is treated as hostile
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+// Deepest snapshot whose two sides still fit a u16 body_len (16 bytes per level, 16 of header and seq)
+pub const MAX_SNAPSHOT_DEPTH: u16 = 2047;
+
+// Most open-order records (29 bytes each, after a 10-byte header) one u16 body_len can hold
+pub const MAX_OPEN_ORDERS: usize = (u16::MAX as usize - 10) / 29;
+
+/// Byte order of every integer on a connection, length prefix included.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    #[default]
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    pub fn read_u16(self, b: &[u8]) -> u16 {
+        let raw = b[..2].try_into().unwrap();
+        match self { ByteOrder::Little => u16::from_le_bytes(raw), ByteOrder::Big => u16::from_be_bytes(raw) }
+    }
+
+    pub fn read_u32(self, b: &[u8]) -> u32 {
+        let raw = b[..4].try_into().unwrap();
+        match self { ByteOrder::Little => u32::from_le_bytes(raw), ByteOrder::Big => u32::from_be_bytes(raw) }
+    }
+
+    pub fn read_u64(self, b: &[u8]) -> u64 {
+        let raw = b[..8].try_into().unwrap();
+        match self { ByteOrder::Little => u64::from_le_bytes(raw), ByteOrder::Big => u64::from_be_bytes(raw) }
+    }
+
+    pub fn read_i64(self, b: &[u8]) -> i64 {
+        self.read_u64(b) as i64
+    }
+}
+
+/// How a connection's frames are laid out, as negotiated in its HELLO.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameFormat {
+    pub order: ByteOrder,
+    pub crc: bool, // Frames end with a CRC32 of everything between the length prefix and itself
+}
+
+/// Body under construction, written in the connection's byte order.
+struct Body {
+    buf: BytesMut,
+    order: ByteOrder,
+}
+
+impl Body {
+    fn new(order: ByteOrder, capacity: usize) -> Self {
+        Body { buf: BytesMut::with_capacity(capacity), order }
+    }
+
+    fn put_u8(&mut self, v: u8) {
+        self.buf.put_u8(v);
+    }
+
+    fn put_u16(&mut self, v: u16) {
+        match self.order { ByteOrder::Little => self.buf.put_u16_le(v), ByteOrder::Big => self.buf.put_u16(v) }
+    }
+
+    fn put_u32(&mut self, v: u32) {
+        match self.order { ByteOrder::Little => self.buf.put_u32_le(v), ByteOrder::Big => self.buf.put_u32(v) }
+    }
+
+    fn put_u64(&mut self, v: u64) {
+        match self.order { ByteOrder::Little => self.buf.put_u64_le(v), ByteOrder::Big => self.buf.put_u64(v) }
+    }
+
+    fn put_i64(&mut self, v: i64) {
+        self.put_u64(v as u64);
+    }
+
+    fn extend_from_slice(&mut self, b: &[u8]) {
+        self.buf.extend_from_slice(b);
+    }
+
+    fn reserve(&mut self, n: usize) {
+        self.buf.reserve(n);
+    }
+}
+
+/// One complete inbound frame, length prefix and `body_len` stripped. `body` shares the
+/// connection's read buffer, so handing it around or slicing it copies nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub msg_type: u16,
+    pub body: Bytes,
+}
+
+/// Recovery policy: if the length prefix can't be trusted, the stream is desynced and
+/// the connection is closed after a NACK (`is_fatal`). If the frame is well delimited
+/// and only its body is inconsistent, it is NACKed and the next frame is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// `len` is over `MAX_FRAME_LEN`. Nothing is consumed. Fatal.
+    FrameTooLarge { msg_type: u16, len: usize },
+    /// `len` is too short to hold `[u16 type][u16 body_len]`. Fatal.
+    ShortFrame { len: usize },
+    /// `body_len` runs past the end of the frame. The frame is consumed.
+    BodyOverrun { msg_type: u16, body_len: usize, available: usize },
+    /// The frame's CRC32 trailer doesn't match its contents. Fatal.
+    BadChecksum { msg_type: u16, expected: u32, actual: u32 },
+}
+
+impl ProtocolError {
+    /// Message type to reference in a NACK (0 when the frame never got that far).
+    pub fn msg_type(&self) -> u16 {
+        match *self {
+            ProtocolError::FrameTooLarge { msg_type, .. }
+            | ProtocolError::BodyOverrun { msg_type, .. }
+            | ProtocolError::BadChecksum { msg_type, .. } => msg_type,
+            ProtocolError::ShortFrame { .. } => 0,
+        }
+    }
+
+    /// NACK reason.
+    pub fn reason(&self) -> RejectReason {
+        match self {
+            ProtocolError::FrameTooLarge { .. } => RejectReason::FrameTooLarge,
+            ProtocolError::ShortFrame { .. } | ProtocolError::BodyOverrun { .. } => RejectReason::MalformedFrame,
+            ProtocolError::BadChecksum { .. } => RejectReason::BadChecksum,
+        }
+    }
+
+    /// Whether the connection has to be dropped rather than carrying on with the next frame.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ProtocolError::FrameTooLarge { .. } | ProtocolError::ShortFrame { .. } | ProtocolError::BadChecksum { .. })
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::FrameTooLarge { msg_type, len } => {
+                write!(f, "frame of {len} bytes (msg_type={msg_type}) exceeds {MAX_FRAME_LEN}")
+            }
+            ProtocolError::ShortFrame { len } => write!(f, "frame of {len} bytes has no header"),
+            ProtocolError::BodyOverrun { msg_type, body_len, available } => {
+                write!(f, "body_len {body_len} exceeds the {available} bytes left in frame (msg_type={msg_type})")
+            }
+            ProtocolError::BadChecksum { msg_type, expected, actual } => {
+                write!(f, "crc32 {actual:08x} doesn't match the frame's {expected:08x} (msg_type={msg_type})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Pull the next complete frame off the front of `buf`.
+/// `Ok(None)` means more bytes are needed; `buf` is left untouched.
+/// Any bytes after `body_len` but inside `len` are ignored. With `crc` the last four of
+/// them are the checksum, which is checked before the header is trusted.
+pub fn decode_frame(buf: &mut BytesMut, fmt: FrameFormat) -> Result<Option<Frame>, ProtocolError> {
+    let order = fmt.order;
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+    let len = order.read_u32(&buf[0..4]) as usize;
+    if len > MAX_FRAME_LEN {
+        let msg_type = buf.get(4..6).map_or(0, |b| order.read_u16(b));
+        return Err(ProtocolError::FrameTooLarge { msg_type, len });
+    }
+    if buf.len() < 4 + len {
+        return Ok(None);
+    }
+
+    let mut frame = buf.split_to(4 + len);
+    frame.advance(4);
+    if frame.len() < if fmt.crc { 8 } else { 4 } {
+        return Err(ProtocolError::ShortFrame { len });
+    }
+    let msg_type = order.read_u16(&frame[0..2]);
+    if fmt.crc {
+        let (covered, trailer) = frame.split_at(frame.len() - 4);
+        let expected = order.read_u32(trailer);
+        let actual = crc32fast::hash(covered);
+        if actual != expected {
+            return Err(ProtocolError::BadChecksum { msg_type, expected, actual });
+        }
+        frame.truncate(frame.len() - 4);
+    }
+    let body_len = order.read_u16(&frame[2..4]) as usize;
+    frame.advance(4);
+    if body_len > frame.len() {
+        return Err(ProtocolError::BodyOverrun { msg_type, body_len, available: frame.len() });
+    }
+    // The body stays a view into the read buffer: trimmed in place, never copied
+    frame.truncate(body_len);
+    Ok(Some(Frame { msg_type, body: frame.freeze() }))
+}
+
+/// NEW_ORDER fixed part: [u64 client_id][u64 cl_ord_id][u32 symbol_id][u8 side][i64 price][i64 qty][u8 tif]
+pub const NEW_ORDER_MIN_LEN: usize = 8 + 8 + 4 + 1 + 8 + 8 + 1;
+
+/// Decode and validate a NEW_ORDER body; optional trailing bytes are
+/// [u8 ord_type][u8 flags][u8 stp][u64 expire_ms][u64 display_qty][u64 stop_price]. Bad input becomes a soft reject, never a panic.
+pub fn parse_new_order(body: &[u8], byte_order: ByteOrder) -> Result<Order, RejectReason> {
+    if body.len() < NEW_ORDER_MIN_LEN {
+        return Err(RejectReason::BadLength);
+    }
+    let client_id = byte_order.read_u64(&body[0..8]);
+    let cl_ord_id = byte_order.read_u64(&body[8..16]);
+    let symbol = byte_order.read_u32(&body[16..20]);
+    let side = match body[20] {
+        0 => Side::Bid,
+        1 => Side::Ask,
+        _ => return Err(RejectReason::BadSide),
+    };
+    let price = byte_order.read_i64(&body[21..29]);
+    let qty = byte_order.read_i64(&body[29..37]);
+    let tif = match body[37] {
+        0 => Tif::Gtc,
+        1 => Tif::Ioc,
+        2 => Tif::Fok,
+        3 => Tif::Gtd,
+        4 => Tif::Day,
+        _ => return Err(RejectReason::BadTif),
+    };
+    // Optional trailing flag: 0=LIMIT (default), 1=MARKET, 2=STOP, 3=STOP_LIMIT
+    let ord_type = match body.get(38).copied().unwrap_or(0) {
+        0 => OrderType::Limit,
+        1 => OrderType::Market,
+        2 => OrderType::Stop,
+        3 => OrderType::StopLimit,
+        _ => return Err(RejectReason::BadOrdType),
+    };
+    // Optional trailing flags byte (see FLAG_*)
+    let flags = body.get(39).copied().unwrap_or(0);
+    // Optional STP mode: 0=OFF (default), 1=CANCEL_RESTING, 2=CANCEL_AGGRESSING
+    let stp = match body.get(40).copied().unwrap_or(0) {
+        0 => Stp::Off,
+        1 => Stp::CancelResting,
+        2 => Stp::CancelAggressing,
+        _ => return Err(RejectReason::BadStp),
+    };
+
+    // Market orders may leave price at 0; a limit needs a real price, which may be negative
+    if price == 0 && matches!(ord_type, OrderType::Limit | OrderType::StopLimit) {
+        return Err(RejectReason::BadPrice);
+    }
+    if qty <= 0 {
+        return Err(RejectReason::BadQty);
+    }
+
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    // GTD carries its expiry right after the STP byte; it must still be in the future
+    let expire_ms = if tif == Tif::Gtd {
+        let raw = body.get(41..49).ok_or(RejectReason::BadExpiry)?;
+        let expire_ms = byte_order.read_u64(raw);
+        if expire_ms <= now_ms {
+            return Err(RejectReason::BadExpiry);
+        }
+        expire_ms
+    } else {
+        0
+    };
+    // Iceberg slice size; the expiry slot comes first even when unused. Showing it all is a plain order
+    let display_qty = match body.get(49..57) {
+        Some(raw) => byte_order.read_u64(raw),
+        None => 0,
+    };
+    if display_qty > 0 && matches!(ord_type, OrderType::Market | OrderType::Stop) {
+        return Err(RejectReason::BadDisplay);
+    }
+    let display_qty = if display_qty >= qty as u64 { 0 } else { display_qty };
+    // Stops need their trigger; anything else ignores the slot
+    let stop_price = match (ord_type, body.get(57..65)) {
+        (OrderType::Stop | OrderType::StopLimit, Some(raw)) => byte_order.read_i64(raw),
+        _ => 0,
+    };
+    if stop_price == 0 && matches!(ord_type, OrderType::Stop | OrderType::StopLimit) {
+        return Err(RejectReason::BadStopPrice);
+    }
+
+    Ok(Order {
+        id: cl_ord_id,
+        exch_ord_id: 0, // The engine's to assign
+        cl_id: client_id,
+        symbol,
+        side,
+        price,
+        qty: qty as u64,
+        timestamp: now_ms,
+        arrival_seq: 0, // The engine's to stamp
+        queue_seq: 0,
+        tif,
+        ord_type,
+        post_only: flags & FLAG_POST_ONLY != 0,
+        reduce_only: flags & FLAG_REDUCE_ONLY != 0,
+        stp,
+        expire_ms,
+        display_qty,
+        total_qty: qty as u64,
+        stop_price,
+    })
+}
+
+/// BATCH_NEW record: a NEW_ORDER body with every optional field present (unused ones zero)
+pub const BATCH_ORDER_LEN: usize = NEW_ORDER_MIN_LEN + 3 + 8 + 8 + 8;
+
+/// Split a BATCH_NEW body, `[u16 count]` then `count` records of `BATCH_ORDER_LEN`, into its
+/// records, each a NEW_ORDER body for `parse_new_order`. A body too short for `count` is `BadLength`.
+pub fn batch_records(body: &[u8], order: ByteOrder) -> Result<std::slice::ChunksExact<'_, u8>, RejectReason> {
+    let count = body.get(0..2).map(|b| order.read_u16(b) as usize).ok_or(RejectReason::BadLength)?;
+    let records = body.get(2..2 + count * BATCH_ORDER_LEN).ok_or(RejectReason::BadLength)?;
+    Ok(records.chunks_exact(BATCH_ORDER_LEN))
+}
+
+/// Build an outbound frame: [u32 len][u16 type][u16 body_len][body…], plus [u32 crc32] with `crc`
+pub fn encode_frame(msg_type: u16, body: &[u8], fmt: FrameFormat) -> BytesMut {
+    let total = 2 + 2 + body.len() + if fmt.crc { 4 } else { 0 };
+    let mut out = Body::new(fmt.order, 4 + total);
+    out.put_u32(total as u32);
+    out.put_u16(msg_type);
+    out.put_u16(body.len() as u16);
+    out.extend_from_slice(body);
+    if fmt.crc {
+        let crc = crc32fast::hash(&out.buf[4..]);
+        out.put_u32(crc);
+    }
+    out.buf
+}
+
+/// Gateway-level refusal of a frame (never reached the engine).
+/// Body: [u16 ref_msg_type][u16 code][u16 reason_len][reason...]
+pub fn nack(ref_msg_type: u16, reason: RejectReason, order: ByteOrder) -> Frame {
+    let text = reason.as_str();
+    let mut body = Body::new(order, 6 + text.len());
+    body.put_u16(ref_msg_type);
+    body.put_u16(reason.code());
+    body.put_u16(text.len() as u16);
+    body.extend_from_slice(text.as_bytes());
+    Frame { msg_type: MSG_NACK, body: body.buf.freeze() }
+}
+
+pub fn encode_nack(ref_msg_type: u16, reason: RejectReason, fmt: FrameFormat) -> BytesMut {
+    let f = nack(ref_msg_type, reason, fmt.order);
+    encode_frame(f.msg_type, &f.body, fmt)
+}
+
+/// Encode an engine `Event` into a wire frame. `seq` is the market-data feed sequence number.
+/// - PONG:       (empty)
+/// - ACK:        [u64 ord_id][u16 code][u16 text_len][text...]
+/// - REJECT:     [u64 ord_id][u16 code][u16 reason_len][reason...]
+/// - TRADE:      [i64 price][i64 qty][u64 taker_cl_id][u64 maker_cl_id][u64 taker_exch_ord_id][u64 maker_exch_ord_id][u8 aggressor_side][u32 symbol_id][u64 maker_fee][u64 taker_fee][u64 seq][u64 price_improvement]
+/// - BOOK_DELTA: [u8 side][i64 price][i64 level_qty][u32 symbol_id][u64 seq]
+/// - BBO:        [u32 symbol_id][i64 bid_px][i64 bid_qty][i64 ask_px][i64 ask_qty][u64 seq] (0/0 = empty side)
+/// - STP_CANCELED: [u64 ord_id][i64 qty]
+/// - ACCEPTED:   [u64 ord_id][u8 side][i64 price][i64 filled_qty][i64 resting_qty][u64 exch_ord_id]
+/// - REPLACED:   [u64 ord_id][i64 price][i64 qty][u8 priority_kept]
+/// - DONE:       [u64 ord_id][u8 reason]
+/// - CANCEL_ALL_DONE: [u64 count]
+/// - OPEN_ORDERS: [u64 cl_id][u16 count] then per order [u32 symbol_id][u64 ord_id][u8 side][i64 price][i64 qty]
+/// - SNAPSHOT:   [u32 symbol_id][u16 bid_levels][u16 ask_levels] then per level [i64 price][i64 qty], bids then asks, then [u64 seq]
+/// - DROPCOPY:   [u64 cl_id] then the copied event's frame without its length: [u16 msg_type][u16 body_len][body]
+/// - EXEC_REPORT: the report line, ASCII `key=value` pairs separated by spaces
+/// - HELLO_ACK:  [u16 proto_version]
+/// - ADMIN_CHALLENGE: [16-byte nonce]
+pub fn encode_event(evt: &Event, fmt: FrameFormat) -> BytesMut {
+    let mut body = Body::new(fmt.order, 32);
+    let msg_type = match evt {
+        Event::Pong => MSG_PONG,
+        Event::Ack { ord_id, note } => {
+            body.put_u64(*ord_id);
+            body.put_u16(note.code());
+            body.put_u16(note.as_str().len() as u16);
+            body.extend_from_slice(note.as_str().as_bytes());
+            MSG_ACK
+        }
+        Event::Accepted { ord_id, exch_ord_id, filled_qty, resting_qty, side, price } => {
+            body.put_u64(*ord_id);
+            body.put_u8(match side { Side::Bid => 0, Side::Ask => 1 });
+            body.put_i64(*price);
+            body.put_i64(*filled_qty as i64);
+            body.put_i64(*resting_qty as i64);
+            body.put_u64(*exch_ord_id);
+            MSG_ACCEPTED
+        }
+        Event::Replaced { ord_id, price, qty, priority_kept } => {
+            body.put_u64(*ord_id);
+            body.put_i64(*price);
+            body.put_i64(*qty as i64);
+            body.put_u8(*priority_kept as u8);
+            MSG_REPLACED
+        }
+        Event::Done { ord_id, reason } => {
+            body.put_u64(*ord_id);
+            body.put_u8(reason.code());
+            MSG_DONE
+        }
+        Event::DropCopy { cl_id, evt } => {
+            // The copy is checked as part of the outer frame, so it carries no CRC of its own
+            let inner = encode_event(evt, FrameFormat { crc: false, ..fmt });
+            body.put_u64(*cl_id);
+            body.extend_from_slice(&inner[4..]);
+            MSG_DROPCOPY
+        }
+        Event::Resynced { cl_id, seq, evt } => {
+            // Wrapped like a drop copy
+            let inner = encode_event(evt, FrameFormat { crc: false, ..fmt });
+            body.put_u64(*cl_id);
+            body.put_u64(*seq);
+            body.extend_from_slice(&inner[4..]);
+            MSG_RESYNC_EVENT
+        }
+        Event::ResyncDone { cl_id, last_seq, complete } => {
+            body.put_u64(*cl_id);
+            body.put_u64(*last_seq);
+            body.put_u8(*complete as u8);
+            MSG_RESYNC_DONE
+        }
+        Event::ExecReport(line) => {
+            body.extend_from_slice(line.as_bytes());
+            MSG_EXEC_REPORT
+        }
+        Event::HelloAck { version } => {
+            body.put_u16(*version);
+            MSG_HELLO_ACK
+        }
+        Event::AdminChallenge { nonce } => {
+            body.extend_from_slice(nonce);
+            MSG_ADMIN_CHALLENGE
+        }
+        Event::Canceled { ord_id, unfilled_qty, reason } => {
+            body.put_u64(*ord_id);
+            body.put_i64(*unfilled_qty as i64);
+            body.put_u8(reason.code());
+            MSG_CANCELED
+        }
+        Event::CancelAllDone { count } => {
+            body.put_u64(*count);
+            MSG_CANCEL_ALL_DONE
+        }
+        Event::BatchAck { results } => {
+            body.reserve(2 + 10 * results.len());
+            body.put_u16(results.len() as u16);
+            for (ord_id, reject) in results {
+                body.put_u64(*ord_id);
+                body.put_u16(reject.map_or(0, RejectReason::code));
+            }
+            MSG_BATCH_ACK
+        }
+        Event::Stats { symbol, last, high, low, volume, trades } => {
+            body.put_u32(*symbol);
+            body.put_i64(*last);
+            body.put_i64(*high);
+            body.put_i64(*low);
+            body.put_i64(*volume as i64);
+            body.put_u64(*trades);
+            MSG_STATS
+        }
+        Event::Candle { symbol, open, high, low, close, volume, start_ts } => {
+            body.put_u32(*symbol);
+            body.put_i64(*open);
+            body.put_i64(*high);
+            body.put_i64(*low);
+            body.put_i64(*close);
+            body.put_i64(*volume as i64);
+            body.put_u64(*start_ts);
+            MSG_CANDLE
+        }
+        Event::Reject { ord_id, reason } => {
+            body.put_u64(*ord_id);
+            body.put_u16(reason.code());
+            body.put_u16(reason.as_str().len() as u16);
+            body.extend_from_slice(reason.as_str().as_bytes());
+            MSG_REJECT
+        }
+        // A trade print goes out as a TRADE: subscribers that asked for them see fewer, larger trades
+        Event::Trade(Trade { symbol, price, qty, taker_cl_id, maker_cl_id, taker_ord_id, maker_ord_id, aggressor_side, maker_fee, taker_fee, seq, price_improvement })
+        | Event::TradePrint(Trade { symbol, price, qty, taker_cl_id, maker_cl_id, taker_ord_id, maker_ord_id, aggressor_side, maker_fee, taker_fee, seq, price_improvement }) => {
+            body.put_i64(*price);
+            body.put_i64(*qty as i64);
+            body.put_u64(*taker_cl_id);
+            body.put_u64(*maker_cl_id);
+            body.put_u64(*taker_ord_id);
+            body.put_u64(*maker_ord_id);
+            body.put_u8(match aggressor_side { Side::Bid => 0, Side::Ask => 1 });
+            body.put_u32(*symbol);
+            body.put_u64(*maker_fee);
+            body.put_u64(*taker_fee);
+            body.put_u64(*seq);
+            body.put_u64(*price_improvement);
+            MSG_TRADE
+        }
+        Event::BookDelta { symbol, side, price, level_qty, seq } => {
+            body.put_u8(match side { Side::Bid => 0, Side::Ask => 1 });
+            body.put_i64(*price);
+            body.put_i64(*level_qty as i64);
+            body.put_u32(*symbol);
+            body.put_u64(*seq);
+            MSG_BOOK_DELTA
+        }
+        Event::Bbo { symbol, bid_px, bid_qty, ask_px, ask_qty, seq } => {
+            body.put_u32(*symbol);
+            body.put_i64(*bid_px);
+            body.put_i64(*bid_qty as i64);
+            body.put_i64(*ask_px);
+            body.put_i64(*ask_qty as i64);
+            body.put_u64(*seq);
+            MSG_BBO
+        }
+        Event::OrderAdd { symbol, ord_id, side, price, qty, seq } => {
+            body.put_u64(*ord_id);
+            body.put_u8(match side { Side::Bid => 0, Side::Ask => 1 });
+            body.put_i64(*price);
+            body.put_i64(*qty as i64);
+            body.put_u32(*symbol);
+            body.put_u64(*seq);
+            MSG_ORDER_ADD
+        }
+        Event::OrderExecute { symbol, ord_id, qty, seq } => {
+            body.put_u64(*ord_id);
+            body.put_i64(*qty as i64);
+            body.put_u32(*symbol);
+            body.put_u64(*seq);
+            MSG_ORDER_EXECUTE
+        }
+        Event::OrderReduce { symbol, ord_id, qty, seq } => {
+            body.put_u64(*ord_id);
+            body.put_i64(*qty as i64);
+            body.put_u32(*symbol);
+            body.put_u64(*seq);
+            MSG_ORDER_REDUCE
+        }
+        Event::OrderRemove { symbol, ord_id, seq } => {
+            body.put_u64(*ord_id);
+            body.put_u32(*symbol);
+            body.put_u64(*seq);
+            MSG_ORDER_REMOVE
+        }
+        Event::StpCanceled { ord_id, qty } => {
+            body.put_u64(*ord_id);
+            body.put_i64(*qty as i64);
+            MSG_STP_CANCELED
+        }
+        Event::OpenOrders { cl_id, orders } => {
+            if orders.len() > MAX_OPEN_ORDERS {
+                warn!("[gw] client {} has {} open orders, reporting the first {}", cl_id, orders.len(), MAX_OPEN_ORDERS);
+            }
+            let shown = &orders[..orders.len().min(MAX_OPEN_ORDERS)];
+            body.reserve(10 + 29 * shown.len());
+            body.put_u64(*cl_id);
+            body.put_u16(shown.len() as u16);
+            for (symbol, ord_id, side, price, qty) in shown {
+                body.put_u32(*symbol);
+                body.put_u64(*ord_id);
+                body.put_u8(match side { Side::Bid => 0, Side::Ask => 1 });
+                body.put_i64(*price);
+                body.put_i64(*qty as i64);
+            }
+            MSG_OPEN_ORDERS
+        }
+        Event::Snapshot { symbol, bids, asks, seq } => {
+            body.reserve(16 + 16 * (bids.len() + asks.len()));
+            body.put_u32(*symbol);
+            body.put_u16(bids.len() as u16);
+            body.put_u16(asks.len() as u16);
+            for (price, qty) in bids.iter().chain(asks.iter()) {
+                body.put_i64(*price);
+                body.put_i64(*qty as i64);
+            }
+            body.put_u64(*seq);
+            MSG_SNAPSHOT
+        }
+    };
+    encode_frame(msg_type, &body.buf, fmt)
+}
+
+/// Cursor over a body in the connection's byte order; every read is `None` once it runs out.
+struct Reader<'a> {
+    b: &'a [u8],
+    order: ByteOrder,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (head, tail) = self.b.split_at_checked(n)?;
+        self.b = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| self.order.read_u16(b))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| self.order.read_u32(b))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|b| self.order.read_u64(b))
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take(8).map(|b| self.order.read_i64(b))
+    }
+
+    fn side(&mut self) -> Option<Side> {
+        match self.u8()? {
+            0 => Some(Side::Bid),
+            1 => Some(Side::Ask),
+            _ => None,
+        }
+    }
+}
+
+/// Turn an event frame back into the `Event` that `encode_event` wrote: the client side of the
+/// protocol. `None` for request types, unknown types and bodies too short for their layout.
+pub fn decode_event(msg_type: u16, body: &[u8], order: ByteOrder) -> Option<Event> {
+    let mut r = Reader { b: body, order };
+    let evt = match msg_type {
+        MSG_PONG => Event::Pong,
+        MSG_ACK => Event::Ack { ord_id: r.u64()?, note: AckNote::from_code(r.u16()?)? },
+        MSG_ACCEPTED => {
+            let (ord_id, side, price) = (r.u64()?, r.side()?, r.i64()?);
+            Event::Accepted { ord_id, side, price, filled_qty: r.u64()?, resting_qty: r.u64()?, exch_ord_id: r.u64()? }
+        }
+        MSG_REPLACED => Event::Replaced { ord_id: r.u64()?, price: r.i64()?, qty: r.u64()?, priority_kept: r.u8()? != 0 },
+        MSG_DONE => Event::Done { ord_id: r.u64()?, reason: DoneReason::from_code(r.u8()?)? },
+        MSG_DROPCOPY => {
+            let (cl_id, inner_type, inner_len) = (r.u64()?, r.u16()?, r.u16()?);
+            let evt = decode_event(inner_type, r.take(inner_len as usize)?, order)?;
+            Event::DropCopy { cl_id, evt: Box::new(evt) }
+        }
+        MSG_RESYNC_EVENT => {
+            let (cl_id, seq, inner_type, inner_len) = (r.u64()?, r.u64()?, r.u16()?, r.u16()?);
+            let evt = decode_event(inner_type, r.take(inner_len as usize)?, order)?;
+            Event::Resynced { cl_id, seq, evt: Box::new(evt) }
+        }
+        MSG_RESYNC_DONE => Event::ResyncDone { cl_id: r.u64()?, last_seq: r.u64()?, complete: r.u8()? != 0 },
+        MSG_EXEC_REPORT => Event::ExecReport(String::from_utf8_lossy(body).into_owned()),
+        MSG_HELLO_ACK => Event::HelloAck { version: r.u16()? },
+        MSG_ADMIN_CHALLENGE => Event::AdminChallenge { nonce: r.take(16)?.try_into().ok()? },
+        MSG_CANCELED => Event::Canceled { ord_id: r.u64()?, unfilled_qty: r.u64()?, reason: DoneReason::from_code(r.u8()?)? },
+        MSG_CANCEL_ALL_DONE => Event::CancelAllDone { count: r.u64()? },
+        MSG_BATCH_ACK => {
+            let count = r.u16()?;
+            let results = (0..count)
+                .map(|_| {
+                    let (ord_id, code) = (r.u64()?, r.u16()?);
+                    let reject = match code {
+                        0 => None,
+                        code => Some(RejectReason::from_code(code)?),
+                    };
+                    Some((ord_id, reject))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Event::BatchAck { results }
+        }
+        MSG_STATS => Event::Stats { symbol: r.u32()?, last: r.i64()?, high: r.i64()?, low: r.i64()?, volume: r.u64()?, trades: r.u64()? },
+        MSG_CANDLE => Event::Candle {
+            symbol: r.u32()?,
+            open: r.i64()?,
+            high: r.i64()?,
+            low: r.i64()?,
+            close: r.i64()?,
+            volume: r.u64()?,
+            start_ts: r.u64()?,
+        },
+        MSG_REJECT => Event::Reject { ord_id: r.u64()?, reason: RejectReason::from_code(r.u16()?)? },
+        MSG_TRADE => Event::Trade(Trade {
+            price: r.i64()?,
+            qty: r.u64()?,
+            taker_cl_id: r.u64()?,
+            maker_cl_id: r.u64()?,
+            taker_ord_id: r.u64()?,
+            maker_ord_id: r.u64()?,
+            aggressor_side: r.side()?,
+            symbol: r.u32()?,
+            maker_fee: r.u64()?,
+            taker_fee: r.u64()?,
+            seq: r.u64()?,
+            price_improvement: r.u64()?,
+        }),
+        MSG_BOOK_DELTA => {
+            let (side, price, level_qty) = (r.side()?, r.i64()?, r.u64()?);
+            Event::BookDelta { side, price, level_qty, symbol: r.u32()?, seq: r.u64()? }
+        }
+        MSG_BBO => Event::Bbo { symbol: r.u32()?, bid_px: r.i64()?, bid_qty: r.u64()?, ask_px: r.i64()?, ask_qty: r.u64()?, seq: r.u64()? },
+        MSG_ORDER_ADD => {
+            let (ord_id, side, price, qty) = (r.u64()?, r.side()?, r.i64()?, r.u64()?);
+            Event::OrderAdd { ord_id, side, price, qty, symbol: r.u32()?, seq: r.u64()? }
+        }
+        MSG_ORDER_EXECUTE => Event::OrderExecute { ord_id: r.u64()?, qty: r.u64()?, symbol: r.u32()?, seq: r.u64()? },
+        MSG_ORDER_REDUCE => Event::OrderReduce { ord_id: r.u64()?, qty: r.u64()?, symbol: r.u32()?, seq: r.u64()? },
+        MSG_ORDER_REMOVE => Event::OrderRemove { ord_id: r.u64()?, symbol: r.u32()?, seq: r.u64()? },
+        MSG_STP_CANCELED => Event::StpCanceled { ord_id: r.u64()?, qty: r.u64()? },
+        MSG_OPEN_ORDERS => {
+            let (cl_id, count) = (r.u64()?, r.u16()?);
+            let orders = (0..count)
+                .map(|_| Some((r.u32()?, r.u64()?, r.side()?, r.i64()?, r.u64()?)))
+                .collect::<Option<Vec<_>>>()?;
+            Event::OpenOrders { cl_id, orders }
+        }
+        MSG_SNAPSHOT => {
+            let (symbol, n_bids, n_asks) = (r.u32()?, r.u16()?, r.u16()?);
+            let mut level = || Some((r.i64()?, r.u64()?));
+            let bids = (0..n_bids).map(|_| level()).collect::<Option<Vec<_>>>()?;
+            let asks = (0..n_asks).map(|_| level()).collect::<Option<Vec<_>>>()?;
+            Event::Snapshot { symbol, bids, asks, seq: r.u64()? }
+        }
+        _ => return None,
+    };
+    Some(evt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A full-length little-endian NEW_ORDER body: client 7, order 42, symbol 3, GTC limit bid.
+    fn new_order(price: i64, qty: i64) -> Vec<u8> {
+        let mut b = Vec::with_capacity(BATCH_ORDER_LEN);
+        b.extend_from_slice(&7u64.to_le_bytes());
+        b.extend_from_slice(&42u64.to_le_bytes());
+        b.extend_from_slice(&3u32.to_le_bytes());
+        b.push(0);
+        b.extend_from_slice(&price.to_le_bytes());
+        b.extend_from_slice(&qty.to_le_bytes());
+        b.push(0);
+        b.resize(BATCH_ORDER_LEN, 0);
+        b
+    }
+
+    fn parse(body: &[u8]) -> Result<Order, RejectReason> {
+        parse_new_order(body, ByteOrder::Little)
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let mut b = new_order(-250, 1_000);
+        b[20] = 1; // ask
+        b[38] = 3; // stop-limit
+        b[39] = FLAG_POST_ONLY | FLAG_REDUCE_ONLY;
+        b[40] = 2; // cancel-aggressing
+        b[49..57].copy_from_slice(&100u64.to_le_bytes());
+        b[57..65].copy_from_slice(&(-240i64).to_le_bytes());
+        let o = parse(&b).unwrap();
+        assert_eq!((o.cl_id, o.id, o.symbol, o.side), (7, 42, 3, Side::Ask));
+        assert_eq!((o.price, o.qty, o.total_qty, o.display_qty, o.stop_price), (-250, 1_000, 1_000, 100, -240));
+        assert_eq!((o.tif, o.ord_type, o.stp), (Tif::Gtc, OrderType::StopLimit, Stp::CancelAggressing));
+        assert!(o.post_only && o.reduce_only);
+        assert_eq!((o.exch_ord_id, o.arrival_seq, o.expire_ms), (0, 0, 0));
+    }
+
+    #[test]
+    fn optional_fields_default_when_absent() {
+        let o = parse(&new_order(100, 5)[..NEW_ORDER_MIN_LEN]).unwrap();
+        assert_eq!((o.ord_type, o.stp, o.display_qty, o.stop_price), (OrderType::Limit, Stp::Off, 0, 0));
+        assert!(!o.post_only && !o.reduce_only);
+    }
+
+    #[test]
+    fn big_endian_bodies_read_the_same() {
+        let mut b = Vec::new();
+        b.extend_from_slice(&7u64.to_be_bytes());
+        b.extend_from_slice(&42u64.to_be_bytes());
+        b.extend_from_slice(&3u32.to_be_bytes());
+        b.push(0);
+        b.extend_from_slice(&(-5i64).to_be_bytes());
+        b.extend_from_slice(&9i64.to_be_bytes());
+        b.push(0);
+        let o = parse_new_order(&b, ByteOrder::Big).unwrap();
+        assert_eq!((o.cl_id, o.id, o.symbol, o.price, o.qty), (7, 42, 3, -5, 9));
+    }
+
+    #[test]
+    fn same_order_framed_in_either_byte_order_decodes_the_same() {
+        // Every multi-byte field set, with a sign and bytes that differ when swapped
+        let mut le = new_order(-250, 1_000);
+        le[0..8].copy_from_slice(&0x0102_0304_0506_0708u64.to_le_bytes());
+        le[16..20].copy_from_slice(&0x0a0b_0c0du32.to_le_bytes());
+        le[20] = 1; // ask
+        le[37..41].copy_from_slice(&[4, 3, FLAG_POST_ONLY, 1]); // DAY stop-limit, cancel-resting
+        le[49..57].copy_from_slice(&100u64.to_le_bytes());
+        le[57..65].copy_from_slice(&(-240i64).to_le_bytes());
+        // Big endian is each field's bytes reversed
+        let mut be = le.clone();
+        let mut at = 0;
+        for width in [8, 8, 4, 1, 8, 8, 1, 1, 1, 1, 8, 8, 8] {
+            be[at..at + width].reverse();
+            at += width;
+        }
+        let body = |order: ByteOrder| if order == ByteOrder::Little { le.clone() } else { be.clone() };
+        let decode = |order: ByteOrder| {
+            let fmt = FrameFormat { order, crc: true };
+            let mut buf = encode_frame(MSG_NEW_ORDER, &body(order), fmt);
+            let frame = decode_frame(&mut buf, fmt).unwrap().unwrap();
+            assert_eq!(frame.msg_type, MSG_NEW_ORDER);
+            parse_new_order(&frame.body, order).unwrap()
+        };
+        assert_ne!(body(ByteOrder::Little), body(ByteOrder::Big));
+        let (le, mut be) = (decode(ByteOrder::Little), decode(ByteOrder::Big));
+        // Each parse stamps its own arrival time, which can fall in the next millisecond
+        be.timestamp = le.timestamp;
+        assert_eq!((be.cl_id, be.symbol, be.side, be.price, be.qty), (0x0102_0304_0506_0708, 0x0a0b_0c0d, Side::Ask, -250, 1_000));
+        assert_eq!(format!("{le:?}"), format!("{be:?}"));
+    }
+
+    #[test]
+    fn every_short_body_is_bad_length() {
+        let b = new_order(100, 5);
+        for len in 0..NEW_ORDER_MIN_LEN {
+            assert_eq!(parse(&b[..len]).unwrap_err(), RejectReason::BadLength, "len {len}");
+        }
+    }
+
+    #[test]
+    fn qty_must_be_positive_up_to_i64_max() {
+        for qty in [0, -1, i64::MIN] {
+            assert_eq!(parse(&new_order(100, qty)).unwrap_err(), RejectReason::BadQty, "qty {qty}");
+        }
+        // u64::MAX and anything past i64::MAX read back negative
+        let mut b = new_order(100, 1);
+        b[29..37].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&b).unwrap_err(), RejectReason::BadQty);
+        b[29..37].copy_from_slice(&(i64::MAX as u64 + 1).to_le_bytes());
+        assert_eq!(parse(&b).unwrap_err(), RejectReason::BadQty);
+        assert_eq!(parse(&new_order(100, i64::MAX)).unwrap().qty, i64::MAX as u64);
+        assert_eq!(parse(&new_order(100, 1)).unwrap().qty, 1);
+    }
+
+    #[test]
+    fn limit_prices_take_any_sign_but_not_zero() {
+        for price in [i64::MIN, -1, 1, i64::MAX] {
+            assert_eq!(parse(&new_order(price, 1)).unwrap().price, price);
+        }
+        assert_eq!(parse(&new_order(0, 1)).unwrap_err(), RejectReason::BadPrice);
+        let mut stop_limit = new_order(0, 1);
+        stop_limit[38] = 3;
+        stop_limit[57..65].copy_from_slice(&5i64.to_le_bytes());
+        assert_eq!(parse(&stop_limit).unwrap_err(), RejectReason::BadPrice);
+        // A market order needs no price
+        let mut market = new_order(0, 1);
+        market[38] = 1;
+        assert_eq!(parse(&market).unwrap().ord_type, OrderType::Market);
+    }
+
+    #[test]
+    fn unknown_enum_bytes_are_rejected() {
+        for (offset, value, reason) in [
+            (20, 2, RejectReason::BadSide),
+            (20, u8::MAX, RejectReason::BadSide),
+            (37, 5, RejectReason::BadTif),
+            (38, 4, RejectReason::BadOrdType),
+            (40, 3, RejectReason::BadStp),
+        ] {
+            let mut b = new_order(100, 1);
+            b[offset] = value;
+            assert_eq!(parse(&b).unwrap_err(), reason, "byte {offset} = {value}");
+        }
+    }
+
+    #[test]
+    fn gtd_needs_a_future_expiry() {
+        let mut b = new_order(100, 1);
+        b[37] = 3;
+        assert_eq!(parse(&b[..41]).unwrap_err(), RejectReason::BadExpiry);
+        assert_eq!(parse(&b).unwrap_err(), RejectReason::BadExpiry);
+        let later = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64 + 60_000;
+        b[41..49].copy_from_slice(&later.to_le_bytes());
+        assert_eq!(parse(&b).unwrap().expire_ms, later);
+    }
+
+    #[test]
+    fn display_and_stop_slots_are_checked() {
+        // Showing the whole order, or more, is a plain order
+        let mut b = new_order(100, 10);
+        b[49..57].copy_from_slice(&10u64.to_le_bytes());
+        assert_eq!(parse(&b).unwrap().display_qty, 0);
+        b[49..57].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&b).unwrap().display_qty, 0);
+        b[38] = 1;
+        assert_eq!(parse(&b).unwrap_err(), RejectReason::BadDisplay);
+
+        let mut stop = new_order(100, 10);
+        stop[38] = 2;
+        assert_eq!(parse(&stop).unwrap_err(), RejectReason::BadStopPrice);
+        assert_eq!(parse(&stop[..41]).unwrap_err(), RejectReason::BadStopPrice);
+        // Other order types ignore the slot
+        let mut limit = new_order(100, 10);
+        limit[57..65].copy_from_slice(&99i64.to_le_bytes());
+        assert_eq!(parse(&limit).unwrap().stop_price, 0);
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
+        decode_frame(buf, FrameFormat::default())
+    }
+
+    /// `[u32 len][u16 type][u16 body_len]` as given, then `body`: for frames `encode_frame` won't build.
+    fn raw_frame(len: u32, msg_type: u16, body_len: u16, body: &[u8]) -> BytesMut {
+        let mut b = BytesMut::new();
+        b.extend_from_slice(&len.to_le_bytes());
+        b.extend_from_slice(&msg_type.to_le_bytes());
+        b.extend_from_slice(&body_len.to_le_bytes());
+        b.extend_from_slice(body);
+        b
+    }
+
+    #[test]
+    fn empty_buffer_needs_more() {
+        let mut buf = BytesMut::new();
+        assert!(decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn every_partial_frame_needs_more_and_is_left_alone() {
+        let whole = encode_frame(MSG_CANCEL, &[1, 2, 3, 4, 5], FrameFormat::default());
+        // Partial length prefix, partial header, partial body
+        for cut in 0..whole.len() {
+            let mut buf = BytesMut::from(&whole[..cut]);
+            assert!(decode(&mut buf).unwrap().is_none(), "cut at {cut}");
+            assert_eq!(&buf[..], &whole[..cut]);
+        }
+        let mut buf = whole.clone();
+        let frame = decode(&mut buf).unwrap().unwrap();
+        assert_eq!((frame.msg_type, &frame.body[..]), (MSG_CANCEL, &[1, 2, 3, 4, 5][..]));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn frames_come_off_one_buffer_in_order() {
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&encode_frame(MSG_PING, &[], FrameFormat::default()));
+        buf.extend_from_slice(&encode_frame(MSG_CANCEL, &[9; 20], FrameFormat::default()));
+        let third = encode_frame(MSG_NEW_ORDER, &[7; 38], FrameFormat::default());
+        buf.extend_from_slice(&third[..10]);
+
+        let first = decode(&mut buf).unwrap().unwrap();
+        assert_eq!((first.msg_type, first.body.len()), (MSG_PING, 0));
+        let second = decode(&mut buf).unwrap().unwrap();
+        assert_eq!((second.msg_type, &second.body[..]), (MSG_CANCEL, &[9; 20][..]));
+        assert!(decode(&mut buf).unwrap().is_none());
+        assert_eq!(&buf[..], &third[..10]);
+
+        buf.extend_from_slice(&third[10..]);
+        let third = decode(&mut buf).unwrap().unwrap();
+        assert_eq!((third.msg_type, &third.body[..]), (MSG_NEW_ORDER, &[7; 38][..]));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn body_len_past_the_frame_consumes_it_and_the_next_one_still_decodes() {
+        let mut buf = raw_frame(4 + 3, MSG_CANCEL, 20, &[0; 3]);
+        buf.extend_from_slice(&encode_frame(MSG_PING, &[], FrameFormat::default()));
+        let err = decode(&mut buf).unwrap_err();
+        assert!(matches!(err, ProtocolError::BodyOverrun { msg_type: MSG_CANCEL, body_len: 20, available: 3 }), "{err:?}");
+        assert!(!err.is_fatal());
+        assert_eq!(decode(&mut buf).unwrap().unwrap().msg_type, MSG_PING);
+    }
+
+    #[test]
+    fn bytes_past_body_len_are_dropped() {
+        let mut buf = raw_frame(4 + 6, MSG_CANCEL, 2, &[1, 2, 3, 4, 5, 6]);
+        let frame = decode(&mut buf).unwrap().unwrap();
+        assert_eq!(&frame.body[..], &[1, 2]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn oversized_length_is_fatal_and_consumes_nothing() {
+        let mut buf = raw_frame(MAX_FRAME_LEN as u32 + 1, MSG_NEW_ORDER, 0, &[]);
+        let err = decode(&mut buf).unwrap_err();
+        assert!(matches!(err, ProtocolError::FrameTooLarge { msg_type: MSG_NEW_ORDER, len } if len == MAX_FRAME_LEN + 1), "{err:?}");
+        assert!(err.is_fatal());
+        assert_eq!(buf.len(), 8);
+        // At the limit it is only waiting for the rest
+        let mut buf = raw_frame(MAX_FRAME_LEN as u32, MSG_NEW_ORDER, u16::MAX, &[]);
+        assert!(decode(&mut buf).unwrap().is_none());
+        // u32::MAX can't wrap past the check
+        let mut buf = raw_frame(u32::MAX, MSG_NEW_ORDER, 0, &[]);
+        assert!(matches!(decode(&mut buf), Err(ProtocolError::FrameTooLarge { .. })));
+    }
+
+    #[test]
+    fn oversized_length_is_answered_with_a_frame_too_large_nack() {
+        // What the gateway writes back before it hangs up
+        let mut buf = raw_frame(u32::MAX, MSG_NEW_ORDER, 0, &[]);
+        let err = decode(&mut buf).unwrap_err();
+        assert!(err.is_fatal());
+        let mut out = encode_nack(err.msg_type(), err.reason(), FrameFormat::default());
+        let nack = decode(&mut out).unwrap().unwrap();
+        assert_eq!(nack.msg_type, MSG_NACK);
+        assert_eq!(ByteOrder::Little.read_u16(&nack.body[0..2]), MSG_NEW_ORDER);
+        assert_eq!(RejectReason::from_code(ByteOrder::Little.read_u16(&nack.body[2..4])), Some(RejectReason::FrameTooLarge));
+    }
+
+    #[test]
+    fn length_too_short_for_the_header_is_fatal() {
+        for len in 0..4u32 {
+            let mut buf = BytesMut::from(&len.to_le_bytes()[..]);
+            buf.extend_from_slice(&[0xAB; 3][..len as usize]);
+            let err = decode(&mut buf).unwrap_err();
+            assert!(matches!(err, ProtocolError::ShortFrame { len: l } if l == len as usize), "{err:?}");
+            assert!(err.is_fatal());
+            assert_eq!(err.msg_type(), 0);
+        }
+    }
+
+    #[test]
+    fn checksummed_and_big_endian_frames_round_trip() {
+        for fmt in [
+            FrameFormat { order: ByteOrder::Big, crc: false },
+            FrameFormat { order: ByteOrder::Little, crc: true },
+            FrameFormat { order: ByteOrder::Big, crc: true },
+        ] {
+            let mut buf = encode_frame(MSG_REDUCE, &[3; 28], fmt);
+            let frame = decode_frame(&mut buf, fmt).unwrap().unwrap();
+            assert_eq!((frame.msg_type, &frame.body[..]), (MSG_REDUCE, &[3; 28][..]), "{fmt:?}");
+        }
+        let crc = FrameFormat { order: ByteOrder::Little, crc: true };
+        let mut buf = encode_frame(MSG_REDUCE, &[3; 28], crc);
+        buf[10] ^= 1;
+        let err = decode_frame(&mut buf, crc).unwrap_err();
+        assert!(matches!(err, ProtocolError::BadChecksum { msg_type: MSG_REDUCE, .. }), "{err:?}");
+        assert!(err.is_fatal());
+        // Too short to even hold header and checksum
+        let mut buf = BytesMut::from(&6u32.to_le_bytes()[..]);
+        buf.extend_from_slice(&[0; 6]);
+        assert!(matches!(decode_frame(&mut buf, crc), Err(ProtocolError::ShortFrame { len: 6 })));
+    }
+}
@@ -0,0 +1,212 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::warn;
+use crate::book::LadderSpec;
+use crate::protocol::{encode_event, FrameFormat};
+use crate::types::{Event, FeeSchedule, Increments, MatchPolicy, OrderBook, PriceBand, QtyScale, RiskLimit, RiskLimits};
+use crate::wal::{decode_record, encode_record, Record};
+
+// ========================== Session recordings ==========================
+// Header: [u32 magic][u8 version][u32 books] then per configured book:
+// [u32 symbol][u8 policy][u64 tick_size][u64 lot_size][u64 qty_scale][u8 band][u64 band_value][u64 maker_bps][u64 taker_bps][u64 max_depth][u8 ladder]
+// and, if ladder, [i64 min][i64 max][u64 tick]. Policy: 0 = fifo, 1 = pro-rata, 2 = size-time.
+// Band: 0 = none, 1 = basis points, 2 = ticks; max_depth 0 = unlimited.
+// Then the risk limits: [u32 clients] and per client, ascending id:
+// [u64 cl_id][u64 max_position][u64 max_notional][u64 max_orders], u64::MAX for no limit.
+// Then entries: [u32 len][u8 kind][u64 at_ns][payload], where a command's payload is
+// its WAL record and an event's is its wire frame, in the order the engine saw them.
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CREC");
+const VERSION: u8 = 11;
+const ENTRY_COMMAND: u8 = 1;
+const ENTRY_EVENT: u8 = 2;
+
+/// Matching config of one book, so a replay builds the same books the session ran on.
+pub struct BookConfig {
+    pub symbol: u32,
+    pub policy: MatchPolicy,
+    pub increments: Increments,
+    pub qty_scale: QtyScale,
+    pub band: Option<PriceBand>,
+    pub fees: FeeSchedule,
+    pub max_depth: Option<usize>,
+    pub ladder: Option<LadderSpec>,
+}
+
+pub enum Entry {
+    Command { at_ns: u64, rec: Record },
+    Event { at_ns: u64, frame: Bytes },
+}
+
+/// Appends every command the engine applies and every market-data event it publishes.
+/// Lives on the engine thread; `RefCell` because events are recorded through `&MdFeed`.
+pub struct Recorder {
+    out: RefCell<BufWriter<File>>,
+}
+
+impl Recorder {
+    /// Start a recording of an engine about to run on `books`, which must hold no orders, under `risk`.
+    pub fn create(path: impl AsRef<Path>, books: &HashMap<u32, OrderBook>, risk: &RiskLimits) -> io::Result<Recorder> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut symbols: Vec<u32> = books.keys().copied().collect();
+        symbols.sort_unstable();
+        let mut head = BytesMut::with_capacity(64);
+        head.put_u32_le(MAGIC);
+        head.put_u8(VERSION);
+        head.put_u32_le(symbols.len() as u32);
+        for symbol in symbols {
+            let b = &books[&symbol];
+            head.put_u32_le(symbol);
+            head.put_u8(match b.policy { MatchPolicy::FifoPriceTime => 0, MatchPolicy::ProRata => 1, MatchPolicy::SizeTime => 2 });
+            head.put_u64_le(b.increments.tick_size);
+            head.put_u64_le(b.increments.lot_size);
+            head.put_u64_le(b.qty_scale.0);
+            let (kind, value) = match b.band {
+                None => (0, 0),
+                Some(PriceBand::Bps(bps)) => (1, bps),
+                Some(PriceBand::Ticks(n)) => (2, n),
+            };
+            head.put_u8(kind);
+            head.put_u64_le(value);
+            head.put_u64_le(b.fees.maker_bps);
+            head.put_u64_le(b.fees.taker_bps);
+            head.put_u64_le(b.max_depth.unwrap_or(0) as u64);
+            match b.bids.bounds() {
+                Some(spec) => {
+                    head.put_u8(1);
+                    head.put_i64_le(spec.min);
+                    head.put_i64_le(spec.max);
+                    head.put_u64_le(spec.tick);
+                }
+                None => head.put_u8(0),
+            }
+        }
+        let mut clients: Vec<u64> = risk.keys().copied().collect();
+        clients.sort_unstable();
+        head.put_u32_le(clients.len() as u32);
+        for cl_id in clients {
+            let limit = risk[&cl_id];
+            head.put_u64_le(cl_id);
+            head.put_u64_le(limit.max_position.unwrap_or(u64::MAX));
+            head.put_u64_le(limit.max_notional.unwrap_or(u64::MAX));
+            head.put_u64_le(limit.max_orders.unwrap_or(u64::MAX));
+        }
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&head)?;
+        Ok(Recorder { out: RefCell::new(out) })
+    }
+
+    pub fn command(&self, rec: &Record) {
+        let mut body = BytesMut::with_capacity(64);
+        encode_record(rec, &mut body);
+        self.entry(ENTRY_COMMAND, &body);
+    }
+
+    pub fn event(&self, evt: &Event) {
+        self.entry(ENTRY_EVENT, &encode_event(evt, FrameFormat::default()));
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.borrow_mut().flush()
+    }
+
+    // A failed write loses the rest of the recording, never the engine
+    fn entry(&self, kind: u8, payload: &[u8]) {
+        let at_ns = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let mut out = self.out.borrow_mut();
+        let _ = out.write_all(&(9 + payload.len() as u32).to_le_bytes())
+            .and_then(|_| out.write_all(&[kind]))
+            .and_then(|_| out.write_all(&at_ns.to_le_bytes()))
+            .and_then(|_| out.write_all(payload));
+    }
+}
+
+/// Read a whole recording. A torn tail (the engine died mid-write) ends it early.
+pub fn read(path: impl AsRef<Path>) -> io::Result<(Vec<BookConfig>, RiskLimits, Vec<Entry>)> {
+    let raw = fs::read(path.as_ref())?;
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "not a session recording");
+    let mut raw = &raw[..];
+    if raw.remaining() < 9 || raw.get_u32_le() != MAGIC || raw.get_u8() != VERSION {
+        return Err(corrupt());
+    }
+
+    let mut books = Vec::new();
+    for _ in 0..raw.get_u32_le() {
+        if raw.remaining() < 63 {
+            return Err(corrupt());
+        }
+        let symbol = raw.get_u32_le();
+        let policy = match raw.get_u8() {
+            0 => MatchPolicy::FifoPriceTime,
+            1 => MatchPolicy::ProRata,
+            2 => MatchPolicy::SizeTime,
+            _ => return Err(corrupt()),
+        };
+        let increments = Increments { tick_size: raw.get_u64_le(), lot_size: raw.get_u64_le() };
+        let qty_scale = match raw.get_u64_le() {
+            0 => return Err(corrupt()),
+            n => QtyScale(n),
+        };
+        let band = match (raw.get_u8(), raw.get_u64_le()) {
+            (0, _) => None,
+            (1, bps) => Some(PriceBand::Bps(bps)),
+            (2, n) => Some(PriceBand::Ticks(n)),
+            _ => return Err(corrupt()),
+        };
+        let fees = FeeSchedule { maker_bps: raw.get_u64_le(), taker_bps: raw.get_u64_le() };
+        let max_depth = Some(raw.get_u64_le() as usize).filter(|&n| n > 0);
+        let ladder = match raw.get_u8() {
+            0 => None,
+            _ if raw.remaining() >= 24 => Some(LadderSpec { min: raw.get_i64_le(), max: raw.get_i64_le(), tick: raw.get_u64_le() }),
+            _ => return Err(corrupt()),
+        };
+        books.push(BookConfig { symbol, policy, increments, qty_scale, band, fees, max_depth, ladder });
+    }
+
+    if raw.remaining() < 4 {
+        return Err(corrupt());
+    }
+    let mut risk = RiskLimits::new();
+    for _ in 0..raw.get_u32_le() {
+        if raw.remaining() < 32 {
+            return Err(corrupt());
+        }
+        let cl_id = raw.get_u64_le();
+        let limit = |v: u64| (v != u64::MAX).then_some(v);
+        let (max_position, max_notional, max_orders) = (limit(raw.get_u64_le()), limit(raw.get_u64_le()), limit(raw.get_u64_le()));
+        risk.insert(cl_id, RiskLimit { max_position, max_notional, max_orders });
+    }
+
+    let mut entries = Vec::new();
+    while raw.remaining() >= 4 {
+        let len = (&raw[..4]).get_u32_le() as usize;
+        let Some(mut body) = raw.get(4..4 + len) else { break };
+        if body.remaining() < 9 {
+            break;
+        }
+        let kind = body.get_u8();
+        let at_ns = body.get_u64_le();
+        let entry = match kind {
+            ENTRY_COMMAND => match decode_record(body) {
+                Some(rec) => Entry::Command { at_ns, rec },
+                None => break,
+            },
+            ENTRY_EVENT => Entry::Event { at_ns, frame: Bytes::copy_from_slice(body) },
+            _ => break,
+        };
+        entries.push(entry);
+        raw.advance(4 + len);
+    }
+    if raw.has_remaining() {
+        warn!("[record] ⚠️ Ignoring a torn tail of {} bytes", raw.remaining());
+    }
+    Ok((books, risk, entries))
+}
@@ -0,0 +1,84 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use tracing::{info, warn};
+use crate::types::Command;
+
+// ========================== Ingress sequencer ==========================
+// Optional stage between the connection tasks and the engine. Each command is stamped when its
+// frame comes off the socket and held here for `window`, then handed on in stamp order. Frames
+// that land within `window` of each other reach the engine in the order they landed, whichever
+// connection task the runtime happened to poll first. Every command pays about `window` of latency.
+
+/// A command and the instant its frame reached the gateway.
+pub struct Stamped {
+    pub ingress: Instant,
+    pub cmd: Command,
+}
+
+// Held command: earliest stamp first, then the order they reached the sequencer
+struct Held {
+    ingress: Instant,
+    n: u64,
+    cmd: Command,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Held) -> bool {
+        (self.ingress, self.n) == (other.ingress, other.n)
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Held) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Held) -> Ordering {
+        (self.ingress, self.n).cmp(&(other.ingress, other.n))
+    }
+}
+
+/// Forward everything from `rx` to the engine in stamp order, each command once `window` has passed
+/// since its stamp. A command that reaches the sequencer later than that goes straight through.
+/// Returns once every sender is gone (what is still held goes out first) or the engine is.
+pub fn run_sequencer(rx: Receiver<Stamped>, tx_cmd: Sender<Command>, window: Duration) {
+    info!(window_us = window.as_micros() as u64, "[seq] ✅ Sequencer started");
+    let mut held: BinaryHeap<Reverse<Held>> = BinaryHeap::new();
+    let mut n = 0u64;
+    loop {
+        let msg = match held.peek() {
+            Some(Reverse(first)) => rx.recv_deadline(first.ingress + window),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match msg {
+            Ok(Stamped { ingress, cmd }) => {
+                n += 1;
+                held.push(Reverse(Held { ingress, n, cmd }));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let now = Instant::now();
+        while let Some(Reverse(first)) = held.peek()
+            && first.ingress + window <= now
+        {
+            let Reverse(first) = held.pop().expect("peeked");
+            if tx_cmd.send(first.cmd).is_err() {
+                warn!("[seq] ⚙️ Engine is gone, sequencer stopping");
+                return;
+            }
+        }
+    }
+    while let Some(Reverse(first)) = held.pop() {
+        if tx_cmd.send(first.cmd).is_err() {
+            break;
+        }
+    }
+    info!("[seq] 🛑 Sequencer stopped");
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::warn;
+use crate::types::Event;
+
+// ========================== Per-connection event sinks ==========================
+// The engine never waits on a client. Events go out with `try_send`; a full queue drops
+// the event, and a connection whose queue is still full after `slow_after` sends in a
+// row is flagged as a slow consumer. Its gateway task sees the flag and disconnects it.
+
+#[derive(Clone)]
+pub struct ClientSink {
+    tx: mpsc::Sender<Event>,
+    state: Arc<SinkState>,
+}
+
+struct SinkState {
+    session: u64, // Id of the connection behind it; 0 for a detached sink
+    slow_after: u32,
+    full_streak: AtomicU32, // Consecutive sends that found the queue full; only the engine writes it
+    dropped: AtomicU64,
+    slow: Notify,
+}
+
+/// A client's outbound queue for connection `session`: the engine's end and the connection task's end.
+pub fn channel(capacity: usize, slow_after: u32, session: u64) -> (ClientSink, mpsc::Receiver<Event>) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let state = SinkState { session, slow_after, full_streak: AtomicU32::new(0), dropped: AtomicU64::new(0), slow: Notify::new() };
+    (ClientSink { tx, state: Arc::new(state) }, rx)
+}
+
+impl ClientSink {
+    /// A sink nobody reads, for replays and benches: every event is dropped.
+    pub fn detached() -> ClientSink {
+        channel(1, u32::MAX, 0).0
+    }
+
+    /// Session id of the connection this sink queues to, for logs.
+    pub fn session(&self) -> u64 {
+        self.state.session
+    }
+
+    /// Queue `evt` for the client without blocking.
+    pub fn send(&self, evt: Event) {
+        match self.tx.try_send(evt) {
+            Ok(()) => self.state.full_streak.store(0, Relaxed),
+            Err(TrySendError::Full(_)) => {
+                self.state.dropped.fetch_add(1, Relaxed);
+                let streak = self.state.full_streak.fetch_add(1, Relaxed) + 1;
+                if streak == self.state.slow_after {
+                    warn!("[engine] 🐢 slow consumer: outbound queue full for {streak} sends in a row");
+                    self.state.slow.notify_one();
+                }
+            }
+            // The connection is already gone
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Whether both ends queue to the same connection.
+    pub fn same_connection(&self, other: &ClientSink) -> bool {
+        self.tx.same_channel(&other.tx)
+    }
+
+    /// Whether the connection behind it is gone.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Resolves once the client is flagged as a slow consumer, with the events dropped so far.
+    pub async fn slow_consumer(&self) -> u64 {
+        self.state.slow.notified().await;
+        self.state.dropped.load(Relaxed)
+    }
+}
@@ -0,0 +1,192 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use bytes::{Buf, BufMut, BytesMut};
+use crate::types::{Flow, Level, MarketState, OrderBook, SessionStats, Stops, Tape, Tif};
+use crate::wal::{decode_order, encode_order};
+
+// ========================== Book snapshots ==========================
+// [u32 magic][u8 version][u64 wal_offset][u64 next_exch_ord_id][u32 books] then per book:
+// [u32 symbol] bids, asks — each [u32 levels] then per level, ascending price:
+// [i64 price][u32 orders] and the orders in queue order (WAL order encoding) —
+// then the session stats: [i64 last][i64 high][i64 low][u64 volume][u64 trades], the previous
+// session's close: [i64 prev_close], the market state: [u8 state] (0 = open, 1 = halted,
+// 2 = auction) and the client positions: [u32 clients] then per client, ascending id:
+// [u64 cl_id][i64 position],
+// then the pending stops: [u32 stops] and the orders, oldest first.
+// `lookup` and `expiries` are indexes over the resting orders (and stops) and are rebuilt on load;
+// arrival sequences are restamped in queue order.
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CLOB");
+const VERSION: u8 = 8;
+
+/// Persist every book plus the WAL offset it reflects and the next exchange order id.
+/// Written to a temp file and renamed, so a crash mid-write leaves the previous snapshot intact.
+pub fn write(path: impl AsRef<Path>, books: &HashMap<u32, OrderBook>, wal_offset: u64, next_exch_ord_id: u64) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut out = BytesMut::with_capacity(4096);
+    out.put_u32_le(MAGIC);
+    out.put_u8(VERSION);
+    out.put_u64_le(wal_offset);
+    out.put_u64_le(next_exch_ord_id);
+    out.put_u32_le(books.len() as u32);
+
+    let mut symbols: Vec<u32> = books.keys().copied().collect();
+    symbols.sort_unstable();
+    for symbol in symbols {
+        let b = &books[&symbol];
+        out.put_u32_le(symbol);
+        for side in [&b.bids, &b.asks] {
+            out.put_u32_le(side.len() as u32);
+            for (px, level) in side.iter() {
+                out.put_i64_le(px);
+                out.put_u32_le(level.orders.len() as u32);
+                for o in &level.orders {
+                    encode_order(o, &mut out);
+                }
+            }
+        }
+        let st = &b.tape.session;
+        for v in [st.last, st.high, st.low] {
+            out.put_i64_le(v);
+        }
+        out.put_u64_le(st.volume);
+        out.put_u64_le(st.trades);
+        out.put_i64_le(b.tape.prev_close);
+        out.put_u8(match b.state { MarketState::Open => 0, MarketState::Halted => 1, MarketState::Auction => 2 });
+        let mut positions: Vec<(u64, i64)> = b.tape.positions.iter().map(|(&cl, &pos)| (cl, pos)).collect();
+        positions.sort_unstable();
+        out.put_u32_le(positions.len() as u32);
+        for (cl_id, pos) in positions {
+            out.put_u64_le(cl_id);
+            out.put_i64_le(pos);
+        }
+        out.put_u32_le(b.stops.len() as u32);
+        for o in b.stops.iter() {
+            encode_order(o, &mut out);
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, &out)?;
+    fs::rename(&tmp, path)
+}
+
+/// What a snapshot restores: the books, the WAL offset to resume replay from
+/// and the next exchange order id.
+pub struct Loaded {
+    pub books: HashMap<u32, OrderBook>,
+    pub wal_offset: u64,
+    pub next_exch_ord_id: u64,
+}
+
+/// Latest snapshot; `None` if there is none yet.
+pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Loaded>> {
+    let raw = match fs::read(path.as_ref()) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    decode(&raw)
+        .map(Some)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupt book snapshot"))
+}
+
+fn decode(mut raw: &[u8]) -> Option<Loaded> {
+    if raw.remaining() < 25 || raw.get_u32_le() != MAGIC || raw.get_u8() != VERSION {
+        return None;
+    }
+    let wal_offset = raw.get_u64_le();
+    let next_exch_ord_id = raw.get_u64_le();
+    let n_books = raw.get_u32_le();
+
+    let mut books = HashMap::new();
+    for _ in 0..n_books {
+        if raw.remaining() < 4 {
+            return None;
+        }
+        let symbol = raw.get_u32_le();
+        let mut next_arrival_seq = 0;
+        let bids = decode_side(&mut raw, &mut next_arrival_seq)?;
+        let asks = decode_side(&mut raw, &mut next_arrival_seq)?;
+        if raw.remaining() < 53 {
+            return None;
+        }
+        let stats = SessionStats {
+            last: raw.get_i64_le(),
+            high: raw.get_i64_le(),
+            low: raw.get_i64_le(),
+            volume: raw.get_u64_le(),
+            trades: raw.get_u64_le(),
+        };
+        let prev_close = raw.get_i64_le();
+        let state = match raw.get_u8() {
+            0 => MarketState::Open,
+            1 => MarketState::Halted,
+            2 => MarketState::Auction,
+            _ => return None,
+        };
+        let n_positions = raw.get_u32_le() as usize;
+        if raw.remaining() < n_positions * 16 {
+            return None;
+        }
+        let positions = (0..n_positions).map(|_| (raw.get_u64_le(), raw.get_i64_le())).collect();
+        if raw.remaining() < 4 {
+            return None;
+        }
+        let mut stops = Stops::default();
+        for _ in 0..raw.get_u32_le() {
+            stops.insert(decode_order(&mut raw)?);
+        }
+        let mut b = OrderBook {
+            bids: Box::new(bids),
+            asks: Box::new(asks),
+            tape: Tape { session: stats, prev_close, candle: None, flow: Flow::default(), positions },
+            state,
+            stops,
+            next_arrival_seq,
+            ..Default::default()
+        };
+        for o in b.stops.iter().filter(|o| o.tif == Tif::Gtd) {
+            b.expiries.push(Reverse((o.expire_ms, o.exch_ord_id)));
+        }
+        for (_, level) in b.bids.iter().chain(b.asks.iter()) {
+            for o in &level.orders {
+                b.lookup.insert(o);
+                if o.tif == Tif::Gtd {
+                    b.expiries.push(Reverse((o.expire_ms, o.exch_ord_id)));
+                }
+            }
+        }
+        books.insert(symbol, b);
+    }
+    Some(Loaded { books, wal_offset, next_exch_ord_id })
+}
+
+/// Arrival sequences only order a level's queue, so loaded orders are stamped afresh in queue order.
+fn decode_side(raw: &mut &[u8], next_arrival_seq: &mut u64) -> Option<BTreeMap<i64, Level>> {
+    if raw.remaining() < 4 {
+        return None;
+    }
+    let mut side = BTreeMap::new();
+    for _ in 0..raw.get_u32_le() {
+        if raw.remaining() < 12 {
+            return None;
+        }
+        let px = raw.get_i64_le();
+        let mut level = Level::default();
+        for _ in 0..raw.get_u32_le() {
+            let mut o = decode_order(raw)?;
+            o.arrival_seq = *next_arrival_seq;
+            *next_arrival_seq += 1;
+            level.insert(o);
+        }
+        side.insert(px, level);
+    }
+    Some(side)
+}
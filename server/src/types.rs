@@ -1,65 +1,1314 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
-use crossbeam::channel::Sender;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use crate::sink::ClientSink;
+use crate::book::{BookBackend, Ladder, LadderSpec};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Side {
     Bid,
     Ask
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[allow(dead_code)]
 pub enum Tif {
     Gtc,
     Ioc,
+    Fok, // All-or-nothing: fill completely right now or reject untouched
+    Gtd, // Rests like GTC until `Order::expire_ms`, then the engine pulls it
+    Day, // Rests like GTC until its symbol's session rolls, then the engine pulls it
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    Limit,
+    Market,    // Sweeps the opposite side regardless of price; never rests
+    Stop,      // Held off-book until the last trade reaches `stop_price`, then a market order
+    StopLimit, // Likewise, then a limit order at `price`
+}
+
+// Self-trade prevention: what to do when an order would match its own client's resting order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stp {
+    Off,
+    CancelResting,    // Pull the resting order and keep matching
+    CancelAggressing, // Stop the incoming order and cancel its remainder
+}
+
+// How an incoming order's quantity is split among the resting orders of one price level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchPolicy {
+    #[default]
+    FifoPriceTime, // Oldest order first
+    ProRata,       // In proportion to each order's size; leftover units go to the largest, oldest first among equals
+    SizeTime,      // Largest order first, oldest first among equal sizes
+}
+
+impl FromStr for MatchPolicy {
+    type Err = String;
+
+    /// "fifo", "pro_rata" or "size_time".
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "fifo" => Ok(MatchPolicy::FifoPriceTime),
+            "pro_rata" => Ok(MatchPolicy::ProRata),
+            "size_time" => Ok(MatchPolicy::SizeTime),
+            other => Err(format!("unknown match policy {other:?}: expected fifo, pro_rata or size_time")),
+        }
+    }
+}
+
+// Whether a symbol takes new orders, and whether they match. Cancels go through either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketState {
+    #[default]
+    Open,
+    Halted,
+    Auction, // Orders rest without matching until the book is opened again, which uncrosses it
+}
+
+// Order increments of a symbol: prices are multiples of `tick_size`, quantities of `lot_size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Increments {
+    pub tick_size: u64,
+    pub lot_size: u64,
+}
+
+impl Default for Increments {
+    fn default() -> Self {
+        Increments { tick_size: 1, lot_size: 1 }
+    }
+}
+
+impl Increments {
+    /// Whether an order at `price` for `qty` fits. A market order's price of 0 always does.
+    pub fn check(&self, price: i64, qty: u64) -> Result<(), RejectReason> {
+        if !price.unsigned_abs().is_multiple_of(self.tick_size) {
+            return Err(RejectReason::BadTick);
+        }
+        if !qty.is_multiple_of(self.lot_size) {
+            return Err(RejectReason::BadLot);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Increments {
+    type Err = String;
+
+    /// "tick_size:lot_size", e.g. "5:100".
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((tick, lot)) = s.split_once(':') else {
+            return Err(format!("increments {s:?} are not tick_size:lot_size"));
+        };
+        let parse = |v: &str| v.trim().parse::<u64>().ok().filter(|&n| n > 0);
+        match (parse(tick), parse(lot)) {
+            (Some(tick_size), Some(lot_size)) => Ok(Increments { tick_size, lot_size }),
+            _ => Err(format!("increments {s:?} need a tick_size and lot_size > 0")),
+        }
+    }
+}
+
+// Fixed-point quantities of a symbol: raw units per whole unit, a power of ten.
+// Matching only ever sees the raw u64s; the scale is how they read, e.g. 500000 at 1e6 is 0.5
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QtyScale(pub u64);
+
+impl Default for QtyScale {
+    fn default() -> Self {
+        QtyScale(1)
+    }
+}
+
+impl QtyScale {
+    /// Digits after the decimal point.
+    pub fn decimals(&self) -> usize {
+        self.0.ilog10() as usize
+    }
+
+    /// `raw` as a decimal, without trailing zeros: 1500000 at 1e6 is "1.5".
+    pub fn render(&self, raw: u64) -> String {
+        let (whole, frac) = (raw / self.0, raw % self.0);
+        if frac == 0 {
+            return whole.to_string();
+        }
+        let frac = format!("{frac:0width$}", width = self.decimals());
+        format!("{whole}.{}", frac.trim_end_matches('0'))
+    }
+
+    /// Whether a whole unit is a whole number of lots, so "1" is always a valid quantity.
+    pub fn fits(&self, lot_size: u64) -> bool {
+        self.0.is_multiple_of(lot_size)
+    }
+}
+
+impl FromStr for QtyScale {
+    type Err = String;
+
+    /// A power of ten, e.g. "1000000" or "1e6".
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let scale = match s.split_once(['e', 'E']) {
+            Some(("1", exp)) => exp.parse::<u32>().ok().and_then(|e| 10u64.checked_pow(e)),
+            Some(_) => None,
+            None => s.parse::<u64>().ok(),
+        };
+        match scale {
+            Some(n) if n > 0 && 10u64.pow(n.ilog10()) == n => Ok(QtyScale(n)),
+            _ => Err(format!("qty scale {s:?} is not a power of ten")),
+        }
+    }
+}
+
+// Fat-finger guard of a symbol: how far a limit price may sit from the last trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceBand {
+    Bps(u64),   // Basis points of the last trade price's magnitude
+    Ticks(u64), // Multiples of the symbol's tick size
+}
+
+impl PriceBand {
+    /// Whether `price` is inside the band around `reference`. No reference yet (0) means an open band.
+    pub fn allows(&self, price: i64, reference: i64, tick_size: u64) -> bool {
+        if reference == 0 {
+            return true;
+        }
+        let dev = price.abs_diff(reference) as u128;
+        match *self {
+            PriceBand::Bps(bps) => dev * 10_000 <= reference.unsigned_abs() as u128 * bps as u128,
+            PriceBand::Ticks(n) => dev <= n as u128 * tick_size as u128,
+        }
+    }
+}
+
+impl FromStr for PriceBand {
+    type Err = String;
+
+    /// "<pct>%" or "<n>t", e.g. "5%", "0.5%" or "20t".
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let band = if let Some(pct) = s.strip_suffix('%') {
+            pct.trim().parse::<f64>().ok()
+                .filter(|p| p.is_finite() && *p > 0.0)
+                .map(|p| PriceBand::Bps((p * 100.0).round() as u64))
+        } else if let Some(n) = s.strip_suffix('t') {
+            n.trim().parse::<u64>().ok().filter(|&n| n > 0).map(PriceBand::Ticks)
+        } else {
+            None
+        };
+        band.ok_or_else(|| format!("price band {s:?} is not <pct>% or <ticks>t"))
+    }
+}
+
+// Exchange fees of a symbol, in basis points of a fill's notional (|price| * qty)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub maker_bps: u64, // Charged to the resting order's owner
+    pub taker_bps: u64, // Charged to the aggressor's owner
+}
+
+impl FeeSchedule {
+    /// (maker fee, taker fee) of one fill, each `|price| * qty * bps / 10_000` rounded half up
+    /// to a whole price unit: a fee of exactly half a unit is charged as one.
+    pub fn fees(&self, price: i64, qty: u64) -> (u64, u64) {
+        let fee = |bps: u64| ((price.unsigned_abs() as u128 * qty as u128 * bps as u128 + 5_000) / 10_000) as u64;
+        (fee(self.maker_bps), fee(self.taker_bps))
+    }
+}
+
+impl FromStr for FeeSchedule {
+    type Err = String;
+
+    /// "maker_bps:taker_bps", e.g. "1:5".
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((maker, taker)) = s.split_once(':') else {
+            return Err(format!("fee schedule {s:?} is not maker_bps:taker_bps"));
+        };
+        match (maker.trim().parse::<u64>(), taker.trim().parse::<u64>()) {
+            (Ok(maker_bps), Ok(taker_bps)) if maker_bps <= 10_000 && taker_bps <= 10_000 => Ok(FeeSchedule { maker_bps, taker_bps }),
+            _ => Err(format!("fee schedule {s:?} needs two rates of 0..=10000 bps")),
+        }
+    }
+}
+
+// Pre-trade limits of one client, per symbol; `None` is no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RiskLimit {
+    pub max_position: Option<u64>, // |net position| once this order fully fills
+    pub max_notional: Option<u64>, // |Price| * qty of the resting orders plus this one
+    pub max_orders: Option<u64>, // Open orders over every symbol, pending stops included
+}
+
+// Limits by client id; clients not listed trade unchecked
+pub type RiskLimits = HashMap<u64, RiskLimit>;
+
+impl FromStr for RiskLimit {
+    type Err = String;
+
+    /// "max_position:max_notional[:max_orders]", any of them empty for no limit, e.g. "500:", ":1000000" or "::50".
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((pos, rest)) = s.split_once(':') else {
+            return Err(format!("risk limit {s:?} is not max_position:max_notional[:max_orders]"));
+        };
+        let (notional, orders) = rest.split_once(':').unwrap_or((rest, ""));
+        let parse = |v: &str| match v.trim() {
+            "" => Ok(None),
+            v => v.parse::<u64>().map(Some).map_err(|_| format!("risk limit {s:?}: {v:?} is not a number")),
+        };
+        Ok(RiskLimit { max_position: parse(pos)?, max_notional: parse(notional)?, max_orders: parse(orders)? })
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct Order {
-    pub id: u64,
+    pub id: u64, // The client's cl_ord_id: only unique per client
+    pub exch_ord_id: u64, // Assigned by the engine on arrival, unique across all clients and symbols
     pub cl_id: u64,
+    pub symbol: u32,
     pub side: Side,
-    pub price: u64,
+    pub price: i64, // May be negative (spreads); 0 only for a market order
     pub qty: u64,
-    #[allow(dead_code)]
-    pub timestamp: u64,
+    pub timestamp: u64, // Unix millis the gateway stamped it with; for reporting only
+    pub arrival_seq: u64, // Stamped by the engine as the order reaches its book: time priority within a level
+    pub queue_seq: u64, // Tiebreak between equal arrival_seqs, stamped by the level the order joins
     pub tif: Tif,
+    pub ord_type: OrderType,
+    pub post_only: bool, // Reject instead of taking liquidity
+    pub reduce_only: bool, // Trimmed on entry to the client's opposite position, so it can only flatten it
+    pub stp: Stp,
+    pub expire_ms: u64, // Unix millis after which a GTD order is swept; 0 for every other TIF
+    pub display_qty: u64, // Iceberg: size of each visible slice; 0 for a plain order
+    pub total_qty: u64, // Visible `qty` plus the hidden reserve; == qty except for a resting iceberg
+    pub stop_price: i64, // Trigger of a stop or stop-limit; 0 for every other type
+}
+
+impl Order {
+    /// Whether a stop on `side` at `stop_price` fires at last trade price `last`:
+    /// a buy stop once the market trades at or above it, a sell stop at or below. Never before a first trade.
+    pub fn stop_reached(side: Side, stop_price: i64, last: i64) -> bool {
+        match side {
+            Side::Bid => last != 0 && last >= stop_price,
+            Side::Ask => last != 0 && last <= stop_price,
+        }
+    }
+
+    /// Turn a triggered stop into the order it stands for.
+    pub fn activate(&mut self) {
+        self.ord_type = match self.ord_type {
+            OrderType::Stop => OrderType::Market,
+            OrderType::StopLimit => OrderType::Limit,
+            other => other,
+        };
+    }
+
+    /// How much better than its limit this order trades at `px`, per unit. A market order has no limit to beat.
+    pub fn improvement(&self, px: i64) -> u64 {
+        match (self.ord_type, self.side) {
+            (OrderType::Limit, Side::Bid) if self.price > px => self.price.abs_diff(px),
+            (OrderType::Limit, Side::Ask) if px > self.price => px.abs_diff(self.price),
+            _ => 0,
+        }
+    }
+
+    /// Show an iceberg's next slice from its reserve, once the visible one has filled.
+    pub fn refill(&mut self) {
+        self.qty = self.total_qty.min(self.display_qty);
+    }
+}
+
+// One price level: queue in time priority plus its running total, so the hot path never re-sums
+#[derive(Default)]
+pub struct Level {
+    pub orders: VecDeque<Order>, // Ascending (arrival_seq, queue_seq)
+    pub level_qty: u64, // Always == sum of orders[..].qty
+    next_seq: u64, // The `queue_seq` of the next order to join
 }
 
+impl Level {
+    /// Queue `o` behind every order that reached the engine before it, or at the same `arrival_seq` and joined first.
+    pub fn insert(&mut self, mut o: Order) {
+        o.queue_seq = self.next_seq;
+        self.restore(o);
+    }
+
+    /// Put back an order taken out with `remove`: it keeps its `queue_seq`, so it lands where it was.
+    pub fn restore(&mut self, o: Order) {
+        self.next_seq = self.next_seq.max(o.queue_seq + 1);
+        let pos = self.orders.partition_point(|r| (r.arrival_seq, r.queue_seq) < (o.arrival_seq, o.queue_seq));
+        self.level_qty += o.qty;
+        self.orders.insert(pos, o);
+    }
+
+    /// Queue an iceberg's refilled slice at the back: it takes the newest `arrival_seq` here, so it loses priority to every order already resting.
+    pub fn requeue(&mut self, mut o: Order) {
+        if let Some(back) = self.orders.back() {
+            o.arrival_seq = o.arrival_seq.max(back.arrival_seq);
+        }
+        self.insert(o);
+    }
+
+    pub fn pop_front(&mut self) -> Option<Order> {
+        let o = self.orders.pop_front()?;
+        self.level_qty -= o.qty;
+        Some(o)
+    }
+
+    pub fn remove(&mut self, pos: usize) -> Option<Order> {
+        let o = self.orders.remove(pos)?;
+        self.level_qty -= o.qty;
+        Some(o)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// Everything resting here, icebergs' hidden reserves included. Never for market data.
+    pub fn total_qty(&self) -> u64 {
+        self.orders.iter().map(|o| o.total_qty).sum()
+    }
+}
+
+// Index over the resting orders: exchange id → (side, price), plus the
+// client-facing (cl_id, cl_ord_id) → exchange id that cancels and replaces go through
+#[derive(Default)]
+pub struct Lookup {
+    by_exch: HashMap<u64, (Side, i64)>,
+    by_client: HashMap<(u64, u64), u64>,
+    notional: HashMap<u64, u128>, // cl_id → sum of |price| * qty over its resting orders
+    resting: HashMap<u64, u64>, // cl_id → how many orders it has resting
+    owners: HashMap<u64, u32>,  // ord_id → how many clients have a resting order under it
+}
+
+impl Lookup {
+    pub fn insert(&mut self, o: &Order) {
+        self.by_exch.insert(o.exch_ord_id, (o.side, o.price));
+        self.by_client.insert((o.cl_id, o.id), o.exch_ord_id);
+        *self.notional.entry(o.cl_id).or_default() += o.price.unsigned_abs() as u128 * o.total_qty as u128;
+        *self.resting.entry(o.cl_id).or_default() += 1;
+        *self.owners.entry(o.id).or_default() += 1;
+    }
+
+    /// Drop `o` from the index, at the qty it still rests with.
+    pub fn remove(&mut self, o: &Order) {
+        self.by_exch.remove(&o.exch_ord_id);
+        self.by_client.remove(&(o.cl_id, o.id));
+        self.reduce(o.cl_id, o.price, o.total_qty);
+        if let Some(n) = self.resting.get_mut(&o.cl_id) {
+            *n -= 1;
+            if *n == 0 {
+                self.resting.remove(&o.cl_id);
+            }
+        }
+        if let Some(n) = self.owners.get_mut(&o.id) {
+            *n -= 1;
+            if *n == 0 {
+                self.owners.remove(&o.id);
+            }
+        }
+    }
+
+    /// A resting order of `cl_id` at `price` lost `qty` in place (a fill or an amend down).
+    pub fn reduce(&mut self, cl_id: u64, price: i64, qty: u64) {
+        if let Some(n) = self.notional.get_mut(&cl_id) {
+            *n -= price.unsigned_abs() as u128 * qty as u128;
+            // Resting prices are never 0, so nothing of the client's is left
+            if *n == 0 {
+                self.notional.remove(&cl_id);
+            }
+        }
+    }
+
+    /// |Price| * qty over the resting orders of `cl_id`.
+    pub fn notional(&self, cl_id: u64) -> u128 {
+        self.notional.get(&cl_id).copied().unwrap_or(0)
+    }
+
+    /// How many orders `cl_id` has resting.
+    pub fn resting(&self, cl_id: u64) -> u64 {
+        self.resting.get(&cl_id).copied().unwrap_or(0)
+    }
+
+    /// Where a resting order sits: (side, price).
+    pub fn get(&self, exch_ord_id: u64) -> Option<(Side, i64)> {
+        self.by_exch.get(&exch_ord_id).copied()
+    }
+
+    /// Exchange id of client `cl_id`'s resting order `ord_id`.
+    pub fn exch_id(&self, cl_id: u64, ord_id: u64) -> Option<u64> {
+        self.by_client.get(&(cl_id, ord_id)).copied()
+    }
+
+    /// Whether some client other than `cl_id` has a resting order `ord_id`.
+    pub fn owned_by_other(&self, cl_id: u64, ord_id: u64) -> bool {
+        let owners = self.owners.get(&ord_id).copied().unwrap_or(0);
+        owners > self.by_client.contains_key(&(cl_id, ord_id)) as u32
+    }
+
+    /// Exchange ids of every resting order of `cl_id`.
+    pub fn of_client(&self, cl_id: u64) -> impl Iterator<Item = u64> + '_ {
+        self.by_client.iter().filter(move |((owner, _), _)| *owner == cl_id).map(|(_, &exch)| exch)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_exch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_exch.is_empty()
+    }
+}
+
+// Stop orders of one symbol waiting for their trigger, keyed by (stop_price, exch_ord_id),
+// plus the exchange-id and client-id indexes cancels and expiry go through
+#[derive(Default)]
+pub struct Stops {
+    buys: BTreeMap<(i64, u64), Order>,
+    sells: BTreeMap<(i64, u64), Order>,
+    by_exch: HashMap<u64, (Side, i64)>,
+    by_client: HashMap<(u64, u64), u64>,
+}
+
+impl Stops {
+    pub fn insert(&mut self, o: Order) {
+        self.by_exch.insert(o.exch_ord_id, (o.side, o.stop_price));
+        self.by_client.insert((o.cl_id, o.id), o.exch_ord_id);
+        let side = match o.side {
+            Side::Bid => &mut self.buys,
+            Side::Ask => &mut self.sells,
+        };
+        side.insert((o.stop_price, o.exch_ord_id), o);
+    }
+
+    /// Pull a pending stop by exchange id.
+    pub fn remove(&mut self, exch_ord_id: u64) -> Option<Order> {
+        let (side, stop_price) = self.by_exch.remove(&exch_ord_id)?;
+        let o = match side {
+            Side::Bid => self.buys.remove(&(stop_price, exch_ord_id)),
+            Side::Ask => self.sells.remove(&(stop_price, exch_ord_id)),
+        }?;
+        self.by_client.remove(&(o.cl_id, o.id));
+        Some(o)
+    }
+
+    /// Exchange id of client `cl_id`'s pending stop `ord_id`.
+    pub fn exch_id(&self, cl_id: u64, ord_id: u64) -> Option<u64> {
+        self.by_client.get(&(cl_id, ord_id)).copied()
+    }
+
+    /// Exchange ids of every pending stop of `cl_id`.
+    pub fn of_client(&self, cl_id: u64) -> impl Iterator<Item = u64> + '_ {
+        self.by_client.iter().filter(move |((owner, _), _)| *owner == cl_id).map(|(_, &exch)| exch)
+    }
+
+    /// Take out every stop that `last` reaches, oldest first.
+    pub fn take_triggered(&mut self, last: i64) -> Vec<Order> {
+        if last == 0 {
+            return Vec::new();
+        }
+        let mut due: Vec<u64> = self.buys.range(..=(last, u64::MAX)).map(|(&(_, id), _)| id)
+            .chain(self.sells.range((last, 0)..).map(|(&(_, id), _)| id))
+            .collect();
+        due.sort_unstable();
+        due.into_iter().filter_map(|id| self.remove(id)).collect()
+    }
+
+    /// Every pending stop, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        let mut all: Vec<&Order> = self.buys.values().chain(self.sells.values()).collect();
+        all.sort_unstable_by_key(|o| o.exch_ord_id);
+        all.into_iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_exch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_exch.is_empty()
+    }
+}
+
+// Trading statistics of one symbol since the last session roll. Prices are 0 until the first trade
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub last: i64,
+    pub high: i64,
+    pub low: i64,
+    pub volume: u64,
+    pub trades: u64,
+}
+
+impl SessionStats {
+    /// Fold one fill into the session.
+    pub fn record(&mut self, price: i64, qty: u64) {
+        if self.trades == 0 {
+            self.high = price;
+            self.low = price;
+        } else {
+            self.high = self.high.max(price);
+            self.low = self.low.min(price);
+        }
+        self.last = price;
+        self.volume += qty;
+        self.trades += 1;
+    }
+}
+
+// Open, high, low, close and volume of the trades in one candle interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ohlc {
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: u64,
+}
+
+// Aggressive volume by side since the summary ticker last reported it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flow {
+    pub buy_qty: u64,
+    pub sell_qty: u64,
+}
+
+impl Flow {
+    /// Aggressive buy over sell volume; `None` while nothing has been sold aggressively.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.sell_qty > 0).then(|| self.buy_qty as f64 / self.sell_qty as f64)
+    }
+}
+
+// What the fills on a book feed: the session stats, the candle still open, the flow since the
+// last summary and each client's net position
+#[derive(Debug, Clone, Default)]
+pub struct Tape {
+    pub session: SessionStats,
+    pub prev_close: i64, // Last price of the sessions before this one; 0 if the symbol never traded
+    pub candle: Option<Ohlc>,
+    pub flow: Flow,
+    pub positions: HashMap<u64, i64>, // cl_id → bought minus sold; never reset by a session roll
+}
+
+impl Tape {
+    /// Fold one fill into the session, the open candle, the flow and both sides' positions.
+    pub fn record(&mut self, t: &Trade) {
+        let (price, qty) = (t.price, t.qty);
+        self.session.record(price, qty);
+        match &mut self.candle {
+            Some(c) => {
+                c.high = c.high.max(price);
+                c.low = c.low.min(price);
+                c.close = price;
+                c.volume += qty;
+            }
+            None => self.candle = Some(Ohlc { open: price, high: price, low: price, close: price, volume: qty }),
+        }
+        let bought = match t.aggressor_side {
+            Side::Bid => {
+                self.flow.buy_qty += qty;
+                qty as i64
+            }
+            Side::Ask => {
+                self.flow.sell_qty += qty;
+                -(qty as i64)
+            }
+        };
+        *self.positions.entry(t.taker_cl_id).or_default() += bought;
+        *self.positions.entry(t.maker_cl_id).or_default() -= bought;
+    }
+
+    /// Price the band and the stops work from: the last trade, or the previous close until this session trades.
+    pub fn reference(&self) -> i64 {
+        if self.session.trades > 0 { self.session.last } else { self.prev_close }
+    }
+
+    /// Start a new session. Its stats start over, but the close of the old one stays the reference.
+    pub fn roll_session(&mut self) {
+        self.prev_close = self.reference();
+        self.session = SessionStats::default();
+    }
+
+    /// Net position of `cl_id`: positive long, negative short.
+    pub fn position(&self, cl_id: u64) -> i64 {
+        self.positions.get(&cl_id).copied().unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
 pub struct OrderBook {
-    pub bids: BTreeMap<u64, VecDeque<Order>>, // Descending for bids
-    pub asks: BTreeMap<u64, VecDeque<Order>>, // Ascending for asks
-    pub lookup: HashMap<u64, (Side, u64)>, // Fast lookup by IDs: (Side, price)
+    pub bids: Box<dyn BookBackend>, // Best = highest price
+    pub asks: Box<dyn BookBackend>, // Best = lowest price
+    pub lookup: Lookup,
+    pub expiries: BinaryHeap<Reverse<(u64, u64)>>, // GTD min-heap of (expire_ms, exch_ord_id); stale entries skipped lazily
+    pub policy: MatchPolicy, // Engine config, set at startup; not part of snapshots
+    pub increments: Increments, // Engine config, like `policy`
+    pub band: Option<PriceBand>, // Engine config, like `policy`
+    pub fees: FeeSchedule, // Engine config, like `policy`
+    pub max_depth: Option<usize>, // Engine config, like `policy`: orders rest only within the best N levels per side
+    pub qty_scale: QtyScale, // Engine config, like `policy`: raw qty units per whole unit
+    pub state: MarketState, // Set by admin HALT/RESUME; part of snapshots
+    pub tape: Tape,
+    pub stops: Stops, // Pending stop orders, not on the book yet; part of snapshots
+    pub next_arrival_seq: u64, // The `arrival_seq` of the next order to reach this book; restamped on load
 }
 
-impl Default for OrderBook {fn default() -> Self {Self{bids:BTreeMap::new(), asks:BTreeMap::new(), lookup:HashMap::new()}}}
+// Best level of one side as (price, level qty), if the side has any
+pub type Top = Option<(i64, u64)>;
+
+impl OrderBook {
+    /// How many orders `cl_id` has open here: resting, or pending as a stop.
+    pub fn open_orders(&self, cl_id: u64) -> u64 {
+        self.lookup.resting(cl_id) + self.stops.of_client(cl_id).count() as u64
+    }
+
+    /// Top of book: (best bid, best ask)
+    pub fn bbo(&self) -> (Top, Top) {
+        let bid = self.bids.highest().map(|(px, l)| (px, l.level_qty));
+        let ask = self.asks.lowest().map(|(px, l)| (px, l.level_qty));
+        (bid, ask)
+    }
+
+    /// (best_bid + best_ask) / 2, if both sides have a level.
+    pub fn mid(&self) -> Option<f64> {
+        let (Some((bid, _)), Some((ask, _))) = self.bbo() else {
+            return None;
+        };
+        Some((bid as f64 + ask as f64) / 2.0)
+    }
+
+    /// Top-of-book prices weighted by the opposite side's size:
+    /// (bid_px * ask_qty + ask_px * bid_qty) / (bid_qty + ask_qty).
+    /// Leans toward the ask when bids outweigh asks, where the next trade is likelier to print.
+    pub fn microprice(&self) -> Option<f64> {
+        let (Some((bid_px, bid_qty)), Some((ask_px, ask_qty))) = self.bbo() else {
+            return None;
+        };
+        let (bid_qty, ask_qty) = (bid_qty as f64, ask_qty as f64);
+        Some((bid_px as f64 * ask_qty + ask_px as f64 * bid_qty) / (bid_qty + ask_qty))
+    }
+
+    /// The microprice over the best `depth` levels per side: each side's volume-weighted price
+    /// over those levels stands in for its best price, and its total qty for the best level's:
+    /// (bid_vwap * ask_qty + ask_vwap * bid_qty) / (bid_qty + ask_qty). Equal to `microprice` at
+    /// depth 1; a side with fewer levels contributes all it has. `None` for depth 0.
+    pub fn weighted_mid(&self, depth: usize) -> Option<f64> {
+        // (volume-weighted price, qty) over the best `depth` levels of one side
+        let side = |levels: &mut dyn Iterator<Item = (i64, &Level)>| {
+            let (notional, qty) = levels.take(depth).fold((0.0, 0.0), |(n, q), (px, l)| {
+                (n + px as f64 * l.level_qty as f64, q + l.level_qty as f64)
+            });
+            (qty > 0.0).then(|| (notional / qty, qty))
+        };
+        let (bid_vwap, bid_qty) = side(&mut self.bids.iter().rev())?;
+        let (ask_vwap, ask_qty) = side(&mut self.asks.iter())?;
+        Some((bid_vwap * ask_qty + ask_vwap * bid_qty) / (bid_qty + ask_qty))
+    }
+
+    /// Order-flow imbalance over the best `depth` levels per side: (bid_qty - ask_qty) /
+    /// (bid_qty + ask_qty), from -1 (all asks) to 1 (all bids). `None` if both are empty there.
+    pub fn imbalance(&self, depth: usize) -> Option<f64> {
+        let bid_qty: u64 = self.bids.iter().rev().take(depth).map(|(_, l)| l.level_qty).sum();
+        let ask_qty: u64 = self.asks.iter().take(depth).map(|(_, l)| l.level_qty).sum();
+        let total = bid_qty + ask_qty;
+        (total > 0).then(|| (bid_qty as f64 - ask_qty as f64) / total as f64)
+    }
+
+    /// Price-band check of a limit price against the reference price, if the symbol has a band.
+    pub fn check_band(&self, price: i64) -> Result<(), RejectReason> {
+        match self.band {
+            Some(band) if !band.allows(price, self.tape.reference(), self.increments.tick_size) => Err(RejectReason::PriceBand),
+            _ => Ok(()),
+        }
+    }
+
+    /// Depth check of a price an order would rest at, if the symbol has a max depth: fewer than
+    /// `max_depth` levels of its side may be better, so it opens or joins one of the best N.
+    pub fn check_depth(&self, side: Side, price: i64) -> Result<(), RejectReason> {
+        let Some(max) = self.max_depth else {
+            return Ok(());
+        };
+        // Walk in from the touch, and stop counting at `max`
+        let levels = self.levels(side).iter();
+        let ahead = match side {
+            Side::Bid => levels.rev().take_while(|&(px, _)| px > price).take(max).count(),
+            Side::Ask => levels.take_while(|&(px, _)| px < price).take(max).count(),
+        };
+        if ahead < max { Ok(()) } else { Err(RejectReason::TooDeep) }
+    }
+
+    pub fn levels(&self, side: Side) -> &dyn BookBackend {
+        match side {
+            Side::Bid => &*self.bids,
+            Side::Ask => &*self.asks,
+        }
+    }
+
+    pub fn levels_mut(&mut self, side: Side) -> &mut dyn BookBackend {
+        match side {
+            Side::Bid => &mut *self.bids,
+            Side::Ask => &mut *self.asks,
+        }
+    }
+
+    /// Move both sides onto price ladders. Fails, leaving the book as it was,
+    /// if a resting level sits outside `spec`.
+    pub fn use_ladder(&mut self, spec: LadderSpec) -> Result<(), String> {
+        let mut sides = [Ladder::new(spec), Ladder::new(spec)];
+        for (side, ladder) in [Side::Bid, Side::Ask].into_iter().zip(&sides) {
+            if let Some((px, _)) = self.levels(side).iter().find(|&(px, _)| !ladder.accepts(px)) {
+                return Err(format!("resting {side:?} level {px} is outside the ladder"));
+            }
+        }
+        for (side, ladder) in [Side::Bid, Side::Ask].into_iter().zip(&mut sides) {
+            let prices: Vec<i64> = self.levels(side).iter().map(|(px, _)| px).collect();
+            for px in prices {
+                *ladder.level_mut(px) = self.levels_mut(side).remove(px).expect("level listed above");
+            }
+        }
+        let [bids, asks] = sides;
+        self.bids = Box::new(bids);
+        self.asks = Box::new(asks);
+        Ok(())
+    }
+
+    /// Full consistency check (O(book)), for debug builds:
+    /// uncrossed (unless out of continuous trading), no empty levels, cached level qty == sum of its orders,
+    /// and `lookup` indexes exactly the resting orders, notional included.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if let (Some((bp, _)), Some((ap, _))) = self.bbo()
+            && bp >= ap
+            && self.state == MarketState::Open
+        {
+            return Err(format!("crossed: best bid {bp} >= best ask {ap}"));
+        }
+
+        let mut resting = 0usize;
+        let mut notional: HashMap<u64, u128> = HashMap::new();
+        let mut per_client: HashMap<u64, u64> = HashMap::new();
+        let mut owners: HashMap<u64, u32> = HashMap::new();
+        for side in [Side::Bid, Side::Ask] {
+            let levels = self.levels(side);
+            // The backend's cached ends must agree with a full walk
+            let ends = (levels.lowest().map(|(px, _)| px), levels.highest().map(|(px, _)| px));
+            if ends != (levels.iter().next().map(|(px, _)| px), levels.iter().next_back().map(|(px, _)| px)) {
+                return Err(format!("{side:?} cached lowest/highest {ends:?} disagree with the levels"));
+            }
+            for (px, level) in levels.iter() {
+                if !levels.accepts(px) {
+                    return Err(format!("{side:?} level {px} outside the backend's price range"));
+                }
+                if level.is_empty() {
+                    return Err(format!("empty {side:?} level left at {px}"));
+                }
+                let sum: u64 = level.orders.iter().map(|o| o.qty).sum();
+                if sum != level.level_qty {
+                    return Err(format!("{side:?} {px}: cached level_qty {} != sum {sum}", level.level_qty));
+                }
+                for o in &level.orders {
+                    if o.side != side || o.price != px || o.qty == 0 {
+                        return Err(format!("order {} misfiled: {:?} {}x{} under {side:?} {px}", o.id, o.side, o.price, o.qty));
+                    }
+                    if o.total_qty < o.qty || (o.display_qty == 0 && o.total_qty != o.qty) || (o.display_qty > 0 && o.qty > o.display_qty) {
+                        return Err(format!("order {} shows {} of {} with display {}", o.id, o.qty, o.total_qty, o.display_qty));
+                    }
+                    if self.lookup.get(o.exch_ord_id) != Some((side, px)) || self.lookup.exch_id(o.cl_id, o.id) != Some(o.exch_ord_id) {
+                        return Err(format!("order {} (client {}, exch {}) at {side:?} {px} missing from lookup", o.id, o.cl_id, o.exch_ord_id));
+                    }
+                    resting += 1;
+                    *notional.entry(o.cl_id).or_default() += o.price.unsigned_abs() as u128 * o.total_qty as u128;
+                    *per_client.entry(o.cl_id).or_default() += 1;
+                    *owners.entry(o.id).or_default() += 1;
+                }
+            }
+        }
+        // Every resting order is indexed both ways, so a size mismatch means a dangling entry
+        if resting != self.lookup.by_exch.len() || resting != self.lookup.by_client.len() {
+            return Err(format!("lookup has {}/{} entries for {resting} resting orders", self.lookup.by_exch.len(), self.lookup.by_client.len()));
+        }
+        if notional != self.lookup.notional {
+            return Err(format!("lookup notional {:?} != resting orders' {notional:?}", self.lookup.notional));
+        }
+        if per_client != self.lookup.resting {
+            return Err(format!("lookup resting counts {:?} != resting orders' {per_client:?}", self.lookup.resting));
+        }
+        if owners != self.lookup.owners {
+            return Err(format!("lookup owner counts {:?} != resting orders' {owners:?}", self.lookup.owners));
+        }
+        Ok(())
+    }
+}
+
+/// Depth ladder: asks above bids, both from the highest price down, one level per line with
+/// its visible qty and order count, and a marker at the spread. A precision (`{:.5}`) keeps
+/// only that many levels per side nearest the spread.
+impl fmt::Display for OrderBook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let depth = f.precision().unwrap_or(usize::MAX);
+        let mut asks: Vec<(i64, &Level)> = self.asks.iter().take(depth).collect();
+        asks.reverse();
+        let bids: Vec<(i64, &Level)> = self.bids.iter().rev().take(depth).collect();
+        let px_w = asks.iter().chain(&bids).map(|(px, _)| px.to_string().len()).max().unwrap_or(1);
+        let qty_w = asks.iter().chain(&bids).map(|(_, l)| l.level_qty.to_string().len()).max().unwrap_or(1);
+        let side = |f: &mut fmt::Formatter<'_>, name: &str, levels: &[(i64, &Level)]| {
+            if levels.is_empty() {
+                return writeln!(f, "{name}  (empty)");
+            }
+            for (px, l) in levels {
+                writeln!(f, "{name}  {px:>px_w$}  {:>qty_w$}  ({})", l.level_qty, l.orders.len())?;
+            }
+            Ok(())
+        };
+        side(f, "ASK", &asks)?;
+        match self.bbo() {
+            (Some((bid, _)), Some((ask, _))) if ask >= bid => writeln!(f, "----- spread {} -----", ask - bid)?,
+            (Some(_), Some(_)) => writeln!(f, "----- crossed -----")?,
+            _ => writeln!(f, "----- no spread -----")?,
+        }
+        side(f, "BID", &bids)
+    }
+}
+
+// Why a command was refused, by the gateway (REJECT/NACK) or the engine (REJECT).
+// The discriminant is the stable wire code, so the compiler rejects a duplicate;
+// codes are grouped by who refuses and must never be renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u16)]
+pub enum RejectReason {
+    // 1xx: malformed order fields, refused by the gateway
+    BadLength = 100,
+    BadSide = 101,
+    BadPrice = 102,
+    BadQty = 103,
+    BadTif = 104,
+    BadOrdType = 105,
+    BadStp = 106,
+    BadExpiry = 107,
+    BadDisplay = 108,
+    BadStopPrice = 109,
+    // 2xx: new orders the engine won't take
+    DupOrderId = 200,
+    PriceOutOfRange = 201,
+    FokUnfilled = 202,
+    WouldCross = 203,
+    Unfilled = 204, // Market order remainder with nothing left to trade against
+    BadTick = 205,
+    BadLot = 206,
+    PriceBand = 207,
+    Halted = 208,
+    AuctionRestingOnly = 209,
+    RiskPosition = 210,
+    RiskNotional = 211,
+    TooDeep = 212, // Would rest beyond the symbol's max depth
+    MaxOrders = 213, // Client already has its `max_orders` open
+    ReduceOnly = 214, // Reduce-only order, but the client has no opposite position to reduce
+    SelfTrade = 215, // Replace whose re-entry cancel-aggressing STP would stop on the client's own order
+    // 3xx: cancels and replaces
+    NotFound = 300,
+    WrongOwner = 301,
+    ReduceOnlyIncrease = 302, // REDUCE to a size at or above the order's current one
+    // 4xx: flow control (NACK)
+    RateLimited = 400,
+    EngineBusy = 401,
+    ServerFull = 402, // Sent on accept, when every connection slot is taken, before closing
+    // 5xx: framing (NACK)
+    FrameTooLarge = 500,
+    MalformedFrame = 501,
+    BadChecksum = 502,
+    UnknownType = 503,
+    // 6xx: permissions (NACK)
+    Unauthorized = 600, // Admin command or failed ADMIN_AUTH on a connection that isn't admin
+    // 7xx: handshake
+    HelloRequired = 700, // NACK: something other than HELLO came first
+    UnsupportedVersion = 701, // REJECT to a HELLO, then the connection is closed
+}
+
+impl RejectReason {
+    // Every variant, for `from_code`
+    const ALL: [RejectReason; 39] = [
+        RejectReason::BadLength, RejectReason::BadSide, RejectReason::BadPrice, RejectReason::BadQty,
+        RejectReason::BadTif, RejectReason::BadOrdType, RejectReason::BadStp, RejectReason::BadExpiry,
+        RejectReason::BadDisplay, RejectReason::BadStopPrice, RejectReason::DupOrderId,
+        RejectReason::PriceOutOfRange, RejectReason::FokUnfilled, RejectReason::WouldCross,
+        RejectReason::Unfilled, RejectReason::BadTick, RejectReason::BadLot, RejectReason::PriceBand,
+        RejectReason::Halted, RejectReason::AuctionRestingOnly, RejectReason::RiskPosition,
+        RejectReason::RiskNotional, RejectReason::TooDeep, RejectReason::MaxOrders, RejectReason::ReduceOnly, RejectReason::SelfTrade,
+        RejectReason::NotFound, RejectReason::WrongOwner, RejectReason::ReduceOnlyIncrease,
+        RejectReason::RateLimited, RejectReason::EngineBusy, RejectReason::ServerFull,
+        RejectReason::FrameTooLarge, RejectReason::MalformedFrame, RejectReason::BadChecksum, RejectReason::UnknownType,
+        RejectReason::Unauthorized, RejectReason::HelloRequired, RejectReason::UnsupportedVersion,
+    ];
+
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// The variant with this wire code, if any.
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.code() == code)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RejectReason::BadLength => "bad_length",
+            RejectReason::BadSide => "bad_side",
+            RejectReason::BadPrice => "bad_price",
+            RejectReason::BadQty => "bad_qty",
+            RejectReason::BadTif => "bad_tif",
+            RejectReason::BadOrdType => "bad_ord_type",
+            RejectReason::BadStp => "bad_stp",
+            RejectReason::BadExpiry => "bad_expiry",
+            RejectReason::BadDisplay => "bad_display",
+            RejectReason::BadStopPrice => "bad_stop_price",
+            RejectReason::DupOrderId => "dup_order_id",
+            RejectReason::PriceOutOfRange => "price_out_of_range",
+            RejectReason::BadTick => "bad_tick",
+            RejectReason::BadLot => "bad_lot",
+            RejectReason::PriceBand => "price_band",
+            RejectReason::Halted => "halted",
+            RejectReason::AuctionRestingOnly => "auction_resting_only",
+            RejectReason::RiskPosition => "risk_position",
+            RejectReason::RiskNotional => "risk_notional",
+            RejectReason::TooDeep => "too_deep",
+            RejectReason::MaxOrders => "max_orders",
+            RejectReason::ReduceOnly => "reduce_only",
+            RejectReason::SelfTrade => "self_trade",
+            RejectReason::FokUnfilled => "fok_unfilled",
+            RejectReason::WouldCross => "would_cross",
+            RejectReason::Unfilled => "unfilled",
+            RejectReason::NotFound => "not_found",
+            RejectReason::WrongOwner => "wrong_owner",
+            RejectReason::ReduceOnlyIncrease => "reduce_only_increase",
+            RejectReason::RateLimited => "rate_limited",
+            RejectReason::EngineBusy => "engine_busy",
+            RejectReason::ServerFull => "server_full",
+            RejectReason::FrameTooLarge => "frame_too_large",
+            RejectReason::MalformedFrame => "malformed_frame",
+            RejectReason::BadChecksum => "bad_checksum",
+            RejectReason::UnknownType => "unknown_type",
+            RejectReason::Unauthorized => "unauthorized",
+            RejectReason::HelloRequired => "hello_required",
+            RejectReason::UnsupportedVersion => "unsupported_version",
+        }
+    }
+}
+
+// What an ACK confirms; the discriminant is its wire code, as for `RejectReason`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u16)]
+pub enum AckNote {
+    Subscribed = 3, // 1 was `canceled`, now a `Done`; 2 was `hello`, now a `HelloAck`
+    SessionRolled = 4,
+    Halted = 5,
+    Resumed = 6,
+    Auction = 7,
+    Uncrossed = 8,
+    StopPending = 9,
+    Registered = 10,
+    DropCopy = 11,
+    Admin = 12,
+}
+
+impl AckNote {
+    // Every variant, for `from_code`
+    const ALL: [AckNote; 10] = [
+        AckNote::Subscribed, AckNote::SessionRolled, AckNote::Halted, AckNote::Resumed, AckNote::Auction,
+        AckNote::Uncrossed, AckNote::StopPending, AckNote::Registered, AckNote::DropCopy, AckNote::Admin,
+    ];
+
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// The variant with this wire code, if any.
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.code() == code)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AckNote::Subscribed => "subscribed",
+            AckNote::SessionRolled => "session_rolled",
+            AckNote::Halted => "halted",
+            AckNote::Resumed => "resumed",
+            AckNote::Auction => "auction",
+            AckNote::Uncrossed => "uncrossed",
+            AckNote::StopPending => "stop_pending",
+            AckNote::Registered => "registered",
+            AckNote::DropCopy => "drop_copy",
+            AckNote::Admin => "admin",
+        }
+    }
+}
+
+// Why an order left the book for good; the discriminant is its wire code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum DoneReason {
+    Filled = 1,
+    Canceled = 2,
+    Expired = 3,
+    IocRemainder = 4, // Only on CANCELED: the part of an IOC/FOK order that didn't trade
+    ReduceOnly = 5, // Only on CANCELED, before the ACCEPTED: what a reduce-only order had beyond the position
+    SessionEnd = 6, // DAY order pulled by a session roll
+}
+
+impl DoneReason {
+    // Every variant, for `from_code`
+    const ALL: [DoneReason; 6] = [
+        DoneReason::Filled, DoneReason::Canceled, DoneReason::Expired, DoneReason::IocRemainder, DoneReason::ReduceOnly,
+        DoneReason::SessionEnd,
+    ];
+
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// The variant with this wire code, if any.
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.code() == code)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DoneReason::Filled => "filled",
+            DoneReason::Canceled => "canceled",
+            DoneReason::Expired => "expired",
+            DoneReason::IocRemainder => "ioc_remainder",
+            DoneReason::ReduceOnly => "reduce_only",
+            DoneReason::SessionEnd => "session_end",
+        }
+    }
+}
+
+// A fill. `Copy`, so the taker's event and the market-data event are two plain copies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Trade {
+    pub symbol: u32,
+    pub price: i64,
+    pub qty: u64,
+    pub taker_cl_id: u64,
+    pub maker_cl_id: u64,
+    pub taker_ord_id: u64, // Exchange ids, so the public feed never shows client-chosen ids
+    pub maker_ord_id: u64,
+    pub aggressor_side: Side, // The taker's side: Bid when a buy lifted an ask
+    pub maker_fee: u64, // From the symbol's `FeeSchedule`
+    pub taker_fee: u64,
+    pub seq: u64,
+    pub price_improvement: u64, // Per unit: how far the price beat the taker's limit; 0 for a market taker
+}
 
 // Action from engine → gateway → client
 // send the same event to the requesting client and
 // also broadcast it to market-data subscribers (another channel).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 #[allow(dead_code)]
 pub enum Event {
-    Ack {ord_id: u64, note: &'static str }, // I got your command
-    Reject {ord_id: u64, reason: &'static str}, // Couldn't do it
-    Trade {price: u64, qty: u64, taker_cl_id: u64, maker_cl_id: u64}, // A fill happened
-    BookDelta {side: Side, price: u64, level_qty: u64}, // This price level changed
+    Ack {ord_id: u64, note: AckNote }, // I got your command
+    Accepted {ord_id: u64, exch_ord_id: u64, filled_qty: u64, resting_qty: u64, side: Side, price: i64}, // New order done matching: how much filled vs rested
+    Reject {ord_id: u64, reason: RejectReason}, // Couldn't do it
+    Trade(Trade), // A fill happened
+    TradePrint(Trade), // Consecutive fills of one taker at one price, summed; maker fields 0 when several makers traded
+    BookDelta {symbol: u32, side: Side, price: i64, level_qty: u64, seq: u64}, // This price level changed
+    Bbo {symbol: u32, bid_px: i64, bid_qty: u64, ask_px: i64, ask_qty: u64, seq: u64}, // Top of book moved; 0/0 = empty side
+    StpCanceled {ord_id: u64, qty: u64}, // Order (resting or incoming) pulled by self-trade prevention
+    Snapshot {symbol: u32, bids: Vec<(i64, u64)>, asks: Vec<(i64, u64)>, seq: u64}, // L2 levels (price, qty), best first, as of feed `seq`
+    Replaced {ord_id: u64, price: i64, qty: u64, priority_kept: bool}, // Amend applied
+    Done {ord_id: u64, reason: DoneReason}, // Order is gone: a resting order fully filled, a cancel, or a GTD expiry
+    Canceled {ord_id: u64, unfilled_qty: u64, reason: DoneReason}, // An order that won't rest dropped what it didn't fill
+    CancelAllDone {count: u64}, // Cancel-all finished: this many orders were pulled
+    BatchAck {results: Vec<(u64, Option<RejectReason>)>}, // Gateway's receipt for a batch: per order in batch order, forwarded (None) or refused
+    OpenOrders {cl_id: u64, orders: Vec<(u32, u64, Side, i64, u64)>}, // Resting (symbol, ord_id, side, price, qty)
+    Stats {symbol: u32, last: i64, high: i64, low: i64, volume: u64, trades: u64}, // Session statistics of a symbol
+    Candle {symbol: u32, open: i64, high: i64, low: i64, close: i64, volume: u64, start_ts: u64}, // OHLC of the interval starting at `start_ts` (unix ms)
+    DropCopy {cl_id: u64, evt: Box<Event>}, // An order event sent to client `cl_id`, copied for the drop-copy feed
+    Resynced {cl_id: u64, seq: u64, evt: Box<Event>}, // Client `cl_id`'s journaled event number `seq`, replayed for a resync
+    ResyncDone {cl_id: u64, last_seq: u64, complete: bool}, // Resync replayed up to `last_seq`; `complete` unless some were already gone
+    #[serde(skip)]
+    ExecReport(String), // Gateway-side text rendering of an order event (see exec_report)
+    HelloAck {version: u16}, // Handshake done: the protocol version this connection speaks
+    AdminChallenge {nonce: [u8; 16]}, // Gateway's nonce for ADMIN_AUTH, to answer with its HMAC
+    // Order-level (L3) feed: `ord_id` is the exchange order id and `qty` only ever the visible slice
+    OrderAdd {symbol: u32, ord_id: u64, side: Side, price: i64, qty: u64, seq: u64}, // Joined the back of its level
+    OrderExecute {symbol: u32, ord_id: u64, qty: u64, seq: u64}, // Traded `qty`; gone once nothing is left showing
+    OrderReduce {symbol: u32, ord_id: u64, qty: u64, seq: u64}, // Shrunk by `qty` in place, keeping its queue position
+    OrderRemove {symbol: u32, ord_id: u64, seq: u64}, // Pulled from the book before it fully traded
     Pong, // Just a pong
 }
 
+impl Event {
+    /// The sequence number slot of a market-data event, to stamp it when published.
+    pub fn seq_mut(&mut self) -> Option<&mut u64> {
+        match self {
+            Event::Trade(Trade { seq, .. }) | Event::TradePrint(Trade { seq, .. }) | Event::BookDelta { seq, .. } | Event::Bbo { seq, .. } | Event::Snapshot { seq, .. } => Some(seq),
+            Event::OrderAdd { seq, .. } | Event::OrderExecute { seq, .. } | Event::OrderReduce { seq, .. } | Event::OrderRemove { seq, .. } => Some(seq),
+            _ => None,
+        }
+    }
+
+    /// Market-data sequence number, for events that carry one.
+    pub fn seq(&self) -> Option<u64> {
+        match self {
+            Event::Trade(Trade { seq, .. }) | Event::TradePrint(Trade { seq, .. }) | Event::BookDelta { seq, .. } | Event::Bbo { seq, .. } | Event::Snapshot { seq, .. } => Some(*seq),
+            Event::OrderAdd { seq, .. } | Event::OrderExecute { seq, .. } | Event::OrderReduce { seq, .. } | Event::OrderRemove { seq, .. } => Some(*seq),
+            _ => None,
+        }
+    }
+
+    /// Whether this goes on the order-level feed, which keeps its own `seq`.
+    pub fn is_order_level(&self) -> bool {
+        matches!(self, Event::OrderAdd { .. } | Event::OrderExecute { .. } | Event::OrderReduce { .. } | Event::OrderRemove { .. })
+    }
+}
+
+/// One book as the debug endpoint shows it: `summarize_book`, but structured.
+#[derive(Debug, Clone, Serialize)]
+pub struct BookView {
+    pub symbol: u32,
+    pub seq: u64, // Last market-data seq the view reflects
+    pub state: MarketState,
+    pub best_bid: Option<LevelView>,
+    pub best_ask: Option<LevelView>,
+    pub spread: Option<u64>, // None with an empty side, or a book crossed in auction
+    pub bid_orders: usize,
+    pub ask_orders: usize,
+    pub bids: Vec<LevelView>, // Best first, at most the requested depth
+    pub asks: Vec<LevelView>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LevelView {
+    pub price: i64,
+    pub qty: u64, // Visible quantity, as on the feed
+    pub orders: usize,
+}
+
 // Action from gateway → engine
 #[allow(dead_code)]
 pub enum Command {
-    // Place a new order and tell results back through this Sender<Event>
-    Order(Order, crossbeam::channel::Sender<Event>),
+    // Place a new order and tell results back through this sink;
+    // the Instant is when the gateway parsed it, for the engine's latency histogram
+    Order(Order, ClientSink, Instant),
     // Cancel a specific client order; send result via 'sink'
-    Cancel {cl_id: u64, ord_id: u64, sink: crossbeam::channel::Sender<Event>},
+    Cancel {symbol: u32, cl_id: u64, ord_id: u64, sink: ClientSink},
+    // Amend a resting order's price and/or quantity
+    Replace {symbol: u32, cl_id: u64, ord_id: u64, new_price: i64, new_qty: u64, timestamp: u64, sink: ClientSink},
+    // Cut a resting order's quantity, keeping its price and queue position
+    Reduce {symbol: u32, cl_id: u64, ord_id: u64, new_qty: u64, sink: ClientSink},
+    // L2 depth snapshot of one symbol, at most `depth` levels per side
+    Snapshot {symbol: u32, depth: u16, sink: ClientSink},
+    // Structured view of one book for the HTTP debug endpoint, at most `depth` levels per side
+    DebugSnapshot {symbol: u32, depth: u16, reply: oneshot::Sender<BookView>},
     // Just a ping
-    Ping(Sender<Event>),
+    Ping(ClientSink),
+    // Pull every resting order of a client, on one symbol or (None) all of them
+    CancelAll {cl_id: u64, symbol: Option<u32>, sink: ClientSink},
+    // List a client's resting orders across all symbols
+    QueryOrders {cl_id: u64, sink: ClientSink},
+    // Replay a client's journaled order events after `since`, e.g. what it missed while disconnected
+    Resync {cl_id: u64, since: u64, sink: ClientSink},
+    // Session statistics of one symbol
+    QueryStats {symbol: u32, sink: ClientSink},
+    // Start a new trading session: reset the statistics of one symbol or (None) all of them
+    SessionRoll {symbol: Option<u32>, sink: ClientSink},
+    // Admin halt or resume of one symbol
+    SetMarketState {symbol: u32, state: MarketState, sink: ClientSink},
+    // Route this client's events (maker fills, stops, expiries) to `sink` from now on, unless another
+    // open connection has them; `admin` takes them over regardless
+    Register {cl_id: u64, admin: bool, sink: ClientSink},
+    // The connection behind `sink` is gone; forget the client if it still routes there
+    Deregister {cl_id: u64, sink: ClientSink},
+    // GTD sweep of every book as of `now_ms`: a recorded heartbeat sweep, re-issued by replay
+    Sweep {now_ms: u64},
+    // Sentinel: drain whatever is still queued, persist, and stop the engine loop
+    Shutdown,
+}
+
+impl Command {
+    /// Session id of the connection that sent it (carried by its sink); `None` for the engine's own.
+    pub fn session(&self) -> Option<u64> {
+        match self {
+            Command::Order(_, sink, _)
+            | Command::Cancel { sink, .. }
+            | Command::Replace { sink, .. }
+            | Command::Reduce { sink, .. }
+            | Command::Snapshot { sink, .. }
+            | Command::Ping(sink)
+            | Command::CancelAll { sink, .. }
+            | Command::QueryOrders { sink, .. }
+            | Command::Resync { sink, .. }
+            | Command::QueryStats { sink, .. }
+            | Command::SessionRoll { sink, .. }
+            | Command::SetMarketState { sink, .. }
+            | Command::Register { sink, .. }
+            | Command::Deregister { sink, .. } => Some(sink.session()),
+            Command::DebugSnapshot { .. } | Command::Sweep { .. } | Command::Shutdown => None,
+        }
+    }
 }
 
 /*
-Why include the Sender<Event> inside the command?
+Why include the ClientSink inside the command?
 Because your engine runs in a separate thread and handles many clients. 
 Passing the sink makes the engine connection-aware without global maps or locks. 
 It can emit client-specific responses without guessing where to send them.
-*/
\ No newline at end of file
+*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn every_reject_reason_has_its_own_code_and_name() {
+        let codes: HashSet<u16> = RejectReason::ALL.iter().map(|r| r.code()).collect();
+        let names: HashSet<&str> = RejectReason::ALL.iter().map(|r| r.as_str()).collect();
+        assert_eq!(codes.len(), RejectReason::ALL.len());
+        assert_eq!(names.len(), RejectReason::ALL.len());
+        for r in RejectReason::ALL {
+            assert_eq!(RejectReason::from_code(r.code()), Some(r));
+        }
+        assert_eq!(RejectReason::from_code(0), None);
+
+        let codes: HashSet<u16> = AckNote::ALL.iter().map(|n| n.code()).collect();
+        assert_eq!(codes.len(), AckNote::ALL.len());
+        for n in AckNote::ALL {
+            assert_eq!(AckNote::from_code(n.code()), Some(n));
+        }
+    }
+
+    #[test]
+    fn fees_round_half_up_and_a_zero_schedule_charges_nothing() {
+        let fees = FeeSchedule { maker_bps: 1, taker_bps: 5 };
+        // 100 * qty * bps / 10_000: taker 0.05, 0.45, 0.5 and 0.55; maker 0.01 … 0.11
+        assert_eq!(fees.fees(100, 1), (0, 0));
+        assert_eq!(fees.fees(100, 9), (0, 0));
+        assert_eq!(fees.fees(100, 10), (0, 1));
+        assert_eq!(fees.fees(100, 11), (0, 1));
+        assert_eq!(fees.fees(100, 50), (1, 3), "maker 0.5 rounds up, taker 2.5 rounds up");
+        assert_eq!(fees.fees(-100, 10), (0, 1), "charged on |price|");
+        assert_eq!(FeeSchedule::default().fees(1_000_000, 1_000_000), (0, 0));
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use bytes::{Buf, BufMut, BytesMut};
+use tracing::warn;
+use crate::types::{MarketState, Order, OrderType, Side, Stp, Tif};
+
+// ========================== Write-ahead log ==========================
+// Record: [u32 len][u8 kind][payload...], integers little-endian.
+// Every book-mutating command is appended before it is applied, so replaying
+// the file in order through the same handlers rebuilds the exact same books.
+
+const REC_ORDER: u8 = 1;
+const REC_CANCEL: u8 = 2;
+const REC_REPLACE: u8 = 3;
+const REC_SWEEP: u8 = 4;
+const REC_CANCEL_ALL: u8 = 5;
+const REC_SESSION_ROLL: u8 = 6;
+const REC_MARKET_STATE: u8 = 7;
+const REC_REDUCE: u8 = 8;
+
+pub enum Record {
+    Order(Order),
+    Cancel { symbol: u32, cl_id: u64, ord_id: u64 },
+    Replace { symbol: u32, cl_id: u64, ord_id: u64, new_price: i64, new_qty: u64, timestamp: u64 }, // Re-entry's time priority
+    Reduce { symbol: u32, cl_id: u64, ord_id: u64, new_qty: u64 },
+    CancelAll { cl_id: u64, symbol: Option<u32> },
+    // GTD sweep across all books at `now_ms` (the ticker's clock isn't in any command)
+    Sweep { now_ms: u64 },
+    SessionRoll { symbol: Option<u32> },
+    MarketState { symbol: u32, state: MarketState },
+}
+
+pub struct Wal {
+    out: BufWriter<File>,
+    offset: u64, // File length once everything appended so far is flushed
+}
+
+impl Wal {
+    /// Open for appending at `valid_len`, dropping any torn tail `replay` stopped at.
+    pub fn open(path: impl AsRef<Path>, valid_len: u64) -> io::Result<Wal> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.set_len(valid_len)?;
+        Ok(Wal { out: BufWriter::new(file), offset: valid_len })
+    }
+
+    /// Buffer one record; it reaches the file on the next `flush`.
+    pub fn append(&mut self, rec: &Record) -> io::Result<()> {
+        let mut body = BytesMut::with_capacity(64);
+        encode_record(rec, &mut body);
+        self.out.write_all(&(body.len() as u32).to_le_bytes())?;
+        self.out.write_all(&body)?;
+        self.offset += 4 + body.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// Read every complete record from byte offset `from` on. Also returns the byte length
+/// they cover: a crash mid-append leaves a torn or garbled tail, which is reported and ignored.
+/// Only the part past `from` is read: what a snapshot already covers stays on disk.
+pub fn replay(path: impl AsRef<Path>, from: u64) -> io::Result<(Vec<Record>, u64)> {
+    let mut f = match File::open(path.as_ref()) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && from == 0 => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e),
+    };
+    let file_len = f.metadata()?.len();
+    if from > file_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("replay offset {from} is past the end of the WAL ({file_len} bytes)"),
+        ));
+    }
+    f.seek(SeekFrom::Start(from))?;
+    let mut raw = Vec::with_capacity((file_len - from) as usize);
+    f.read_to_end(&mut raw)?;
+
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while raw.len() - pos >= 4 {
+        let len = u32::from_le_bytes(raw[pos..pos + 4].try_into().unwrap()) as usize;
+        let Some(body) = raw.get(pos + 4..pos + 4 + len) else { break };
+        let Some(rec) = decode_record(body) else { break };
+        records.push(rec);
+        pos += 4 + len;
+    }
+    let end = from + pos as u64;
+    if pos < raw.len() {
+        warn!("[wal] ⚠️ Ignoring {} trailing bytes after offset {}", raw.len() - pos, end);
+    }
+    Ok((records, end))
+}
+
+pub(crate) fn encode_record(rec: &Record, body: &mut BytesMut) {
+    match rec {
+        Record::Order(o) => {
+            body.put_u8(REC_ORDER);
+            encode_order(o, body);
+        }
+        Record::Cancel { symbol, cl_id, ord_id } => {
+            body.put_u8(REC_CANCEL);
+            body.put_u32_le(*symbol);
+            body.put_u64_le(*cl_id);
+            body.put_u64_le(*ord_id);
+        }
+        Record::Replace { symbol, cl_id, ord_id, new_price, new_qty, timestamp } => {
+            body.put_u8(REC_REPLACE);
+            body.put_u32_le(*symbol);
+            body.put_u64_le(*cl_id);
+            body.put_u64_le(*ord_id);
+            body.put_i64_le(*new_price);
+            body.put_u64_le(*new_qty);
+            body.put_u64_le(*timestamp);
+        }
+        Record::Reduce { symbol, cl_id, ord_id, new_qty } => {
+            body.put_u8(REC_REDUCE);
+            body.put_u32_le(*symbol);
+            body.put_u64_le(*cl_id);
+            body.put_u64_le(*ord_id);
+            body.put_u64_le(*new_qty);
+        }
+        Record::CancelAll { cl_id, symbol } => {
+            body.put_u8(REC_CANCEL_ALL);
+            body.put_u64_le(*cl_id);
+            if let Some(symbol) = symbol {
+                body.put_u32_le(*symbol);
+            }
+        }
+        Record::Sweep { now_ms } => {
+            body.put_u8(REC_SWEEP);
+            body.put_u64_le(*now_ms);
+        }
+        Record::SessionRoll { symbol } => {
+            body.put_u8(REC_SESSION_ROLL);
+            if let Some(symbol) = symbol {
+                body.put_u32_le(*symbol);
+            }
+        }
+        Record::MarketState { symbol, state } => {
+            body.put_u8(REC_MARKET_STATE);
+            body.put_u32_le(*symbol);
+            body.put_u8(match state { MarketState::Open => 0, MarketState::Halted => 1, MarketState::Auction => 2 });
+        }
+    }
+}
+
+pub(crate) fn decode_record(mut body: &[u8]) -> Option<Record> {
+    if body.is_empty() {
+        return None;
+    }
+    let rec = match body.get_u8() {
+        REC_ORDER => Record::Order(decode_order(&mut body)?),
+        REC_CANCEL if body.len() >= 20 => Record::Cancel {
+            symbol: body.get_u32_le(),
+            cl_id: body.get_u64_le(),
+            ord_id: body.get_u64_le(),
+        },
+        REC_REPLACE if body.len() >= 44 => Record::Replace {
+            symbol: body.get_u32_le(),
+            cl_id: body.get_u64_le(),
+            ord_id: body.get_u64_le(),
+            new_price: body.get_i64_le(),
+            new_qty: body.get_u64_le(),
+            timestamp: body.get_u64_le(),
+        },
+        REC_REDUCE if body.len() >= 28 => Record::Reduce {
+            symbol: body.get_u32_le(),
+            cl_id: body.get_u64_le(),
+            ord_id: body.get_u64_le(),
+            new_qty: body.get_u64_le(),
+        },
+        REC_CANCEL_ALL if body.len() >= 8 => Record::CancelAll {
+            cl_id: body.get_u64_le(),
+            symbol: (body.len() >= 4).then(|| body.get_u32_le()),
+        },
+        REC_SWEEP if body.len() >= 8 => Record::Sweep { now_ms: body.get_u64_le() },
+        REC_SESSION_ROLL => Record::SessionRoll { symbol: (body.len() >= 4).then(|| body.get_u32_le()) },
+        REC_MARKET_STATE if body.len() >= 5 => Record::MarketState {
+            symbol: body.get_u32_le(),
+            state: match body.get_u8() {
+                0 => MarketState::Open,
+                1 => MarketState::Halted,
+                2 => MarketState::Auction,
+                _ => return None,
+            },
+        },
+        _ => return None,
+    };
+    Some(rec)
+}
+
+// [u64 id][u64 cl_id][u32 symbol][u8 side][i64 price][u64 qty][u64 timestamp]
+// [u8 tif][u8 ord_type][u8 flags][u8 stp][u64 expire_ms][u64 exch_ord_id]
+// [u64 display_qty][u64 total_qty][i64 stop_price]
+// Flags: bit 0 post_only, bit 1 reduce_only
+const ORDER_LEN: usize = 8 + 8 + 4 + 1 + 8 + 8 + 8 + 4 + 8 + 8 + 8 + 8 + 8;
+
+pub(crate) fn encode_order(o: &Order, body: &mut BytesMut) {
+    body.put_u64_le(o.id);
+    body.put_u64_le(o.cl_id);
+    body.put_u32_le(o.symbol);
+    body.put_u8(match o.side { Side::Bid => 0, Side::Ask => 1 });
+    body.put_i64_le(o.price);
+    body.put_u64_le(o.qty);
+    body.put_u64_le(o.timestamp);
+    body.put_u8(match o.tif { Tif::Gtc => 0, Tif::Ioc => 1, Tif::Fok => 2, Tif::Gtd => 3, Tif::Day => 4 });
+    body.put_u8(match o.ord_type { OrderType::Limit => 0, OrderType::Market => 1, OrderType::Stop => 2, OrderType::StopLimit => 3 });
+    body.put_u8(o.post_only as u8 | (o.reduce_only as u8) << 1);
+    body.put_u8(match o.stp { Stp::Off => 0, Stp::CancelResting => 1, Stp::CancelAggressing => 2 });
+    body.put_u64_le(o.expire_ms);
+    body.put_u64_le(o.exch_ord_id);
+    body.put_u64_le(o.display_qty);
+    body.put_u64_le(o.total_qty);
+    body.put_i64_le(o.stop_price);
+}
+
+pub(crate) fn decode_order(body: &mut &[u8]) -> Option<Order> {
+    if body.len() < ORDER_LEN {
+        return None;
+    }
+    let id = body.get_u64_le();
+    let cl_id = body.get_u64_le();
+    let symbol = body.get_u32_le();
+    let side = match body.get_u8() { 0 => Side::Bid, 1 => Side::Ask, _ => return None };
+    let price = body.get_i64_le();
+    let qty = body.get_u64_le();
+    let timestamp = body.get_u64_le();
+    let tif = match body.get_u8() { 0 => Tif::Gtc, 1 => Tif::Ioc, 2 => Tif::Fok, 3 => Tif::Gtd, 4 => Tif::Day, _ => return None };
+    let ord_type = match body.get_u8() {
+        0 => OrderType::Limit,
+        1 => OrderType::Market,
+        2 => OrderType::Stop,
+        3 => OrderType::StopLimit,
+        _ => return None,
+    };
+    let flags = body.get_u8();
+    let (post_only, reduce_only) = (flags & 0x01 != 0, flags & 0x02 != 0);
+    let stp = match body.get_u8() { 0 => Stp::Off, 1 => Stp::CancelResting, 2 => Stp::CancelAggressing, _ => return None };
+    let expire_ms = body.get_u64_le();
+    let exch_ord_id = body.get_u64_le();
+    let display_qty = body.get_u64_le();
+    let total_qty = body.get_u64_le();
+    let stop_price = body.get_i64_le();
+    Some(Order {
+        id, exch_ord_id, cl_id, symbol, side, price, qty, timestamp, arrival_seq: 0, queue_seq: 0, tif, ord_type, post_only, reduce_only, stp, expire_ms, display_qty, total_qty, stop_price,
+    })
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use clob_engine::protocol::*;
+use clob_engine::types::{AckNote, Event, RejectReason};
+use common::{hello, Engine, Reply};
+
+const TOKEN: &str = "s3cret";
+
+#[tokio::test]
+async fn halt_is_refused_before_admin_auth_and_accepted_after() {
+    let engine = Engine::start(&[("ADMIN_TOKEN", TOKEN)]);
+    let mut c = engine.connect().await;
+
+    c.send(MSG_HALT, &7u32.to_le_bytes()).await;
+    assert_eq!(c.nack(MSG_HALT).await, RejectReason::Unauthorized);
+
+    assert!(matches!(c.admin_auth(TOKEN).await, Reply::Event(Event::Ack { note: AckNote::Admin, .. })));
+    c.send(MSG_HALT, &7u32.to_le_bytes()).await;
+    assert!(matches!(c.event().await, Event::Ack { note: AckNote::Halted, .. }));
+}
+
+#[tokio::test]
+async fn wrong_hmac_and_unsolicited_answer_are_unauthorized() {
+    let engine = Engine::start(&[("ADMIN_TOKEN", TOKEN)]);
+    let mut c = engine.connect().await;
+
+    assert!(matches!(c.admin_auth("guess").await, Reply::Nack { reason: RejectReason::Unauthorized, .. }));
+    // The nonce was used up by the failed answer
+    c.send(MSG_ADMIN_AUTH, &[0; 32]).await;
+    assert_eq!(c.nack(MSG_ADMIN_AUTH).await, RejectReason::Unauthorized);
+    c.send(MSG_HALT, &7u32.to_le_bytes()).await;
+    assert_eq!(c.nack(MSG_HALT).await, RejectReason::Unauthorized);
+}
+
+#[tokio::test]
+async fn token_in_hello_grants_nothing() {
+    let engine = Engine::start(&[("ADMIN_TOKEN", TOKEN)]);
+    let mut c = engine.connect().await;
+
+    let mut body = hello(0);
+    body.extend_from_slice(TOKEN.as_bytes());
+    c.send(MSG_HELLO, &body).await;
+    assert!(matches!(c.event().await, Event::HelloAck { .. }));
+    c.send(MSG_DROPCOPY_SUBSCRIBE, &[]).await;
+    assert_eq!(c.nack(MSG_DROPCOPY_SUBSCRIBE).await, RejectReason::Unauthorized);
+}
+
+#[tokio::test]
+async fn no_admin_token_means_no_admin() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+
+    c.send(MSG_ADMIN_AUTH, &[]).await;
+    assert_eq!(c.nack(MSG_ADMIN_AUTH).await, RejectReason::Unauthorized);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use clob_engine::protocol::MSG_BATCH_NEW;
+use clob_engine::types::{Event, RejectReason, Side};
+use common::{Engine, NewOrder};
+
+/// BATCH_NEW body: `[u16 count]` then the records as given.
+fn batch(count: u16, orders: &[NewOrder]) -> Vec<u8> {
+    let mut body = count.to_le_bytes().to_vec();
+    for o in orders {
+        body.extend_from_slice(&o.body());
+    }
+    body
+}
+
+#[tokio::test]
+async fn mixed_batch_gets_a_status_per_order() {
+    let engine = Engine::start(&[]);
+    let mut owner = engine.connect().await;
+    owner.register(2).await;
+    let mut c = engine.connect().await;
+
+    let mut bad_side = NewOrder::limit(1, 2, 0, Side::Bid, 100, 10);
+    bad_side.side = 7;
+    let orders = [
+        NewOrder::limit(1, 1, 0, Side::Bid, 100, 10),
+        bad_side,
+        NewOrder::limit(1, 3, 0, Side::Bid, 100, 0),
+        NewOrder::limit(2, 4, 0, Side::Ask, 100, 10), // Another connection's client
+        NewOrder::limit(1, 5, 0, Side::Ask, 99, 4),   // Crosses order 1
+    ];
+    c.send(MSG_BATCH_NEW, &batch(5, &orders)).await;
+    let results = c.event_where(|e| if let Event::BatchAck { results } = e { Some(results) } else { None }).await;
+    assert_eq!(results, [
+        (1, None),
+        (2, Some(RejectReason::BadSide)),
+        (3, Some(RejectReason::BadQty)),
+        (4, Some(RejectReason::Unauthorized)),
+        (5, None),
+    ]);
+
+    // The forwarded ones reach the engine in batch order
+    assert_eq!(c.accepted(1).await.resting_qty, 10);
+    assert_eq!(c.accepted(5).await.filled_qty, 4);
+}
+
+#[tokio::test]
+async fn batch_shorter_than_its_count_sends_nothing() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+    let orders = [NewOrder::limit(1, 1, 0, Side::Bid, 100, 10), NewOrder::limit(1, 2, 0, Side::Bid, 100, 10)];
+    c.send(MSG_BATCH_NEW, &batch(3, &orders)).await;
+    assert_eq!(c.nack(MSG_BATCH_NEW).await, RejectReason::BadLength);
+    c.limit(1, 1, 0, Side::Bid, 100, 10).await;
+}
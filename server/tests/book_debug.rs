@@ -0,0 +1,45 @@
+mod common;
+
+use clob_engine::types::Side;
+use common::{http_get, Engine};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn book_endpoint_shows_a_populated_book() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+    for (ord_id, side, price, qty) in [
+        (1, Side::Bid, 99, 5),
+        (2, Side::Bid, 99, 7),
+        (3, Side::Bid, 98, 4),
+        (4, Side::Bid, 97, 1),
+        (5, Side::Ask, 101, 3),
+        (6, Side::Ask, 102, 9),
+        (7, Side::Ask, 102, 2),
+    ] {
+        c.limit(1, ord_id, 4, side, price, qty).await;
+    }
+
+    let (status, body) = http_get(engine.debug_addr, "/book/4?depth=2").await;
+    assert_eq!(status, 200, "{body}");
+    let mut view: Value = serde_json::from_str(&body).expect("JSON book");
+    assert!(view["seq"].as_u64().unwrap() > 0, "{body}");
+    view.as_object_mut().unwrap().remove("seq");
+    assert_eq!(view, json!({
+        "symbol": 4,
+        "state": "open",
+        "best_bid": {"price": 99, "qty": 12, "orders": 2},
+        "best_ask": {"price": 101, "qty": 3, "orders": 1},
+        "spread": 2,
+        "bid_orders": 4,
+        "ask_orders": 3,
+        // Two levels deep: the bid at 97 is left out of the ladder, not the counts
+        "bids": [{"price": 99, "qty": 12, "orders": 2}, {"price": 98, "qty": 4, "orders": 1}],
+        "asks": [{"price": 101, "qty": 3, "orders": 1}, {"price": 102, "qty": 11, "orders": 2}],
+    }));
+
+    let (status, _) = http_get(engine.debug_addr, "/book/four").await;
+    assert_eq!(status, 400);
+    let (status, _) = http_get(engine.debug_addr, "/nope").await;
+    assert_eq!(status, 404);
+}
@@ -0,0 +1,18 @@
+mod common;
+
+use clob_engine::protocol::MSG_CANCEL;
+use clob_engine::types::{DoneReason, Event, RejectReason, Side};
+use common::{order_ref, Engine};
+
+#[tokio::test]
+async fn cancel_reaches_the_engine_once() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+    let accepted = c.limit(1, 1, 0, Side::Bid, 100, 10).await;
+    assert_eq!(accepted.resting_qty, 10);
+
+    c.send(MSG_CANCEL, &order_ref(1, 1, 0)).await;
+    assert!(matches!(c.event().await, Event::Done { ord_id: 1, reason: DoneReason::Canceled }));
+    c.send(MSG_CANCEL, &order_ref(1, 1, 0)).await;
+    assert!(matches!(c.event().await, Event::Reject { ord_id: 1, reason: RejectReason::NotFound }));
+}
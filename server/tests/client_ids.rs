@@ -0,0 +1,98 @@
+mod common;
+
+use clob_engine::protocol::*;
+use clob_engine::types::{DoneReason, Event, RejectReason, Side};
+use common::{order_ref, Engine, NewOrder, Reply};
+
+#[tokio::test]
+async fn two_clients_can_use_the_same_order_id() {
+    let engine = Engine::start(&[]);
+    let mut a = engine.connect().await;
+    let mut b = engine.connect().await;
+
+    let a7 = a.limit(1, 7, 0, Side::Bid, 100, 10).await;
+    let b7 = b.limit(2, 7, 0, Side::Bid, 99, 10).await;
+    let b7_other_symbol = b.limit(2, 7, 1, Side::Bid, 99, 10).await;
+    // The engine numbers them itself, whatever the clients called them
+    assert!(a7.exch_ord_id != 0 && b7.exch_ord_id > a7.exch_ord_id && b7_other_symbol.exch_ord_id > b7.exch_ord_id);
+
+    // B cancels its own order 7; A's is untouched
+    b.send(MSG_CANCEL, &order_ref(2, 7, 0)).await;
+    assert!(matches!(b.event().await, Event::Done { ord_id: 7, reason: DoneReason::Canceled }));
+    // Another client still has an order 7 resting
+    b.send(MSG_CANCEL, &order_ref(2, 7, 0)).await;
+    assert!(matches!(b.event().await, Event::Reject { ord_id: 7, reason: RejectReason::WrongOwner }));
+
+    a.send(MSG_CANCEL, &order_ref(1, 7, 0)).await;
+    assert!(matches!(a.event().await, Event::Done { ord_id: 7, reason: DoneReason::Canceled }));
+    a.send(MSG_CANCEL, &order_ref(1, 7, 0)).await;
+    assert!(matches!(a.event().await, Event::Reject { ord_id: 7, reason: RejectReason::NotFound }));
+}
+
+#[tokio::test]
+async fn orders_of_another_connections_client_are_off_limits() {
+    let engine = Engine::start(&[]);
+    let mut a = engine.connect().await;
+    let mut b = engine.connect().await;
+    a.limit(1, 7, 0, Side::Bid, 100, 10).await;
+
+    b.send(MSG_CANCEL, &order_ref(1, 7, 0)).await;
+    assert_eq!(b.nack(MSG_CANCEL).await, RejectReason::Unauthorized);
+
+    let mut replace = order_ref(1, 7, 0);
+    replace.extend_from_slice(&101i64.to_le_bytes());
+    replace.extend_from_slice(&10i64.to_le_bytes());
+    b.send(MSG_REPLACE, &replace).await;
+    assert_eq!(b.nack(MSG_REPLACE).await, RejectReason::Unauthorized);
+
+    let mut reduce = order_ref(1, 7, 0);
+    reduce.extend_from_slice(&5i64.to_le_bytes());
+    b.send(MSG_REDUCE, &reduce).await;
+    assert_eq!(b.nack(MSG_REDUCE).await, RejectReason::Unauthorized);
+
+    // Nor can B take client 1 over while A is connected, by REGISTER or by an order of its own
+    b.send(MSG_REGISTER, &1u64.to_le_bytes()).await;
+    assert_eq!(b.nack(MSG_REGISTER).await, RejectReason::Unauthorized);
+    b.send(MSG_CANCEL, &order_ref(1, 7, 0)).await;
+    assert_eq!(b.nack(MSG_CANCEL).await, RejectReason::Unauthorized);
+    b.send(MSG_NEW_ORDER, &NewOrder::limit(1, 8, 0, Side::Ask, 100, 10).body()).await;
+    assert_eq!(b.rejected(8).await, RejectReason::Unauthorized);
+
+    // A still has its order, and nothing traded against it
+    a.send(MSG_CANCEL, &order_ref(1, 7, 0)).await;
+    assert!(matches!(a.event().await, Event::Done { ord_id: 7, reason: DoneReason::Canceled }));
+}
+
+#[tokio::test]
+async fn a_client_id_is_free_again_once_its_connection_closes() {
+    let engine = Engine::start(&[]);
+    let mut a = engine.connect().await;
+    a.limit(1, 7, 0, Side::Bid, 100, 10).await;
+    drop(a);
+
+    let mut b = engine.connect().await;
+    b.reclaim(1).await;
+    b.send(MSG_CANCEL, &order_ref(1, 7, 0)).await;
+    assert!(matches!(b.event().await, Event::Done { ord_id: 7, reason: DoneReason::Canceled }));
+}
+
+#[tokio::test]
+async fn open_orders_are_only_for_their_own_connection() {
+    let engine = Engine::start(&[("ADMIN_TOKEN", "s3cret")]);
+    let mut a = engine.connect().await;
+    a.limit(1, 7, 0, Side::Bid, 100, 10).await;
+    a.limit(1, 8, 0, Side::Ask, 105, 5).await;
+
+    let mut b = engine.connect().await;
+    b.send(MSG_QUERY_ORDERS, &1u64.to_le_bytes()).await;
+    assert_eq!(b.nack(MSG_QUERY_ORDERS).await, RejectReason::Unauthorized);
+
+    // Its own connection and an admin one may ask
+    let mut admin = engine.connect().await;
+    assert!(matches!(admin.admin_auth("s3cret").await, Reply::Event(Event::Ack { .. })));
+    for c in [&mut a, &mut admin] {
+        c.send(MSG_QUERY_ORDERS, &1u64.to_le_bytes()).await;
+        let orders = c.event_where(|e| if let Event::OpenOrders { cl_id: 1, orders } = e { Some(orders) } else { None }).await;
+        assert_eq!(orders.len(), 2);
+    }
+}
@@ -0,0 +1,405 @@
+// Round-trip harness: runs the `clob-engine` binary on ephemeral ports in a scratch
+// directory and talks the binary protocol to it, as a gateway client would.
+#![allow(dead_code)] // Each test crate uses its own part of this
+
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+use clob_engine::codec::ClobCodec;
+use clob_engine::protocol::*;
+use clob_engine::types::{Event, RejectReason, Side};
+
+/// How long a test waits for a reply before calling the engine stuck.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One engine process, killed and cleaned up on drop.
+pub struct Engine {
+    child: Child,
+    pub addr: SocketAddr,
+    pub metrics_addr: SocketAddr,
+    pub debug_addr: SocketAddr,
+    dir: PathBuf,
+}
+
+impl Engine {
+    /// Start an engine with `env` on top of ephemeral ports and a WAL of its own.
+    pub fn start(env: &[(&str, &str)]) -> Engine {
+        let dir = scratch_dir();
+        let mut child = engine_command(&dir, env).stdout(Stdio::piped()).stderr(Stdio::null()).spawn().expect("spawn clob-engine");
+        let mut lines = BufReader::new(child.stdout.take().expect("piped stdout")).lines();
+        // The HTTP endpoints come up after the order port; the debug one is last
+        let (mut addr, mut metrics_addr) = (None, None);
+        let debug_addr = loop {
+            let line = lines.next().expect("engine exited before listening").expect("engine stdout");
+            let line = line.trim();
+            if let Some(a) = line.strip_prefix("🚀 Listening on ") {
+                addr = Some(a.parse().expect("listen address"));
+            } else if let Some(a) = line.strip_prefix("📈 Metrics on http://").and_then(|a| a.strip_suffix("/metrics")) {
+                metrics_addr = Some(a.parse().expect("metrics address"));
+            } else if let Some(a) = line.strip_prefix("🔬 Book debug endpoint on http://").and_then(|a| a.strip_suffix("/book/<symbol>")) {
+                break a.parse().expect("debug address");
+            }
+        };
+        // Keep draining, or the engine blocks once the pipe fills up
+        std::thread::spawn(move || lines.for_each(drop));
+        Engine { child, addr: addr.expect("listen address"), metrics_addr: metrics_addr.expect("metrics address"), debug_addr, dir }
+    }
+
+    /// Run an engine that is expected to refuse `env` at startup; returns what it printed on stderr.
+    pub fn start_err(env: &[(&str, &str)]) -> String {
+        let dir = scratch_dir();
+        let out = engine_command(&dir, env).stdout(Stdio::null()).output().expect("run clob-engine");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(!out.status.success(), "engine started with {env:?}");
+        String::from_utf8_lossy(&out.stderr).into_owned()
+    }
+
+    /// A file in this engine's scratch directory, e.g. a config to point it at.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub async fn connect(&self) -> Client {
+        Client::connect(self.addr).await
+    }
+
+    /// Everything the engine has logged so far, colours stripped.
+    pub fn log_lines(&self) -> Vec<String> {
+        let Ok(dir) = std::fs::read_dir(self.path("logs")) else { return Vec::new() };
+        let mut text = String::new();
+        for entry in dir {
+            text += &std::fs::read_to_string(entry.expect("log entry").path()).expect("read log");
+        }
+        let mut plain = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                plain.push(c);
+            }
+        }
+        plain.lines().map(str::to_owned).collect()
+    }
+
+    /// The log once a line `pick` accepts is in it; the log writer runs behind the engine.
+    pub async fn logged(&self, pick: impl Fn(&str) -> bool) -> Vec<String> {
+        let deadline = tokio::time::Instant::now() + REPLY_TIMEOUT;
+        loop {
+            let lines = self.log_lines();
+            if lines.iter().any(|l| pick(l)) {
+                return lines;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "not logged; log so far: {lines:#?}");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    /// A connection that hasn't said HELLO yet.
+    pub async fn connect_raw(&self) -> Client {
+        Client::connect_raw(self.addr).await
+    }
+}
+
+/// `GET path` on `addr` over HTTP/1.1; returns the status code and the body.
+pub async fn http_get(addr: SocketAddr, path: &str) -> (u16, String) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut stream = TcpStream::connect(addr).await.expect("connect to http endpoint");
+    let req = format!("GET {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n");
+    stream.write_all(req.as_bytes()).await.expect("send http request");
+    let mut resp = String::new();
+    tokio::time::timeout(REPLY_TIMEOUT, stream.read_to_string(&mut resp)).await.expect("http reply").expect("read http reply");
+    let (head, body) = resp.split_once("\r\n\r\n").expect("http head");
+    let status = head.split(' ').nth(1).and_then(|s| s.parse().ok()).expect("http status");
+    (status, body.to_owned())
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A fresh directory under the system temp dir, unique per test process and call.
+pub fn scratch_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("clob-engine-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+    std::fs::create_dir_all(&dir).expect("scratch dir");
+    dir
+}
+
+fn engine_command(dir: &PathBuf, env: &[(&str, &str)]) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_clob-engine"));
+    cmd.current_dir(dir)
+        .env_clear()
+        .env("ADDR", "127.0.0.1:0")
+        .env("METRICS_ADDR", "127.0.0.1:0")
+        .env("DEBUG_ADDR", "127.0.0.1:0")
+        .envs(env.iter().copied());
+    cmd
+}
+
+/// What the engine sent back: an event, or a gateway NACK.
+#[derive(Debug)]
+pub enum Reply {
+    Event(Event),
+    Nack { ref_msg_type: u16, reason: RejectReason },
+}
+
+/// A little-endian, checksum-free connection that has done its HELLO.
+pub struct Client {
+    framed: Framed<TcpStream, ClobCodec>,
+}
+
+impl Client {
+    pub async fn connect(addr: SocketAddr) -> Client {
+        let mut client = Client::connect_raw(addr).await;
+        client.send(MSG_HELLO, &hello(0)).await;
+        match client.recv().await {
+            Reply::Event(Event::HelloAck { version }) => assert_eq!(version, PROTO_VERSION),
+            other => panic!("expected HELLO_ACK, got {other:?}"),
+        }
+        client
+    }
+
+    pub async fn connect_raw(addr: SocketAddr) -> Client {
+        let stream = TcpStream::connect(addr).await.expect("connect to engine");
+        Client { framed: Framed::new(stream, ClobCodec::default()) }
+    }
+
+    /// This end of the connection, as the engine's logs name the peer.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.framed.get_ref().local_addr().expect("local address")
+    }
+
+    /// Frame everything from here on, both ways, as `format` says (after a HELLO asking for it).
+    pub fn set_format(&mut self, format: FrameFormat) {
+        self.framed.codec_mut().format = format;
+    }
+
+    pub async fn send(&mut self, msg_type: u16, body: &[u8]) {
+        let frame = Frame { msg_type, body: Bytes::copy_from_slice(body) };
+        self.framed.send(frame).await.expect("send frame");
+    }
+
+    /// Like `send`, but false instead of a panic once the engine has hung up.
+    pub async fn try_send(&mut self, msg_type: u16, body: &[u8]) -> bool {
+        let frame = Frame { msg_type, body: Bytes::copy_from_slice(body) };
+        self.framed.send(frame).await.is_ok()
+    }
+
+    /// Raw bytes, for frames the codec wouldn't produce.
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        use tokio::io::AsyncWriteExt;
+        self.framed.get_mut().write_all(bytes).await.expect("send bytes");
+    }
+
+    /// The next frame, whatever it is. Panics if nothing comes or the connection closes.
+    pub async fn recv(&mut self) -> Reply {
+        let frame = tokio::time::timeout(REPLY_TIMEOUT, self.framed.next())
+            .await
+            .expect("no reply from engine")
+            .expect("engine closed the connection")
+            .expect("read frame")
+            .expect("well-formed frame");
+        if frame.msg_type == MSG_NACK {
+            let ref_msg_type = ByteOrder::Little.read_u16(&frame.body[0..2]);
+            let reason = RejectReason::from_code(ByteOrder::Little.read_u16(&frame.body[2..4])).expect("NACK reason");
+            return Reply::Nack { ref_msg_type, reason };
+        }
+        let evt = decode_event(frame.msg_type, &frame.body, ByteOrder::Little)
+            .unwrap_or_else(|| panic!("undecodable msg_type={}", frame.msg_type));
+        Reply::Event(evt)
+    }
+
+    /// The next event, panicking on a NACK.
+    pub async fn event(&mut self) -> Event {
+        match self.recv().await {
+            Reply::Event(evt) => evt,
+            nack => panic!("expected an event, got {nack:?}"),
+        }
+    }
+
+    /// The next event `pick` accepts, skipping the ones it doesn't (market data, say).
+    pub async fn event_where<T>(&mut self, mut pick: impl FnMut(Event) -> Option<T>) -> T {
+        loop {
+            if let Some(t) = pick(self.event().await) {
+                return t;
+            }
+        }
+    }
+
+    /// The next reply, which has to be a NACK; returns its reason.
+    pub async fn nack(&mut self, msg_type: u16) -> RejectReason {
+        match self.recv().await {
+            Reply::Nack { ref_msg_type, reason } => {
+                assert_eq!(ref_msg_type, msg_type, "NACK for another message");
+                reason
+            }
+            evt => panic!("expected a NACK, got {evt:?}"),
+        }
+    }
+
+    /// Whether the engine hangs up (EOF or reset) before sending anything else.
+    pub async fn closed(&mut self) -> bool {
+        match tokio::time::timeout(REPLY_TIMEOUT, self.framed.next()).await.expect("engine neither replied nor closed") {
+            None | Some(Err(_)) => true,
+            Some(Ok(_)) => false,
+        }
+    }
+
+    /// Whether the engine hangs up once whatever it had already sent has been read.
+    pub async fn hung_up(&mut self) -> bool {
+        tokio::time::timeout(REPLY_TIMEOUT, async { while let Some(Ok(_)) = self.framed.next().await {} }).await.is_ok()
+    }
+
+    /// Nothing arrives within `wait`.
+    pub async fn quiet(&mut self, wait: Duration) -> bool {
+        tokio::time::timeout(wait, self.framed.next()).await.is_err()
+    }
+
+    /// Run the ADMIN_AUTH challenge with `token`; returns how the answer went.
+    pub async fn admin_auth(&mut self, token: &str) -> Reply {
+        self.send(MSG_ADMIN_AUTH, &[]).await;
+        let Reply::Event(Event::AdminChallenge { nonce }) = self.recv().await else {
+            panic!("expected ADMIN_CHALLENGE");
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC takes keys of any length");
+        mac.update(&nonce);
+        self.send(MSG_ADMIN_AUTH, &mac.finalize().into_bytes()).await;
+        self.recv().await
+    }
+
+    /// REGISTER `cl_id` here and wait for its ACK.
+    pub async fn register(&mut self, cl_id: u64) {
+        self.send(MSG_REGISTER, &cl_id.to_le_bytes()).await;
+        self.event_where(|e| matches!(e, Event::Ack { .. }).then_some(())).await;
+    }
+
+    /// REGISTER `cl_id` once the connection that held it has been noticed gone, as a reconnecting client does.
+    pub async fn reclaim(&mut self, cl_id: u64) {
+        let deadline = tokio::time::Instant::now() + REPLY_TIMEOUT;
+        loop {
+            self.send(MSG_REGISTER, &cl_id.to_le_bytes()).await;
+            match self.recv().await {
+                Reply::Event(Event::Ack { .. }) => return,
+                Reply::Nack { ref_msg_type: MSG_REGISTER, reason: RejectReason::Unauthorized } if tokio::time::Instant::now() < deadline => {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                other => panic!("REGISTER {cl_id}: {other:?}"),
+            }
+        }
+    }
+
+    /// Send a plain GTC limit order and wait for the engine to finish matching it.
+    pub async fn limit(&mut self, cl_id: u64, ord_id: u64, symbol: u32, side: Side, price: i64, qty: i64) -> Accepted {
+        self.send(MSG_NEW_ORDER, &NewOrder::limit(cl_id, ord_id, symbol, side, price, qty).body()).await;
+        self.accepted(ord_id).await
+    }
+
+    /// Skip to the ACCEPTED of `ord_id`; panics on a REJECT or NACK on the way.
+    pub async fn accepted(&mut self, ord_id: u64) -> Accepted {
+        loop {
+            match self.event().await {
+                Event::Accepted { ord_id: id, exch_ord_id, filled_qty, resting_qty, .. } if id == ord_id => {
+                    return Accepted { exch_ord_id, filled_qty, resting_qty };
+                }
+                Event::Reject { ord_id: id, reason } if id == ord_id => panic!("order {ord_id} rejected: {reason:?}"),
+                _ => {}
+            }
+        }
+    }
+
+    /// Skip to the REJECT of `ord_id`; panics if it is accepted instead.
+    pub async fn rejected(&mut self, ord_id: u64) -> RejectReason {
+        loop {
+            match self.event().await {
+                Event::Reject { ord_id: id, reason } if id == ord_id => return reason,
+                Event::Accepted { ord_id: id, .. } if id == ord_id => panic!("order {ord_id} accepted"),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accepted {
+    pub exch_ord_id: u64,
+    pub filled_qty: u64,
+    pub resting_qty: u64,
+}
+
+/// HELLO body for this protocol version.
+pub fn hello(flags: u8) -> Vec<u8> {
+    let mut body = PROTO_VERSION.to_le_bytes().to_vec();
+    body.push(flags);
+    body
+}
+
+/// A NEW_ORDER body with every optional field present.
+#[derive(Debug, Clone, Copy)]
+pub struct NewOrder {
+    pub cl_id: u64,
+    pub ord_id: u64,
+    pub symbol: u32,
+    pub side: u8,
+    pub price: i64,
+    pub qty: i64,
+    pub tif: u8,
+    pub ord_type: u8,
+    pub flags: u8,
+    pub stp: u8,
+    pub expire_ms: u64,
+    pub display_qty: u64,
+    pub stop_price: i64,
+}
+
+impl NewOrder {
+    pub fn limit(cl_id: u64, ord_id: u64, symbol: u32, side: Side, price: i64, qty: i64) -> NewOrder {
+        NewOrder { cl_id, ord_id, symbol, side: side as u8, price, qty, tif: 0, ord_type: 0, flags: 0, stp: 0, expire_ms: 0, display_qty: 0, stop_price: 0 }
+    }
+
+    pub fn body(&self) -> Vec<u8> {
+        let mut b = BytesMut::with_capacity(BATCH_ORDER_LEN);
+        b.put_u64_le(self.cl_id);
+        b.put_u64_le(self.ord_id);
+        b.put_u32_le(self.symbol);
+        b.put_u8(self.side);
+        b.put_i64_le(self.price);
+        b.put_i64_le(self.qty);
+        b.put_u8(self.tif);
+        b.put_u8(self.ord_type);
+        b.put_u8(self.flags);
+        b.put_u8(self.stp);
+        b.put_u64_le(self.expire_ms);
+        b.put_u64_le(self.display_qty);
+        b.put_i64_le(self.stop_price);
+        b.to_vec()
+    }
+}
+
+/// `[u64 client_id][u64 cl_ord_id][u32 symbol_id]`, the head of CANCEL, REPLACE and REDUCE.
+pub fn order_ref(cl_id: u64, ord_id: u64, symbol: u32) -> Vec<u8> {
+    let mut b = Vec::with_capacity(20);
+    b.extend_from_slice(&cl_id.to_le_bytes());
+    b.extend_from_slice(&ord_id.to_le_bytes());
+    b.extend_from_slice(&symbol.to_le_bytes());
+    b
+}
+
+/// SUBSCRIBE to the market-data feed, unconflated.
+pub async fn subscribe(c: &mut Client) {
+    c.send(MSG_SUBSCRIBE, &[0, 0, 0]).await;
+    c.event_where(|e| matches!(e, Event::Ack { .. }).then_some(())).await;
+}
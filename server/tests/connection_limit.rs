@@ -0,0 +1,36 @@
+mod common;
+
+use std::time::Duration;
+use clob_engine::protocol::{MSG_HELLO, MSG_PING};
+use clob_engine::types::{Event, RejectReason};
+use common::{hello, Engine, Reply};
+
+#[tokio::test]
+async fn connection_past_the_limit_is_refused_until_one_closes() {
+    let engine = Engine::start(&[("MAX_CONNECTIONS", "2")]);
+    let first = engine.connect().await;
+    let mut second = engine.connect().await;
+
+    // The third is told why, before it says anything, and hung up on
+    let mut third = engine.connect_raw().await;
+    assert_eq!(third.nack(0).await, RejectReason::ServerFull);
+    assert!(third.closed().await);
+    // The two inside carry on
+    second.send(MSG_PING, &[]).await;
+    assert!(matches!(second.event().await, Event::Pong));
+
+    // Its slot frees once the server has seen the close
+    drop(first);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let mut next = engine.connect_raw().await;
+        next.send(MSG_HELLO, &hello(0)).await;
+        match next.recv().await {
+            Reply::Event(Event::HelloAck { .. }) => break,
+            Reply::Nack { reason: RejectReason::ServerFull, .. } if tokio::time::Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            other => panic!("expected a slot to free up, got {other:?}"),
+        }
+    }
+}
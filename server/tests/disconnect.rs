@@ -0,0 +1,42 @@
+mod common;
+
+use std::time::Duration;
+use clob_engine::protocol::{MSG_BOOK_SNAPSHOT, MSG_HELLO};
+use clob_engine::types::{Event, Side};
+use common::{hello, Client, Engine};
+
+/// Bid levels of `symbol` as the engine has them now.
+async fn bids(c: &mut Client, symbol: u32) -> Vec<(i64, u64)> {
+    let mut body = symbol.to_le_bytes().to_vec();
+    body.extend_from_slice(&10u16.to_le_bytes());
+    c.send(MSG_BOOK_SNAPSHOT, &body).await;
+    c.event_where(|e| if let Event::Snapshot { bids, .. } = e { Some(bids) } else { None }).await
+}
+
+#[tokio::test]
+async fn resting_orders_go_with_a_cancel_on_disconnect_connection() {
+    let engine = Engine::start(&[]);
+    let mut opted_in = engine.connect().await;
+    opted_in.send(MSG_HELLO, &hello(0x01)).await; // CANCEL_ON_DISCONNECT
+    opted_in.event_where(|e| matches!(e, Event::HelloAck { .. }).then_some(())).await;
+    opted_in.limit(1, 1, 0, Side::Bid, 100, 10).await;
+    opted_in.limit(1, 2, 0, Side::Bid, 99, 10).await;
+    let mut plain = engine.connect().await;
+    plain.limit(2, 1, 0, Side::Bid, 98, 10).await;
+
+    let mut watcher = engine.connect().await;
+    assert_eq!(bids(&mut watcher, 0).await, [(100, 10), (99, 10), (98, 10)]);
+    drop(opted_in);
+    drop(plain);
+
+    // The disconnect is noticed asynchronously; only the opted-in client's orders go
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let left = bids(&mut watcher, 0).await;
+        if left == [(98, 10)] {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "still resting: {left:?}");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use clob_engine::protocol::*;
+use clob_engine::types::{AckNote, Event, RejectReason, Side};
+use common::{Engine, NewOrder, Reply};
+
+const TOKEN: &str = "s3cret";
+
+#[tokio::test]
+async fn drop_copy_sees_other_clients_acks_rejects_and_trades() {
+    let engine = Engine::start(&[("ADMIN_TOKEN", TOKEN)]);
+    let mut desk = engine.connect().await;
+    assert!(matches!(desk.admin_auth(TOKEN).await, Reply::Event(Event::Ack { note: AckNote::Admin, .. })));
+    desk.send(MSG_DROPCOPY_SUBSCRIBE, &[]).await;
+    assert!(matches!(desk.event().await, Event::Ack { note: AckNote::DropCopy, .. }));
+
+    let mut a = engine.connect().await;
+    let mut b = engine.connect().await;
+    a.limit(1, 1, 0, Side::Ask, 100, 10).await;
+    a.send(MSG_NEW_ORDER, &NewOrder::limit(1, 1, 0, Side::Ask, 101, 10).body()).await;
+    assert_eq!(a.rejected(1).await, RejectReason::DupOrderId);
+    b.limit(2, 1, 0, Side::Bid, 100, 4).await;
+
+    let (mut accepted, mut rejected, mut traded, mut taker_filled) = (false, false, false, false);
+    while !(accepted && rejected && traded && taker_filled) {
+        match desk.event().await {
+            Event::DropCopy { cl_id: 1, evt } => match *evt {
+                Event::Accepted { ord_id: 1, resting_qty: 10, .. } => accepted = true,
+                Event::Reject { ord_id: 1, reason: RejectReason::DupOrderId } => rejected = true,
+                _ => {}
+            },
+            Event::DropCopy { cl_id: 2, evt } => {
+                taker_filled |= matches!(*evt, Event::Accepted { ord_id: 1, filled_qty: 4, resting_qty: 0, .. });
+            }
+            Event::Trade(t) => {
+                assert_eq!((t.maker_cl_id, t.taker_cl_id, t.price, t.qty), (1, 2, 100, 4));
+                traded = true;
+            }
+            _ => {}
+        }
+    }
+}
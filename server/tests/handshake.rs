@@ -0,0 +1,48 @@
+mod common;
+
+use clob_engine::protocol::{MSG_HELLO, MSG_PING, PROTO_VERSION, PROTO_VERSION_MIN};
+use clob_engine::types::{Event, RejectReason};
+use common::{hello, Engine};
+
+#[tokio::test]
+async fn matching_version_is_acked() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect_raw().await;
+    c.send(MSG_HELLO, &hello(0)).await;
+    assert!(matches!(c.event().await, Event::HelloAck { version: PROTO_VERSION }));
+    c.send(MSG_PING, &[]).await;
+    assert!(matches!(c.event().await, Event::Pong));
+}
+
+#[tokio::test]
+async fn newer_client_is_talked_down_to_ours() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect_raw().await;
+    c.send(MSG_HELLO, &hello_version(PROTO_VERSION + 1)).await;
+    assert!(matches!(c.event().await, Event::HelloAck { version: PROTO_VERSION }));
+}
+
+#[tokio::test]
+async fn version_below_the_minimum_is_rejected_and_closed() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect_raw().await;
+    c.send(MSG_HELLO, &hello_version(PROTO_VERSION_MIN - 1)).await;
+    assert!(matches!(c.event().await, Event::Reject { ord_id: 0, reason: RejectReason::UnsupportedVersion }));
+    assert!(c.closed().await);
+}
+
+#[tokio::test]
+async fn anything_before_hello_is_nacked_and_closed() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect_raw().await;
+    c.send(MSG_PING, &[]).await;
+    assert_eq!(c.nack(MSG_PING).await, RejectReason::HelloRequired);
+    assert!(c.closed().await);
+}
+
+/// HELLO body offering `version`, no flags.
+fn hello_version(version: u16) -> Vec<u8> {
+    let mut body = version.to_le_bytes().to_vec();
+    body.push(0);
+    body
+}
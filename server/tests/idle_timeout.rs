@@ -0,0 +1,20 @@
+mod common;
+
+use std::time::Duration;
+use clob_engine::protocol::MSG_PING;
+use clob_engine::types::Event;
+use common::Engine;
+
+#[tokio::test]
+async fn a_ping_then_silence_times_out() {
+    let engine = Engine::start(&[("IDLE_TIMEOUT_SECS", "1")]);
+    let mut c = engine.connect().await;
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    c.send(MSG_PING, &[]).await;
+    assert!(matches!(c.event().await, Event::Pong));
+
+    // The PING pushed the deadline out, so the connection outlives a second from connecting
+    assert!(c.quiet(Duration::from_millis(600)).await);
+    // Then nothing more comes in and it is dropped
+    assert!(c.closed().await);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::net::TcpStream;
+use common::Engine;
+
+/// A `json_gateway` process in front of an engine, killed on drop.
+struct Gateway {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Gateway {
+    fn start(engine: &Engine) -> Gateway {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_json_gateway"))
+            .env_clear()
+            .env("JSON_ADDR", "127.0.0.1:0")
+            .env("ENGINE_ADDR", engine.addr.to_string())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn json_gateway");
+        let mut lines = BufReader::new(child.stdout.take().expect("piped stdout")).lines();
+        let addr = loop {
+            let line = lines.next().expect("gateway exited before listening").expect("gateway stdout");
+            if let Some(rest) = line.trim().strip_prefix("🚀 JSON gateway listening on ") {
+                break rest.split(' ').next().unwrap().parse().expect("gateway address");
+            }
+        };
+        std::thread::spawn(move || lines.for_each(drop));
+        Gateway { child, addr }
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn new_order_line_comes_back_accepted() {
+    let engine = Engine::start(&[]);
+    let gw = Gateway::start(&engine);
+    let (read, mut write) = TcpStream::connect(gw.addr).await.expect("connect to gateway").into_split();
+    let mut lines = AsyncBufReader::new(read).lines();
+    let mut next = async || -> Value {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line()).await.expect("no reply line").expect("read line").expect("gateway closed");
+        serde_json::from_str(&line).unwrap_or_else(|e| panic!("{e}: {line}"))
+    };
+
+    // The gateway says HELLO for us
+    assert_eq!(next().await["type"], "hello_ack");
+
+    let order = json!({"type": "new_order", "cl_id": 1, "ord_id": 7, "side": "bid", "price": 100, "qty": 10});
+    write.write_all(format!("{order}\n").as_bytes()).await.unwrap();
+    let accepted = loop {
+        let evt = next().await;
+        if evt["type"] == "accepted" {
+            break evt;
+        }
+    };
+    assert_eq!((accepted["ord_id"].as_u64(), accepted["resting_qty"].as_u64(), accepted["filled_qty"].as_u64()), (Some(7), Some(10), Some(0)));
+    assert!(accepted["exch_ord_id"].as_u64().unwrap() > 0);
+
+    // A line that isn't a request is answered in place, without reaching the engine
+    write.write_all(b"{\"type\":\"nope\"}\n").await.unwrap();
+    assert_eq!(next().await["type"], "error");
+}
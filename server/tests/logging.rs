@@ -0,0 +1,39 @@
+mod common;
+
+use clob_engine::types::Side;
+use common::Engine;
+
+/// The `session=` field of a log line.
+fn session_of(line: &str) -> Option<&str> {
+    line.split(' ').find_map(|field| field.strip_prefix("session="))
+}
+
+#[tokio::test]
+async fn one_connections_lines_share_its_session_id() {
+    let engine = Engine::start(&[]);
+    let mut a = engine.connect().await;
+    let mut b = engine.connect().await;
+    a.limit(1, 11, 0, Side::Bid, 100, 10).await;
+    b.limit(2, 22, 0, Side::Ask, 100, 4).await;
+    a.limit(1, 33, 0, Side::Bid, 99, 10).await;
+
+    let lines = engine.logged(|l| l.contains("New Order") && l.contains(" id=33 ")).await;
+
+    // Every gateway line about a connection carries one session id, and a different one per connection
+    let mut sessions = Vec::new();
+    for c in [&a, &b] {
+        let peer = format!("peer={}", c.local_addr());
+        let mine: Vec<_> = lines.iter().filter(|l| l.ends_with(&peer)).collect();
+        assert!(mine.len() >= 3, "{peer}: {lines:#?}");
+        let session = session_of(mine[0]).expect("session field");
+        assert!(mine.iter().all(|l| session_of(l) == Some(session)), "{mine:#?}");
+        sessions.push(session);
+    }
+    assert_ne!(sessions[0], sessions[1]);
+
+    // And so does the engine's line about each order it sent
+    for (ord_id, session) in [(11, sessions[0]), (22, sessions[1]), (33, sessions[0])] {
+        let line = lines.iter().find(|l| l.contains("New Order") && l.contains(&format!(" id={ord_id} "))).expect("engine line");
+        assert_eq!(session_of(line), Some(session), "{line}");
+    }
+}
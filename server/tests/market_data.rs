@@ -0,0 +1,124 @@
+mod common;
+
+use std::collections::BTreeMap;
+use clob_engine::protocol::{MSG_BOOK_SNAPSHOT, MSG_NEW_ORDER, MSG_SUBSCRIBE};
+use clob_engine::types::{Event, Side};
+use common::{subscribe, Client, Engine, NewOrder};
+
+#[tokio::test]
+async fn every_subscriber_sees_a_third_clients_trade() {
+    let engine = Engine::start(&[]);
+    let mut watchers = [engine.connect().await, engine.connect().await];
+    for w in &mut watchers {
+        subscribe(w).await;
+    }
+
+    let mut trader = engine.connect().await;
+    trader.limit(1, 1, 0, Side::Ask, 100, 10).await;
+    trader.send(MSG_NEW_ORDER, &NewOrder::limit(2, 1, 0, Side::Bid, 100, 4).body()).await;
+    trader.accepted(1).await;
+
+    for w in &mut watchers {
+        let trade = w.event_where(|e| if let Event::Trade(t) = e { Some(t) } else { None }).await;
+        assert_eq!((trade.price, trade.qty, trade.taker_cl_id, trade.maker_cl_id), (100, 4, 2, 1));
+    }
+}
+
+#[tokio::test]
+async fn snapshot_sums_each_level_best_first() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+    for (ord_id, side, price, qty) in [
+        (1, Side::Bid, 99, 5),
+        (2, Side::Bid, 99, 7),
+        (3, Side::Bid, 98, 4),
+        (4, Side::Bid, 97, 1),
+        (5, Side::Ask, 101, 3),
+        (6, Side::Ask, 101, 2),
+        (7, Side::Ask, 101, 1),
+        (8, Side::Ask, 102, 9),
+    ] {
+        c.limit(1, ord_id, 3, side, price, qty).await;
+    }
+
+    // Two levels deep: the bid at 97 is left out
+    let mut body = 3u32.to_le_bytes().to_vec();
+    body.extend_from_slice(&2u16.to_le_bytes());
+    c.send(MSG_BOOK_SNAPSHOT, &body).await;
+    let (bids, asks) = c.event_where(|e| if let Event::Snapshot { symbol: 3, bids, asks, .. } = e { Some((bids, asks)) } else { None }).await;
+    assert_eq!(bids, [(99, 12), (98, 4)]);
+    assert_eq!(asks, [(101, 6), (102, 9)]);
+}
+
+/// `BOOK_SNAPSHOT` of every level of `symbol`, as `(bids, asks, seq)`.
+async fn full_snapshot(c: &mut Client, symbol: u32) -> (Vec<(i64, u64)>, Vec<(i64, u64)>, u64) {
+    let mut body = symbol.to_le_bytes().to_vec();
+    body.extend_from_slice(&u16::MAX.to_le_bytes());
+    c.send(MSG_BOOK_SNAPSHOT, &body).await;
+    c.event_where(|e| if let Event::Snapshot { bids, asks, seq, .. } = e { Some((bids, asks, seq)) } else { None }).await
+}
+
+/// Snapshot sides as one map of level quantities.
+fn by_level(bids: Vec<(i64, u64)>, asks: Vec<(i64, u64)>) -> BTreeMap<(Side, i64), u64> {
+    let bids = bids.into_iter().map(|(px, qty)| ((Side::Bid, px), qty));
+    bids.chain(asks.into_iter().map(|(px, qty)| ((Side::Ask, px), qty))).collect()
+}
+
+#[tokio::test]
+async fn subscriber_joining_mid_trading_ends_up_with_the_engines_book() {
+    let engine = Engine::start(&[]);
+    let mut trader = engine.connect().await;
+    // Orders around 100, half of them crossing something
+    let mut x = 0x2545_f491_4f6c_dd1du64;
+    let mut next_order = move |ord_id: u64| {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        let side = if x & 1 == 0 { Side::Bid } else { Side::Ask };
+        NewOrder::limit(1 + (x >> 8) % 3, ord_id, 0, side, 95 + ((x >> 16) % 11) as i64, 1 + ((x >> 24) % 10) as i64)
+    };
+    for ord_id in 1..=50 {
+        trader.send(MSG_NEW_ORDER, &next_order(ord_id).body()).await;
+    }
+    trader.accepted(50).await;
+
+    // Keep trading while a subscriber anchors to a snapshot, and carry on once it has
+    let (anchored, wait_for_anchor) = tokio::sync::oneshot::channel();
+    let trading = tokio::spawn(async move {
+        for ord_id in 51..=150 {
+            trader.send(MSG_NEW_ORDER, &next_order(ord_id).body()).await;
+        }
+        wait_for_anchor.await.unwrap();
+        for ord_id in 151..=250 {
+            trader.send(MSG_NEW_ORDER, &next_order(ord_id).body()).await;
+        }
+        trader.accepted(250).await;
+        trader
+    });
+    let mut sub = engine.connect().await;
+    let mut body = 0u16.to_le_bytes().to_vec();
+    body.push(0x02); // Anchor to a snapshot
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&u16::MAX.to_le_bytes());
+    sub.send(MSG_SUBSCRIBE, &body).await;
+    let (bids, asks, mut seq) = sub.event_where(|e| if let Event::Snapshot { bids, asks, seq, .. } = e { Some((bids, asks, seq)) } else { None }).await;
+    let mut book = by_level(bids, asks);
+    anchored.send(()).unwrap();
+
+    let mut trader = trading.await.unwrap();
+    let (bids, asks, last) = full_snapshot(&mut trader, 0).await;
+    while seq < last {
+        let mut evt = sub.event().await;
+        let Some(&mut s) = evt.seq_mut() else { continue };
+        assert_eq!(s, seq + 1, "gap in the feed");
+        seq = s;
+        if let Event::BookDelta { side, price, level_qty, .. } = evt {
+            if level_qty == 0 {
+                book.remove(&(side, price));
+            } else {
+                book.insert((side, price), level_qty);
+            }
+        }
+    }
+    assert_eq!(book, by_level(bids, asks));
+}
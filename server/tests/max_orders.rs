@@ -0,0 +1,33 @@
+mod common;
+
+use clob_engine::protocol::{MSG_CANCEL, MSG_NEW_ORDER};
+use clob_engine::types::{AckNote, DoneReason, Event, RejectReason, Side};
+use common::{order_ref, Engine, NewOrder};
+
+#[tokio::test]
+async fn cap_counts_every_symbol_and_pending_stops_and_a_cancel_frees_a_slot() {
+    let engine = Engine::start(&[("RISK_LIMITS", "1=::2")]);
+    let mut c = engine.connect().await;
+
+    // One resting on symbol 0, one stop waiting on symbol 1 (nothing has traded there)
+    c.limit(1, 1, 0, Side::Bid, 100, 10).await;
+    let stop = NewOrder { ord_type: 2, stop_price: 150, ..NewOrder::limit(1, 2, 1, Side::Bid, 0, 5) };
+    c.send(MSG_NEW_ORDER, &stop.body()).await;
+    assert!(matches!(c.event().await, Event::Ack { ord_id: 2, note: AckNote::StopPending }));
+
+    // The third, on a symbol it has nothing on yet, is over the cap
+    c.send(MSG_NEW_ORDER, &NewOrder::limit(1, 3, 2, Side::Ask, 200, 1).body()).await;
+    assert_eq!(c.rejected(3).await, RejectReason::MaxOrders);
+    // An IOC never rests, so it isn't capped; with nothing to hit it rests nothing
+    c.send(MSG_NEW_ORDER, &NewOrder { tif: 1, ..NewOrder::limit(1, 4, 2, Side::Ask, 200, 1) }.body()).await;
+    assert_eq!(c.accepted(4).await.resting_qty, 0);
+    // Other clients have no cap
+    c.limit(2, 1, 2, Side::Ask, 200, 1).await;
+
+    // Pulling the stop frees its slot
+    c.send(MSG_CANCEL, &order_ref(1, 2, 1)).await;
+    assert!(matches!(c.event().await, Event::Done { ord_id: 2, reason: DoneReason::Canceled }));
+    c.limit(1, 5, 2, Side::Ask, 201, 1).await;
+    c.send(MSG_NEW_ORDER, &NewOrder::limit(1, 6, 0, Side::Bid, 99, 1).body()).await;
+    assert_eq!(c.rejected(6).await, RejectReason::MaxOrders);
+}
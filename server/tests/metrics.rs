@@ -0,0 +1,78 @@
+mod common;
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+use clob_engine::protocol::{MSG_CANCEL, MSG_NEW_ORDER};
+use clob_engine::types::{Event, Side};
+use common::{http_get, order_ref, Engine, NewOrder};
+
+/// Parse a Prometheus text exposition, checking its shape on the way: every sample is
+/// `name[{labels}] value`, with a numeric value and its `# HELP` and `# TYPE` lines ahead of it.
+fn parse_exposition(text: &str) -> BTreeMap<String, f64> {
+    let (mut helped, mut typed) = (HashSet::new(), HashSet::new());
+    let mut samples = BTreeMap::new();
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            let (name, help) = rest.split_once(' ').expect("HELP without text");
+            assert!(!help.is_empty(), "{line}");
+            helped.insert(name.to_owned());
+        } else if let Some(rest) = line.strip_prefix("# TYPE ") {
+            let (name, kind) = rest.split_once(' ').expect("TYPE without kind");
+            assert!(["counter", "gauge"].contains(&kind), "{line}");
+            assert!(typed.insert(name.to_owned()), "{name} typed twice");
+        } else {
+            let (series, value) = line.rsplit_once(' ').unwrap_or_else(|| panic!("malformed sample {line:?}"));
+            let name = series.split('{').next().unwrap();
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "{line}");
+            if let Some(labels) = series.strip_prefix(name).filter(|l| !l.is_empty()) {
+                assert!(labels.starts_with('{') && labels.ends_with('}') && labels.contains("=\""), "{line}");
+            }
+            assert!(helped.contains(name) && typed.contains(name), "{name} has no HELP/TYPE ahead of it");
+            let value = value.parse().unwrap_or_else(|_| panic!("non-numeric value in {line:?}"));
+            assert!(samples.insert(series.to_owned(), value).is_none(), "{series} sampled twice");
+        }
+    }
+    samples
+}
+
+#[tokio::test]
+async fn metrics_are_well_formed_and_count_the_traffic() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+    c.limit(1, 1, 1, Side::Bid, 100, 10).await;
+    c.limit(1, 2, 1, Side::Bid, 99, 10).await;
+    c.limit(1, 3, 2, Side::Bid, 50, 10).await;
+    c.limit(2, 1, 1, Side::Ask, 100, 4).await;
+    c.send(MSG_NEW_ORDER, &NewOrder::limit(1, 2, 1, Side::Bid, 98, 10).body()).await;
+    c.rejected(2).await;
+    c.send(MSG_CANCEL, &order_ref(1, 2, 1)).await;
+    c.event_where(|e| matches!(e, Event::Done { ord_id: 2, .. }).then_some(())).await;
+
+    let expected = [
+        ("clob_orders_accepted_total", 4.0),
+        ("clob_orders_rejected_total", 1.0),
+        ("clob_orders_canceled_total", 1.0),
+        ("clob_trades_total", 1.0),
+        ("clob_traded_volume_total", 4.0),
+        ("clob_slow_consumers_total", 0.0),
+        ("clob_resting_orders{symbol=\"1\"}", 1.0),
+        ("clob_resting_orders{symbol=\"2\"}", 1.0),
+    ];
+    // Resting counts are refreshed once the engine's batch is over, just after the replies went out
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let (status, body) = http_get(engine.metrics_addr, "/metrics").await;
+        assert_eq!(status, 200);
+        let samples = parse_exposition(&body);
+        assert!(samples.contains_key("clob_command_queue_depth"), "{body}");
+        let got: Vec<_> = expected.iter().map(|(name, _)| (*name, samples.get(*name).copied().unwrap_or(-1.0))).collect();
+        if got == expected {
+            break;
+        }
+        assert!(Instant::now() < deadline, "{got:?}\n{body}");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let (status, _) = http_get(engine.metrics_addr, "/nope").await;
+    assert_eq!(status, 404);
+}
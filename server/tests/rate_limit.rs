@@ -0,0 +1,59 @@
+mod common;
+
+use clob_engine::protocol::{MSG_BATCH_NEW, MSG_CANCEL_ALL, MSG_NEW_ORDER};
+use clob_engine::types::{Event, RejectReason, Side};
+use common::{Engine, NewOrder};
+
+#[tokio::test]
+async fn orders_past_the_burst_are_nacked() {
+    let engine = Engine::start(&[("RATE_LIMIT_PER_SEC", "1"), ("RATE_LIMIT_BURST", "1")]);
+    let mut c = engine.connect().await;
+
+    c.limit(1, 1, 0, Side::Bid, 100, 10).await;
+    c.send(MSG_NEW_ORDER, &NewOrder::limit(1, 2, 0, Side::Bid, 100, 10).body()).await;
+    assert_eq!(c.nack(MSG_NEW_ORDER).await, RejectReason::RateLimited);
+}
+
+#[tokio::test]
+async fn cancel_all_is_metered_too() {
+    let engine = Engine::start(&[("RATE_LIMIT_PER_SEC", "1"), ("RATE_LIMIT_BURST", "1")]);
+    let mut c = engine.connect().await;
+    c.register(1).await; // Not metered
+
+    c.send(MSG_CANCEL_ALL, &1u64.to_le_bytes()).await;
+    c.event_where(|e| matches!(e, Event::CancelAllDone { .. }).then_some(())).await;
+    c.send(MSG_CANCEL_ALL, &1u64.to_le_bytes()).await;
+    assert_eq!(c.nack(MSG_CANCEL_ALL).await, RejectReason::RateLimited);
+}
+
+#[tokio::test]
+async fn each_order_of_a_batch_takes_a_token() {
+    let engine = Engine::start(&[("RATE_LIMIT_PER_SEC", "1"), ("RATE_LIMIT_BURST", "2")]);
+    let mut c = engine.connect().await;
+
+    let mut body = 3u16.to_le_bytes().to_vec();
+    for ord_id in 1..=3 {
+        body.extend_from_slice(&NewOrder::limit(1, ord_id, 0, Side::Bid, 100, 10).body());
+    }
+    c.send(MSG_BATCH_NEW, &body).await;
+    let results = c.event_where(|e| if let Event::BatchAck { results } = e { Some(results) } else { None }).await;
+    assert_eq!(results, [(1, None), (2, None), (3, Some(RejectReason::RateLimited))]);
+    c.accepted(2).await;
+    // The bucket is empty for single orders as well
+    c.send(MSG_NEW_ORDER, &NewOrder::limit(1, 4, 0, Side::Bid, 100, 10).body()).await;
+    assert_eq!(c.nack(MSG_NEW_ORDER).await, RejectReason::RateLimited);
+}
+
+#[tokio::test]
+async fn zero_or_garbage_limits_fall_back_to_the_default() {
+    for (var, value) in [("RATE_LIMIT_PER_SEC", "0"), ("RATE_LIMIT_BURST", "0"), ("RATE_LIMIT_PER_SEC", "fast")] {
+        let engine = Engine::start(&[(var, value)]);
+        engine.logged(|l| l.contains(&format!("{var}={value:?} is not a valid setting, using the default 1000"))).await;
+        // 1000/s with as big a burst: a quick run of orders all goes through
+        let mut c = engine.connect().await;
+        for ord_id in 1..=20 {
+            c.send(MSG_NEW_ORDER, &NewOrder::limit(1, ord_id, 0, Side::Bid, 100, 1).body()).await;
+        }
+        c.accepted(20).await;
+    }
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use clob_engine::types::Side;
+use common::Engine;
+
+#[tokio::test]
+async fn bad_settings_fall_back_to_their_defaults_with_a_warning() {
+    let bad = [
+        ("SNAPSHOT_SECS", "soon", "60"),
+        ("DRAIN_BATCH", "0", "256"),
+        ("RESYNC_DEPTH", "-1", "1024"),
+        ("BOOK_LADDER_LEVELS", "lots", "0"),
+        ("IDLE_TIMEOUT_SECS", "1.5", "30"),
+        ("SLOW_CONSUMER_SENDS", "0", "64"),
+        ("CANDLE_SECS", "", "60"),
+        ("MAX_CONNECTIONS", "0", "1024"),
+        ("SEQUENCE_WINDOW_US", "-5", "0"),
+        ("CMD_CAP", "0", "10000"),
+        ("CONN_EVT_CAP", "99999999999", "2048"),
+    ];
+    let env: Vec<_> = bad.iter().map(|&(var, value, _)| (var, value)).collect();
+    let engine = Engine::start(&env);
+    for (var, value, default) in bad {
+        let warning = format!("WARN {var}={value:?} is not a valid setting, using the default {default}");
+        engine.logged(|l| l.contains(&warning)).await;
+    }
+
+    // And the engine runs on the defaults
+    let mut c = engine.connect().await;
+    c.limit(1, 1, 0, Side::Bid, 100, 10).await;
+    assert_eq!(c.limit(2, 1, 0, Side::Ask, 100, 4).await.filled_qty, 4);
+}
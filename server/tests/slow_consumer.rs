@@ -0,0 +1,38 @@
+mod common;
+
+use std::time::{Duration, Instant};
+use clob_engine::protocol::MSG_QUERY_ORDERS;
+use clob_engine::types::Side;
+use common::{http_get, Engine};
+
+/// Current value of the `clob_slow_consumers_total` counter.
+async fn slow_consumers(engine: &Engine) -> u64 {
+    let (_, body) = http_get(engine.metrics_addr, "/metrics").await;
+    body.lines().find_map(|l| l.strip_prefix("clob_slow_consumers_total ")).and_then(|v| v.parse().ok()).expect("slow consumer counter")
+}
+
+#[tokio::test]
+async fn client_that_stops_reading_is_disconnected() {
+    let engine = Engine::start(&[("CONN_EVT_CAP", "4"), ("SLOW_CONSUMER_SENDS", "8")]);
+    let mut c = engine.connect().await;
+    c.register(1).await;
+    // Enough resting orders that every QUERY_ORDERS answer is a few kilobytes
+    for ord_id in 1..=200 {
+        c.limit(1, ord_id, 0, Side::Bid, 100 - ord_id as i64 % 50, 1).await;
+    }
+
+    // Keep asking without reading a single answer until the socket, then the queue, backs up
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut sent = 0;
+    while slow_consumers(&engine).await == 0 {
+        assert!(Instant::now() < deadline, "never flagged after {sent} queries");
+        // A full socket stalls the send; a closed one fails it, and the counter is about to move
+        for _ in 0..100 {
+            match tokio::time::timeout(Duration::from_millis(50), c.try_send(MSG_QUERY_ORDERS, &1u64.to_le_bytes())).await {
+                Ok(true) => sent += 1,
+                Ok(false) | Err(_) => break,
+            }
+        }
+    }
+    assert!(c.hung_up().await);
+}
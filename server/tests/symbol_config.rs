@@ -0,0 +1,97 @@
+mod common;
+
+use std::path::PathBuf;
+use clob_engine::protocol::MSG_NEW_ORDER;
+use clob_engine::types::{Event, RejectReason, Side};
+use common::{scratch_dir, Engine, NewOrder};
+
+#[tokio::test]
+async fn each_symbol_checks_its_own_tick_and_lot() {
+    let engine = Engine::start(&[("TICK_LOT_SYMBOLS", "1=5:100,2=1:10")]);
+    let mut c = engine.connect().await;
+
+    c.limit(1, 1, 1, Side::Bid, 105, 200).await;
+    c.limit(1, 2, 2, Side::Bid, 103, 30).await;
+
+    for (ord_id, symbol, price, qty, reason) in [
+        (3, 1, 103, 200, RejectReason::BadTick),
+        (4, 1, 105, 150, RejectReason::BadLot),
+        (5, 2, 103, 35, RejectReason::BadLot),
+    ] {
+        c.send(MSG_NEW_ORDER, &NewOrder::limit(1, ord_id, symbol, Side::Bid, price, qty).body()).await;
+        assert_eq!(c.rejected(ord_id).await, reason, "order {ord_id}");
+    }
+}
+
+#[test]
+fn malformed_symbol_entries_stop_startup() {
+    let err = Engine::start_err(&[("TICK_LOT_SYMBOLS", "1=5")]);
+    assert!(err.contains("TICK_LOT_SYMBOLS entry \"1=5\""), "{err}");
+    let err = Engine::start_err(&[("MAX_DEPTH_SYMBOLS", "1=0")]);
+    assert!(err.contains("MAX_DEPTH_SYMBOLS entry \"1=0\""), "{err}");
+    let err = Engine::start_err(&[("FEE_SYMBOLS", "x=1:2")]);
+    assert!(err.contains("FEE_SYMBOLS entry \"x=1:2\": symbol"), "{err}");
+}
+
+/// A scratch directory holding `json` as a SYMBOL_CONFIG file; returns it and the file's path.
+fn config_file(json: &str) -> (PathBuf, String) {
+    let dir = scratch_dir();
+    let path = dir.join("symbols.json");
+    std::fs::write(&path, json).unwrap();
+    let path = path.to_str().unwrap().to_owned();
+    (dir, path)
+}
+
+/// (maker client, qty) of each fill a sell of 20 at 100 gets against bids of 10 (client 1) then 30 (client 2).
+async fn fills(engine: &Engine) -> Vec<(u64, i64)> {
+    let mut makers = engine.connect().await;
+    makers.limit(1, 1, 1, Side::Bid, 100, 10).await;
+    makers.limit(2, 1, 1, Side::Bid, 100, 30).await;
+    let mut taker = engine.connect().await;
+    taker.send(MSG_NEW_ORDER, &NewOrder::limit(3, 1, 1, Side::Ask, 100, 20).body()).await;
+    let mut fills = Vec::new();
+    loop {
+        match taker.event().await {
+            Event::Trade(t) => fills.push((t.maker_cl_id, t.qty as i64)),
+            Event::Accepted { ord_id: 1, .. } => break,
+            _ => {}
+        }
+    }
+    fills.sort_unstable();
+    fills
+}
+
+#[tokio::test]
+async fn same_flow_fills_differently_under_fifo_and_pro_rata() {
+    for (policy, expected) in [("fifo", [(1, 10), (2, 10)]), ("pro_rata", [(1, 5), (2, 15)])] {
+        let (dir, path) = config_file(&format!(r#"{{"symbols": [{{"symbol": 1, "policy": "{policy}"}}]}}"#));
+        let engine = Engine::start(&[("SYMBOL_CONFIG", &path)]);
+        assert_eq!(fills(&engine).await, expected, "{policy}");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+#[test]
+fn bad_or_conflicting_config_stops_startup() {
+    let start_err = |json: &str, env: &[(&str, &str)]| {
+        let (dir, path) = config_file(json);
+        let mut env = env.to_vec();
+        env.push(("SYMBOL_CONFIG", &path));
+        let err = Engine::start_err(&env);
+        let _ = std::fs::remove_dir_all(dir);
+        err
+    };
+
+    let err = start_err(r#"{"symbols": [{"symbol": 1, "policy": "lifo"}]}"#, &[]);
+    assert!(err.contains("unknown match policy \"lifo\""), "{err}");
+    let err = start_err(r#"{"symbols": [{"symbol": 1, "tick": "5:100"}]}"#, &[]);
+    assert!(err.contains("unknown field `tick`"), "{err}");
+    let err = start_err(r#"{"symbols": [{"symbol": 1}, {"symbol": 1}]}"#, &[]);
+    assert!(err.contains("symbol 1 is listed twice"), "{err}");
+    let err = start_err(r#"{"symbols": [{"symbol": 1, "policy": "fifo"}]}"#, &[("PRO_RATA_SYMBOLS", "1")]);
+    assert!(err.contains("symbol 1: policy is set more than once (again by PRO_RATA_SYMBOLS)"), "{err}");
+    let err = start_err(r#"{"symbols": [{"symbol": 1, "tick_lot": "5:100"}]}"#, &[("TICK_LOT_SYMBOLS", "1=1:10")]);
+    assert!(err.contains("symbol 1: tick_lot is set more than once (again by TICK_LOT_SYMBOLS)"), "{err}");
+    let err = start_err(r#"{"symbols": [{"symbol": 1, "tick_lot": "1:3", "qty_scale": "1e6"}]}"#, &[]);
+    assert!(err.contains("qty scale 1000000 is not a whole number of lots of 3"), "{err}");
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use clob_engine::protocol::MSG_NEW_ORDER;
+use clob_engine::types::{Event, Side};
+use common::{Engine, NewOrder};
+
+#[tokio::test]
+async fn symbols_match_independently() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+
+    // Would cross if they shared a book
+    c.limit(1, 1, 1, Side::Bid, 100, 10).await;
+    let ask = c.limit(2, 1, 2, Side::Ask, 99, 10).await;
+    assert_eq!((ask.filled_qty, ask.resting_qty), (0, 10));
+
+    // A sell on symbol 1 trades with symbol 1's bid only
+    c.send(MSG_NEW_ORDER, &NewOrder::limit(3, 1, 1, Side::Ask, 99, 4).body()).await;
+    let trade = c.event_where(|e| if let Event::Trade(t) = e { Some(t) } else { None }).await;
+    assert_eq!((trade.symbol, trade.price, trade.qty, trade.maker_cl_id), (1, 100, 4, 1));
+    assert_eq!(c.accepted(1).await.filled_qty, 4);
+
+    // And a buy on symbol 2 with symbol 2's ask
+    c.send(MSG_NEW_ORDER, &NewOrder::limit(3, 2, 2, Side::Bid, 100, 10).body()).await;
+    let trade = c.event_where(|e| if let Event::Trade(t) = e { Some(t) } else { None }).await;
+    assert_eq!((trade.symbol, trade.price, trade.qty, trade.maker_cl_id), (2, 99, 10, 2));
+}
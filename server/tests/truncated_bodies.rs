@@ -0,0 +1,102 @@
+mod common;
+
+use clob_engine::protocol::*;
+use clob_engine::types::{Event, RejectReason, Side};
+use common::{hello, order_ref, Engine};
+
+#[tokio::test]
+async fn short_bodies_are_nacked_and_the_connection_carries_on() {
+    let engine = Engine::start(&[("ADMIN_TOKEN", "s3cret")]);
+    let mut c = engine.connect().await;
+
+    let short: &[(u16, &[u8])] = &[
+        (MSG_CANCEL, &[0; 19]),
+        (MSG_CANCEL_ALL, &[0; 7]),
+        (MSG_REPLACE, &[0; 35]),
+        (MSG_REDUCE, &[0; 27]),
+        (MSG_BOOK_SNAPSHOT, &[0; 5]),
+        (MSG_REGISTER, &[0; 4]),
+        (MSG_QUERY_ORDERS, &[0; 4]),
+        (MSG_RESYNC, &[0; 15]),
+        (MSG_QUERY_STATS, &[0; 3]),
+        (MSG_SUBSCRIBE, &[0, 0, 0x02, 0]), // SUBSCRIBE_SNAPSHOT without its symbol and depth
+        (MSG_ADMIN_AUTH, &[0; 5]),
+    ];
+    for &(msg_type, body) in short {
+        c.send(msg_type, body).await;
+        assert_eq!(c.nack(msg_type).await, RejectReason::BadLength, "msg_type={msg_type}");
+    }
+    c.limit(1, 1, 0, Side::Bid, 100, 10).await;
+}
+
+#[tokio::test]
+async fn body_len_past_the_frame_is_skipped() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+
+    // [u32 len][u16 type][u16 body_len = 20] with only 3 body bytes
+    let mut frame = 7u32.to_le_bytes().to_vec();
+    frame.extend_from_slice(&MSG_CANCEL.to_le_bytes());
+    frame.extend_from_slice(&20u16.to_le_bytes());
+    frame.extend_from_slice(&[0; 3]);
+    c.send_raw(&frame).await;
+    assert_eq!(c.nack(MSG_CANCEL).await, RejectReason::MalformedFrame);
+    c.limit(1, 1, 0, Side::Bid, 100, 10).await;
+}
+
+#[tokio::test]
+async fn frame_too_short_for_its_header_closes_the_connection() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+
+    let mut frame = 2u32.to_le_bytes().to_vec();
+    frame.extend_from_slice(&MSG_CANCEL.to_le_bytes());
+    c.send_raw(&frame).await;
+    assert_eq!(c.nack(0).await, RejectReason::MalformedFrame);
+    assert!(c.closed().await);
+}
+
+#[tokio::test]
+async fn oversized_length_prefix_closes_the_connection() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+
+    // Claims 4 GiB, far past MAX_FRAME_LEN; nothing of it is buffered
+    let mut frame = u32::MAX.to_le_bytes().to_vec();
+    frame.extend_from_slice(&MSG_NEW_ORDER.to_le_bytes());
+    frame.extend_from_slice(&0u16.to_le_bytes());
+    c.send_raw(&frame).await;
+    assert_eq!(c.nack(MSG_NEW_ORDER).await, RejectReason::FrameTooLarge);
+    assert!(c.closed().await);
+
+    // The server carries on for everyone else
+    engine.connect().await.limit(1, 1, 0, Side::Bid, 100, 10).await;
+}
+
+#[tokio::test]
+async fn flipped_byte_fails_the_checksum_and_closes_the_connection() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect_raw().await;
+    let crc = FrameFormat { order: ByteOrder::Little, crc: true };
+    c.send(MSG_HELLO, &hello(0x08)).await; // CRC32
+    c.set_format(crc);
+    assert!(matches!(c.event().await, Event::HelloAck { .. }));
+    c.send(MSG_PING, &[]).await;
+    assert!(matches!(c.event().await, Event::Pong));
+
+    let mut frame = encode_frame(MSG_CANCEL, &order_ref(1, 1, 0), crc);
+    frame[12] ^= 0x10;
+    c.send_raw(&frame).await;
+    assert_eq!(c.nack(MSG_CANCEL).await, RejectReason::BadChecksum);
+    assert!(c.closed().await);
+}
+
+#[tokio::test]
+async fn unknown_type_is_nacked_and_the_connection_carries_on() {
+    let engine = Engine::start(&[]);
+    let mut c = engine.connect().await;
+    c.send(0x7777, &[1, 2, 3]).await;
+    assert_eq!(c.nack(0x7777).await, RejectReason::UnknownType);
+    c.send(MSG_PING, &[]).await;
+    assert!(matches!(c.event().await, Event::Pong));
+}